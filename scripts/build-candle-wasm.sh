@@ -16,6 +16,11 @@ SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
 PROJECT_ROOT="$(cd "$SCRIPT_DIR/.." && pwd)"
 CANDLE_DIR="$PROJECT_ROOT/src/embeddings/candle-wasm"
 OUTPUT_DIR="$PROJECT_ROOT/src/embeddings/wasm/pkg"
+SIMD_OUTPUT_DIR="$PROJECT_ROOT/src/embeddings/wasm/pkg-simd"
+
+# RUSTFLAGS replaces the rustflags in .cargo/config.toml, so the getrandom
+# cfg set there is repeated for the SIMD build
+SIMD_RUSTFLAGS='--cfg getrandom_backend="wasm_js" -C target-feature=+simd128'
 
 # Colors for output
 RED='\033[0;31m'
@@ -85,20 +90,25 @@ download_model() {
     echo -e "${GREEN}Model files downloaded.${NC}"
 }
 
-# Build WASM
+# Build WASM: a baseline package, and a simd128 package for runtimes that
+# support it (js/capabilities.js select_artifact() picks between them)
 build_wasm() {
     local BUILD_MODE="${1:-release}"
+    local PROFILE_FLAG="--release"
+
+    if [ "$BUILD_MODE" != "release" ]; then
+        PROFILE_FLAG="--dev"
+    fi
 
-    echo -e "${GREEN}Building WASM (${BUILD_MODE})...${NC}"
     cd "$CANDLE_DIR"
 
-    if [ "$BUILD_MODE" = "release" ]; then
-        wasm-pack build --target web --release --out-dir "$OUTPUT_DIR"
-    else
-        wasm-pack build --target web --dev --out-dir "$OUTPUT_DIR"
-    fi
+    echo -e "${GREEN}Building baseline WASM (${BUILD_MODE})...${NC}"
+    wasm-pack build --target web "$PROFILE_FLAG" --out-dir "$OUTPUT_DIR"
+
+    echo -e "${GREEN}Building SIMD WASM (${BUILD_MODE})...${NC}"
+    RUSTFLAGS="$SIMD_RUSTFLAGS" wasm-pack build --target web "$PROFILE_FLAG" --out-dir "$SIMD_OUTPUT_DIR"
 
-    echo -e "${GREEN}WASM build complete. Output: $OUTPUT_DIR${NC}"
+    echo -e "${GREEN}WASM build complete. Output: $OUTPUT_DIR, $SIMD_OUTPUT_DIR${NC}"
 }
 
 # Main
@@ -126,7 +136,7 @@ main() {
     download_model
     build_wasm "$mode"
 
-    echo -e "${GREEN}Done! WASM package ready at: $OUTPUT_DIR${NC}"
+    echo -e "${GREEN}Done! WASM packages ready at: $OUTPUT_DIR (baseline), $SIMD_OUTPUT_DIR (simd)${NC}"
 }
 
 main "$@"
@@ -0,0 +1,52 @@
+// Runtime feature probes used by the Rust bindings (see src/capabilities.rs).
+//
+// Nothing here depends on the WASM module, so a loader can also import this
+// file on its own and call `select_artifact` before deciding which build to
+// instantiate: a SIMD build fails validation outright on engines without
+// SIMD, so the choice has to be made up front.
+
+// (module (func (result v128) i32.const 0 i8x16.splat i8x16.popcnt))
+const SIMD_PROBE = new Uint8Array([
+  0, 97, 115, 109, 1, 0, 0, 0, 1, 5, 1, 96, 0, 1, 123, 3, 2, 1, 0, 10, 10, 1, 8, 0, 65, 0, 253, 15,
+  253, 98, 11,
+])
+
+/** Whether the engine validates modules using 128-bit SIMD. */
+export function wasm_simd_supported() {
+  try {
+    return typeof WebAssembly === 'object' && WebAssembly.validate(SIMD_PROBE)
+  } catch {
+    return false
+  }
+}
+
+/** Whether `SharedArrayBuffer` can be constructed. */
+export function shared_array_buffer_supported() {
+  return typeof SharedArrayBuffer === 'function'
+}
+
+/**
+ * Whether the page is cross-origin isolated, which browsers require before
+ * handing out shared memory. Runtimes without the flag (Node, Deno, Bun)
+ * don't restrict it.
+ */
+export function cross_origin_isolated() {
+  return typeof crossOriginIsolated === 'undefined' || crossOriginIsolated === true
+}
+
+/**
+ * Pick the build to load: `artifacts.simd` where SIMD is supported and a SIMD
+ * build was given, `artifacts.baseline` otherwise.
+ *
+ * scripts/build-candle-wasm.sh writes the baseline package to
+ * `src/embeddings/wasm/pkg/` and the simd128 one to
+ * `src/embeddings/wasm/pkg-simd/`, so a loader next to them would pass
+ *
+ *   select_artifact({
+ *     simd: new URL('./pkg-simd/candle_embeddings_bg.wasm', import.meta.url),
+ *     baseline: new URL('./pkg/candle_embeddings_bg.wasm', import.meta.url),
+ *   })
+ */
+export function select_artifact(artifacts) {
+  return artifacts.simd !== undefined && wasm_simd_supported() ? artifacts.simd : artifacts.baseline
+}
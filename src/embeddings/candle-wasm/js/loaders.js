@@ -0,0 +1,56 @@
+// Runtime-neutral asset readers used by the Rust loaders (see src/loaders.rs).
+//
+// wasm-bindgen copies this file next to the generated bindings as a snippet, so
+// it must only use APIs that exist in the runtime that calls it. Nothing here
+// touches Node's `fs`/`path`, which keeps the `--target web` output usable from
+// Deno and edge runtimes without hand-patching.
+
+const isUrl = (path) => /^[a-z][a-z0-9+.-]*:\/\//i.test(path)
+
+/**
+ * Read a file or URL into a Uint8Array.
+ *
+ * Deno: plain paths go through `Deno.readFile` (needs `--allow-read`), URLs
+ * through `fetch` (needs `--allow-net` for remote hosts).
+ * Everywhere else: `fetch`.
+ */
+export async function read_asset(path) {
+  if (typeof Deno !== 'undefined' && !isUrl(path)) {
+    try {
+      return await Deno.readFile(path)
+    } catch (error) {
+      if (error instanceof Deno.errors.PermissionDenied) {
+        throw new Error(`Permission denied reading ${path}; run Deno with --allow-read=${path}`)
+      }
+      throw error
+    }
+  }
+
+  const response = await fetch(path)
+  if (!response.ok) {
+    throw new Error(`Failed to fetch ${path}: ${response.status} ${response.statusText}`)
+  }
+  return new Uint8Array(await response.arrayBuffer())
+}
+
+/**
+ * Read an asset embedded in a `bun build --compile` binary.
+ *
+ * Bun only embeds files referenced by static imports in the bundled code, e.g.
+ *   import modelPath from './model.safetensors' with { type: 'file' }
+ * which yields a `/$bunfs/...` path that only `Bun.file` can open. Outside Bun
+ * this falls back to `read_asset` so the same call works in development.
+ */
+export async function read_embedded_asset(path) {
+  if (typeof Bun !== 'undefined') {
+    const file = Bun.file(path)
+    if (!(await file.exists())) {
+      throw new Error(
+        `Embedded asset not found: ${path}. ` +
+        `Reference it with a static import so bun --compile embeds it.`
+      )
+    }
+    return new Uint8Array(await file.arrayBuffer())
+  }
+  return read_asset(path)
+}
@@ -0,0 +1,130 @@
+//! Attention maps alongside embeddings, for explaining which tokens drove them
+//!
+//! Attentions are the final encoder layer's probabilities averaged over heads.
+//! `salience` is the [CLS] row of that matrix: how much the sentence-level
+//! token attends to each input token.
+
+use candle_core::Tensor;
+use js_sys::{Array, Float32Array};
+use wasm_bindgen::prelude::*;
+
+use crate::presets::TextRole;
+use crate::{EmbeddingEngine, MAX_SEQUENCE_LENGTH};
+
+/// An embedding plus the attention that produced it
+#[wasm_bindgen]
+pub struct AttentionEmbedding {
+    embedding: Vec<f32>,
+    tokens: Vec<String>,
+    /// Head-averaged attention, row-major `[seq, seq]`
+    attentions: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl AttentionEmbedding {
+    /// The sentence embedding (same as `embed()` returns)
+    #[wasm_bindgen(getter)]
+    pub fn embedding(&self) -> Float32Array {
+        Float32Array::from(&self.embedding[..])
+    }
+
+    /// Tokens as the model saw them, including special tokens
+    #[wasm_bindgen(getter)]
+    pub fn tokens(&self) -> Array {
+        self.tokens.iter().map(|t| JsValue::from_str(t)).collect()
+    }
+
+    /// Number of tokens (rows and columns of `attentions`)
+    #[wasm_bindgen(getter)]
+    pub fn sequence_length(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Final-layer attention averaged over heads, row-major `[seq, seq]`;
+    /// row i is the distribution of token i's attention over all tokens
+    #[wasm_bindgen(getter)]
+    pub fn attentions(&self) -> Float32Array {
+        Float32Array::from(&self.attentions[..])
+    }
+
+    /// Per-token salience: attention from [CLS] to each token (sums to 1)
+    #[wasm_bindgen(getter)]
+    pub fn salience(&self) -> Float32Array {
+        Float32Array::from(&self.attentions[..self.tokens.len()])
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Embed a text and also return the final-layer attention matrix and
+    /// per-token salience, so apps can show which words drove the embedding
+    ///
+    /// The text is preprocessed and given the preset's document prefix as in
+    /// `embed()`, so `tokens` include the prefix. Only available for the
+    /// built-in BERT encoder (not ONNX models).
+    #[wasm_bindgen]
+    pub fn embed_with_attentions(&self, text: &str) -> Result<AttentionEmbedding, JsValue> {
+        let encodings =
+            self.tokenize_as(&self.preprocessed(&[text.to_string()]), TextRole::Document)?;
+        self.admit(&encodings)?;
+        let output = self.embed_encodings(&encodings, true)?;
+
+        let (Some(embedding), Some(attentions)) = (
+            output.embeddings.into_rows().into_iter().next(),
+            output.attentions,
+        ) else {
+            return Err(JsValue::from_str("No embedding generated"));
+        };
+
+        // Drop padding the tokenizer may have added
+        let encoding = &encodings[0];
+        let len = encoding
+            .get_attention_mask()
+            .iter()
+            .take(MAX_SEQUENCE_LENGTH)
+            .filter(|&&m| m != 0)
+            .count();
+        let tokens = encoding.get_tokens()[..len].to_vec();
+
+        let attentions = head_mean(&attentions, len)
+            .map_err(|e| JsValue::from_str(&format!("Attention extraction failed: {}", e)))?;
+
+        Ok(AttentionEmbedding {
+            embedding,
+            tokens,
+            attentions,
+        })
+    }
+}
+
+/// Average `[1, heads, seq, seq]` attentions over heads, keeping the leading
+/// `len x len` block, flattened row-major
+fn head_mean(attentions: &Tensor, len: usize) -> candle_core::Result<Vec<f32>> {
+    attentions
+        .squeeze(0)?
+        .mean(0)?
+        .narrow(0, 0, len)?
+        .narrow(1, 0, len)?
+        .flatten_all()?
+        .to_vec1()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+
+    #[test]
+    fn test_head_mean() {
+        let attentions = Tensor::new(
+            &[[[[0.5f32, 0.5], [1.0, 0.0]], [[0.0, 1.0], [0.0, 1.0]]]],
+            &Device::Cpu,
+        )
+        .unwrap();
+        assert_eq!(
+            head_mean(&attentions, 2).unwrap(),
+            vec![0.25, 0.75, 0.5, 0.5]
+        );
+        assert_eq!(head_mean(&attentions, 1).unwrap(), vec![0.25]);
+    }
+}
@@ -0,0 +1,328 @@
+//! Token-level attribution for similarity scores
+//!
+//! Each content token (special tokens are skipped) is represented by its
+//! L2-normalized token embedding from the layer the engine pools over. Tokens
+//! are greedily aligned to their most similar counterpart in the other text,
+//! as in BERTScore / ColBERT MaxSim, and that best similarity is the token's
+//! contribution score.
+
+use candle_core::Tensor;
+use js_sys::{Array, Float32Array, Int32Array, Uint32Array};
+use tokenizers::Encoding;
+use wasm_bindgen::prelude::*;
+
+use crate::presets::{unprefixed_offsets, TextRole};
+use crate::{EmbeddingEngine, MAX_SEQUENCE_LENGTH};
+
+/// A content token with its source span and normalized embedding
+pub(crate) struct TokenVector {
+    pub(crate) id: u32,
+    pub(crate) token: String,
+    /// Byte offsets into the original text
+    pub(crate) offsets: (usize, usize),
+    pub(crate) vector: Vec<f32>,
+}
+
+/// Extract the content tokens of batch row `index` from `[batch, seq, hidden]`
+pub(crate) fn content_tokens(
+    token_embeddings: &Tensor,
+    index: usize,
+    encoding: &Encoding,
+) -> candle_core::Result<Vec<TokenVector>> {
+    let seq_len = token_embeddings.dim(1)?.min(MAX_SEQUENCE_LENGTH);
+    let rows: Vec<Vec<f32>> = token_embeddings.get(index)?.to_vec2()?;
+
+    let mut tokens = Vec::new();
+    for (i, mut vector) in rows
+        .into_iter()
+        .enumerate()
+        .take(encoding.len().min(seq_len))
+    {
+        if encoding.get_attention_mask()[i] == 0 || encoding.get_special_tokens_mask()[i] != 0 {
+            continue;
+        }
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-12);
+        vector.iter_mut().for_each(|v| *v /= norm);
+        tokens.push(TokenVector {
+            id: encoding.get_ids()[i],
+            token: encoding.get_tokens()[i].clone(),
+            offsets: encoding.get_offsets()[i],
+            vector,
+        });
+    }
+    Ok(tokens)
+}
+
+/// Drop the tokens of a `prefix_len`-byte prefix, moving the rest's offsets
+/// back so they index the text after it
+pub(crate) fn drop_prefix_tokens(tokens: &mut Vec<TokenVector>, prefix_len: usize) {
+    tokens.retain_mut(
+        |token| match unprefixed_offsets(token.offsets, prefix_len) {
+            Some(offsets) => {
+                token.offsets = offsets;
+                true
+            }
+            None => false,
+        },
+    );
+}
+
+/// UTF-16 index of every byte position of `text` (valid at char boundaries),
+/// so byte offsets can be turned into JS string indices
+pub(crate) fn utf16_table(text: &str) -> Vec<u32> {
+    let mut table = vec![0u32; text.len() + 1];
+    let mut units = 0u32;
+    for (byte, ch) in text.char_indices() {
+        for slot in &mut table[byte..byte + ch.len_utf8()] {
+            *slot = units;
+        }
+        units += ch.len_utf16() as u32;
+    }
+    table[text.len()] = units;
+    table
+}
+
+/// Flattened UTF-16 `[start, end]` pairs for byte offsets into `text`
+pub(crate) fn utf16_offsets(
+    text: &str,
+    offsets: impl IntoIterator<Item = (usize, usize)>,
+) -> Vec<u32> {
+    let table = utf16_table(text);
+    offsets
+        .into_iter()
+        .flat_map(|(start, end)| [table[start.min(text.len())], table[end.min(text.len())]])
+        .collect()
+}
+
+/// Greedy best-match alignment between two sets of normalized vectors
+struct Alignment {
+    /// Best similarity of each query token to any document token
+    query_scores: Vec<f32>,
+    /// Index of that document token, or -1 if the document is empty
+    query_matches: Vec<i32>,
+    /// Best similarity of each document token to any query token
+    document_scores: Vec<f32>,
+}
+
+fn greedy_align(query: &[&[f32]], document: &[&[f32]]) -> Alignment {
+    let mut query_scores = vec![0.0f32; query.len()];
+    let mut query_matches = vec![-1i32; query.len()];
+    let mut document_scores = vec![f32::NEG_INFINITY; document.len()];
+
+    for (i, q) in query.iter().enumerate() {
+        let mut best = f32::NEG_INFINITY;
+        for (j, d) in document.iter().enumerate() {
+            let sim: f32 = q.iter().zip(d.iter()).map(|(a, b)| a * b).sum();
+            if sim > best {
+                best = sim;
+                query_matches[i] = j as i32;
+            }
+            if sim > document_scores[j] {
+                document_scores[j] = sim;
+            }
+        }
+        if !document.is_empty() {
+            query_scores[i] = best;
+        }
+    }
+    if query.is_empty() {
+        document_scores.iter_mut().for_each(|s| *s = 0.0);
+    }
+
+    Alignment {
+        query_scores,
+        query_matches,
+        document_scores,
+    }
+}
+
+/// Why two texts are similar, token by token
+#[wasm_bindgen]
+pub struct SimilarityExplanation {
+    score: f32,
+    query_tokens: Vec<String>,
+    query_offsets: Vec<u32>,
+    document_tokens: Vec<String>,
+    document_offsets: Vec<u32>,
+    alignment: Alignment,
+}
+
+#[wasm_bindgen]
+impl SimilarityExplanation {
+    /// Cosine similarity of the two sentence embeddings
+    #[wasm_bindgen(getter)]
+    pub fn score(&self) -> f32 {
+        self.score
+    }
+
+    /// Content tokens of the query (special tokens removed)
+    #[wasm_bindgen(getter)]
+    pub fn query_tokens(&self) -> Array {
+        self.query_tokens
+            .iter()
+            .map(|t| JsValue::from_str(t))
+            .collect()
+    }
+
+    /// Per query token: best cosine similarity to any document token
+    #[wasm_bindgen(getter)]
+    pub fn query_scores(&self) -> Float32Array {
+        Float32Array::from(&self.alignment.query_scores[..])
+    }
+
+    /// Per query token: index of the best-matching document token (-1 if none)
+    #[wasm_bindgen(getter)]
+    pub fn query_matches(&self) -> Int32Array {
+        Int32Array::from(&self.alignment.query_matches[..])
+    }
+
+    /// `[start, end]` pairs per query token, as UTF-16 indices into the query
+    #[wasm_bindgen(getter)]
+    pub fn query_offsets(&self) -> Uint32Array {
+        Uint32Array::from(&self.query_offsets[..])
+    }
+
+    /// Content tokens of the document (special tokens removed)
+    #[wasm_bindgen(getter)]
+    pub fn document_tokens(&self) -> Array {
+        self.document_tokens
+            .iter()
+            .map(|t| JsValue::from_str(t))
+            .collect()
+    }
+
+    /// Per document token: best cosine similarity to any query token
+    #[wasm_bindgen(getter)]
+    pub fn document_scores(&self) -> Float32Array {
+        Float32Array::from(&self.alignment.document_scores[..])
+    }
+
+    /// `[start, end]` pairs per document token, as UTF-16 indices into the
+    /// document, suitable for `String.prototype.slice`
+    #[wasm_bindgen(getter)]
+    pub fn document_offsets(&self) -> Uint32Array {
+        Uint32Array::from(&self.document_offsets[..])
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Explain a similarity score with per-token contributions
+    ///
+    /// Every content token is aligned to its most similar token in the other
+    /// text; its contribution is that similarity. Use `document_scores` with
+    /// `document_offsets` to highlight why a result matched.
+    ///
+    /// Each side gets its preset prefix as in `embed_query()` and `embed()`,
+    /// so `score` is their cosine; the prefix tokens are left out of the
+    /// attribution. `set_preprocessing()` is not applied, so offsets index
+    /// the texts as given.
+    #[wasm_bindgen]
+    pub fn explain_similarity(
+        &self,
+        query: &str,
+        document: &str,
+    ) -> Result<SimilarityExplanation, JsValue> {
+        let mut encodings = self.tokenize_as(&[query.to_string()], TextRole::Query)?;
+        encodings.extend(self.tokenize_as(&[document.to_string()], TextRole::Document)?);
+        self.admit(&encodings)?;
+        let output = self.embed_encodings(&encodings, false)?;
+        let token_embeddings = output
+            .token_embeddings
+            .ok_or_else(|| JsValue::from_str("No embedding generated"))?;
+
+        let score = output
+            .embeddings
+            .row(0)
+            .iter()
+            .zip(output.embeddings.row(1))
+            .map(|(a, b)| a * b)
+            .sum();
+
+        let extract = |index: usize, role: TextRole| {
+            let mut tokens = content_tokens(&token_embeddings, index, &encodings[index])
+                .map_err(|e| JsValue::from_str(&format!("Token extraction failed: {}", e)))?;
+            drop_prefix_tokens(&mut tokens, self.role_prefix(role).len());
+            Ok::<_, JsValue>(tokens)
+        };
+        let query_tokens = extract(0, TextRole::Query)?;
+        let document_tokens = extract(1, TextRole::Document)?;
+
+        let alignment = greedy_align(&vector_refs(&query_tokens), &vector_refs(&document_tokens));
+
+        let (query_tokens, query_offsets) = split_tokens(query_tokens, query);
+        let (document_tokens, document_offsets) = split_tokens(document_tokens, document);
+
+        Ok(SimilarityExplanation {
+            score,
+            query_tokens,
+            query_offsets,
+            document_tokens,
+            document_offsets,
+            alignment,
+        })
+    }
+}
+
+/// Borrow the embedding of each token
+pub(crate) fn vector_refs(tokens: &[TokenVector]) -> Vec<&[f32]> {
+    tokens.iter().map(|t| t.vector.as_slice()).collect()
+}
+
+/// Token strings and flattened UTF-16 `[start, end]` offsets
+fn split_tokens(tokens: Vec<TokenVector>, text: &str) -> (Vec<String>, Vec<u32>) {
+    let offsets = utf16_offsets(text, tokens.iter().map(|t| t.offsets));
+    (tokens.into_iter().map(|t| t.token).collect(), offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_greedy_align() {
+        let query: Vec<&[f32]> = vec![&[1.0, 0.0], &[0.0, 1.0]];
+        let document: Vec<&[f32]> = vec![&[0.0, 1.0], &[0.6, 0.8], &[-1.0, 0.0]];
+        let alignment = greedy_align(&query, &document);
+
+        assert_eq!(alignment.query_matches, vec![1, 0]);
+        assert_eq!(alignment.query_scores, vec![0.6, 1.0]);
+        assert_eq!(alignment.document_scores, vec![1.0, 0.8, 0.0]);
+
+        let empty = greedy_align(&query, &[]);
+        assert_eq!(empty.query_matches, vec![-1, -1]);
+        assert_eq!(empty.query_scores, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_drop_prefix_tokens() {
+        let token = |offsets| TokenVector {
+            id: 0,
+            token: String::new(),
+            offsets,
+            vector: Vec::new(),
+        };
+        // "query: hi" tokenized as "query", ":", "hi"
+        let mut tokens = vec![token((0, 5)), token((5, 6)), token((7, 9))];
+        drop_prefix_tokens(&mut tokens, "query: ".len());
+        assert_eq!(
+            tokens.iter().map(|t| t.offsets).collect::<Vec<_>>(),
+            vec![(0, 2)]
+        );
+    }
+
+    #[test]
+    fn test_utf16_table() {
+        // 'é' is 2 bytes / 1 unit, '😀' is 4 bytes / 2 units
+        let text = "é😀a";
+        let table = utf16_table(text);
+        assert_eq!(table[0], 0);
+        assert_eq!(table[2], 1);
+        assert_eq!(table[6], 3);
+        assert_eq!(table[7], 4);
+        assert_eq!(
+            utf16_offsets(text, [(0, 2), (2, 7), (7, 99)]),
+            vec![0, 1, 1, 4, 4, 4]
+        );
+    }
+}
@@ -0,0 +1,434 @@
+//! Settings detected from a checkpoint's own config files
+//!
+//! config.json names the architecture the weights belong to (`model_type`,
+//! `architectures`). `load()` uses it to pick the encoder variant, and rejects
+//! architectures the built-in encoder can't run instead of running them as
+//! BERT and returning plausible-looking vectors.
+//!
+//! Sentence-transformers checkpoints also record how their vectors are made:
+//! the pooling mode (`1_Pooling/config.json`), the modules after the
+//! transformer (`modules.json`) and input lowercasing and length
+//! (`sentence_bert_config.json`). `apply_sentence_transformers_config()` takes
+//! those files, and `load_from_path()` reads them when they exist. Vectors are
+//! always L2-normalized; for checkpoints without a `Normalize` module that
+//! keeps their cosine similarities but not their raw dot products.
+//!
+//! Detection can be overridden: `load_with_options({ config: { model_type } })`
+//! replaces the architecture, and `set_pooling()`/`set_normalization()` after
+//! loading replace the detected settings.
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::{presets, EmbeddingEngine, PoolingStrategy};
+
+/// Encoder variant for a checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ModelFamily {
+    /// BERT and checkpoints sharing its layout
+    Bert,
+    /// RoBERTa, XLM-RoBERTa and CamemBERT: BERT layers, but positions are
+    /// counted from `pad_token_id + 1` and weights are stored under `roberta.`
+    Roberta,
+}
+
+impl ModelFamily {
+    /// Family for a config's `model_type`, falling back to its first
+    /// `architectures` entry; configs with neither are taken to be BERT
+    pub(crate) fn detect(
+        model_type: Option<&str>,
+        architectures: &[String],
+    ) -> Result<Self, String> {
+        let name = match (model_type, architectures.first()) {
+            (Some(model_type), _) => model_type,
+            (None, Some(architecture)) => architecture.as_str(),
+            (None, None) => return Ok(ModelFamily::Bert),
+        };
+        // "xlm-roberta" and "XLMRobertaModel" both become "xlmroberta..."
+        let key: String = name
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        if ["roberta", "xlmroberta", "camembert"]
+            .iter()
+            .any(|family| key.starts_with(family))
+        {
+            Ok(ModelFamily::Roberta)
+        } else if key.starts_with("bert") {
+            Ok(ModelFamily::Bert)
+        } else {
+            Err(format!(
+                "Unsupported architecture '{}': load() runs BERT and RoBERTa-family encoders. \
+                 Convert the model to ONNX and use load_onnx(), or, if the checkpoint really \
+                 has BERT's layout, override model_type with load_with_options()",
+                name
+            ))
+        }
+    }
+
+    /// Prefix of the tensor names in full-model checkpoints
+    pub(crate) fn weight_prefix(&self) -> &'static str {
+        match self {
+            ModelFamily::Bert => "bert",
+            ModelFamily::Roberta => "roberta",
+        }
+    }
+
+    /// Row of the position table used for the first token
+    pub(crate) fn position_offset(&self, pad_token_id: usize) -> usize {
+        match self {
+            ModelFamily::Bert => 0,
+            ModelFamily::Roberta => pad_token_id + 1,
+        }
+    }
+}
+
+/// `1_Pooling/config.json`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PoolingConfig {
+    pooling_mode_cls_token: bool,
+    pooling_mode_mean_tokens: bool,
+    pooling_mode_max_tokens: bool,
+    pooling_mode_mean_sqrt_len_tokens: bool,
+    pooling_mode_weightedmean_tokens: bool,
+    pooling_mode_lasttoken: bool,
+}
+
+impl PoolingConfig {
+    /// The single enabled mode, if the engine implements it
+    fn strategy(&self) -> Result<PoolingStrategy, String> {
+        let modes = [
+            (
+                "cls_token",
+                self.pooling_mode_cls_token,
+                Some(PoolingStrategy::Cls),
+            ),
+            (
+                "mean_tokens",
+                self.pooling_mode_mean_tokens,
+                Some(PoolingStrategy::Mean),
+            ),
+            ("max_tokens", self.pooling_mode_max_tokens, None),
+            (
+                "mean_sqrt_len_tokens",
+                self.pooling_mode_mean_sqrt_len_tokens,
+                None,
+            ),
+            (
+                "weightedmean_tokens",
+                self.pooling_mode_weightedmean_tokens,
+                Some(PoolingStrategy::WeightedMean),
+            ),
+            ("lasttoken", self.pooling_mode_lasttoken, None),
+        ];
+        let enabled: Vec<_> = modes.iter().filter(|(_, on, _)| *on).collect();
+        match enabled.as_slice() {
+            [(_, _, Some(strategy))] => Ok(*strategy),
+            [(name, _, None)] => Err(format!(
+                "Unsupported pooling mode {} (supported: cls_token, mean_tokens, weightedmean_tokens)",
+                name
+            )),
+            [] => Err("Pooling config enables no pooling mode".to_string()),
+            _ => Err(format!(
+                "Pooling config combines several modes ({}); only one is supported",
+                enabled
+                    .iter()
+                    .map(|(name, _, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+/// One entry of `modules.json`
+#[derive(Debug, Deserialize)]
+struct ModuleEntry {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    path: String,
+}
+
+impl ModuleEntry {
+    /// Class name without the `sentence_transformers.models.` package
+    fn class(&self) -> &str {
+        self.kind.rsplit('.').next().unwrap_or_default()
+    }
+}
+
+/// `sentence_bert_config.json`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SentenceBertConfig {
+    max_seq_length: Option<usize>,
+    do_lower_case: bool,
+}
+
+/// Parse one optional JSON file
+fn parse_file<T: DeserializeOwned>(bytes: Option<&[u8]>, file: &str) -> Result<Option<T>, String> {
+    bytes
+        .map(serde_json::from_slice)
+        .transpose()
+        .map_err(|e| format!("Failed to parse {}: {}", file, e))
+}
+
+/// The sentence-transformers files, as passed to
+/// `apply_sentence_transformers_config()`; each one is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct SentenceTransformersFiles {
+    /// modules.json
+    modules: Option<Vec<ModuleEntry>>,
+    /// 1_Pooling/config.json
+    pooling: Option<PoolingConfig>,
+    /// sentence_bert_config.json
+    sentence_bert_config: Option<SentenceBertConfig>,
+}
+
+/// What the sentence-transformers files ask of the engine
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SentenceTransformersSettings {
+    pub(crate) pooling: Option<PoolingStrategy>,
+    pub(crate) max_length: Option<usize>,
+    pub(crate) lowercase: bool,
+}
+
+impl SentenceTransformersFiles {
+    /// Parse modules.json and sentence_bert_config.json; `None` for files the
+    /// checkpoint lacks
+    pub(crate) fn from_bytes(
+        modules: Option<&[u8]>,
+        sentence_bert_config: Option<&[u8]>,
+    ) -> Result<Self, String> {
+        Ok(SentenceTransformersFiles {
+            modules: parse_file(modules, "modules.json")?,
+            pooling: None,
+            sentence_bert_config: parse_file(sentence_bert_config, "sentence_bert_config.json")?,
+        })
+    }
+
+    /// Add the pooling config, read from `pooling_path()`
+    pub(crate) fn with_pooling(mut self, pooling: Option<&[u8]>) -> Result<Self, String> {
+        self.pooling = parse_file(pooling, "pooling config")?;
+        Ok(self)
+    }
+
+    /// Directory of the pooling module's config, relative to the model
+    /// (`1_Pooling` unless modules.json says otherwise)
+    pub(crate) fn pooling_path(&self) -> &str {
+        self.modules
+            .iter()
+            .flatten()
+            .find(|module| module.class() == "Pooling" && !module.path.is_empty())
+            .map_or("1_Pooling", |module| module.path.as_str())
+    }
+
+    /// Check the pipeline is one the engine reproduces and collect its settings
+    pub(crate) fn settings(&self) -> Result<SentenceTransformersSettings, String> {
+        for module in self.modules.iter().flatten() {
+            match module.class() {
+                "Transformer" | "Pooling" | "Normalize" => {}
+                "Dense" => {
+                    return Err(format!(
+                        "modules.json includes a Dense layer ({}), which the engine does not run; \
+                         its vectors would not match the model's",
+                        module.path
+                    ))
+                }
+                other => {
+                    return Err(format!(
+                        "Unsupported sentence-transformers module: {}",
+                        other
+                    ))
+                }
+            }
+        }
+        let sentence_bert = self.sentence_bert_config.as_ref();
+        Ok(SentenceTransformersSettings {
+            pooling: self
+                .pooling
+                .as_ref()
+                .map(PoolingConfig::strategy)
+                .transpose()?,
+            max_length: sentence_bert.and_then(|config| config.max_seq_length),
+            lowercase: sentence_bert.is_some_and(|config| config.do_lower_case),
+        })
+    }
+}
+
+impl EmbeddingEngine {
+    /// Apply settings from a sentence-transformers checkpoint to the loaded
+    /// model
+    pub(crate) fn apply_sentence_transformers(
+        &mut self,
+        files: &SentenceTransformersFiles,
+    ) -> Result<(), JsValue> {
+        let settings = files.settings().map_err(|e| JsValue::from_str(&e))?;
+        let tokenizer = self.tokenizer.as_mut().ok_or_else(|| {
+            JsValue::from_str("Tokenizer not loaded. Call load_embedded() first.")
+        })?;
+        if let Some(max_length) = settings.max_length {
+            presets::limit_max_length(tokenizer, max_length).map_err(|e| JsValue::from_str(&e))?;
+            self.token_cache.borrow_mut().clear();
+        }
+        if settings.lowercase {
+            self.lowercase_input()?;
+        }
+        if let Some(pooling) = settings.pooling {
+            self.pooling = pooling;
+        }
+        info_log!(
+            "sentence-transformers config: {} pooling, max length {:?}, lowercase {}",
+            self.pooling.name(),
+            settings.max_length,
+            settings.lowercase
+        );
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Configure the loaded model from its sentence-transformers files
+    ///
+    /// Takes the parsed JSON of whichever files the checkpoint has: `{
+    /// modules?, pooling?, sentence_bert_config? }` for `modules.json`,
+    /// `1_Pooling/config.json` and `sentence_bert_config.json`. Sets the
+    /// pooling strategy, maximum length and input lowercasing they specify,
+    /// and errors on pipelines the engine can't reproduce (e.g. an extra
+    /// `Dense` layer). `load_from_path()` does this automatically.
+    #[wasm_bindgen]
+    pub fn apply_sentence_transformers_config(&mut self, files: &JsValue) -> Result<(), JsValue> {
+        let files: SentenceTransformersFiles = parse_options(files)?;
+        self.apply_sentence_transformers(&files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js::options_from_json;
+    use std::str::FromStr;
+    use tokenizers::Tokenizer;
+
+    #[test]
+    fn test_family_detection() {
+        let none: &[String] = &[];
+        let detect =
+            |model_type, architectures: &[String]| ModelFamily::detect(model_type, architectures);
+        assert_eq!(detect(Some("bert"), none).unwrap(), ModelFamily::Bert);
+        assert_eq!(detect(None, none).unwrap(), ModelFamily::Bert);
+        assert_eq!(
+            detect(None, &["XLMRobertaModel".to_string()]).unwrap(),
+            ModelFamily::Roberta
+        );
+        assert_eq!(
+            detect(Some("camembert"), none).unwrap(),
+            ModelFamily::Roberta
+        );
+        let err = detect(Some("mpnet"), &["MPNetForMaskedLM".to_string()]).unwrap_err();
+        assert!(err.contains("mpnet"));
+        assert!(detect(Some("distilbert"), none).is_err());
+        assert_eq!(ModelFamily::Roberta.position_offset(1), 2);
+        assert_eq!(ModelFamily::Bert.position_offset(0), 0);
+    }
+
+    #[test]
+    fn test_sentence_transformers_settings() {
+        let files: SentenceTransformersFiles = options_from_json(
+            r#"{
+                "modules": [
+                    {"idx": 0, "name": "0", "path": "", "type": "sentence_transformers.models.Transformer"},
+                    {"idx": 1, "name": "1", "path": "1_Pooling", "type": "sentence_transformers.models.Pooling"},
+                    {"idx": 2, "name": "2", "path": "2_Normalize", "type": "sentence_transformers.models.Normalize"}
+                ],
+                "pooling": {"word_embedding_dimension": 384, "pooling_mode_cls_token": true,
+                            "pooling_mode_mean_tokens": false},
+                "sentence_bert_config": {"max_seq_length": 512, "do_lower_case": true}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(files.pooling_path(), "1_Pooling");
+        assert_eq!(
+            files.settings().unwrap(),
+            SentenceTransformersSettings {
+                pooling: Some(PoolingStrategy::Cls),
+                max_length: Some(512),
+                lowercase: true,
+            }
+        );
+        let empty = SentenceTransformersFiles::default();
+        assert_eq!(
+            empty.settings().unwrap(),
+            SentenceTransformersSettings::default()
+        );
+    }
+
+    #[test]
+    fn test_unsupported_pipelines_rejected() {
+        let dense = SentenceTransformersFiles::from_bytes(
+            Some(br#"[{"path": "2_Dense", "type": "sentence_transformers.models.Dense"}]"#),
+            None,
+        )
+        .unwrap();
+        assert!(dense.settings().unwrap_err().contains("2_Dense"));
+
+        let pooling = |json: &[u8]| {
+            SentenceTransformersFiles::default()
+                .with_pooling(Some(json))
+                .unwrap()
+                .settings()
+        };
+        assert!(pooling(br#"{"pooling_mode_max_tokens": true}"#)
+            .unwrap_err()
+            .contains("max_tokens"));
+        assert!(
+            pooling(br#"{"pooling_mode_cls_token": true, "pooling_mode_mean_tokens": true}"#)
+                .is_err()
+        );
+        assert_eq!(
+            pooling(br#"{"pooling_mode_weightedmean_tokens": true}"#)
+                .unwrap()
+                .pooling,
+            Some(PoolingStrategy::WeightedMean)
+        );
+        assert!(SentenceTransformersFiles::from_bytes(Some(b"{"), None).is_err());
+    }
+
+    #[test]
+    fn test_max_seq_length_within_engine_limit() {
+        let mut engine = EmbeddingEngine::new();
+        engine.tokenizer = Some(
+            Tokenizer::from_str(
+                r#"{
+                    "version": "1.0", "truncation": null, "padding": null, "added_tokens": [],
+                    "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+                    "post_processor": {"type": "BertProcessing",
+                                       "sep": ["[SEP]", 2], "cls": ["[CLS]", 1]},
+                    "decoder": null,
+                    "model": {"type": "WordLevel",
+                              "vocab": {"[UNK]": 0, "[CLS]": 1, "[SEP]": 2, "cat": 3},
+                              "unk_token": "[UNK]"}
+                }"#,
+            )
+            .unwrap(),
+        );
+        let files: SentenceTransformersFiles =
+            options_from_json(r#"{"sentence_bert_config": {"max_seq_length": 512}}"#).unwrap();
+        engine.apply_sentence_transformers(&files).unwrap();
+        let text = "cat ".repeat(300);
+        let encoding = engine
+            .tokenizer
+            .as_ref()
+            .unwrap()
+            .encode(text.as_str(), true)
+            .unwrap();
+        assert_eq!(encoding.len(), crate::MAX_SEQUENCE_LENGTH);
+        assert_eq!(encoding.get_ids().last(), Some(&2));
+    }
+}
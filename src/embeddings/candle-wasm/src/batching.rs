@@ -0,0 +1,157 @@
+//! Batch preparation helpers
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Unique texts in first-seen order, plus the index into them of every input
+///
+/// Real-world batches (tags, categories) are often largely duplicates, so
+/// inference runs once per unique text and results are fanned back out.
+pub(crate) fn dedup_texts(texts: Vec<String>) -> (Vec<String>, Vec<usize>) {
+    let mut index_of: HashMap<String, usize> = HashMap::with_capacity(texts.len());
+    let mut unique = Vec::new();
+    let positions = texts
+        .into_iter()
+        .map(|text| {
+            *index_of.entry(text).or_insert_with_key(|text| {
+                unique.push(text.clone());
+                unique.len() - 1
+            })
+        })
+        .collect();
+    (unique, positions)
+}
+
+/// Expand per-unique-text results back to the original input order
+pub(crate) fn fan_out<T: Clone>(results: Vec<T>, positions: &[usize]) -> Vec<T> {
+    positions.iter().map(|&i| results[i].clone()).collect()
+}
+
+/// Split sequences (by padded length, in order) into contiguous batches for
+/// which `fits(batch_size, longest)` holds
+///
+/// Each batch is padded to its longest sequence, so a long sequence also
+/// makes the batch it joins more expensive. A sequence that doesn't fit even
+/// on its own still gets a batch to itself.
+pub(crate) fn pack_batches(
+    seq_lens: &[usize],
+    fits: impl Fn(usize, usize) -> bool,
+) -> Vec<Range<usize>> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut longest = 0;
+    for (i, &len) in seq_lens.iter().enumerate() {
+        let candidate = longest.max(len);
+        if i > start && !fits(i + 1 - start, candidate) {
+            batches.push(start..i);
+            start = i;
+            longest = len;
+        } else {
+            longest = candidate;
+        }
+    }
+    if start < seq_lens.len() {
+        batches.push(start..seq_lens.len());
+    }
+    batches
+}
+
+/// Row-major sentence embeddings of a batch in one contiguous buffer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct EmbeddingMatrix {
+    data: Vec<f32>,
+    dimension: usize,
+}
+
+impl EmbeddingMatrix {
+    /// Wrap `data` as rows of `dimension` values
+    pub(crate) fn new(data: Vec<f32>, dimension: usize) -> Self {
+        debug_assert!(dimension == 0 || data.len().is_multiple_of(dimension));
+        EmbeddingMatrix { data, dimension }
+    }
+
+    /// Number of rows
+    pub(crate) fn len(&self) -> usize {
+        self.data.len().checked_div(self.dimension).unwrap_or(0)
+    }
+
+    pub(crate) fn row(&self, index: usize) -> &[f32] {
+        &self.data[index * self.dimension..(index + 1) * self.dimension]
+    }
+
+    /// All rows, back to back
+    pub(crate) fn as_slice(&self) -> &[f32] {
+        &self.data
+    }
+
+    /// Append the rows of another matrix of the same width
+    pub(crate) fn append(&mut self, other: EmbeddingMatrix) {
+        if self.data.is_empty() {
+            *self = other;
+        } else {
+            self.data.extend(other.data);
+        }
+    }
+
+    /// Rows at `positions`, in that order (the matrix form of `fan_out`)
+    pub(crate) fn gather(&self, positions: &[usize]) -> EmbeddingMatrix {
+        let mut data = Vec::with_capacity(positions.len() * self.dimension);
+        for &i in positions {
+            data.extend_from_slice(self.row(i));
+        }
+        EmbeddingMatrix::new(data, self.dimension)
+    }
+
+    /// One vector per row
+    pub(crate) fn into_rows(self) -> Vec<Vec<f32>> {
+        if self.dimension == 0 {
+            return Vec::new();
+        }
+        self.data
+            .chunks_exact(self.dimension)
+            .map(<[f32]>::to_vec)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_round_trip() {
+        let texts: Vec<String> = ["a", "b", "a", "c", "b", "a"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (unique, positions) = dedup_texts(texts.clone());
+        assert_eq!(unique, vec!["a", "b", "c"]);
+        assert_eq!(positions, vec![0, 1, 0, 2, 1, 0]);
+        assert_eq!(fan_out(unique, &positions), texts);
+    }
+
+    #[test]
+    fn test_pack_batches() {
+        let fits = |batch: usize, len: usize| batch * len <= 8;
+        assert_eq!(pack_batches(&[4, 4, 4, 4, 4], fits), vec![0..2, 2..4, 4..5]);
+        // A long sequence forces the batch it joins to be padded to its length
+        assert_eq!(pack_batches(&[2, 2, 6, 2], fits), vec![0..2, 2..3, 3..4]);
+        // Too long on its own: runs alone
+        assert_eq!(pack_batches(&[2, 9, 2], fits), vec![0..1, 1..2, 2..3]);
+        assert!(pack_batches(&[], fits).is_empty());
+    }
+
+    #[test]
+    fn test_embedding_matrix() {
+        let mut matrix = EmbeddingMatrix::default();
+        matrix.append(EmbeddingMatrix::new(vec![1.0, 2.0], 2));
+        matrix.append(EmbeddingMatrix::new(vec![3.0, 4.0, 5.0, 6.0], 2));
+        assert_eq!(matrix.len(), 3);
+        assert_eq!(matrix.row(1), &[3.0, 4.0]);
+
+        let gathered = matrix.gather(&[2, 0, 2]);
+        assert_eq!(gathered.as_slice(), &[5.0, 6.0, 1.0, 2.0, 5.0, 6.0]);
+        assert_eq!(gathered.into_rows()[1], vec![1.0, 2.0]);
+        assert!(EmbeddingMatrix::default().into_rows().is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+//! Recall and latency benchmarking for vector indexes
+//!
+//! ANN parameters (HNSW `ef`, PQ code sizes) trade recall for speed, and the
+//! tradeoff measured by native libraries doesn't carry over to WASM, where
+//! memory bandwidth and the absence of threads change the balance.
+//! `benchmark_index()` runs a query set against any index object in the
+//! target runtime and reports recall@k against exact results along with
+//! throughput and latency percentiles.
+
+use js_sys::{Array, Function, Promise, Reflect};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::clock;
+
+/// Result of `benchmark_index()`
+#[wasm_bindgen]
+pub struct BenchmarkReport {
+    queries: usize,
+    recall: f64,
+    qps: f64,
+    mean_ms: f64,
+    p50_ms: f64,
+    p99_ms: f64,
+}
+
+#[wasm_bindgen]
+impl BenchmarkReport {
+    /// Number of queries run
+    #[wasm_bindgen(getter)]
+    pub fn queries(&self) -> usize {
+        self.queries
+    }
+
+    /// Mean recall@k over all queries
+    #[wasm_bindgen(getter)]
+    pub fn recall(&self) -> f64 {
+        self.recall
+    }
+
+    /// Queries per second, run one after another
+    #[wasm_bindgen(getter)]
+    pub fn qps(&self) -> f64 {
+        self.qps
+    }
+
+    /// Mean latency per query
+    #[wasm_bindgen(getter)]
+    pub fn mean_ms(&self) -> f64 {
+        self.mean_ms
+    }
+
+    /// Median latency
+    #[wasm_bindgen(getter)]
+    pub fn p50_ms(&self) -> f64 {
+        self.p50_ms
+    }
+
+    /// 99th percentile latency
+    #[wasm_bindgen(getter)]
+    pub fn p99_ms(&self) -> f64 {
+        self.p99_ms
+    }
+
+    /// One-line human-readable summary
+    #[wasm_bindgen]
+    pub fn summary(&self) -> String {
+        format!(
+            "{} queries: recall {:.3}, {:.0} QPS, p50 {:.2}ms, p99 {:.2}ms",
+            self.queries, self.recall, self.qps, self.p50_ms, self.p99_ms
+        )
+    }
+}
+
+/// Fraction of the true top-k found in the first k results (1 when there
+/// is nothing to find)
+fn recall(retrieved: &[String], truth: &[String], k: usize) -> f64 {
+    let truth = &truth[..k.min(truth.len())];
+    if truth.is_empty() {
+        return 1.0;
+    }
+    let retrieved = &retrieved[..k.min(retrieved.len())];
+    let found = truth.iter().filter(|id| retrieved.contains(id)).count();
+    found as f64 / truth.len() as f64
+}
+
+/// Nearest-rank percentile of sorted values (0 for none)
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Comparable form of a result id: a number, a string, or an object with an
+/// `id` property holding either
+fn result_id(value: &JsValue) -> Result<String, JsValue> {
+    if let Some(n) = value.as_f64() {
+        return Ok(n.to_string());
+    }
+    if let Some(s) = value.as_string() {
+        return Ok(s);
+    }
+    if value.is_object() {
+        let id = Reflect::get(value, &JsValue::from_str("id"))?;
+        if !id.is_object() && !id.is_undefined() {
+            return result_id(&id);
+        }
+    }
+    Err(JsValue::from_str(
+        "Result ids must be numbers, strings or objects with an id",
+    ))
+}
+
+fn result_ids(results: &JsValue) -> Result<Vec<String>, JsValue> {
+    let results = results
+        .dyn_ref::<Array>()
+        .ok_or_else(|| JsValue::from_str("Index search must return an array"))?;
+    results.iter().map(|r| result_id(&r)).collect()
+}
+
+/// Measure recall@k and latency of `index.search(query, k)` over a query set
+///
+/// `index` is any object with a `search(query, k)` method returning (or
+/// resolving to) an array of ids, or objects with an `id`. `ground_truth[i]`
+/// lists the exact nearest ids for `queries[i]`, best first, e.g. from a
+/// brute-force search. With `k` 0, each query uses the length of its ground
+/// truth. Queries run one at a time, so latency excludes queueing.
+#[wasm_bindgen]
+pub async fn benchmark_index(
+    index: JsValue,
+    queries: Array,
+    ground_truth: Array,
+    k: usize,
+) -> Result<BenchmarkReport, JsValue> {
+    if queries.length() != ground_truth.length() {
+        return Err(JsValue::from_str(&format!(
+            "Got {} queries but {} ground truth lists",
+            queries.length(),
+            ground_truth.length()
+        )));
+    }
+    let search: Function = Reflect::get(&index, &JsValue::from_str("search"))?
+        .dyn_into()
+        .map_err(|_| JsValue::from_str("Index has no search(query, k) method"))?;
+
+    let mut latencies = Vec::with_capacity(queries.length() as usize);
+    let mut recall_sum = 0.0;
+    let start = clock::now_ms();
+    for (query, truth) in queries.iter().zip(ground_truth.iter()) {
+        let truth = result_ids(&truth)?;
+        let query_k = if k == 0 { truth.len() } else { k };
+        let timer = clock::now_ms();
+        let mut results = search.call2(&index, &query, &JsValue::from(query_k as u32))?;
+        if let Some(promise) = results.dyn_ref::<Promise>() {
+            results = JsFuture::from(promise.clone()).await?;
+        }
+        latencies.push(clock::now_ms() - timer);
+        recall_sum += recall(&result_ids(&results)?, &truth, query_k);
+    }
+    let elapsed = clock::now_ms() - start;
+
+    let queries = latencies.len();
+    let mean_ms = latencies.iter().sum::<f64>() / queries.max(1) as f64;
+    latencies.sort_by(f64::total_cmp);
+    Ok(BenchmarkReport {
+        queries,
+        recall: recall_sum / queries.max(1) as f64,
+        qps: if elapsed > 0.0 {
+            queries as f64 * 1000.0 / elapsed
+        } else {
+            0.0
+        },
+        mean_ms,
+        p50_ms: percentile(&latencies, 50.0),
+        p99_ms: percentile(&latencies, 99.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_recall() {
+        let truth = ids(&["a", "b", "c", "d"]);
+        assert_eq!(recall(&ids(&["a", "x", "c"]), &truth, 3), 2.0 / 3.0);
+        // Only the first k results count
+        assert_eq!(recall(&ids(&["x", "y", "a"]), &truth, 2), 0.0);
+        assert_eq!(recall(&ids(&[]), &ids(&[]), 10), 1.0);
+        assert_eq!(recall(&ids(&["b"]), &ids(&["b"]), 10), 1.0);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&sorted, 50.0), 50.0);
+        assert_eq!(percentile(&sorted, 99.0), 99.0);
+        assert_eq!(percentile(&[3.0], 99.0), 3.0);
+        assert_eq!(percentile(&[], 50.0), 0.0);
+    }
+}
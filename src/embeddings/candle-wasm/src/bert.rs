@@ -0,0 +1,793 @@
+//! BERT encoder used by `EmbeddingEngine`
+//!
+//! Derived from `candle_transformers::models::bert` (inference only: no dropout,
+//! no tracing spans, no MLM head). Kept in-crate so the forward pass can be
+//! adapted to the checkpoints we load rather than the other way around.
+//!
+//! Differences from upstream:
+//! - `token_type_ids` is optional. Architectures with `type_vocab_size <= 1`
+//!   (RoBERTa and friends) never need a segment tensor; when none is passed the
+//!   first token-type row is added (what HF does for all-zero ids), or nothing
+//!   at all if the checkpoint has no token-type table.
+//! - `position_embedding_type` is honoured: `absolute`, `relative_key` and
+//!   `relative_key_query` are implemented; anything else is rejected at load
+//!   time instead of silently running as absolute.
+//! - The forward pass can also return intermediate hidden states and the final
+//!   layer's attention probabilities (`forward_with_outputs`).
+//! - Dense layers and attention products go through `crate::linear`, which
+//!   uses a blocked matmul kernel in WASM. Weight matrices and embedding
+//!   tables can be stored as f16, and dense layers as Q4
+//!   (`Config::weight_precision`); everything else, and all activations,
+//!   stay f32.
+//! - RoBERTa-family checkpoints (`model_type`/`architectures`, see
+//!   `crate::autodetect`) count positions from `pad_token_id + 1`; other
+//!   architectures are rejected at load time.
+//! - Shape-only constants aren't rebuilt per call: absolute positions are a
+//!   view of the table's first rows, and relative-distance embeddings are
+//!   cached per sequence length.
+
+use std::cell::RefCell;
+
+use candle_core::{DType, Module, Result, Tensor, D};
+use candle_nn::{layer_norm, Embedding, LayerNorm, VarBuilder};
+use serde::Deserialize;
+
+use crate::autodetect::ModelFamily;
+use crate::linear::{self, linear, Linear, WeightPrecision};
+use crate::q4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HiddenAct {
+    Gelu,
+    GeluApproximate,
+    Relu,
+}
+
+impl HiddenAct {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        match self {
+            // https://github.com/huggingface/transformers/blob/cd4584e3c809bb9e1392ccd3fe38b40daba5519a/src/transformers/activations.py#L213
+            HiddenAct::Gelu => xs.gelu_erf(),
+            HiddenAct::GeluApproximate => xs.gelu(),
+            HiddenAct::Relu => xs.relu(),
+        }
+    }
+}
+
+fn default_type_vocab_size() -> usize {
+    2
+}
+
+fn default_layer_norm_eps() -> f64 {
+    1e-12
+}
+
+fn default_position_embedding_type() -> String {
+    "absolute".to_string()
+}
+
+/// How token positions enter the model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionEmbeddingType {
+    /// Learned absolute position embeddings added to the input
+    Absolute,
+    /// Learned relative-distance embeddings interacting with queries (Shaw et al.)
+    RelativeKey,
+    /// Relative-distance embeddings interacting with both queries and keys (Huang et al.)
+    RelativeKeyQuery,
+}
+
+impl PositionEmbeddingType {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "absolute" => Ok(Self::Absolute),
+            "relative_key" => Ok(Self::RelativeKey),
+            "relative_key_query" => Ok(Self::RelativeKeyQuery),
+            other => candle_core::bail!(
+                "Unsupported position_embedding_type '{}' (supported: absolute, relative_key, relative_key_query)",
+                other
+            ),
+        }
+    }
+}
+
+/// Which hidden state to pool over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerSelection {
+    /// Output of the final encoder layer
+    Last,
+    /// Index into `[embeddings, layer 1, ..., layer N]`; negative counts from
+    /// the end, so -1 is the final layer and -2 the one before it
+    Index(isize),
+    /// Element-wise mean of the last N layer outputs
+    MeanOfLast(usize),
+}
+
+impl LayerSelection {
+    /// Parse "last", an integer index such as "-2", or "avg_last_N"
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        if spec == "last" || spec == "-1" {
+            return Ok(Self::Last);
+        }
+        if let Some(count) = spec.strip_prefix("avg_last_") {
+            return match count.parse::<usize>() {
+                Ok(count) if count > 0 => Ok(Self::MeanOfLast(count)),
+                _ => candle_core::bail!("Invalid layer count in '{}'", spec),
+            };
+        }
+        match spec.parse::<isize>() {
+            Ok(index) => Ok(Self::Index(index)),
+            Err(_) => candle_core::bail!(
+                "Invalid pooling layer '{}' (expected last, an index such as -2, or avg_last_N)",
+                spec
+            ),
+        }
+    }
+
+    /// Canonical spelling, as accepted by `parse`
+    pub fn name(&self) -> String {
+        match self {
+            Self::Last => "last".to_string(),
+            Self::Index(index) => index.to_string(),
+            Self::MeanOfLast(count) => format!("avg_last_{}", count),
+        }
+    }
+
+    /// Check the selection against a model with `layers` encoder layers
+    pub fn validate(&self, layers: usize) -> Result<()> {
+        match *self {
+            Self::Last => {}
+            Self::Index(index) => {
+                let available = layers as isize + 1;
+                if index >= available || index < -available {
+                    candle_core::bail!(
+                        "Pooling layer {} out of range for a model with {} layers",
+                        index,
+                        layers
+                    );
+                }
+            }
+            Self::MeanOfLast(count) => {
+                if count > layers {
+                    candle_core::bail!(
+                        "Cannot average the last {} layers of a model with {} layers",
+                        count,
+                        layers
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pick the selected state from `[embeddings, layer 1, ..., layer N]`
+    pub fn select(&self, hidden_states: &[Tensor]) -> Result<Tensor> {
+        let Some(last) = hidden_states.last() else {
+            candle_core::bail!("No hidden states available");
+        };
+        let available = hidden_states.len();
+        self.validate(available - 1)?;
+        match *self {
+            Self::Last => Ok(last.clone()),
+            Self::Index(index) => {
+                let resolved = if index < 0 {
+                    (available as isize + index) as usize
+                } else {
+                    index as usize
+                };
+                Ok(hidden_states[resolved].clone())
+            }
+            Self::MeanOfLast(count) => {
+                Tensor::stack(&hidden_states[available - count..], 0)?.mean(0)
+            }
+        }
+    }
+}
+
+/// Everything `BertModel::forward_with_outputs` can return
+pub struct ForwardOutputs {
+    /// Final layer output `[batch, seq, hidden]`
+    pub last_hidden_state: Tensor,
+    /// `[embeddings, layer 1, ..., layer N]`; empty unless requested
+    pub hidden_states: Vec<Tensor>,
+    /// Final layer attention probabilities `[batch, heads, seq, seq]`
+    pub attentions: Tensor,
+}
+
+/// BERT config.json (fields not needed for inference are ignored)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Config {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub intermediate_size: usize,
+    pub hidden_act: HiddenAct,
+    pub max_position_embeddings: usize,
+    #[serde(default = "default_type_vocab_size")]
+    pub type_vocab_size: usize,
+    #[serde(default = "default_layer_norm_eps")]
+    pub layer_norm_eps: f64,
+    #[serde(default)]
+    pub pad_token_id: usize,
+    #[serde(default = "default_position_embedding_type")]
+    pub position_embedding_type: String,
+    #[serde(default)]
+    pub model_type: Option<String>,
+    #[serde(default)]
+    pub architectures: Vec<String>,
+    /// Storage type for weight matrices; set by the engine, not config.json
+    #[serde(skip)]
+    pub(crate) weight_precision: WeightPrecision,
+}
+
+impl Config {
+    /// Whether the model distinguishes segments and so needs `token_type_ids`
+    pub fn uses_token_types(&self) -> bool {
+        self.type_vocab_size > 1
+    }
+
+    /// Parsed `position_embedding_type`, erroring on unsupported variants
+    pub fn position_embeddings(&self) -> Result<PositionEmbeddingType> {
+        PositionEmbeddingType::parse(&self.position_embedding_type)
+    }
+
+    /// Encoder variant named by `model_type`/`architectures`, erroring on
+    /// architectures this encoder can't run
+    pub(crate) fn family(&self) -> Result<ModelFamily> {
+        ModelFamily::detect(self.model_type.as_deref(), &self.architectures)
+            .map_err(candle_core::Error::Msg)
+    }
+}
+
+// https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L180
+struct BertEmbeddings {
+    word_embeddings: Embedding,
+    /// Only present for absolute position embeddings
+    position_embeddings: Option<Embedding>,
+    /// Table row of the first position (non-zero for RoBERTa)
+    position_offset: usize,
+    token_type_embeddings: Option<Embedding>,
+    layer_norm: LayerNorm,
+}
+
+impl BertEmbeddings {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let precision = config.weight_precision;
+        let word_embeddings = linear::embedding(
+            config.vocab_size,
+            config.hidden_size,
+            precision,
+            vb.pp("word_embeddings"),
+        )?;
+        let position_embeddings =
+            if config.position_embeddings()? == PositionEmbeddingType::Absolute {
+                Some(linear::embedding(
+                    config.max_position_embeddings,
+                    config.hidden_size,
+                    precision,
+                    vb.pp("position_embeddings"),
+                )?)
+            } else {
+                None
+            };
+        // Some single-segment checkpoints drop the token-type table entirely
+        let token_type_embeddings = if q4::contains(&vb, "token_type_embeddings.weight") {
+            Some(linear::embedding(
+                config.type_vocab_size.max(1),
+                config.hidden_size,
+                precision,
+                vb.pp("token_type_embeddings"),
+            )?)
+        } else {
+            None
+        };
+        let layer_norm = layer_norm(
+            config.hidden_size,
+            config.layer_norm_eps,
+            vb.pp("LayerNorm"),
+        )?;
+        Ok(Self {
+            word_embeddings,
+            position_embeddings,
+            position_offset: config.family()?.position_offset(config.pad_token_id),
+            token_type_embeddings,
+            layer_norm,
+        })
+    }
+
+    fn forward(&self, input_ids: &Tensor, token_type_ids: Option<&Tensor>) -> Result<Tensor> {
+        let (_bsize, seq_len) = input_ids.dims2()?;
+        // Tables may be stored as f16; rows are upcast as they are gathered
+        let mut embeddings = self
+            .word_embeddings
+            .forward(input_ids)?
+            .to_dtype(DType::F32)?;
+
+        match (&self.token_type_embeddings, token_type_ids) {
+            (Some(table), Some(ids)) => {
+                embeddings = (embeddings + table.forward(ids)?.to_dtype(DType::F32)?)?
+            }
+            (Some(table), None) => {
+                // Equivalent to all-zero segment ids without building the tensor
+                let first = table.embeddings().narrow(0, 0, 1)?.to_dtype(DType::F32)?;
+                embeddings = embeddings.broadcast_add(&first)?;
+            }
+            (None, Some(_)) => {
+                candle_core::bail!("token_type_ids passed to a model without token type embeddings")
+            }
+            (None, None) => {}
+        }
+
+        if let Some(position_embeddings) = &self.position_embeddings {
+            // Positions 0..seq_len are consecutive table rows; a view of them
+            // avoids building and gathering with an id tensor every call
+            let table = position_embeddings.embeddings();
+            let available = table.dim(0)?.saturating_sub(self.position_offset);
+            if seq_len > available {
+                candle_core::bail!(
+                    "Sequence length {} exceeds max_position_embeddings {}",
+                    seq_len,
+                    available
+                );
+            }
+            let positions = table
+                .narrow(0, self.position_offset, seq_len)?
+                .to_dtype(DType::F32)?;
+            embeddings = embeddings.broadcast_add(&positions)?;
+        }
+
+        self.layer_norm.forward(&embeddings)
+    }
+}
+
+struct BertSelfAttention {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    num_attention_heads: usize,
+    attention_head_size: usize,
+    position_embedding_type: PositionEmbeddingType,
+    /// `[2 * max_position_embeddings - 1, head_size]` for relative variants
+    distance_embedding: Option<Embedding>,
+    max_position_embeddings: usize,
+    /// Distance embeddings of recent sequence lengths, newest last
+    distance_cache: RefCell<Vec<(usize, Tensor)>>,
+}
+
+/// Sequence lengths whose distance embeddings are kept per layer
+const DISTANCE_CACHE_SIZE: usize = 4;
+
+impl BertSelfAttention {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let attention_head_size = config.hidden_size / config.num_attention_heads;
+        let all_head_size = config.num_attention_heads * attention_head_size;
+        let hidden_size = config.hidden_size;
+        let precision = config.weight_precision;
+        let query = linear(hidden_size, all_head_size, precision, vb.pp("query"))?;
+        let value = linear(hidden_size, all_head_size, precision, vb.pp("value"))?;
+        let key = linear(hidden_size, all_head_size, precision, vb.pp("key"))?;
+        let position_embedding_type = config.position_embeddings()?;
+        let distance_embedding = match position_embedding_type {
+            PositionEmbeddingType::Absolute => None,
+            // Small and read whole every call, so kept at f32
+            _ => Some(linear::embedding(
+                2 * config.max_position_embeddings - 1,
+                attention_head_size,
+                WeightPrecision::F32,
+                vb.pp("distance_embedding"),
+            )?),
+        };
+        Ok(Self {
+            query,
+            key,
+            value,
+            num_attention_heads: config.num_attention_heads,
+            attention_head_size,
+            position_embedding_type,
+            distance_embedding,
+            max_position_embeddings: config.max_position_embeddings,
+            distance_cache: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// `distance_embeddings()` for `seq_len`, reusing a recent result
+    fn cached_distance_embeddings(&self, table: &Embedding, seq_len: usize) -> Result<Tensor> {
+        if let Some((_, cached)) = self
+            .distance_cache
+            .borrow()
+            .iter()
+            .find(|(len, _)| *len == seq_len)
+        {
+            return Ok(cached.clone());
+        }
+        let positional = self.distance_embeddings(table, seq_len)?;
+        let mut cache = self.distance_cache.borrow_mut();
+        if cache.len() == DISTANCE_CACHE_SIZE {
+            cache.remove(0);
+        }
+        cache.push((seq_len, positional.clone()));
+        Ok(positional)
+    }
+
+    /// Relative-distance embeddings `[seq, seq, head_size]`, indexed by `l - r`
+    fn distance_embeddings(&self, table: &Embedding, seq_len: usize) -> Result<Tensor> {
+        if seq_len > self.max_position_embeddings {
+            candle_core::bail!(
+                "Sequence length {} exceeds max_position_embeddings {}",
+                seq_len,
+                self.max_position_embeddings
+            );
+        }
+        let offset = self.max_position_embeddings - 1;
+        let mut distance = Vec::with_capacity(seq_len * seq_len);
+        for l in 0..seq_len {
+            for r in 0..seq_len {
+                distance.push((l + offset - r) as u32);
+            }
+        }
+        let distance = Tensor::from_vec(distance, (seq_len, seq_len), table.embeddings().device())?;
+        table.forward(&distance)
+    }
+
+    fn transpose_for_scores(&self, xs: &Tensor) -> Result<Tensor> {
+        let mut new_x_shape = xs.dims().to_vec();
+        new_x_shape.pop();
+        new_x_shape.push(self.num_attention_heads);
+        new_x_shape.push(self.attention_head_size);
+        let xs = xs.reshape(new_x_shape.as_slice())?.transpose(1, 2)?;
+        xs.contiguous()
+    }
+
+    /// Returns the context layer and the attention probabilities
+    /// `[batch, heads, seq, seq]`
+    fn forward(&self, hidden_states: &Tensor, attention_mask: &Tensor) -> Result<(Tensor, Tensor)> {
+        let query_layer = self.query.forward(hidden_states)?;
+        let key_layer = self.key.forward(hidden_states)?;
+        let value_layer = self.value.forward(hidden_states)?;
+
+        let query_layer = self.transpose_for_scores(&query_layer)?;
+        let key_layer = self.transpose_for_scores(&key_layer)?;
+        let value_layer = self.transpose_for_scores(&value_layer)?;
+
+        let mut attention_scores = linear::matmul_transposed(&query_layer, &key_layer)?;
+
+        // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L330
+        if let Some(table) = &self.distance_embedding {
+            let seq_len = query_layer.dim(2)?;
+            let positional = self.cached_distance_embeddings(table, seq_len)?;
+            attention_scores = (attention_scores + relative_scores(&query_layer, &positional)?)?;
+            if self.position_embedding_type == PositionEmbeddingType::RelativeKeyQuery {
+                let key_scores = relative_scores(&key_layer, &positional.transpose(0, 1)?)?;
+                attention_scores = (attention_scores + key_scores.transpose(2, 3)?)?;
+            }
+        }
+
+        let attention_scores = (attention_scores / (self.attention_head_size as f64).sqrt())?;
+        let attention_scores = attention_scores.broadcast_add(attention_mask)?;
+        let attention_probs = candle_nn::ops::softmax(&attention_scores, D::Minus1)?;
+
+        let context_layer = linear::matmul(&attention_probs, &value_layer)?;
+        let context_layer = context_layer.transpose(1, 2)?.contiguous()?;
+        Ok((context_layer.flatten_from(D::Minus2)?, attention_probs))
+    }
+}
+
+/// `einsum("bhld,lrd->bhlr", xs, positional)` without materializing a broadcast
+///
+/// `xs` is `[batch, heads, seq, head_size]` and `positional` `[seq, seq, head_size]`.
+/// Computed as one batched matmul over the `l` axis.
+fn relative_scores(xs: &Tensor, positional: &Tensor) -> Result<Tensor> {
+    let (b, h, l, d) = xs.dims4()?;
+    let r = positional.dim(1)?;
+    // [l, b*h, d] x [l, d, r] -> [l, b*h, r]
+    let xs = xs.permute((2, 0, 1, 3))?.reshape((l, b * h, d))?;
+    let positional = positional.transpose(1, 2)?.contiguous()?;
+    let scores = xs.contiguous()?.matmul(&positional)?;
+    scores
+        .reshape((l, b, h, r))?
+        .permute((1, 2, 0, 3))?
+        .contiguous()
+}
+
+/// Dense projection + residual + LayerNorm (BertSelfOutput / BertOutput)
+struct BertResidual {
+    dense: Linear,
+    layer_norm: LayerNorm,
+}
+
+impl BertResidual {
+    fn load(vb: VarBuilder, in_size: usize, config: &Config) -> Result<Self> {
+        let dense = linear(
+            in_size,
+            config.hidden_size,
+            config.weight_precision,
+            vb.pp("dense"),
+        )?;
+        let layer_norm = layer_norm(
+            config.hidden_size,
+            config.layer_norm_eps,
+            vb.pp("LayerNorm"),
+        )?;
+        Ok(Self { dense, layer_norm })
+    }
+
+    fn forward(&self, hidden_states: &Tensor, input_tensor: &Tensor) -> Result<Tensor> {
+        let hidden_states = self.dense.forward(hidden_states)?;
+        self.layer_norm.forward(&(hidden_states + input_tensor)?)
+    }
+}
+
+// https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L470
+struct BertLayer {
+    self_attention: BertSelfAttention,
+    self_output: BertResidual,
+    intermediate: Linear,
+    intermediate_act: HiddenAct,
+    output: BertResidual,
+}
+
+impl BertLayer {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let attention = vb.pp("attention");
+        Ok(Self {
+            self_attention: BertSelfAttention::load(attention.pp("self"), config)?,
+            self_output: BertResidual::load(attention.pp("output"), config.hidden_size, config)?,
+            intermediate: linear(
+                config.hidden_size,
+                config.intermediate_size,
+                config.weight_precision,
+                vb.pp("intermediate").pp("dense"),
+            )?,
+            intermediate_act: config.hidden_act,
+            output: BertResidual::load(vb.pp("output"), config.intermediate_size, config)?,
+        })
+    }
+
+    /// Returns the layer output and its attention probabilities
+    fn forward(&self, hidden_states: &Tensor, attention_mask: &Tensor) -> Result<(Tensor, Tensor)> {
+        let (self_outputs, attention_probs) =
+            self.self_attention.forward(hidden_states, attention_mask)?;
+        let attention_output = self.self_output.forward(&self_outputs, hidden_states)?;
+        let intermediate_output = self
+            .intermediate_act
+            .forward(&self.intermediate.forward(&attention_output)?)?;
+        let layer_output = self
+            .output
+            .forward(&intermediate_output, &attention_output)?;
+        Ok((layer_output, attention_probs))
+    }
+}
+
+// https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L874
+pub struct BertModel {
+    embeddings: BertEmbeddings,
+    layers: Vec<BertLayer>,
+    config: Config,
+}
+
+impl BertModel {
+    /// Load weights, accepting both bare (`embeddings.*`) and prefixed
+    /// (`bert.embeddings.*`, `roberta.embeddings.*`) tensor names
+    pub fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let prefix = config.family()?.weight_prefix();
+        Self::load_with_prefix(vb.clone(), config)
+            .or_else(|err| Self::load_with_prefix(vb.pp(prefix), config).map_err(|_| err))
+    }
+
+    fn load_with_prefix(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let embeddings = BertEmbeddings::load(vb.pp("embeddings"), config)?;
+        let encoder = vb.pp("encoder");
+        let layers = (0..config.num_hidden_layers)
+            .map(|index| BertLayer::load(encoder.pp(format!("layer.{index}")), config))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            embeddings,
+            layers,
+            config: config.clone(),
+        })
+    }
+
+    /// The config the model was loaded with
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Run the encoder, returning the last hidden state `[batch, seq, hidden]`
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: Option<&Tensor>,
+        attention_mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        let outputs =
+            self.forward_with_outputs(input_ids, token_type_ids, attention_mask, false)?;
+        Ok(outputs.last_hidden_state)
+    }
+
+    /// Run the encoder, also returning the final layer's attention
+    /// probabilities and, if `output_hidden_states`, every layer's output
+    pub fn forward_with_outputs(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: Option<&Tensor>,
+        attention_mask: Option<&Tensor>,
+        output_hidden_states: bool,
+    ) -> Result<ForwardOutputs> {
+        let mut hidden_states = self.embeddings.forward(input_ids, token_type_ids)?;
+        let attention_mask = match attention_mask {
+            Some(attention_mask) => attention_mask.clone(),
+            None => input_ids.ones_like()?,
+        };
+        // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L995
+        let attention_mask = get_extended_attention_mask(&attention_mask, DType::F32)?;
+        let mut all_hidden_states = Vec::new();
+        let mut attentions = None;
+        for layer in &self.layers {
+            if output_hidden_states {
+                all_hidden_states.push(hidden_states.clone());
+            }
+            let (layer_output, attention_probs) = layer.forward(&hidden_states, &attention_mask)?;
+            hidden_states = layer_output;
+            attentions = Some(attention_probs);
+        }
+        let Some(attentions) = attentions else {
+            candle_core::bail!("Model has no encoder layers");
+        };
+        if output_hidden_states {
+            all_hidden_states.push(hidden_states.clone());
+        }
+        Ok(ForwardOutputs {
+            last_hidden_state: hidden_states,
+            hidden_states: all_hidden_states,
+            attentions,
+        })
+    }
+}
+
+/// Turn a `[batch, seq]` 0/1 mask into an additive `[batch, 1, 1, seq]` bias
+fn get_extended_attention_mask(attention_mask: &Tensor, dtype: DType) -> Result<Tensor> {
+    let attention_mask = match attention_mask.rank() {
+        3 => attention_mask.unsqueeze(1)?,
+        2 => attention_mask.unsqueeze(1)?.unsqueeze(1)?,
+        _ => candle_core::bail!("Wrong shape for input_ids or attention_mask"),
+    };
+    let attention_mask = attention_mask.to_dtype(dtype)?;
+    // torch.finfo(dtype).min
+    (attention_mask.ones_like()? - &attention_mask)?
+        .broadcast_mul(&Tensor::try_from(f32::MIN)?.to_device(attention_mask.device())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+
+    #[test]
+    fn test_relative_scores_matches_einsum() {
+        let device = Device::Cpu;
+        let (b, h, l, d) = (2, 3, 4, 5);
+        let xs = Tensor::arange(0f32, (b * h * l * d) as f32, &device)
+            .unwrap()
+            .reshape((b, h, l, d))
+            .unwrap();
+        let positional = Tensor::arange(0f32, (l * l * d) as f32, &device)
+            .unwrap()
+            .affine(0.01, 0.0)
+            .unwrap()
+            .reshape((l, l, d))
+            .unwrap();
+
+        let got = relative_scores(&xs, &positional).unwrap();
+        let xs_v = xs.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+        let pos_v = positional.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+        let got_v = got.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+
+        for bi in 0..b {
+            for hi in 0..h {
+                for li in 0..l {
+                    for ri in 0..l {
+                        let mut expected = 0.0f32;
+                        for di in 0..d {
+                            expected += xs_v[((bi * h + hi) * l + li) * d + di]
+                                * pos_v[(li * l + ri) * d + di];
+                        }
+                        let actual = got_v[((bi * h + hi) * l + li) * l + ri];
+                        assert!((actual - expected).abs() < 1e-3 * expected.abs().max(1.0));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_distance_embeddings_cached() {
+        let device = Device::Cpu;
+        let zeros = || Linear::new(Tensor::zeros((2, 2), DType::F32, &device).unwrap(), None);
+        let table = Embedding::new(
+            Tensor::arange(0f32, 30.0, &device)
+                .unwrap()
+                .reshape((15, 2))
+                .unwrap(),
+            2,
+        );
+        let attention = BertSelfAttention {
+            query: zeros(),
+            key: zeros(),
+            value: zeros(),
+            num_attention_heads: 1,
+            attention_head_size: 2,
+            position_embedding_type: PositionEmbeddingType::RelativeKey,
+            distance_embedding: None,
+            max_position_embeddings: 8,
+            distance_cache: RefCell::new(Vec::new()),
+        };
+
+        let first = attention.cached_distance_embeddings(&table, 3).unwrap();
+        assert_eq!(first.dims(), &[3, 3, 2]);
+        // l - r = 0 on the diagonal: row max_position_embeddings - 1
+        assert_eq!(
+            first
+                .get(1)
+                .unwrap()
+                .get(1)
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap(),
+            vec![14.0, 15.0]
+        );
+        let again = attention.cached_distance_embeddings(&table, 3).unwrap();
+        assert_eq!(first.id(), again.id());
+
+        // Older lengths are evicted
+        for len in 4..=7 {
+            attention.cached_distance_embeddings(&table, len).unwrap();
+        }
+        assert_eq!(attention.distance_cache.borrow().len(), DISTANCE_CACHE_SIZE);
+        let rebuilt = attention.cached_distance_embeddings(&table, 3).unwrap();
+        assert_ne!(first.id(), rebuilt.id());
+        assert!(attention.cached_distance_embeddings(&table, 9).is_err());
+    }
+
+    #[test]
+    fn test_layer_selection() {
+        let states: Vec<Tensor> = (0..4)
+            .map(|i| Tensor::new(&[i as f32], &Device::Cpu).unwrap())
+            .collect();
+        let pick = |spec: &str| {
+            LayerSelection::parse(spec)
+                .unwrap()
+                .select(&states)
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap()[0]
+        };
+        assert_eq!(pick("last"), 3.0);
+        assert_eq!(pick("-2"), 2.0);
+        assert_eq!(pick("0"), 0.0);
+        assert_eq!(pick("avg_last_2"), 2.5);
+
+        assert!(LayerSelection::parse("-5")
+            .unwrap()
+            .select(&states)
+            .is_err());
+        assert!(LayerSelection::parse("avg_last_4")
+            .unwrap()
+            .select(&states)
+            .is_err());
+        assert!(LayerSelection::parse("avg_last_0").is_err());
+        assert!(LayerSelection::parse("penultimate").is_err());
+    }
+
+    #[test]
+    fn test_position_embedding_type_parse() {
+        assert_eq!(
+            PositionEmbeddingType::parse("relative_key").unwrap(),
+            PositionEmbeddingType::RelativeKey
+        );
+        let err = PositionEmbeddingType::parse("rotary").unwrap_err();
+        assert!(err.to_string().contains("rotary"));
+    }
+}
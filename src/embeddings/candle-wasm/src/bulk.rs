@@ -0,0 +1,323 @@
+//! Resumable bulk embedding (`BulkEmbedJob`)
+//!
+//! Indexing a large corpus in a browser tab takes long enough that the tab may
+//! be reloaded or killed partway through. A job walks the corpus in chunks;
+//! after storing each chunk's vectors the app saves `checkpoint()`, optionally
+//! with its own partial index state (`set_state()`), and after a reload
+//! `BulkEmbedJob.resume()` carries on from the next chunk:
+//!
+//! ```js
+//! const job = saved ? BulkEmbedJob.resume(engine, corpus, saved) : new BulkEmbedJob(engine, corpus);
+//! let chunk;
+//! while ((chunk = job.next_chunk(engine))) {
+//!   await index.add(chunk.start, chunk.vectors);
+//!   localStorage.setItem('checkpoint', job.checkpoint());
+//! }
+//! ```
+//!
+//! The checkpoint records the engine's compatibility fingerprint and a hash of
+//! the texts embedded so far, so resuming with a different model, or against
+//! a corpus whose embedded part changed, fails instead of mixing vectors.
+
+use js_sys::{Array, Float32Array};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::fingerprint::ModelHasher;
+use crate::js::parse_options;
+use crate::{js_array_to_strings, EmbeddingEngine};
+
+/// Checkpoint format version; bump when fields change meaning
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// Texts per chunk unless `chunk_size` is given
+const DEFAULT_CHUNK_SIZE: usize = 256;
+
+/// Options for `new BulkEmbedJob()`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct JobOptions {
+    chunk_size: usize,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        JobOptions {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// Serialized job progress
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Checkpoint {
+    version: u32,
+    /// Texts embedded so far
+    position: usize,
+    total: usize,
+    chunk_size: usize,
+    /// `compatibility_fingerprint()` of the engine that embedded them
+    fingerprint: String,
+    /// Hex hash of the embedded texts (see `extend_hash`)
+    processed_hash: String,
+    /// Caller-supplied state, e.g. a serialized partial index
+    #[serde(default)]
+    state: Option<String>,
+}
+
+impl Checkpoint {
+    fn from_json(json: &str) -> Result<Self, String> {
+        let checkpoint: Checkpoint =
+            serde_json::from_str(json).map_err(|e| format!("Invalid checkpoint: {}", e))?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(format!(
+                "Unsupported checkpoint version {} (expected {})",
+                checkpoint.version, CHECKPOINT_VERSION
+            ));
+        }
+        if checkpoint.position > checkpoint.total || checkpoint.chunk_size == 0 {
+            return Err("Invalid checkpoint: position or chunk size out of range".to_string());
+        }
+        Ok(checkpoint)
+    }
+}
+
+/// Hash of the texts embedded so far, extended by one more
+fn extend_hash(hash: u64, text: &str) -> u64 {
+    let mut hasher = ModelHasher::new();
+    hasher.update(&hash.to_le_bytes());
+    hasher.update(text.as_bytes());
+    hasher.finish()
+}
+
+/// Vectors for one chunk of a `BulkEmbedJob`
+#[wasm_bindgen]
+pub struct BulkChunk {
+    start: usize,
+    dimension: usize,
+    vectors: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl BulkChunk {
+    /// Corpus index of the chunk's first text
+    #[wasm_bindgen(getter)]
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Number of texts in the chunk
+    #[wasm_bindgen(getter)]
+    pub fn count(&self) -> usize {
+        self.vectors.len().checked_div(self.dimension).unwrap_or(0)
+    }
+
+    /// Length of each vector
+    #[wasm_bindgen(getter)]
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// The chunk's vectors, back to back
+    #[wasm_bindgen(getter)]
+    pub fn vectors(&self) -> Float32Array {
+        Float32Array::from(&self.vectors[..])
+    }
+}
+
+/// Chunked, resumable embedding of a corpus
+#[wasm_bindgen]
+pub struct BulkEmbedJob {
+    /// The corpus, left in JS; only the current chunk is copied in
+    texts: Array,
+    position: usize,
+    chunk_size: usize,
+    fingerprint: String,
+    processed_hash: u64,
+    state: Option<String>,
+}
+
+#[wasm_bindgen]
+impl BulkEmbedJob {
+    /// Start a job over `texts` (an array of strings) for `engine`
+    ///
+    /// Options: `{ chunk_size?: number }` (texts per `next_chunk()`, default
+    /// 256).
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        engine: &EmbeddingEngine,
+        texts: Array,
+        options: &JsValue,
+    ) -> Result<BulkEmbedJob, JsValue> {
+        let options: JobOptions = parse_options(options)?;
+        if options.chunk_size == 0 {
+            return Err(JsValue::from_str("chunk_size must be at least 1"));
+        }
+        Ok(BulkEmbedJob {
+            texts,
+            position: 0,
+            chunk_size: options.chunk_size,
+            fingerprint: engine.compatibility_fingerprint()?,
+            processed_hash: 0,
+            state: None,
+        })
+    }
+
+    /// Continue a job from a `checkpoint()`
+    ///
+    /// `texts` must be the same corpus: its length and the texts already
+    /// embedded are checked, as is `engine`'s fingerprint.
+    #[wasm_bindgen]
+    pub fn resume(
+        engine: &EmbeddingEngine,
+        texts: Array,
+        checkpoint: &str,
+    ) -> Result<BulkEmbedJob, JsValue> {
+        let checkpoint = Checkpoint::from_json(checkpoint).map_err(|e| JsValue::from_str(&e))?;
+        if texts.length() as usize != checkpoint.total {
+            return Err(JsValue::from_str(&format!(
+                "Corpus has {} texts but the checkpoint was made for {}",
+                texts.length(),
+                checkpoint.total
+            )));
+        }
+        if engine.compatibility_fingerprint()? != checkpoint.fingerprint {
+            return Err(JsValue::from_str(
+                "Engine fingerprint differs from the checkpoint's; the model or its settings changed",
+            ));
+        }
+        let processed = js_array_to_strings(&texts.slice(0, checkpoint.position as u32))?;
+        let hash = processed
+            .iter()
+            .fold(0, |hash, text| extend_hash(hash, text));
+        if format!("{:016x}", hash) != checkpoint.processed_hash {
+            return Err(JsValue::from_str(
+                "Texts already embedded differ from the checkpoint's; start a new job",
+            ));
+        }
+        Ok(BulkEmbedJob {
+            texts,
+            position: checkpoint.position,
+            chunk_size: checkpoint.chunk_size,
+            fingerprint: checkpoint.fingerprint,
+            processed_hash: hash,
+            state: checkpoint.state,
+        })
+    }
+
+    /// Embed the next chunk, or return `undefined` when the corpus is done
+    ///
+    /// The job only advances when the chunk succeeds, so a failed call can be
+    /// retried.
+    #[wasm_bindgen]
+    pub fn next_chunk(&mut self, engine: &EmbeddingEngine) -> Result<Option<BulkChunk>, JsValue> {
+        if self.done() {
+            return Ok(None);
+        }
+        if engine.compatibility_fingerprint()? != self.fingerprint {
+            return Err(JsValue::from_str(
+                "Engine fingerprint differs from the one the job started with",
+            ));
+        }
+        let start = self.position;
+        let end = (start + self.chunk_size).min(self.total());
+        let texts = js_array_to_strings(&self.texts.slice(start as u32, end as u32))?;
+        let embeddings = engine.embed_matrix(&texts)?;
+
+        self.processed_hash = texts
+            .iter()
+            .fold(self.processed_hash, |hash, text| extend_hash(hash, text));
+        self.position = end;
+        Ok(Some(BulkChunk {
+            start,
+            dimension: engine.dimension(),
+            vectors: embeddings.as_slice().to_vec(),
+        }))
+    }
+
+    /// Progress as JSON, to save after storing each chunk's vectors
+    #[wasm_bindgen]
+    pub fn checkpoint(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&Checkpoint {
+            version: CHECKPOINT_VERSION,
+            position: self.position,
+            total: self.total(),
+            chunk_size: self.chunk_size,
+            fingerprint: self.fingerprint.clone(),
+            processed_hash: format!("{:016x}", self.processed_hash),
+            state: self.state.clone(),
+        })
+        .map_err(|e| JsValue::from_str(&format!("Failed to write checkpoint: {}", e)))
+    }
+
+    /// Attach application state (e.g. a serialized partial index) to
+    /// future checkpoints
+    #[wasm_bindgen]
+    pub fn set_state(&mut self, state: Option<String>) {
+        self.state = state;
+    }
+
+    /// State attached with `set_state()`, restored by `resume()`
+    #[wasm_bindgen(getter)]
+    pub fn state(&self) -> Option<String> {
+        self.state.clone()
+    }
+
+    /// Texts embedded so far
+    #[wasm_bindgen(getter)]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Texts in the corpus
+    #[wasm_bindgen(getter)]
+    pub fn total(&self) -> usize {
+        self.texts.length() as usize
+    }
+
+    /// Whether every text has been embedded
+    #[wasm_bindgen(getter)]
+    pub fn done(&self) -> bool {
+        self.position >= self.total()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint() -> Checkpoint {
+        Checkpoint {
+            version: CHECKPOINT_VERSION,
+            position: 512,
+            total: 1000,
+            chunk_size: 256,
+            fingerprint: "v2:0000000000000abc:mean:last:l2".to_string(),
+            processed_hash: "00000000000000ff".to_string(),
+            state: Some("{\"ids\":[1,2]}".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let json = serde_json::to_string(&checkpoint()).unwrap();
+        assert_eq!(Checkpoint::from_json(&json).unwrap(), checkpoint());
+
+        let future = json.replace("\"version\":1", "\"version\":2");
+        assert!(Checkpoint::from_json(&future)
+            .unwrap_err()
+            .contains("version 2"));
+        let past_end = json.replace("\"position\":512", "\"position\":1001");
+        assert!(Checkpoint::from_json(&past_end).is_err());
+        assert!(Checkpoint::from_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_processed_hash_depends_on_order_and_boundaries() {
+        let hash = |texts: &[&str]| texts.iter().fold(0, |hash, text| extend_hash(hash, text));
+        assert_eq!(hash(&["a", "b"]), hash(&["a", "b"]));
+        assert_ne!(hash(&["a", "b"]), hash(&["b", "a"]));
+        assert_ne!(hash(&["ab", ""]), hash(&["a", "b"]));
+        assert_ne!(hash(&["a"]), 0);
+    }
+}
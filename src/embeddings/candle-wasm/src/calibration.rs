@@ -0,0 +1,356 @@
+//! Score calibration (`ScoreCalibration`)
+//!
+//! Cosine similarities aren't probabilities, and where "same meaning" starts
+//! differs per model: 0.7 is a near-duplicate for one and unrelated for
+//! another. Platt scaling fits `p = 1 / (1 + exp(-(slope * score +
+//! intercept)))` to labeled pairs by logistic regression (Newton's method
+//! with Platt's smoothed targets), turning raw scores into match
+//! probabilities. `threshold(p)` maps back to the raw score for a wanted
+//! probability, as the `min_score` of `search_range()`.
+//!
+//! `EmbeddingEngine.is_similar()` gives a yes/no answer at a chosen
+//! strictness: from the preset's cosine cutoffs by default, or from the
+//! match probability once a calibration is set with `set_calibration()`.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::presets::DEFAULT_SIMILARITY_THRESHOLDS;
+use crate::{cosine_similarity, EmbeddingEngine};
+
+/// Newton iterations before giving up on convergence
+const MAX_ITERATIONS: usize = 100;
+
+/// Ridge added to the Hessian diagonal for a stable solve
+const RIDGE: f64 = 1e-12;
+
+/// `ln(1 + exp(x))` without overflow
+fn softplus(x: f64) -> f64 {
+    x.max(0.0) + (-x.abs()).exp().ln_1p()
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x >= 0.0 {
+        1.0 / (1.0 + (-x).exp())
+    } else {
+        let e = x.exp();
+        e / (1.0 + e)
+    }
+}
+
+/// Fitted logistic mapping from raw score to match probability
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Platt {
+    pub(crate) slope: f64,
+    pub(crate) intercept: f64,
+}
+
+impl Platt {
+    /// Fit to `scores` labeled as matches or not; both kinds must be present
+    /// and matches must score higher on the whole
+    pub(crate) fn fit(scores: &[f32], labels: &[bool]) -> Result<Self, String> {
+        if scores.len() != labels.len() {
+            return Err(format!(
+                "Got {} scores but {} labels",
+                scores.len(),
+                labels.len()
+            ));
+        }
+        let positives = labels.iter().filter(|&&l| l).count() as f64;
+        let negatives = labels.len() as f64 - positives;
+        if positives == 0.0 || negatives == 0.0 {
+            return Err("Calibration needs both matching and non-matching pairs".to_string());
+        }
+        // Platt's targets keep the fit finite on separable data
+        let (hi, lo) = (
+            (positives + 1.0) / (positives + 2.0),
+            1.0 / (negatives + 2.0),
+        );
+        let samples: Vec<(f64, f64)> = scores
+            .iter()
+            .zip(labels)
+            .map(|(&s, &l)| (s as f64, if l { hi } else { lo }))
+            .collect();
+        let loss = |p: Platt| -> f64 {
+            samples
+                .iter()
+                .map(|&(s, t)| {
+                    let z = p.slope * s + p.intercept;
+                    t * softplus(-z) + (1.0 - t) * softplus(z)
+                })
+                .sum()
+        };
+
+        let mut platt = Platt {
+            slope: 0.0,
+            intercept: ((positives + 1.0) / (negatives + 1.0)).ln(),
+        };
+        let mut current = loss(platt);
+        for _ in 0..MAX_ITERATIONS {
+            let (mut ga, mut gb, mut haa, mut hab, mut hbb) = (0.0, 0.0, RIDGE, 0.0, RIDGE);
+            for &(s, t) in &samples {
+                let p = sigmoid(platt.slope * s + platt.intercept);
+                let (d, w) = (p - t, p * (1.0 - p));
+                ga += d * s;
+                gb += d;
+                haa += w * s * s;
+                hab += w * s;
+                hbb += w;
+            }
+            if ga.abs() < 1e-5 && gb.abs() < 1e-5 {
+                break;
+            }
+            let det = haa * hbb - hab * hab;
+            let (da, db) = ((hbb * ga - hab * gb) / det, (haa * gb - hab * ga) / det);
+            // Backtrack until the step lowers the loss
+            let mut step = 1.0;
+            while step > 1e-10 {
+                let next = Platt {
+                    slope: platt.slope - step * da,
+                    intercept: platt.intercept - step * db,
+                };
+                let next_loss = loss(next);
+                if next_loss <= current - 1e-4 * step * (ga * da + gb * db) {
+                    platt = next;
+                    current = next_loss;
+                    break;
+                }
+                step /= 2.0;
+            }
+            if step <= 1e-10 {
+                break;
+            }
+        }
+        if platt.slope.is_nan() || platt.slope <= 0.0 {
+            return Err("Matching pairs don't score higher than non-matching ones".to_string());
+        }
+        Ok(platt)
+    }
+
+    pub(crate) fn probability(&self, score: f32) -> f32 {
+        sigmoid(self.slope * score as f64 + self.intercept) as f32
+    }
+
+    /// Raw score at which the probability reaches `probability` (in (0, 1))
+    pub(crate) fn threshold(&self, probability: f64) -> f32 {
+        (((probability / (1.0 - probability)).ln() - self.intercept) / self.slope) as f32
+    }
+}
+
+/// How sure `is_similar()` has to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Strictness {
+    Loose,
+    Normal,
+    Strict,
+}
+
+impl Strictness {
+    pub(crate) fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "loose" => Ok(Strictness::Loose),
+            "normal" => Ok(Strictness::Normal),
+            "strict" => Ok(Strictness::Strict),
+            _ => Err(format!(
+                "Unknown strictness: {} (expected loose, normal or strict)",
+                name
+            )),
+        }
+    }
+
+    /// Match probability required with a calibration
+    pub(crate) fn probability(self) -> f64 {
+        match self {
+            Strictness::Loose => 0.3,
+            Strictness::Normal => 0.5,
+            Strictness::Strict => 0.8,
+        }
+    }
+
+    /// Raw cosine cutoff, from the calibration if there is one and the
+    /// preset's `[loose, normal, strict]` cutoffs otherwise
+    fn cutoff(self, calibration: Option<&Platt>, thresholds: [f32; 3]) -> f32 {
+        match calibration {
+            Some(platt) => platt.threshold(self.probability()),
+            None => thresholds[self as usize],
+        }
+    }
+}
+
+/// Options for `is_similar`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct SimilarOptions {
+    /// "loose", "normal" or "strict"
+    strictness: String,
+}
+
+impl Default for SimilarOptions {
+    fn default() -> Self {
+        SimilarOptions {
+            strictness: "normal".to_string(),
+        }
+    }
+}
+
+/// Mapping from raw similarity scores to match probabilities, fitted on
+/// labeled pairs
+#[wasm_bindgen]
+pub struct ScoreCalibration {
+    platt: Platt,
+}
+
+#[wasm_bindgen]
+impl ScoreCalibration {
+    /// Fit to the similarity `scores` of labeled pairs, with `labels` 1 for
+    /// pairs that match and 0 for pairs that don't
+    #[wasm_bindgen]
+    pub fn fit(scores: &[f32], labels: &[u8]) -> Result<ScoreCalibration, JsValue> {
+        let labels: Vec<bool> = labels.iter().map(|&l| l != 0).collect();
+        let platt = Platt::fit(scores, &labels).map_err(|e| JsValue::from_str(&e))?;
+        Ok(ScoreCalibration { platt })
+    }
+
+    /// Match probability of a raw score
+    #[wasm_bindgen]
+    pub fn probability(&self, score: f32) -> f32 {
+        self.platt.probability(score)
+    }
+
+    /// Match probabilities of raw scores
+    #[wasm_bindgen]
+    pub fn apply(&self, scores: &[f32]) -> Vec<f32> {
+        scores.iter().map(|&s| self.platt.probability(s)).collect()
+    }
+
+    /// Raw score with match probability `probability`, e.g. as the
+    /// `min_score` of `search_range()`
+    #[wasm_bindgen]
+    pub fn threshold(&self, probability: f64) -> Result<f32, JsValue> {
+        if probability.is_nan() || probability <= 0.0 || probability >= 1.0 {
+            return Err(JsValue::from_str("Probability must be between 0 and 1"));
+        }
+        Ok(self.platt.threshold(probability))
+    }
+
+    /// Restore a calibration saved with `to_json()`
+    #[wasm_bindgen]
+    pub fn from_json(json: &str) -> Result<ScoreCalibration, JsValue> {
+        let platt = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid calibration: {}", e)))?;
+        Ok(ScoreCalibration { platt })
+    }
+
+    /// Serialize the fitted slope and intercept
+    #[wasm_bindgen]
+    pub fn to_json(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.platt)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize calibration: {}", e)))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn slope(&self) -> f64 {
+        self.platt.slope
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn intercept(&self) -> f64 {
+        self.platt.intercept
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Use a fitted calibration for `is_similar()` instead of the preset's
+    /// cutoffs
+    #[wasm_bindgen]
+    pub fn set_calibration(&mut self, calibration: &ScoreCalibration) {
+        self.calibration = Some(calibration.platt);
+    }
+
+    /// Go back to the preset's cutoffs
+    #[wasm_bindgen]
+    pub fn clear_calibration(&mut self) {
+        self.calibration = None;
+    }
+
+    /// Cosine similarity above which `is_similar()` answers yes at
+    /// `strictness` ("loose", "normal" or "strict"), e.g. as the `min_score`
+    /// of `search_range()`
+    #[wasm_bindgen]
+    pub fn similarity_threshold(&self, strictness: &str) -> Result<f32, JsValue> {
+        let strictness = Strictness::from_name(strictness).map_err(|e| JsValue::from_str(&e))?;
+        let thresholds = self
+            .preset
+            .map_or(DEFAULT_SIMILARITY_THRESHOLDS, |p| p.similarity_thresholds);
+        Ok(strictness.cutoff(self.calibration.as_ref(), thresholds))
+    }
+
+    /// Whether two texts mean the same thing closely enough
+    ///
+    /// Options: `{ strictness: "loose" | "normal" | "strict" }` (default
+    /// "normal"). Uses the model preset's cutoffs, or the calibration from
+    /// `set_calibration()` if there is one.
+    #[wasm_bindgen]
+    pub fn is_similar(&self, a: &str, b: &str, options: &JsValue) -> Result<bool, JsValue> {
+        let options: SimilarOptions = parse_options(options)?;
+        let threshold = self.similarity_threshold(&options.strictness)?;
+        let embeddings = self.embed_matrix(&[a.to_string(), b.to_string()])?;
+        Ok(cosine_similarity(embeddings.row(0), embeddings.row(1)) >= threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_separates_overlapping_scores() {
+        // Matches around 0.8, non-matches around 0.4, overlapping in between
+        let mut scores = Vec::new();
+        let mut labels = Vec::new();
+        for i in 0..50 {
+            let jitter = (i as f32 * 0.37).sin() * 0.3;
+            scores.extend([0.8 + jitter, 0.4 + jitter]);
+            labels.extend([true, false]);
+        }
+        let platt = Platt::fit(&scores, &labels).unwrap();
+        assert!(platt.slope > 0.0);
+        assert!((platt.probability(0.6) - 0.5).abs() < 0.05);
+        assert!(platt.probability(0.9) > 0.9);
+        assert!(platt.probability(0.3) < 0.1);
+        assert!((platt.threshold(0.5) - 0.6).abs() < 0.02);
+        assert!((platt.probability(platt.threshold(0.8)) - 0.8).abs() < 1e-4);
+
+        assert!(Platt::fit(&scores, &[true; 100]).is_err());
+        let flipped: Vec<bool> = labels.iter().map(|l| !l).collect();
+        assert!(Platt::fit(&scores, &flipped).is_err());
+    }
+
+    #[test]
+    fn test_strictness_cutoffs() {
+        let thresholds = [0.5, 0.6, 0.75];
+        assert_eq!(Strictness::Loose.cutoff(None, thresholds), 0.5);
+        assert_eq!(Strictness::Strict.cutoff(None, thresholds), 0.75);
+        assert!(Strictness::from_name("lenient").is_err());
+
+        let platt = Platt {
+            slope: 10.0,
+            intercept: -6.0,
+        };
+        let cutoffs: Vec<f32> = [Strictness::Loose, Strictness::Normal, Strictness::Strict]
+            .iter()
+            .map(|s| s.cutoff(Some(&platt), thresholds))
+            .collect();
+        assert!((cutoffs[1] - 0.6).abs() < 1e-6);
+        assert!(cutoffs[0] < cutoffs[1] && cutoffs[1] < cutoffs[2]);
+    }
+
+    #[test]
+    fn test_separable_scores_stay_finite() {
+        let platt = Platt::fit(&[0.9, 0.85, 0.2, 0.1], &[true, true, false, false]).unwrap();
+        assert!(platt.slope.is_finite() && platt.intercept.is_finite());
+        assert!(platt.probability(0.9) > 0.5 && platt.probability(0.1) < 0.5);
+    }
+}
@@ -0,0 +1,121 @@
+//! Runtime feature detection (`capabilities()`)
+//!
+//! The blocked matmul and int8 kernels have SIMD variants, but only in a build
+//! compiled with `-C target-feature=+simd128`, and such a module doesn't
+//! validate at all on engines without SIMD. scripts/build-candle-wasm.sh
+//! builds both (`pkg/` and `pkg-simd/`), and picking one per runtime avoids
+//! shipping only the baseline: `js/capabilities.js` exports
+//! `select_artifact({ simd, baseline })` for the loader, and `capabilities()`
+//! reports what the runtime supports next to what the running build uses, so
+//! a page can tell when it is on the slower one.
+//!
+//! ```js
+//! const caps = capabilities();
+//! if (caps.recommended_build !== caps.build) console.warn(`load the ${caps.recommended_build} build`);
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(module = "/js/capabilities.js")]
+extern "C" {
+    fn wasm_simd_supported() -> bool;
+    fn shared_array_buffer_supported() -> bool;
+    fn cross_origin_isolated() -> bool;
+}
+
+/// Whether this build runs the SIMD kernels
+const BUILD_SIMD128: bool = cfg!(all(target_arch = "wasm32", target_feature = "simd128"));
+
+/// Name of the build for a runtime with or without SIMD
+fn build_name(simd128: bool) -> &'static str {
+    if simd128 {
+        "simd"
+    } else {
+        "baseline"
+    }
+}
+
+/// Runtime and build features, as returned by `capabilities()`
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    simd128: bool,
+    shared_array_buffer: bool,
+    cross_origin_isolated: bool,
+}
+
+#[wasm_bindgen]
+impl Capabilities {
+    /// Whether the runtime supports WebAssembly 128-bit SIMD
+    #[wasm_bindgen(getter)]
+    pub fn simd128(&self) -> bool {
+        self.simd128
+    }
+
+    /// Whether `SharedArrayBuffer` exists
+    #[wasm_bindgen(getter)]
+    pub fn shared_array_buffer(&self) -> bool {
+        self.shared_array_buffer
+    }
+
+    /// Whether the page is cross-origin isolated (always true outside
+    /// browsers)
+    #[wasm_bindgen(getter)]
+    pub fn cross_origin_isolated(&self) -> bool {
+        self.cross_origin_isolated
+    }
+
+    /// Whether shared memory, and so a threaded build, is usable: needs
+    /// `SharedArrayBuffer` and, in browsers, cross-origin isolation
+    #[wasm_bindgen(getter)]
+    pub fn threads(&self) -> bool {
+        self.shared_array_buffer && self.cross_origin_isolated
+    }
+
+    /// The running build: "simd" or "baseline"
+    #[wasm_bindgen(getter)]
+    pub fn build(&self) -> String {
+        build_name(BUILD_SIMD128).to_string()
+    }
+
+    /// The fastest build this runtime can load: "simd" or "baseline"
+    #[wasm_bindgen(getter)]
+    pub fn recommended_build(&self) -> String {
+        build_name(self.simd128).to_string()
+    }
+}
+
+/// Probe the runtime's WebAssembly features
+#[wasm_bindgen]
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        simd128: wasm_simd_supported(),
+        shared_array_buffer: shared_array_buffer_supported(),
+        cross_origin_isolated: cross_origin_isolated(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builds_and_threads() {
+        let caps = Capabilities {
+            simd128: true,
+            shared_array_buffer: true,
+            cross_origin_isolated: false,
+        };
+        assert_eq!(caps.recommended_build(), "simd");
+        assert!(!caps.threads());
+        // Native test builds never use the WASM SIMD kernels
+        assert_eq!(caps.build(), "baseline");
+        let caps = Capabilities {
+            simd128: false,
+            cross_origin_isolated: true,
+            ..caps
+        };
+        assert_eq!(caps.recommended_build(), "baseline");
+        assert!(caps.threads());
+    }
+}
@@ -0,0 +1,203 @@
+//! Running profile vectors (`OnlineCentroid`)
+//!
+//! A user-interest or topic profile is a weighted mean of embeddings that
+//! keeps changing. Averaging in JS tends to drift: lengths shrink or grow with
+//! each update and an old profile outweighs everything new. The centroid
+//! instead keeps an f64 weighted sum, decays it by a factor per update
+//! (`half_life`) or on demand (`decay()`), and hands out the sum's direction
+//! as a unit vector, so profiles stay comparable with `embed()` output
+//! however many updates went in.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+
+/// Options for `new OnlineCentroid()`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct CentroidOptions {
+    /// Updates after which an embedding counts half as much
+    half_life: Option<f64>,
+}
+
+/// Weighted sum of embeddings with exponential decay
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CentroidState {
+    sum: Vec<f64>,
+    /// Total weight in `sum` after decay
+    weight: f64,
+    /// Factor applied to earlier updates at each update
+    decay: f64,
+    updates: u64,
+}
+
+impl CentroidState {
+    fn new(dim: usize, half_life: Option<f64>) -> Self {
+        CentroidState {
+            sum: vec![0.0; dim],
+            weight: 0.0,
+            decay: half_life.map_or(1.0, |h| 0.5f64.powf(1.0 / h)),
+            updates: 0,
+        }
+    }
+
+    fn update(&mut self, embedding: &[f32], weight: f64) {
+        self.scale(self.decay);
+        for (s, &v) in self.sum.iter_mut().zip(embedding) {
+            *s += weight * v as f64;
+        }
+        self.weight += weight;
+        self.updates += 1;
+    }
+
+    fn scale(&mut self, factor: f64) {
+        self.sum.iter_mut().for_each(|s| *s *= factor);
+        self.weight *= factor;
+    }
+
+    /// Unit vector along the sum, or zeros before any update
+    fn centroid(&self) -> Vec<f32> {
+        let norm = self.sum.iter().map(|s| s * s).sum::<f64>().sqrt();
+        if norm == 0.0 {
+            return vec![0.0; self.sum.len()];
+        }
+        self.sum.iter().map(|s| (s / norm) as f32).collect()
+    }
+}
+
+/// Profile vector updated one embedding at a time
+#[wasm_bindgen]
+pub struct OnlineCentroid {
+    state: CentroidState,
+}
+
+#[wasm_bindgen]
+impl OnlineCentroid {
+    /// Empty centroid for `dim`-dimension embeddings
+    ///
+    /// Options: `{ half_life?: number }`, the number of updates after which
+    /// an embedding counts half as much; without it, every update counts by
+    /// its weight until `decay()` is called.
+    #[wasm_bindgen(constructor)]
+    pub fn new(dim: usize, options: &JsValue) -> Result<OnlineCentroid, JsValue> {
+        let options: CentroidOptions = parse_options(options)?;
+        if dim == 0 {
+            return Err(JsValue::from_str("Dimension must be greater than 0"));
+        }
+        if let Some(half_life) = options.half_life {
+            if half_life.is_nan() || half_life <= 0.0 {
+                return Err(JsValue::from_str("half_life must be greater than 0"));
+            }
+        }
+        Ok(OnlineCentroid {
+            state: CentroidState::new(dim, options.half_life),
+        })
+    }
+
+    /// Add `embedding` with `weight` (default 1; negative weights push the
+    /// centroid away)
+    #[wasm_bindgen]
+    pub fn update(&mut self, embedding: &[f32], weight: Option<f64>) -> Result<(), JsValue> {
+        if embedding.len() != self.state.sum.len() {
+            return Err(JsValue::from_str(&format!(
+                "Embedding has {} values, expected {}",
+                embedding.len(),
+                self.state.sum.len()
+            )));
+        }
+        let weight = weight.unwrap_or(1.0);
+        if !weight.is_finite() {
+            return Err(JsValue::from_str("Weight must be a finite number"));
+        }
+        self.state.update(embedding, weight);
+        Ok(())
+    }
+
+    /// Scale everything seen so far by `factor` (in (0, 1]), e.g. once a day
+    /// so old interests fade
+    #[wasm_bindgen]
+    pub fn decay(&mut self, factor: f64) -> Result<(), JsValue> {
+        if factor.is_nan() || factor <= 0.0 || factor > 1.0 {
+            return Err(JsValue::from_str("Decay factor must be in (0, 1]"));
+        }
+        self.state.scale(factor);
+        Ok(())
+    }
+
+    /// The profile as a unit vector (zeros before any update)
+    #[wasm_bindgen]
+    pub fn centroid(&self) -> Vec<f32> {
+        self.state.centroid()
+    }
+
+    /// Restore a centroid saved with `to_json()`
+    #[wasm_bindgen]
+    pub fn from_json(json: &str) -> Result<OnlineCentroid, JsValue> {
+        let state: CentroidState = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid centroid state: {}", e)))?;
+        if state.sum.is_empty() {
+            return Err(JsValue::from_str("Invalid centroid state: empty sum"));
+        }
+        Ok(OnlineCentroid { state })
+    }
+
+    /// Serialize the weighted sum and decay settings
+    #[wasm_bindgen]
+    pub fn to_json(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.state)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize centroid: {}", e)))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn dim(&self) -> usize {
+        self.state.sum.len()
+    }
+
+    /// Total weight of the updates after decay
+    #[wasm_bindgen(getter)]
+    pub fn weight(&self) -> f64 {
+        self.state.weight
+    }
+
+    /// Number of `update()` calls
+    #[wasm_bindgen(getter)]
+    pub fn updates(&self) -> u32 {
+        self.state.updates as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_centroid_stays_unit_length() {
+        let mut state = CentroidState::new(2, None);
+        assert_eq!(state.centroid(), vec![0.0, 0.0]);
+        for _ in 0..1000 {
+            state.update(&[1.0, 0.0], 1.0);
+            state.update(&[0.0, 1.0], 1.0);
+        }
+        let c = state.centroid();
+        assert!((c[0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert!((c[1] - c[0]).abs() < 1e-6);
+
+        state.scale(0.5);
+        assert_eq!(state.weight, 1000.0);
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(serde_json::from_str::<CentroidState>(&json).unwrap(), state);
+    }
+
+    #[test]
+    fn test_half_life_favours_recent() {
+        let mut state = CentroidState::new(2, Some(1.0));
+        state.update(&[1.0, 0.0], 1.0);
+        state.update(&[0.0, 1.0], 1.0);
+        // The first update now counts half
+        assert!((state.sum[0] - 0.5).abs() < 1e-12);
+        assert!((state.weight - 1.5).abs() < 1e-12);
+        let c = state.centroid();
+        assert!(c[1] > c[0]);
+    }
+}
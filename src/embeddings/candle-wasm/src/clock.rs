@@ -0,0 +1,17 @@
+//! Wall-clock milliseconds for timing, on WASM and native test builds
+
+/// Milliseconds since an arbitrary epoch
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+/// Milliseconds since an arbitrary epoch
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now_ms() -> f64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
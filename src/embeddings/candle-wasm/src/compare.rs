@@ -0,0 +1,76 @@
+//! Tolerance checks between embeddings
+//!
+//! For verifying parity between builds and backends (WASM vs native, f32 vs
+//! f16/Q4 weights, this crate vs sentence-transformers reference outputs).
+//! Both helpers take flat arrays, so a batch compares as its concatenated
+//! rows.
+
+use wasm_bindgen::prelude::*;
+
+/// Largest absolute difference between two embeddings
+///
+/// Returns infinity for arrays of different lengths and NaN if either holds
+/// a NaN.
+#[wasm_bindgen]
+pub fn max_abs_diff(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return f32::INFINITY;
+    }
+    a.iter().zip(b).fold(0.0f32, |max, (x, y)| {
+        let diff = (x - y).abs();
+        if diff.is_nan() || max.is_nan() {
+            f32::NAN
+        } else {
+            max.max(diff)
+        }
+    })
+}
+
+/// Whether two embeddings match within tolerance
+///
+/// Same rule as `numpy.allclose`: every component must satisfy
+/// `|a - b| <= atol + rtol * |b|`, with `b` the reference. Arrays of
+/// different lengths, or holding NaN, are never close.
+#[wasm_bindgen]
+pub fn embeddings_close(a: &[f32], b: &[f32], atol: f32, rtol: f32) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(x, y)| (x - y).abs() <= atol + rtol * y.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_abs_diff() {
+        assert_eq!(max_abs_diff(&[1.0, -2.0, 3.0], &[1.5, -2.0, 2.0]), 1.0);
+        assert_eq!(max_abs_diff(&[], &[]), 0.0);
+        assert_eq!(max_abs_diff(&[1.0], &[1.0, 2.0]), f32::INFINITY);
+        assert!(max_abs_diff(&[f32::NAN, 0.0], &[0.0, 5.0]).is_nan());
+    }
+
+    #[test]
+    fn test_embeddings_close() {
+        let reference = [0.5, -0.25, 0.0];
+        assert!(embeddings_close(
+            &[0.5001, -0.25, 0.00005],
+            &reference,
+            1e-4,
+            1e-3
+        ));
+        assert!(!embeddings_close(
+            &[0.51, -0.25, 0.0],
+            &reference,
+            1e-4,
+            1e-3
+        ));
+        // The relative term scales with the reference, so large components
+        // get more slack than small ones
+        assert!(embeddings_close(&[100.05], &[100.0], 0.0, 1e-3));
+        assert!(!embeddings_close(&[0.05], &[0.0], 0.0, 1e-3));
+        assert!(!embeddings_close(&[0.5], &reference, 1.0, 1.0));
+        assert!(!embeddings_close(&[f32::NAN], &[f32::NAN], 1.0, 1.0));
+    }
+}
@@ -0,0 +1,237 @@
+//! Document vectors from overlapping windows
+//!
+//! Documents longer than the model's context are embedded as overlapping
+//! windows and the window vectors combined. A plain mean over-weights text
+//! that falls in two windows: with half-window strides, every token except
+//! those at the very ends is counted twice, and the ends once, so the result
+//! leans toward whatever sits at window boundaries. `embed_document()`
+//! instead weights each window by the tokens it contributes, where a token
+//! covered by `c` windows contributes `1/c` to each, so every token counts
+//! exactly once in the document vector.
+
+use std::ops::Range;
+
+use js_sys::Float32Array;
+use serde::Deserialize;
+use tokenizers::{PostProcessor, Tokenizer};
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::presets::TextRole;
+use crate::telemetry::attended_tokens;
+use crate::{long_text, EmbeddingEngine, MAX_SEQUENCE_LENGTH};
+
+/// How window vectors are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Stitching {
+    /// Weight windows so each token counts once
+    #[default]
+    Overlap,
+    /// Unweighted mean of the windows
+    Mean,
+}
+
+/// Options for `embed_document`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct DocumentOptions {
+    /// Content tokens per window (defaults to the model's limit)
+    window_tokens: Option<usize>,
+    /// Tokens between window starts (defaults to half a window)
+    stride: Option<usize>,
+    stitching: Stitching,
+}
+
+/// Token ranges of windows of `window` tokens starting every `stride` tokens;
+/// the last window always reaches the final token
+pub(crate) fn window_ranges(tokens: usize, window: usize, stride: usize) -> Vec<Range<usize>> {
+    let mut windows = Vec::new();
+    let mut first = 0;
+    while first < tokens {
+        let end = (first + window).min(tokens);
+        windows.push(first..end);
+        if end == tokens {
+            break;
+        }
+        first += stride;
+    }
+    windows
+}
+
+/// Most content tokens a window can hold: the model's limit less the
+/// special tokens added around each text and the tokens of `prefix`, the
+/// preset's document prefix prepended to every window
+pub(crate) fn max_window_tokens(tokenizer: &Tokenizer, prefix: &str) -> usize {
+    let limit = tokenizer.get_truncation().map_or(MAX_SEQUENCE_LENGTH, |t| {
+        t.max_length.min(MAX_SEQUENCE_LENGTH)
+    });
+    let special = tokenizer
+        .get_post_processor()
+        .map_or(0, |p| p.added_tokens(false));
+    let prefix = match prefix {
+        "" => 0,
+        prefix => tokenizer
+            .encode(prefix, false)
+            .map_or(0, |encoding| attended_tokens(&encoding)),
+    };
+    limit.saturating_sub(special + prefix).max(1)
+}
+
+/// Weight of each window: the sum over its tokens of one over the number of
+/// windows covering that token
+pub(crate) fn overlap_weights(windows: &[Range<usize>], tokens: usize) -> Vec<f32> {
+    let mut coverage = vec![0u32; tokens];
+    for window in windows {
+        for count in &mut coverage[window.clone()] {
+            *count += 1;
+        }
+    }
+    windows
+        .iter()
+        .map(|window| {
+            coverage[window.clone()]
+                .iter()
+                .map(|&c| 1.0 / c as f32)
+                .sum()
+        })
+        .collect()
+}
+
+/// Weighted sum of vectors, L2-normalized
+pub(crate) fn combine(vectors: &[Vec<f32>], weights: &[f32]) -> Vec<f32> {
+    let dim = vectors.first().map_or(0, Vec::len);
+    let mut sum = vec![0.0f32; dim];
+    for (vector, &weight) in vectors.iter().zip(weights) {
+        for (s, v) in sum.iter_mut().zip(vector) {
+            *s += weight * v;
+        }
+    }
+    let norm = sum.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        sum.iter_mut().for_each(|v| *v /= norm);
+    }
+    sum
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Embed a document of any length as one vector
+    ///
+    /// Options: `{ window_tokens = model limit, stride = window_tokens / 2,
+    /// stitching = "overlap" | "mean" }`. Texts that fit in one window embed
+    /// exactly as with `embed()`.
+    #[wasm_bindgen]
+    pub fn embed_document(&self, text: &str, options: &JsValue) -> Result<Float32Array, JsValue> {
+        let options: DocumentOptions = parse_options(options)?;
+        Ok(Float32Array::from(
+            self.document_vector(text, &options)?.as_slice(),
+        ))
+    }
+}
+
+impl EmbeddingEngine {
+    /// `embed_document()` with parsed options
+    fn document_vector(&self, text: &str, options: &DocumentOptions) -> Result<Vec<f32>, JsValue> {
+        let mut tokenizer = self.tokenizer.clone().ok_or_else(|| {
+            JsValue::from_str("Tokenizer not loaded. Call load_embedded() first.")
+        })?;
+        let max_window = max_window_tokens(&tokenizer, self.role_prefix(TextRole::Document));
+        let window = options
+            .window_tokens
+            .unwrap_or(max_window)
+            .clamp(1, max_window);
+        let stride = options.stride.unwrap_or(window / 2).clamp(1, window);
+
+        tokenizer
+            .with_truncation(None)
+            .map_err(|e| JsValue::from_str(&format!("Tokenizer setup failed: {}", e)))?
+            .with_padding(None);
+        let offsets = long_text::token_offsets(&tokenizer, text)
+            .map_err(|e| JsValue::from_str(&format!("Tokenization failed: {:?}", e)))?;
+        if offsets.len() <= window {
+            return Ok(self.embed_internal(&[text.to_string()])?.remove(0));
+        }
+
+        // Offsets come from the tokenizer; skip any window that doesn't map to
+        // a valid slice rather than panicking on it
+        let mut windows = window_ranges(offsets.len(), window, stride);
+        windows.retain(|w| text.get(offsets[w.start].0..offsets[w.end - 1].1).is_some());
+        let texts: Vec<String> = windows
+            .iter()
+            .map(|w| text[offsets[w.start].0..offsets[w.end - 1].1].to_string())
+            .collect();
+        let vectors = self.embed_internal(&texts)?;
+        let weights = match options.stitching {
+            Stitching::Overlap => overlap_weights(&windows, offsets.len()),
+            Stitching::Mean => vec![1.0; windows.len()],
+        };
+        Ok(combine(&vectors, &weights))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::ModelPreset;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_window_ranges() {
+        assert_eq!(window_ranges(5, 2, 2), vec![0..2, 2..4, 4..5]);
+        assert_eq!(window_ranges(5, 4, 2), vec![0..4, 2..5]);
+        assert_eq!(window_ranges(5, 10, 5), vec![0..5]);
+        assert!(window_ranges(0, 4, 2).is_empty());
+    }
+
+    #[test]
+    fn test_overlap_weights_count_tokens_once() {
+        let windows = window_ranges(10, 4, 2);
+        let weights = overlap_weights(&windows, 10);
+        assert!((weights.iter().sum::<f32>() - 10.0).abs() < 1e-5);
+        // End windows own their unshared tokens, so they outweigh inner ones
+        assert!(weights[0] > weights[1]);
+    }
+
+    #[test]
+    fn test_combine_normalizes() {
+        let combined = combine(&[vec![1.0, 0.0], vec![0.0, 1.0]], &[3.0, 1.0]);
+        assert!((combined[0] - 0.9486833).abs() < 1e-6);
+        let norm: f32 = combined.iter().map(|v| v * v).sum();
+        assert!((norm - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_window_leaves_room_for_prefix() {
+        let mut tokenizer = Tokenizer::from_str(
+            r#"{
+                "version": "1.0", "truncation": null, "padding": null, "added_tokens": [],
+                "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+                "post_processor": {"type": "BertProcessing",
+                                   "sep": ["[SEP]", 2], "cls": ["[CLS]", 1]},
+                "decoder": null,
+                "model": {"type": "WordLevel",
+                          "vocab": {"[UNK]": 0, "[CLS]": 1, "[SEP]": 2, "passage": 3, ":": 4},
+                          "unk_token": "[UNK]"}
+            }"#,
+        )
+        .unwrap();
+        let preset = ModelPreset::find("e5-small-v2").unwrap();
+        preset.apply_max_length(&mut tokenizer).unwrap();
+        assert_eq!(max_window_tokens(&tokenizer, ""), MAX_SEQUENCE_LENGTH - 2);
+        // "passage: " is two more tokens in front of every window
+        assert_eq!(
+            max_window_tokens(&tokenizer, preset.document_prefix),
+            MAX_SEQUENCE_LENGTH - 4
+        );
+    }
+
+    #[test]
+    fn test_document_options() {
+        let options: DocumentOptions =
+            crate::js::options_from_json(r#"{"stitching": "mean", "stride": 8}"#).unwrap();
+        assert_eq!(options.stitching, Stitching::Mean);
+        assert_eq!(options.stride, Some(8));
+        assert_eq!(options.window_tokens, None);
+    }
+}
@@ -0,0 +1,55 @@
+//! Typed errors thrown to JavaScript
+//!
+//! Most failures are plain messages, but some need to be told apart
+//! programmatically. Those are thrown as `Error` objects whose `name` gives the
+//! kind, so callers can branch on `err.name` instead of parsing messages.
+//!
+//! Release builds abort on panic, and an aborted instance cannot be reused, so
+//! conditions that would otherwise panic (unexpected tensor shapes, malformed
+//! weight files) are checked up front and reported as errors instead. The
+//! engine stays usable after any error it throws.
+
+use wasm_bindgen::prelude::*;
+
+/// Kinds of error surfaced as the `name` of the thrown `Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorKind {
+    /// An internal invariant failed, e.g. the model produced output of an
+    /// unexpected shape (corrupt weights or a config that doesn't match them)
+    Internal,
+    /// The input can't be processed within the configured memory budget
+    MemoryBudget,
+    /// The call exceeds a configured limit and would fail on every retry
+    LimitExceeded,
+    /// The token rate limit is used up for now; retrying later will work
+    RateLimit,
+}
+
+impl ErrorKind {
+    fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Internal => "InternalError",
+            ErrorKind::MemoryBudget => "MemoryBudgetError",
+            ErrorKind::LimitExceeded => "LimitExceededError",
+            ErrorKind::RateLimit => "RateLimitError",
+        }
+    }
+}
+
+/// Build a JS `Error` of the given kind
+pub(crate) fn js_error(kind: ErrorKind, message: &str) -> JsValue {
+    let error = js_sys::Error::new(message);
+    error.set_name(kind.name());
+    error.into()
+}
+
+/// Print Rust panic messages to `console.error` (`panic-hook` feature)
+///
+/// A panic still ends the instance, but without the hook it only shows up as
+/// `RuntimeError: unreachable`. Call once after `init()`; repeat calls are
+/// no-ops.
+#[cfg(feature = "panic-hook")]
+#[wasm_bindgen]
+pub fn install_panic_hook() {
+    console_error_panic_hook::set_once();
+}
@@ -0,0 +1,357 @@
+//! Retrieval quality metrics
+//!
+//! Comparing models or chunking strategies needs the same metrics IR papers
+//! report, computed where the system will actually run. Each function takes
+//! `runs`, the ranked document ids returned for each query (best first), and
+//! `qrels`, one `{ docId: grade }` object per query giving the relevance
+//! judgments; grades above 0 count as relevant. Ids may be numbers or
+//! strings. Results are averaged over queries that have at least one
+//! relevant document, as `trec_eval` does.
+//!
+//! `evaluate_sts()` checks a model against semantic textual similarity data
+//! instead: it correlates the engine's cosine similarities with human scores,
+//! the number STS benchmarks report.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::{batching, cosine_similarity, EmbeddingEngine};
+
+/// Relevance grades of one query's judged documents
+type Qrels = HashMap<String, f64>;
+
+/// Comparable form of a document id
+fn doc_id(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Ranked ids per query and the matching judgments
+fn parse_inputs(
+    runs: &JsValue,
+    qrels: &JsValue,
+) -> Result<(Vec<Vec<String>>, Vec<Qrels>), JsValue> {
+    let runs: Vec<Vec<Value>> = parse_options(runs)?;
+    let qrels: Vec<Qrels> = parse_options(qrels)?;
+    if runs.len() != qrels.len() {
+        return Err(JsValue::from_str(&format!(
+            "Got {} runs but {} qrels",
+            runs.len(),
+            qrels.len()
+        )));
+    }
+    let runs = runs
+        .iter()
+        .map(|run| run.iter().map(doc_id).collect())
+        .collect();
+    Ok((runs, qrels))
+}
+
+/// The first `k` results (all of them for 0)
+fn top_k(run: &[String], k: usize) -> &[String] {
+    if k == 0 {
+        run
+    } else {
+        &run[..k.min(run.len())]
+    }
+}
+
+fn grade(qrels: &Qrels, id: &str) -> f64 {
+    qrels.get(id).copied().unwrap_or(0.0).max(0.0)
+}
+
+/// nDCG@k with linear gains; a repeated result gains nothing
+fn query_ndcg(run: &[String], qrels: &Qrels, k: usize) -> f64 {
+    let discount = |rank: usize| 1.0 / (rank as f64 + 2.0).log2();
+    let mut seen = HashSet::new();
+    let dcg: f64 = top_k(run, k)
+        .iter()
+        .enumerate()
+        .filter(|(_, id)| seen.insert(id.as_str()))
+        .map(|(rank, id)| grade(qrels, id) * discount(rank))
+        .sum();
+    let mut ideal: Vec<f64> = qrels.values().copied().filter(|&g| g > 0.0).collect();
+    ideal.sort_by(|a, b| b.total_cmp(a));
+    if k > 0 {
+        ideal.truncate(k);
+    }
+    let idcg: f64 = ideal
+        .iter()
+        .enumerate()
+        .map(|(rank, g)| g * discount(rank))
+        .sum();
+    dcg / idcg
+}
+
+/// Reciprocal rank of the first relevant result within the top k
+fn query_reciprocal_rank(run: &[String], qrels: &Qrels, k: usize) -> f64 {
+    top_k(run, k)
+        .iter()
+        .position(|id| grade(qrels, id) > 0.0)
+        .map_or(0.0, |rank| 1.0 / (rank + 1) as f64)
+}
+
+/// Fraction of relevant documents found in the top k
+fn query_recall(run: &[String], qrels: &Qrels, k: usize) -> f64 {
+    let relevant = qrels.values().filter(|&&g| g > 0.0).count();
+    let mut seen = HashSet::new();
+    let found = top_k(run, k)
+        .iter()
+        .filter(|id| grade(qrels, id) > 0.0 && seen.insert(id.as_str()))
+        .count();
+    found as f64 / relevant as f64
+}
+
+/// Mean of `metric` over queries with at least one relevant document
+fn mean_over_queries(
+    runs: &[Vec<String>],
+    qrels: &[Qrels],
+    k: usize,
+    metric: fn(&[String], &Qrels, usize) -> f64,
+) -> f64 {
+    let scores: Vec<f64> = runs
+        .iter()
+        .zip(qrels)
+        .filter(|(_, qrels)| qrels.values().any(|&g| g > 0.0))
+        .map(|(run, qrels)| metric(run, qrels, k))
+        .collect();
+    if scores.is_empty() {
+        return 0.0;
+    }
+    scores.iter().sum::<f64>() / scores.len() as f64
+}
+
+/// Mean nDCG@k (graded relevance, linear gains); `k` 0 ranks the full run
+#[wasm_bindgen]
+pub fn ndcg_at_k(runs: &JsValue, qrels: &JsValue, k: usize) -> Result<f64, JsValue> {
+    let (runs, qrels) = parse_inputs(runs, qrels)?;
+    Ok(mean_over_queries(&runs, &qrels, k, query_ndcg))
+}
+
+/// Mean reciprocal rank of the first relevant result within the top k (0
+/// for no cutoff)
+#[wasm_bindgen]
+pub fn mrr(runs: &JsValue, qrels: &JsValue, k: usize) -> Result<f64, JsValue> {
+    let (runs, qrels) = parse_inputs(runs, qrels)?;
+    Ok(mean_over_queries(&runs, &qrels, k, query_reciprocal_rank))
+}
+
+/// Mean fraction of each query's relevant documents found in the top k
+#[wasm_bindgen]
+pub fn recall_at_k(runs: &JsValue, qrels: &JsValue, k: usize) -> Result<f64, JsValue> {
+    let (runs, qrels) = parse_inputs(runs, qrels)?;
+    Ok(mean_over_queries(&runs, &qrels, k, query_recall))
+}
+
+/// A scored sentence pair, as `[a, b, score]` or an STS-B style object
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum StsPair {
+    Tuple(String, String, f64),
+    Object {
+        sentence1: String,
+        sentence2: String,
+        score: f64,
+    },
+}
+
+impl StsPair {
+    fn parts(&self) -> (&str, &str, f64) {
+        match self {
+            StsPair::Tuple(a, b, score) => (a, b, *score),
+            StsPair::Object {
+                sentence1,
+                sentence2,
+                score,
+            } => (sentence1, sentence2, *score),
+        }
+    }
+}
+
+/// Result of `evaluate_sts()`
+#[wasm_bindgen]
+pub struct StsReport {
+    pairs: usize,
+    pearson: f64,
+    spearman: f64,
+}
+
+#[wasm_bindgen]
+impl StsReport {
+    /// Number of pairs evaluated
+    #[wasm_bindgen(getter)]
+    pub fn pairs(&self) -> usize {
+        self.pairs
+    }
+
+    /// Pearson correlation of similarities with the gold scores
+    #[wasm_bindgen(getter)]
+    pub fn pearson(&self) -> f64 {
+        self.pearson
+    }
+
+    /// Spearman rank correlation, the usual headline STS number
+    #[wasm_bindgen(getter)]
+    pub fn spearman(&self) -> f64 {
+        self.spearman
+    }
+}
+
+/// Pearson correlation (0 when either side is constant)
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let mean_x = x.iter().sum::<f64>() / n;
+    let mean_y = y.iter().sum::<f64>() / n;
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (a, b) in x.iter().zip(y) {
+        cov += (a - mean_x) * (b - mean_y);
+        var_x += (a - mean_x) * (a - mean_x);
+        var_y += (b - mean_y) * (b - mean_y);
+    }
+    if var_x == 0.0 || var_y == 0.0 {
+        return 0.0;
+    }
+    cov / (var_x * var_y).sqrt()
+}
+
+/// Ranks starting at 1, tied values sharing their average rank
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &i in &order[start..end] {
+            ranks[i] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
+
+/// Spearman rank correlation
+fn spearman(x: &[f64], y: &[f64]) -> f64 {
+    pearson(&ranks(x), &ranks(y))
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Correlate the engine's similarities with gold scores on STS data
+    ///
+    /// `pairs` is an array of `[sentenceA, sentenceB, score]` or `{ sentence1,
+    /// sentence2, score }` (any score scale). Each distinct sentence is
+    /// embedded once with the current settings, so the result reflects
+    /// pooling, normalization and preprocessing choices as well as the model.
+    #[wasm_bindgen]
+    pub fn evaluate_sts(&self, pairs: &JsValue) -> Result<StsReport, JsValue> {
+        let pairs: Vec<StsPair> = parse_options(pairs)?;
+        self.run_sts(&pairs)
+    }
+}
+
+impl EmbeddingEngine {
+    /// `evaluate_sts()` with parsed pairs
+    fn run_sts(&self, pairs: &[StsPair]) -> Result<StsReport, JsValue> {
+        if pairs.len() < 2 {
+            return Err(JsValue::from_str(
+                "evaluate_sts needs at least two scored pairs",
+            ));
+        }
+        let texts = pairs
+            .iter()
+            .flat_map(|pair| {
+                let (a, b, _) = pair.parts();
+                [a.to_string(), b.to_string()]
+            })
+            .collect();
+        let (unique, positions) = batching::dedup_texts(texts);
+        let embeddings = batching::fan_out(self.embed_internal(&unique)?, &positions);
+
+        let predicted: Vec<f64> = embeddings
+            .chunks_exact(2)
+            .map(|pair| cosine_similarity(&pair[0], &pair[1]) as f64)
+            .collect();
+        let gold: Vec<f64> = pairs.iter().map(|pair| pair.parts().2).collect();
+        Ok(StsReport {
+            pairs: pairs.len(),
+            pearson: pearson(&predicted, &gold),
+            spearman: spearman(&predicted, &gold),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn qrels(grades: &[(&str, f64)]) -> Qrels {
+        grades.iter().map(|&(id, g)| (id.to_string(), g)).collect()
+    }
+
+    #[test]
+    fn test_ndcg() {
+        let judged = qrels(&[("a", 3.0), ("b", 2.0), ("c", 0.0)]);
+        assert_eq!(query_ndcg(&run(&["a", "b", "x"]), &judged, 3), 1.0);
+        let swapped = query_ndcg(&run(&["b", "a"]), &judged, 2);
+        let expected = (2.0 + 3.0 / 3f64.log2()) / (3.0 + 2.0 / 3f64.log2());
+        assert!((swapped - expected).abs() < 1e-12);
+        assert_eq!(query_ndcg(&run(&["c", "x"]), &judged, 2), 0.0);
+        assert!(query_ndcg(&run(&["a", "a", "a"]), &judged, 3) < 1.0);
+    }
+
+    #[test]
+    fn test_mrr_and_recall() {
+        let judged = qrels(&[("a", 1.0), ("b", 1.0)]);
+        let ranked = run(&["x", "b", "y", "a"]);
+        assert_eq!(query_reciprocal_rank(&ranked, &judged, 0), 0.5);
+        assert_eq!(query_reciprocal_rank(&ranked, &judged, 1), 0.0);
+        assert_eq!(query_recall(&ranked, &judged, 2), 0.5);
+        assert_eq!(query_recall(&ranked, &judged, 0), 1.0);
+        // Duplicate results count once
+        assert_eq!(query_recall(&run(&["a", "a"]), &judged, 2), 0.5);
+    }
+
+    #[test]
+    fn test_unjudged_queries_skipped() {
+        let runs = vec![run(&["a"]), run(&["z"])];
+        let judged = vec![qrels(&[("a", 1.0)]), qrels(&[("q", 0.0)])];
+        assert_eq!(mean_over_queries(&runs, &judged, 10, query_recall), 1.0);
+        assert_eq!(doc_id(&serde_json::json!(12)), "12");
+    }
+
+    #[test]
+    fn test_correlations() {
+        let x = [1.0, 2.0, 3.0, 4.0];
+        assert!((pearson(&x, &[2.0, 4.0, 6.0, 8.0]) - 1.0).abs() < 1e-12);
+        assert!((pearson(&x, &[4.0, 3.0, 2.0, 1.0]) + 1.0).abs() < 1e-12);
+        assert_eq!(pearson(&x, &[1.0; 4]), 0.0);
+        // Monotonic but not linear: perfect rank correlation
+        assert!((spearman(&x, &[1.0, 10.0, 100.0, 1000.0]) - 1.0).abs() < 1e-12);
+        assert_eq!(ranks(&[5.0, 1.0, 5.0, 2.0]), vec![3.5, 1.0, 3.5, 2.0]);
+    }
+
+    #[test]
+    fn test_sts_pair_formats() {
+        let pairs: Vec<StsPair> = crate::js::options_from_json(
+            r#"[["a", "b", 4.5], {"sentence1": "c", "sentence2": "d", "score": 1}]"#,
+        )
+        .unwrap();
+        assert_eq!(pairs[0].parts(), ("a", "b", 4.5));
+        assert_eq!(pairs[1].parts(), ("c", "d", 1.0));
+    }
+}
@@ -0,0 +1,214 @@
+//! Re-saving the loaded weights
+//!
+//! `export_model()` writes the weights the engine is running with back out as
+//! a SafeTensors file, optionally converted to a smaller dtype. Converting
+//! once offline and serving the result halves the download for every client;
+//! `load()` converts the stored dtype back to f32 as it builds the model.
+//! `"q4"` writes the matrices 4-bit quantized (`crate::q4`) and everything else
+//! as f16, a seventh of the f32 size at a cost in accuracy.
+
+use std::collections::HashMap;
+
+use candle_core::{DType, Tensor};
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::{q4, EmbeddingEngine};
+
+/// Options for `export_model`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct ExportOptions {
+    /// Stored dtype: "f32", "f16", "bf16" or "q4"
+    dtype: String,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            dtype: "f32".to_string(),
+        }
+    }
+}
+
+/// Map an export dtype name to a candle dtype
+fn export_dtype(name: &str) -> Result<DType, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "f32" => Ok(DType::F32),
+        "f16" => Ok(DType::F16),
+        "bf16" => Ok(DType::BF16),
+        other => Err(format!(
+            "Unsupported export dtype: {} (expected \"f32\", \"f16\", \"bf16\" or \"q4\")",
+            other
+        )),
+    }
+}
+
+/// SafeTensors dtype tag
+fn dtype_tag(dtype: DType) -> &'static str {
+    match dtype {
+        DType::U8 => "U8",
+        DType::U32 => "U32",
+        DType::I64 => "I64",
+        DType::BF16 => "BF16",
+        DType::F16 => "F16",
+        DType::F32 => "F32",
+        DType::F64 => "F64",
+    }
+}
+
+/// Little-endian bytes of a tensor's elements
+fn tensor_bytes(tensor: &Tensor) -> candle_core::Result<Vec<u8>> {
+    let flat = tensor.flatten_all()?;
+    Ok(match tensor.dtype() {
+        DType::F32 => flat
+            .to_vec1::<f32>()?
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect(),
+        DType::F16 => flat
+            .to_vec1::<half::f16>()?
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect(),
+        DType::BF16 => flat
+            .to_vec1::<half::bf16>()?
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect(),
+        DType::F64 => flat
+            .to_vec1::<f64>()?
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect(),
+        DType::I64 => flat
+            .to_vec1::<i64>()?
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect(),
+        DType::U32 => flat
+            .to_vec1::<u32>()?
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect(),
+        DType::U8 => flat.to_vec1::<u8>()?,
+    })
+}
+
+/// Serialize tensors as a SafeTensors file, converting floating point
+/// tensors to `dtype`; tensors are written in name order
+pub(crate) fn serialize(
+    tensors: &HashMap<String, Tensor>,
+    dtype: DType,
+) -> candle_core::Result<Vec<u8>> {
+    let mut names: Vec<&String> = tensors.keys().collect();
+    names.sort();
+
+    let mut header = serde_json::Map::new();
+    let mut data = Vec::new();
+    for name in names {
+        let tensor = &tensors[name];
+        let tensor = if tensor.dtype().is_float() {
+            tensor.to_dtype(dtype)?
+        } else {
+            tensor.clone()
+        };
+        let bytes = tensor_bytes(&tensor)?;
+        let start = data.len();
+        data.extend_from_slice(&bytes);
+        header.insert(
+            name.clone(),
+            serde_json::json!({
+                "dtype": dtype_tag(tensor.dtype()),
+                "shape": tensor.dims(),
+                "data_offsets": [start, data.len()],
+            }),
+        );
+    }
+
+    let mut header = serde_json::Value::Object(header).to_string().into_bytes();
+    // Pad so the data section starts 8-byte aligned, as the reference writer does
+    while !(header.len() + 8).is_multiple_of(8) {
+        header.push(b' ');
+    }
+    let mut file = Vec::with_capacity(8 + header.len() + data.len());
+    file.extend_from_slice(&(header.len() as u64).to_le_bytes());
+    file.extend_from_slice(&header);
+    file.extend_from_slice(&data);
+    Ok(file)
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Serialize the loaded weights as a SafeTensors file
+    ///
+    /// Options: `{ dtype = "f32" }`, or `"f16"`/`"bf16"` to halve the size,
+    /// or `"q4"` for 4-bit matrices. The result loads with `load()` alongside
+    /// the original tokenizer and config. Vectors from a converted model
+    /// differ slightly (from a Q4 one, noticeably), so it gets its own
+    /// `compatibility_fingerprint()`.
+    #[wasm_bindgen]
+    pub fn export_model(&self, options: &JsValue) -> Result<Vec<u8>, JsValue> {
+        let options: ExportOptions = parse_options(options)?;
+        let quantize = options.dtype.eq_ignore_ascii_case("q4");
+        let dtype = if quantize {
+            DType::F16
+        } else {
+            export_dtype(&options.dtype).map_err(|e| JsValue::from_str(&e))?
+        };
+        // Cloning shares the tensors' storage
+        let mut weights = self
+            .weights
+            .clone()
+            .ok_or_else(|| JsValue::from_str("No SafeTensors model loaded. Call load() first."))?;
+        if quantize {
+            q4::quantize_tensors(&mut weights)
+                .map_err(|e| JsValue::from_str(&format!("Failed to quantize weights: {}", e)))?;
+        }
+        serialize(&weights, dtype)
+            .map_err(|e| JsValue::from_str(&format!("Failed to export model: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::SafetensorsStream;
+    use candle_core::Device;
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let mut tensors = HashMap::new();
+        let weight = Tensor::new(&[[0.5f32, -1.25], [3.0, 0.1]], &Device::Cpu).unwrap();
+        let ids = Tensor::new(&[1i64, 2, 3], &Device::Cpu).unwrap();
+        tensors.insert("w".to_string(), weight.clone());
+        tensors.insert("ids".to_string(), ids);
+
+        for dtype in [DType::F32, DType::F16] {
+            let file = serialize(&tensors, dtype).unwrap();
+            let header_len = u64::from_le_bytes(file[..8].try_into().unwrap()) as usize;
+            assert!((8 + header_len).is_multiple_of(8));
+
+            let mut stream = SafetensorsStream::new(&Device::Cpu);
+            stream.push(&file).unwrap();
+            let loaded = stream.finish().unwrap();
+            assert_eq!(loaded["w"].dtype(), dtype);
+            // Integer tensors keep their dtype
+            assert_eq!(loaded["ids"].to_vec1::<i64>().unwrap(), vec![1, 2, 3]);
+            let w = loaded["w"]
+                .to_dtype(DType::F32)
+                .unwrap()
+                .to_vec2::<f32>()
+                .unwrap();
+            assert!((w[1][1] - 0.1).abs() < 1e-3);
+            assert_eq!(w[0], vec![0.5, -1.25]);
+        }
+    }
+
+    #[test]
+    fn test_export_dtype_names() {
+        assert_eq!(export_dtype("F16"), Ok(DType::F16));
+        assert!(export_dtype("q8").is_err());
+    }
+}
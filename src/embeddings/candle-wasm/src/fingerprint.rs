@@ -0,0 +1,302 @@
+//! Embedding-space fingerprints
+//!
+//! Vectors are only comparable when they come from the same weights,
+//! tokenizer, config and pooling. The fingerprint hashes all of those so apps
+//! can store it next to an index and detect when a model upgrade means the
+//! stored vectors must be re-embedded.
+
+use wasm_bindgen::prelude::*;
+
+use crate::bert::LayerSelection;
+use crate::linear::WeightPrecision;
+use crate::preprocess::PreprocessOptions;
+use crate::{EmbeddingEngine, PoolingStrategy, MAX_SEQUENCE_LENGTH};
+
+/// Fingerprint format version; bump when the hashed inputs change
+const FINGERPRINT_VERSION: &str = "v2";
+
+/// Streaming FNV-1a over 64-bit little-endian words
+///
+/// Word-wise rather than byte-wise so hashing ~90MB of weights adds only a few
+/// milliseconds to a load. The result does not depend on how the input is
+/// split into `update` calls.
+pub(crate) struct ModelHasher {
+    hash: u64,
+    tail: [u8; 8],
+    tail_len: usize,
+    len: u64,
+}
+
+impl ModelHasher {
+    pub(crate) fn new() -> Self {
+        ModelHasher {
+            hash: 0xcbf2_9ce4_8422_2325,
+            tail: [0; 8],
+            tail_len: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        if self.tail_len > 0 {
+            let take = (8 - self.tail_len).min(bytes.len());
+            self.tail[self.tail_len..self.tail_len + take].copy_from_slice(&bytes[..take]);
+            self.tail_len += take;
+            bytes = &bytes[take..];
+            if self.tail_len < 8 {
+                return;
+            }
+            self.round(u64::from_le_bytes(self.tail));
+            self.tail_len = 0;
+        }
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(word);
+            self.round(u64::from_le_bytes(buf));
+        }
+        let rest = words.remainder();
+        self.tail[..rest.len()].copy_from_slice(rest);
+        self.tail_len = rest.len();
+    }
+
+    /// Finish, mixing in the length and a final avalanche so every input bit
+    /// affects every output bit
+    pub(crate) fn finish(mut self) -> u64 {
+        if self.tail_len > 0 {
+            let mut last = [0u8; 8];
+            last[..self.tail_len].copy_from_slice(&self.tail[..self.tail_len]);
+            self.round(u64::from_le_bytes(last));
+        }
+        self.round(self.len);
+        // SplitMix64 finalizer
+        let mut z = self.hash;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn round(&mut self, word: u64) {
+        self.hash ^= word;
+        self.hash = self.hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+}
+
+/// Hash a complete buffer
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = ModelHasher::new();
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+/// Combine the weights hash with the other files that shape the vectors
+pub(crate) fn model_hash(weights_hash: u64, tokenizer_bytes: &[u8], config_bytes: &[u8]) -> u64 {
+    let mut hasher = ModelHasher::new();
+    hasher.update(&weights_hash.to_le_bytes());
+    hasher.update(&hash_bytes(tokenizer_bytes).to_le_bytes());
+    hasher.update(&hash_bytes(config_bytes).to_le_bytes());
+    hasher.finish()
+}
+
+/// Mix the weight storage type into the weights hash; f32 leaves it as is
+pub(crate) fn with_weight_precision(weights_hash: u64, precision: WeightPrecision) -> u64 {
+    if precision == WeightPrecision::F32 {
+        return weights_hash;
+    }
+    let mut hasher = ModelHasher::new();
+    hasher.update(&weights_hash.to_le_bytes());
+    hasher.update(precision.name().as_bytes());
+    hasher.finish()
+}
+
+/// Mix a normalizer override (see `set_normalization`) into the model hash,
+/// since it changes the tokens the model sees
+fn with_normalizer(model_hash: u64, normalizer: &str) -> u64 {
+    let mut hasher = ModelHasher::new();
+    hasher.update(&model_hash.to_le_bytes());
+    hasher.update(&hash_bytes(normalizer.as_bytes()).to_le_bytes());
+    hasher.finish()
+}
+
+/// Mix a preset's document prefix into the model hash, since it is part of
+/// every embedded text
+fn with_document_prefix(model_hash: u64, prefix: &str) -> u64 {
+    let mut hasher = ModelHasher::new();
+    hasher.update(&model_hash.to_le_bytes());
+    hasher.update(b"prefix:");
+    hasher.update(prefix.as_bytes());
+    hasher.finish()
+}
+
+/// Mix text preprocessing (see `set_preprocessing`) into the model hash,
+/// since it changes every text before tokenization
+fn with_preprocessing(model_hash: u64, options: &PreprocessOptions) -> u64 {
+    let mut hasher = ModelHasher::new();
+    hasher.update(&model_hash.to_le_bytes());
+    hasher.update(b"preprocess:");
+    // Field order is fixed, so equal options serialize identically
+    hasher.update(
+        serde_json::to_string(options)
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    hasher.finish()
+}
+
+/// Mix the effective truncation length into the model hash: presets and
+/// sentence-transformers configs change it after the tokenizer file is
+/// hashed, and it decides how much of a long text is embedded
+fn with_max_length(model_hash: u64, max_length: usize) -> u64 {
+    let mut hasher = ModelHasher::new();
+    hasher.update(&model_hash.to_le_bytes());
+    hasher.update(b"max_length:");
+    hasher.update(&(max_length as u64).to_le_bytes());
+    hasher.finish()
+}
+
+/// Fingerprint string: version, model hash, then the settings that change
+/// the vectors (pooling, pooled layer, normalization)
+fn format_fingerprint(model_hash: u64, pooling: PoolingStrategy, layer: LayerSelection) -> String {
+    format!(
+        "{}:{:016x}:{}:{}:l2",
+        FINGERPRINT_VERSION,
+        model_hash,
+        pooling.name(),
+        layer.name()
+    )
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Fingerprint of the embedding space this engine produces
+    ///
+    /// Covers the model weights, tokenizer (including any normalization
+    /// override) and config plus pooling, normalization, a preset's document
+    /// prefix, text preprocessing and truncation length. Store it alongside
+    /// persisted vectors and pass it to `check_compatibility()` after loading.
+    #[wasm_bindgen]
+    pub fn compatibility_fingerprint(&self) -> Result<String, JsValue> {
+        let model_hash = self
+            .model_hash
+            .ok_or_else(|| JsValue::from_str("Model not loaded. Call load_embedded() first."))?;
+        let model_hash = match &self.custom_normalizer {
+            Some(normalizer) => with_normalizer(model_hash, normalizer),
+            None => model_hash,
+        };
+        let model_hash = match self.preset {
+            Some(preset) if !preset.document_prefix.is_empty() => {
+                with_document_prefix(model_hash, preset.document_prefix)
+            }
+            _ => model_hash,
+        };
+        let model_hash = match &self.preprocessing {
+            Some(options) => with_preprocessing(model_hash, options),
+            None => model_hash,
+        };
+        let max_length = self
+            .tokenizer
+            .as_ref()
+            .and_then(|tokenizer| tokenizer.get_truncation())
+            .map_or(MAX_SEQUENCE_LENGTH, |t| {
+                t.max_length.min(MAX_SEQUENCE_LENGTH)
+            });
+        let model_hash = with_max_length(model_hash, max_length);
+        Ok(format_fingerprint(
+            model_hash,
+            self.pooling,
+            self.pooling_layer,
+        ))
+    }
+
+    /// Whether vectors with this fingerprint can be compared with vectors
+    /// from this engine; false means stored vectors must be re-embedded
+    #[wasm_bindgen]
+    pub fn check_compatibility(&self, fingerprint: &str) -> Result<bool, JsValue> {
+        Ok(self.compatibility_fingerprint()? == fingerprint.trim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js::options_from_json;
+    use crate::presets;
+    use std::str::FromStr;
+    use tokenizers::Tokenizer;
+
+    #[test]
+    fn test_hash_independent_of_chunking() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let whole = hash_bytes(&data);
+        for size in [1, 3, 7, 8, 13, 999] {
+            let mut hasher = ModelHasher::new();
+            for chunk in data.chunks(size) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finish(), whole, "chunk size {}", size);
+        }
+
+        let mut changed = data.clone();
+        changed[500] ^= 1;
+        assert_ne!(hash_bytes(&changed), whole);
+        // Trailing zero bytes must still change the hash
+        assert_ne!(hash_bytes(&[1, 2, 3]), hash_bytes(&[1, 2, 3, 0]));
+    }
+
+    #[test]
+    fn test_fingerprint_reflects_settings() {
+        let base = format_fingerprint(0xabc, PoolingStrategy::Mean, LayerSelection::Last);
+        assert_eq!(base, "v2:0000000000000abc:mean:last:l2");
+        assert_ne!(
+            base,
+            format_fingerprint(0xabc, PoolingStrategy::Cls, LayerSelection::Last)
+        );
+        assert_ne!(
+            base,
+            format_fingerprint(0xabc, PoolingStrategy::Mean, LayerSelection::Index(-2))
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_covers_preprocessing() {
+        let mut engine = EmbeddingEngine::new();
+        engine.model_hash = Some(0xabc);
+        let plain = engine.compatibility_fingerprint().unwrap();
+        engine.preprocessing = Some(options_from_json(r#"{"strip_html": true}"#).unwrap());
+        let html = engine.compatibility_fingerprint().unwrap();
+        assert_ne!(html, plain);
+        engine.preprocessing = Some(options_from_json(r#"{"collapse_whitespace": true}"#).unwrap());
+        assert_ne!(engine.compatibility_fingerprint().unwrap(), html);
+        engine.preprocessing = Some(options_from_json(r#"{"strip_html": true}"#).unwrap());
+        assert_eq!(engine.compatibility_fingerprint().unwrap(), html);
+    }
+
+    #[test]
+    fn test_fingerprint_covers_truncation() {
+        let mut tokenizer = Tokenizer::from_str(
+            r#"{
+                "version": "1.0", "truncation": null, "padding": null, "added_tokens": [],
+                "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+                "post_processor": null, "decoder": null,
+                "model": {"type": "WordLevel", "vocab": {"[UNK]": 0}, "unk_token": "[UNK]"}
+            }"#,
+        )
+        .unwrap();
+        let mut engine = EmbeddingEngine::new();
+        engine.model_hash = Some(0xabc);
+        let fingerprint = |engine: &EmbeddingEngine| engine.compatibility_fingerprint().unwrap();
+
+        presets::limit_max_length(&mut tokenizer, 128).unwrap();
+        engine.tokenizer = Some(tokenizer.clone());
+        let short = fingerprint(&engine);
+        presets::limit_max_length(&mut tokenizer, 256).unwrap();
+        engine.tokenizer = Some(tokenizer.clone());
+        let long = fingerprint(&engine);
+        assert_ne!(short, long);
+        // Anything past the engine's limit embeds the same tokens
+        presets::limit_max_length(&mut tokenizer, 512).unwrap();
+        engine.tokenizer = Some(tokenizer);
+        assert_eq!(fingerprint(&engine), long);
+    }
+}
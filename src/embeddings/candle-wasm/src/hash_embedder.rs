@@ -0,0 +1,141 @@
+//! Deterministic hashing-trick embedder
+//!
+//! Produces fixed-dimension vectors from hashed word and character n-grams,
+//! with no model at all. Quality is far below a neural model, but it is
+//! available instantly, which keeps search usable while the real model is still
+//! downloading (or permanently offline in a degraded mode).
+//!
+//! Vectors from `HashEmbedder` live in a different space from model embeddings;
+//! never mix the two in one index.
+
+use js_sys::{Array, Float32Array};
+use wasm_bindgen::prelude::*;
+
+use crate::{embeddings_to_js, js_array_to_strings};
+
+/// Character n-gram sizes hashed for every word
+const NGRAM_SIZES: std::ops::RangeInclusive<usize> = 3..=5;
+
+/// 64-bit FNV-1a
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// WASM-compatible hashed n-gram embedder
+#[wasm_bindgen]
+pub struct HashEmbedder {
+    dimension: usize,
+}
+
+#[wasm_bindgen]
+impl HashEmbedder {
+    /// Create an embedder producing vectors of `dimension` components
+    #[wasm_bindgen(constructor)]
+    pub fn new(dimension: usize) -> Result<HashEmbedder, JsValue> {
+        if dimension == 0 {
+            return Err(JsValue::from_str("Dimension must be greater than 0"));
+        }
+        Ok(HashEmbedder { dimension })
+    }
+
+    /// Always true: there is nothing to load
+    #[wasm_bindgen]
+    pub fn is_ready(&self) -> bool {
+        true
+    }
+
+    /// Generate embedding for a single text
+    #[wasm_bindgen]
+    pub fn embed(&self, text: &str) -> Float32Array {
+        let embedding = self.embed_text(text);
+        let arr = Float32Array::new_with_length(embedding.len() as u32);
+        arr.copy_from(&embedding);
+        arr
+    }
+
+    /// Generate embeddings for multiple texts
+    ///
+    /// Takes a JavaScript Array of strings
+    /// Returns a JavaScript Array of Float32Array
+    #[wasm_bindgen]
+    pub fn embed_batch(&self, texts: &Array) -> Result<Array, JsValue> {
+        let rust_texts = js_array_to_strings(texts)?;
+        let embeddings = rust_texts.iter().map(|t| self.embed_text(t)).collect();
+        Ok(embeddings_to_js(embeddings))
+    }
+
+    /// Get the embedding dimension
+    #[wasm_bindgen]
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+}
+
+impl HashEmbedder {
+    /// Hash words and their character n-grams into an L2-normalized vector
+    fn embed_text(&self, text: &str) -> Vec<f32> {
+        let mut out = vec![0.0f32; self.dimension];
+        let lowered = text.to_lowercase();
+
+        for word in lowered.split(|c: char| !c.is_alphanumeric()) {
+            if word.is_empty() {
+                continue;
+            }
+            self.add_feature(&mut out, word.as_bytes(), 1.0);
+
+            // Boundary markers let prefixes/suffixes hash differently from infixes
+            let chars: Vec<char> = format!("<{}>", word).chars().collect();
+            for n in NGRAM_SIZES {
+                for gram in chars.windows(n) {
+                    let gram: String = gram.iter().collect();
+                    self.add_feature(&mut out, gram.as_bytes(), 0.5);
+                }
+            }
+        }
+
+        let norm = out.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            out.iter_mut().for_each(|v| *v /= norm);
+        }
+        out
+    }
+
+    /// Signed hashing trick: the top bit picks the sign to reduce collision bias
+    fn add_feature(&self, out: &mut [f32], feature: &[u8], weight: f32) {
+        let hash = fnv1a(feature);
+        let index = (hash % self.dimension as u64) as usize;
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        out[index] += sign * weight;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cosine_similarity;
+
+    #[test]
+    fn test_deterministic_and_normalized() {
+        let embedder = HashEmbedder::new(256).unwrap();
+        let a = embedder.embed_text("Hello world");
+        assert_eq!(a, embedder.embed_text("hello, WORLD"));
+
+        let norm: f32 = a.iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+        assert!(embedder.embed_text("").iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn test_overlapping_text_is_closer() {
+        let embedder = HashEmbedder::new(512).unwrap();
+        let query = embedder.embed_text("searching documents");
+        let near = embedder.embed_text("search the documentation");
+        let far = embedder.embed_text("purple elephants dancing");
+        assert!(cosine_similarity(&query, &near) > cosine_similarity(&query, &far));
+    }
+}
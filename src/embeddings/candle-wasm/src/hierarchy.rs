@@ -0,0 +1,441 @@
+//! Chunk → section → document embedding trees (`DocumentEmbedder`)
+//!
+//! Coarse-to-fine retrieval finds the right document first and the best
+//! chunk inside it second. That needs vectors at every level and the links
+//! between them. A `DocumentEmbedder` splits a text into sections (at blank
+//! lines or Markdown headings) and each section into token windows, embeds
+//! all the chunks in one batch, and combines chunks into section vectors and
+//! sections into the document vector. By default it uses the token weights
+//! of `embed_document()`, so every token counts once at each level. The
+//! result is a flat tree: node 0 is the document, then the sections, then
+//! the chunks, each with a parent index and its span of the text, ready to
+//! store in an index next to the vectors.
+
+use std::ops::Range;
+
+use js_sys::{Float32Array, Int32Array, Uint32Array, Uint8Array};
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::attribution::utf16_table;
+use crate::document::{combine, max_window_tokens, overlap_weights, window_ranges};
+use crate::js::parse_options;
+use crate::presets::TextRole;
+use crate::EmbeddingEngine;
+
+/// Where sections start
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SectionBreak {
+    /// Paragraphs separated by blank lines
+    #[default]
+    BlankLine,
+    /// Lines starting with `#`
+    Heading,
+}
+
+/// How child vectors are combined into their parent's
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Aggregation {
+    /// Weighted by the tokens each child contributes
+    #[default]
+    Tokens,
+    /// Unweighted mean
+    Mean,
+    /// Elementwise maximum
+    Max,
+}
+
+/// Options for `new DocumentEmbedder()`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct HierarchyOptions {
+    /// Content tokens per chunk (defaults to the model's limit)
+    chunk_tokens: Option<usize>,
+    /// Tokens between chunk starts (defaults to `chunk_tokens`, no overlap)
+    stride: Option<usize>,
+    sections: SectionBreak,
+    aggregation: Aggregation,
+}
+
+/// Level of a node in a `DocumentTree`
+const DOCUMENT: u8 = 0;
+const SECTION: u8 = 1;
+const CHUNK: u8 = 2;
+
+/// Byte ranges of the non-blank sections of `text`, trimmed of surrounding
+/// whitespace
+fn section_ranges(text: &str, breaks: SectionBreak) -> Vec<Range<usize>> {
+    let mut starts = vec![0];
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let starts_section = match breaks {
+            SectionBreak::BlankLine => line.trim().is_empty(),
+            SectionBreak::Heading => line.trim_start().starts_with('#'),
+        };
+        if starts_section && offset > 0 {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    starts.push(text.len());
+
+    starts
+        .windows(2)
+        .filter_map(|bounds| {
+            let section = &text[bounds[0]..bounds[1]];
+            let trimmed = section.trim();
+            if trimmed.is_empty() {
+                return None;
+            }
+            let start = bounds[0] + (section.len() - section.trim_start().len());
+            Some(start..start + trimmed.len())
+        })
+        .collect()
+}
+
+/// A section and the chunks it is cut into
+#[derive(Debug, Clone, PartialEq)]
+struct SectionPlan {
+    /// Byte range in the document
+    range: Range<usize>,
+    /// Token windows over the section's tokens
+    windows: Vec<Range<usize>>,
+    /// Byte range of each window
+    chunks: Vec<Range<usize>>,
+    tokens: usize,
+}
+
+/// Combine child vectors into a parent vector
+fn aggregate(children: &[Vec<f32>], weights: &[f32], aggregation: Aggregation) -> Vec<f32> {
+    match aggregation {
+        Aggregation::Tokens => combine(children, weights),
+        Aggregation::Mean => combine(children, &vec![1.0; children.len()]),
+        Aggregation::Max => {
+            let dim = children.first().map_or(0, Vec::len);
+            let max: Vec<f32> = (0..dim)
+                .map(|d| {
+                    children
+                        .iter()
+                        .map(|c| c[d])
+                        .fold(f32::NEG_INFINITY, f32::max)
+                })
+                .collect();
+            combine(&[max], &[1.0])
+        }
+    }
+}
+
+/// Build the tree from the sections and the vectors of all their chunks, in
+/// order
+fn assemble(
+    text: &str,
+    plan: &[SectionPlan],
+    chunk_vectors: Vec<Vec<f32>>,
+    aggregation: Aggregation,
+) -> DocumentTree {
+    let dim = chunk_vectors.first().map_or(0, Vec::len);
+    let table = utf16_table(text);
+    let sections = plan.len();
+    let mut tree = DocumentTree {
+        dim,
+        levels: vec![DOCUMENT],
+        parents: vec![-1],
+        starts: vec![0],
+        ends: vec![table[text.len()]],
+        vectors: Vec::new(),
+    };
+
+    let mut section_vectors = Vec::with_capacity(sections);
+    let mut chunk_vectors = chunk_vectors.into_iter();
+    let mut chunk_nodes = Vec::new();
+    for (s, section) in plan.iter().enumerate() {
+        tree.levels.push(SECTION);
+        tree.parents.push(0);
+        tree.starts.push(table[section.range.start]);
+        tree.ends.push(table[section.range.end]);
+
+        let vectors: Vec<Vec<f32>> = chunk_vectors.by_ref().take(section.chunks.len()).collect();
+        let weights = overlap_weights(&section.windows, section.tokens);
+        section_vectors.push(aggregate(&vectors, &weights, aggregation));
+        for (chunk, vector) in section.chunks.iter().zip(vectors) {
+            chunk_nodes.push(((s + 1) as i32, chunk.clone(), vector));
+        }
+    }
+    let section_tokens: Vec<f32> = plan.iter().map(|s| s.tokens as f32).collect();
+    let document = aggregate(&section_vectors, &section_tokens, aggregation);
+
+    tree.vectors.extend(document);
+    section_vectors
+        .iter()
+        .for_each(|v| tree.vectors.extend_from_slice(v));
+    for (parent, chunk, vector) in chunk_nodes {
+        tree.levels.push(CHUNK);
+        tree.parents.push(parent);
+        tree.starts.push(table[chunk.start]);
+        tree.ends.push(table[chunk.end]);
+        tree.vectors.extend(vector);
+    }
+    tree
+}
+
+/// Embeddings of a document, its sections and their chunks, from
+/// `DocumentEmbedder.embed()`
+#[wasm_bindgen]
+pub struct DocumentTree {
+    dim: usize,
+    levels: Vec<u8>,
+    parents: Vec<i32>,
+    starts: Vec<u32>,
+    ends: Vec<u32>,
+    /// One row of `dim` per node
+    vectors: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl DocumentTree {
+    /// Number of nodes: the document, its sections and their chunks
+    #[wasm_bindgen(getter)]
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn dimension(&self) -> usize {
+        self.dim
+    }
+
+    /// Level of each node: 0 document, 1 section, 2 chunk
+    #[wasm_bindgen(getter)]
+    pub fn levels(&self) -> Uint8Array {
+        Uint8Array::from(&self.levels[..])
+    }
+
+    /// Parent node of each node (-1 for the document)
+    #[wasm_bindgen(getter)]
+    pub fn parents(&self) -> Int32Array {
+        Int32Array::from(&self.parents[..])
+    }
+
+    /// Start of each node's text as a UTF-16 index
+    #[wasm_bindgen(getter)]
+    pub fn starts(&self) -> Uint32Array {
+        Uint32Array::from(&self.starts[..])
+    }
+
+    /// End (exclusive) of each node's text as a UTF-16 index
+    #[wasm_bindgen(getter)]
+    pub fn ends(&self) -> Uint32Array {
+        Uint32Array::from(&self.ends[..])
+    }
+
+    /// All node vectors back to back, `dimension` values each
+    #[wasm_bindgen(getter)]
+    pub fn vectors(&self) -> Float32Array {
+        Float32Array::from(&self.vectors[..])
+    }
+
+    /// Vector of node `i`
+    #[wasm_bindgen]
+    pub fn vector(&self, i: usize) -> Option<Float32Array> {
+        (i < self.len())
+            .then(|| Float32Array::from(&self.vectors[i * self.dim..(i + 1) * self.dim]))
+    }
+
+    /// Nodes whose parent is node `i`
+    #[wasm_bindgen]
+    pub fn children(&self, i: usize) -> Uint32Array {
+        let children: Vec<u32> = (0..self.len() as u32)
+            .filter(|&j| self.parents[j as usize] == i as i32)
+            .collect();
+        Uint32Array::from(&children[..])
+    }
+}
+
+/// Builds chunk, section and document embeddings of texts
+#[wasm_bindgen]
+pub struct DocumentEmbedder {
+    options: HierarchyOptions,
+}
+
+impl DocumentEmbedder {
+    /// Cut `text` into sections and chunks, given its token offsets
+    fn plan(&self, text: &str, offsets: &[(usize, usize)], max_window: usize) -> Vec<SectionPlan> {
+        let chunk = self
+            .options
+            .chunk_tokens
+            .unwrap_or(max_window)
+            .clamp(1, max_window);
+        let stride = self.options.stride.unwrap_or(chunk).clamp(1, chunk);
+        section_ranges(text, self.options.sections)
+            .into_iter()
+            .filter_map(|range| {
+                // Offsets are in text order, so each section's tokens are a
+                // contiguous run found by binary search
+                let first = offsets.partition_point(|&(s, _)| s < range.start);
+                let count = offsets[first..].partition_point(|&(_, e)| e <= range.end);
+                let tokens = &offsets[first..first + count];
+                // Offsets come from the tokenizer; drop any window that
+                // doesn't map to a valid slice rather than panicking on it
+                let mut windows = window_ranges(tokens.len(), chunk, stride);
+                windows.retain(|w| text.get(tokens[w.start].0..tokens[w.end - 1].1).is_some());
+                if windows.is_empty() {
+                    return None;
+                }
+                let chunks = windows
+                    .iter()
+                    .map(|w| tokens[w.start].0..tokens[w.end - 1].1)
+                    .collect();
+                Some(SectionPlan {
+                    range,
+                    windows,
+                    chunks,
+                    tokens: tokens.len(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl DocumentEmbedder {
+    /// Options: `{ chunk_tokens = model limit, stride = chunk_tokens,
+    /// sections = "blank_line" | "heading", aggregation = "tokens" | "mean" |
+    /// "max" }`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: &JsValue) -> Result<DocumentEmbedder, JsValue> {
+        let options: HierarchyOptions = parse_options(options)?;
+        if options.chunk_tokens == Some(0) || options.stride == Some(0) {
+            return Err(JsValue::from_str(
+                "chunk_tokens and stride must be at least 1",
+            ));
+        }
+        Ok(DocumentEmbedder { options })
+    }
+
+    /// Embed `text` as a tree of chunks, sections and the whole document
+    ///
+    /// A text with no content (only whitespace) gives an empty tree.
+    #[wasm_bindgen]
+    pub fn embed(&self, engine: &EmbeddingEngine, text: &str) -> Result<DocumentTree, JsValue> {
+        let max_window = engine
+            .tokenizer
+            .as_ref()
+            .map(|tokenizer| max_window_tokens(tokenizer, engine.role_prefix(TextRole::Document)))
+            .ok_or_else(|| {
+                JsValue::from_str("Tokenizer not loaded. Call load_embedded() first.")
+            })?;
+        let offsets = engine.document_offsets(text)?;
+        let plan = self.plan(text, &offsets, max_window);
+        if plan.is_empty() {
+            return Ok(DocumentTree {
+                dim: 0,
+                levels: Vec::new(),
+                parents: Vec::new(),
+                starts: Vec::new(),
+                ends: Vec::new(),
+                vectors: Vec::new(),
+            });
+        }
+        let texts: Vec<String> = plan
+            .iter()
+            .flat_map(|section| section.chunks.iter().map(|c| text[c.clone()].to_string()))
+            .collect();
+        let chunk_vectors = engine.embed_internal(&texts)?;
+        Ok(assemble(
+            text,
+            &plan,
+            chunk_vectors,
+            self.options.aggregation,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section_ranges() {
+        let text = "# Intro\nFirst para.\n\n  Second para.\n\n\n# Usage\nRun it.\n";
+        let sections = |breaks| -> Vec<&str> {
+            section_ranges(text, breaks)
+                .into_iter()
+                .map(|r| &text[r])
+                .collect()
+        };
+        assert_eq!(
+            sections(SectionBreak::BlankLine),
+            vec!["# Intro\nFirst para.", "Second para.", "# Usage\nRun it."]
+        );
+        assert_eq!(
+            sections(SectionBreak::Heading),
+            vec!["# Intro\nFirst para.\n\n  Second para.", "# Usage\nRun it."]
+        );
+        assert!(section_ranges(" \n\n", SectionBreak::BlankLine).is_empty());
+    }
+
+    #[test]
+    fn test_plan_splits_tokens_by_section() {
+        let text = "aa bb cc\n\ndd ee";
+        let offsets = [(0, 2), (3, 5), (6, 8), (10, 12), (13, 15)];
+        let embedder = DocumentEmbedder {
+            options: HierarchyOptions {
+                chunk_tokens: Some(2),
+                ..Default::default()
+            },
+        };
+        let plan = embedder.plan(text, &offsets, 128);
+        assert_eq!(plan.len(), 2);
+        assert_eq!((plan[0].tokens, plan[1].tokens), (3, 2));
+        assert_eq!(plan[0].chunks, vec![0..5, 6..8]);
+        assert_eq!(plan[1].chunks, vec![10..15]);
+    }
+
+    #[test]
+    fn test_tree_links_and_vectors() {
+        let text = "ab cd\n\nef gh";
+        let plan = vec![
+            SectionPlan {
+                range: 0..5,
+                windows: vec![0..1, 1..2],
+                chunks: vec![0..2, 3..5],
+                tokens: 2,
+            },
+            SectionPlan {
+                range: 7..12,
+                windows: vec![0..1, 1..2],
+                chunks: vec![7..9, 10..12],
+                tokens: 2,
+            },
+        ];
+        let chunks = vec![
+            vec![1.0, 0.0],
+            vec![0.0, 1.0],
+            vec![1.0, 0.0],
+            vec![1.0, 0.0],
+        ];
+        let tree = assemble(text, &plan, chunks, Aggregation::Tokens);
+        assert_eq!(tree.levels, vec![0, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(tree.parents, vec![-1, 0, 0, 1, 1, 2, 2]);
+        assert_eq!(tree.starts, vec![0, 0, 7, 0, 3, 7, 10]);
+        assert_eq!(tree.ends, vec![12, 5, 12, 2, 5, 9, 12]);
+        assert_eq!(tree.vectors.len(), 7 * 2);
+        // Section 1 is the normalized sum of its two chunks
+        let h = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((tree.vectors[2] - h).abs() < 1e-6 && (tree.vectors[3] - h).abs() < 1e-6);
+
+        let max = aggregate(
+            &[vec![1.0, -2.0], vec![-1.0, 0.0]],
+            &[1.0, 1.0],
+            Aggregation::Max,
+        );
+        assert_eq!(max, vec![1.0, 0.0]);
+    }
+}
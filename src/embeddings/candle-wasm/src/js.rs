@@ -0,0 +1,24 @@
+//! Conversions for plain JS option objects
+//!
+//! Options arrive as ordinary objects (`{ window_tokens: 32 }`); they are
+//! round-tripped through `JSON.stringify` and deserialized with serde, so each
+//! options struct is just a `#[derive(Deserialize)]` with defaults.
+
+use serde::de::DeserializeOwned;
+use wasm_bindgen::prelude::*;
+
+/// Parse an optional options object; `undefined`/`null` give the defaults
+pub(crate) fn parse_options<T: DeserializeOwned + Default>(value: &JsValue) -> Result<T, JsValue> {
+    if value.is_undefined() || value.is_null() {
+        return Ok(T::default());
+    }
+    let json = js_sys::JSON::stringify(value)?
+        .as_string()
+        .ok_or_else(|| JsValue::from_str("Options must be a plain object"))?;
+    options_from_json(&json)
+}
+
+/// Deserialize options from their JSON text
+pub(crate) fn options_from_json<T: DeserializeOwned>(json: &str) -> Result<T, JsValue> {
+    serde_json::from_str(json).map_err(|e| JsValue::from_str(&format!("Invalid options: {}", e)))
+}
@@ -0,0 +1,323 @@
+//! Fused and blocked CPU kernels
+//!
+//! Some steps are a chain of candle ops that each materialize a full
+//! `[batch, seq, hidden]` tensor. When the inputs are in contiguous CPU
+//! memory (always the case in WASM) these run as a single pass over the data
+//! instead; other devices keep the op chain.
+//!
+//! `matmul_bt()` is the encoder's matmul for WASM, where candle's generic CPU
+//! path isn't tuned for the memory model. Built with
+//! `-C target-feature=+simd128` it uses SIMD multiply-adds; other builds use
+//! the same blocking with scalar accumulators. `matmul_bt_f16()` and
+//! `matmul_bt_expanded()` take weights in a compact format and expand them
+//! to f32 a block at a time, accumulating in f32.
+
+use candle_core::{Storage, Tensor, WithDType};
+use half::f16;
+use half::slice::HalfFloatSliceExt;
+
+/// Run `f` on the contiguous CPU data of `tensor`, or return None when it is
+/// on another device, strided, or of another dtype
+pub(crate) fn with_cpu_slice<T: WithDType, R>(
+    tensor: &Tensor,
+    f: impl FnOnce(&[T]) -> R,
+) -> Option<R> {
+    let (storage, layout) = tensor.storage_and_layout();
+    let (start, end) = layout.contiguous_offsets()?;
+    match &*storage {
+        Storage::Cpu(cpu) => Some(f(&cpu.as_slice::<T>().ok()?[start..end])),
+        _ => None,
+    }
+}
+
+/// Mean of the unmasked token vectors of each sequence
+///
+/// `hidden` is `[batch, seq, dim]` and `mask` `[batch, seq]`, both row-major.
+/// A sequence with no unmasked tokens pools to zeros.
+fn masked_mean(hidden: &[f32], mask: &[u8], seq: usize, dim: usize) -> Vec<f32> {
+    let batch = mask.len() / seq.max(1);
+    let mut pooled = vec![0.0f32; batch * dim];
+    for (b, out) in pooled.chunks_exact_mut(dim.max(1)).enumerate().take(batch) {
+        let mut count = 0usize;
+        for s in 0..seq {
+            if mask[b * seq + s] == 0 {
+                continue;
+            }
+            count += 1;
+            let row = &hidden[(b * seq + s) * dim..(b * seq + s + 1) * dim];
+            for (o, v) in out.iter_mut().zip(row) {
+                *o += v;
+            }
+        }
+        if count > 0 {
+            let scale = 1.0 / count as f32;
+            out.iter_mut().for_each(|o| *o *= scale);
+        }
+    }
+    pooled
+}
+
+/// `masked_mean()` over an f32 `[batch, seq, dim]` hidden state and a u8
+/// `[batch, seq]` mask, giving `[batch, dim]`; None when the tensors aren't
+/// contiguous CPU tensors of those types
+pub(crate) fn mean_pool(hidden: &Tensor, mask: &Tensor) -> candle_core::Result<Option<Tensor>> {
+    let (batch, seq, dim) = hidden.dims3()?;
+    if mask.dims2()? != (batch, seq) {
+        candle_core::bail!(
+            "mask shape {:?} doesn't match hidden states {:?}",
+            mask.dims(),
+            hidden.dims()
+        );
+    }
+    let pooled = with_cpu_slice::<f32, _>(hidden, |hidden| {
+        with_cpu_slice::<u8, _>(mask, |mask| masked_mean(hidden, mask, seq, dim))
+    })
+    .flatten();
+    pooled
+        .map(|pooled| Tensor::from_vec(pooled, (batch, dim), hidden.device()))
+        .transpose()
+}
+
+/// Rows of `b` kept in cache while every row of `a` is multiplied with them
+///
+/// Rows are not also split along k: at encoder widths (384 to 1536) that
+/// measured no faster.
+const GEMM_BLOCK: usize = 64;
+
+/// `a · bᵀ` for row-major `a` `[n, k]` and `b` `[m, k]`, giving `[n, m]`
+///
+/// Both operands are read along rows, so each inner loop is a contiguous dot
+/// product. Blocks of `GEMM_BLOCK` rows of `b` are reused across all of `a`
+/// while they are in cache, and 4x4 output tiles share every load between
+/// four dot products.
+pub(crate) fn matmul_bt(a: &[f32], b: &[f32], n: usize, m: usize, k: usize) -> Vec<f32> {
+    debug_assert!(a.len() == n * k && b.len() == m * k);
+    let mut c = vec![0.0f32; n * m];
+    if k == 0 {
+        return c;
+    }
+    for block in (0..m).step_by(GEMM_BLOCK) {
+        let block_end = (block + GEMM_BLOCK).min(m);
+        gemm_block(a, &b[block * k..block_end * k], &mut c, block, m, k);
+    }
+    c
+}
+
+/// `matmul_bt()` with `b` stored as f16
+///
+/// Each block of `b` is upcast into an f32 scratch buffer just before it is
+/// used, so products and sums are all f32 and only one block is ever held at
+/// full precision.
+pub(crate) fn matmul_bt_f16(a: &[f32], b: &[f16], n: usize, m: usize, k: usize) -> Vec<f32> {
+    debug_assert!(a.len() == n * k && b.len() == m * k);
+    matmul_bt_expanded(a, n, m, k, |rows, out| {
+        b[rows.start * k..rows.end * k].convert_to_f32_slice(out)
+    })
+}
+
+/// `matmul_bt()` with `b` in a compact format: `expand(rows, out)` writes
+/// rows `rows` of `b` as f32 into `out`, one `GEMM_BLOCK` at a time
+pub(crate) fn matmul_bt_expanded(
+    a: &[f32],
+    n: usize,
+    m: usize,
+    k: usize,
+    mut expand: impl FnMut(std::ops::Range<usize>, &mut [f32]),
+) -> Vec<f32> {
+    debug_assert!(a.len() == n * k);
+    let mut c = vec![0.0f32; n * m];
+    if k == 0 {
+        return c;
+    }
+    let mut scratch = vec![0.0f32; GEMM_BLOCK.min(m) * k];
+    for block in (0..m).step_by(GEMM_BLOCK) {
+        let block_end = (block + GEMM_BLOCK).min(m);
+        let rows = &mut scratch[..(block_end - block) * k];
+        expand(block..block_end, rows);
+        gemm_block(a, rows, &mut c, block, m, k);
+    }
+    c
+}
+
+/// Columns `col..col + rows(b)` of `c` (`[n, m]`) from all of `a` and one
+/// block of rows of `b`
+fn gemm_block(a: &[f32], b: &[f32], c: &mut [f32], col: usize, m: usize, k: usize) {
+    let n = a.len() / k;
+    let cols_in_block = b.len() / k;
+    let a_row = |i: usize| &a[i * k..(i + 1) * k];
+    let b_row = |j: usize| &b[j * k..(j + 1) * k];
+    for i in (0..n).step_by(4) {
+        let rows = (n - i).min(4);
+        for j in (0..cols_in_block).step_by(4) {
+            let cols = (cols_in_block - j).min(4);
+            let out = col + j;
+            if rows == 4 && cols == 4 {
+                let tile = tile_4x4(
+                    [a_row(i), a_row(i + 1), a_row(i + 2), a_row(i + 3)],
+                    [b_row(j), b_row(j + 1), b_row(j + 2), b_row(j + 3)],
+                );
+                for (r, values) in tile.iter().enumerate() {
+                    c[(i + r) * m + out..(i + r) * m + out + 4].copy_from_slice(values);
+                }
+            } else {
+                for r in 0..rows {
+                    for s in 0..cols {
+                        c[(i + r) * m + out + s] = dot(a_row(i + r), b_row(j + s));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+fn tile_4x4(a: [&[f32]; 4], b: [&[f32]; 4]) -> [[f32; 4]; 4] {
+    use core::arch::wasm32::*;
+
+    let k = a[0].len();
+    let chunks = k / 4;
+    let mut acc = [[f32x4_splat(0.0); 4]; 4];
+    for p in 0..chunks {
+        // SAFETY: every row has k >= (p + 1) * 4 elements, and v128 loads
+        // have no alignment requirement
+        let load = |row: &[f32]| unsafe { v128_load(row.as_ptr().add(p * 4) as *const v128) };
+        let av = a.map(load);
+        let bv = b.map(load);
+        for (acc_row, &x) in acc.iter_mut().zip(&av) {
+            for (acc, &y) in acc_row.iter_mut().zip(&bv) {
+                *acc = f32x4_add(*acc, f32x4_mul(x, y));
+            }
+        }
+    }
+    let mut out = [[0.0f32; 4]; 4];
+    for r in 0..4 {
+        for s in 0..4 {
+            let v = acc[r][s];
+            let mut sum = f32x4_extract_lane::<0>(v)
+                + f32x4_extract_lane::<1>(v)
+                + f32x4_extract_lane::<2>(v)
+                + f32x4_extract_lane::<3>(v);
+            for p in chunks * 4..k {
+                sum += a[r][p] * b[s][p];
+            }
+            out[r][s] = sum;
+        }
+    }
+    out
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+fn tile_4x4(a: [&[f32]; 4], b: [&[f32]; 4]) -> [[f32; 4]; 4] {
+    let mut out = [[0.0f32; 4]; 4];
+    for p in 0..a[0].len() {
+        let bv = [b[0][p], b[1][p], b[2][p], b[3][p]];
+        for (out_row, a_row) in out.iter_mut().zip(&a) {
+            let x = a_row[p];
+            for (o, y) in out_row.iter_mut().zip(&bv) {
+                *o += x * y;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    use core::arch::wasm32::*;
+
+    let chunks = a.len() / 4;
+    let mut acc = f32x4_splat(0.0);
+    for p in 0..chunks {
+        // SAFETY: both slices have at least (p + 1) * 4 elements
+        let (x, y) = unsafe {
+            (
+                v128_load(a.as_ptr().add(p * 4) as *const v128),
+                v128_load(b.as_ptr().add(p * 4) as *const v128),
+            )
+        };
+        acc = f32x4_add(acc, f32x4_mul(x, y));
+    }
+    let mut sum = f32x4_extract_lane::<0>(acc)
+        + f32x4_extract_lane::<1>(acc)
+        + f32x4_extract_lane::<2>(acc)
+        + f32x4_extract_lane::<3>(acc);
+    for p in chunks * 4..a.len() {
+        sum += a[p] * b[p];
+    }
+    sum
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::{DType, Device};
+
+    #[test]
+    fn test_masked_mean() {
+        // batch 2, seq 3, dim 2; the second sequence is all padding
+        let hidden = [1.0, 0.0, 0.0, 1.0, 9.0, 9.0, 5.0, 5.0, 5.0, 5.0, 5.0, 5.0];
+        let pooled = masked_mean(&hidden, &[1, 1, 0, 0, 0, 0], 3, 2);
+        assert_eq!(pooled, vec![0.5, 0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_mean_pool_matches_op_chain() {
+        let device = Device::Cpu;
+        let hidden = Tensor::arange(0f32, 24.0, &device)
+            .unwrap()
+            .reshape((2, 4, 3))
+            .unwrap();
+        let mask = Tensor::new(&[[1u8, 1, 1, 0], [1, 0, 0, 0]], &device).unwrap();
+        let fused = mean_pool(&hidden, &mask).unwrap().unwrap();
+
+        let weights = mask.to_dtype(DType::F32).unwrap().unsqueeze(2).unwrap();
+        let expected = hidden
+            .broadcast_mul(&weights)
+            .unwrap()
+            .sum(1)
+            .unwrap()
+            .broadcast_div(&weights.sum(1).unwrap())
+            .unwrap();
+        assert_eq!(
+            fused.to_vec2::<f32>().unwrap(),
+            expected.to_vec2::<f32>().unwrap()
+        );
+
+        // Strided inputs fall back to the op chain
+        let strided = hidden.narrow(2, 0, 2).unwrap();
+        assert!(mean_pool(&strided, &mask).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_matmul_bt() {
+        // Edge tiles in both dimensions, more than one block of b, k % 4 != 0
+        let (n, m, k) = (6, GEMM_BLOCK + 3, 7);
+        let a: Vec<f32> = (0..n * k).map(|i| (i % 13) as f32 - 6.0).collect();
+        let b: Vec<f32> = (0..m * k).map(|i| (i % 7) as f32 * 0.5).collect();
+        let c = matmul_bt(&a, &b, n, m, k);
+        for i in 0..n {
+            for j in 0..m {
+                let expected: f32 = (0..k).map(|p| a[i * k + p] * b[j * k + p]).sum();
+                assert!((c[i * m + j] - expected).abs() < 1e-4, "{} {}", i, j);
+            }
+        }
+        assert_eq!(matmul_bt(&[], &[], 2, 3, 0), vec![0.0; 6]);
+    }
+
+    #[test]
+    fn test_matmul_bt_f16_accumulates_in_f32() {
+        // Values exact in f16, and a k large enough that an f16 accumulator
+        // would round: 4096 * 0.75 * 1.5 needs more than f16's 11 bits
+        let (n, m, k) = (5, GEMM_BLOCK + 6, 4096);
+        let a = vec![0.75f32; n * k];
+        let b = vec![f16::from_f32(1.5); m * k];
+        let b32: Vec<f32> = b.iter().map(|x| x.to_f32()).collect();
+        assert_eq!(matmul_bt_f16(&a, &b, n, m, k), matmul_bt(&a, &b32, n, m, k));
+        assert_eq!(matmul_bt_f16(&a, &b, n, m, k)[0], 4608.0);
+    }
+}
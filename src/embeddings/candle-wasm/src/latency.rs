@@ -0,0 +1,227 @@
+//! Latency histograms for embedding calls (`latency_stats()`)
+//!
+//! Every call that runs the model is timed into a histogram with log-spaced
+//! buckets, 8 per doubling from 10µs (about 9% wide), so percentiles come
+//! out of a fixed 1.5KB however many calls are recorded. Averages hide the
+//! slow tail that users feel; p90/p99 don't, and apps no longer need to wrap
+//! every call in their own timers.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{clock, EmbeddingEngine};
+
+/// Lower edge of the first bucket
+const MIN_MS: f64 = 0.01;
+
+const BUCKETS_PER_DOUBLING: f64 = 8.0;
+
+/// 24 doublings from `MIN_MS` reach about 168s; slower calls share the last
+/// bucket
+const BUCKET_COUNT: usize = 8 * 24;
+
+/// Call latencies since creation or the last reset
+#[derive(Debug, Clone)]
+pub(crate) struct LatencyHistogram {
+    buckets: Vec<u64>,
+    calls: u64,
+    texts: u64,
+    total_ms: f64,
+    max_ms: f64,
+    /// `now_ms()` when the first call since the last reset started
+    first_ms: Option<f64>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: vec![0; BUCKET_COUNT],
+            calls: 0,
+            texts: 0,
+            total_ms: 0.0,
+            max_ms: 0.0,
+            first_ms: None,
+        }
+    }
+}
+
+/// Bucket holding `ms`: the first whose upper bound is at least `ms`
+fn bucket(ms: f64) -> usize {
+    if ms <= MIN_MS {
+        return 0;
+    }
+    (((ms / MIN_MS).log2() * BUCKETS_PER_DOUBLING).ceil() as usize).min(BUCKET_COUNT - 1)
+}
+
+fn upper_bound(bucket: usize) -> f64 {
+    MIN_MS * (bucket as f64 / BUCKETS_PER_DOUBLING).exp2()
+}
+
+impl LatencyHistogram {
+    /// Record a call of `texts` texts that took `ms` and ended at `end_ms`
+    pub(crate) fn record(&mut self, ms: f64, texts: usize, end_ms: f64) {
+        self.buckets[bucket(ms)] += 1;
+        self.calls += 1;
+        self.texts += texts as u64;
+        self.total_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+        self.first_ms.get_or_insert(end_ms - ms);
+    }
+
+    /// Nearest-rank percentile, as its bucket's upper bound (0 for no calls)
+    fn percentile(&self, p: f64) -> f64 {
+        if self.calls == 0 {
+            return 0.0;
+        }
+        let rank = ((p / 100.0 * self.calls as f64).ceil() as u64).clamp(1, self.calls);
+        let mut seen = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return upper_bound(i).min(self.max_ms);
+            }
+        }
+        self.max_ms
+    }
+
+    fn stats(&self, now_ms: f64) -> LatencyStats {
+        let elapsed_s = self.first_ms.map_or(0.0, |first| (now_ms - first) / 1000.0);
+        let per_second = |n: u64| {
+            if elapsed_s > 0.0 {
+                n as f64 / elapsed_s
+            } else {
+                0.0
+            }
+        };
+        LatencyStats {
+            calls: self.calls,
+            texts: self.texts,
+            mean_ms: self.total_ms / self.calls.max(1) as f64,
+            p50_ms: self.percentile(50.0),
+            p90_ms: self.percentile(90.0),
+            p99_ms: self.percentile(99.0),
+            max_ms: self.max_ms,
+            calls_per_second: per_second(self.calls),
+            texts_per_second: per_second(self.texts),
+        }
+    }
+}
+
+/// Embedding call latencies and throughput, from `latency_stats()`
+#[wasm_bindgen]
+pub struct LatencyStats {
+    calls: u64,
+    texts: u64,
+    mean_ms: f64,
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+    calls_per_second: f64,
+    texts_per_second: f64,
+}
+
+#[wasm_bindgen]
+impl LatencyStats {
+    /// Calls recorded
+    #[wasm_bindgen(getter)]
+    pub fn calls(&self) -> f64 {
+        self.calls as f64
+    }
+
+    /// Texts embedded by those calls
+    #[wasm_bindgen(getter)]
+    pub fn texts(&self) -> f64 {
+        self.texts as f64
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn mean_ms(&self) -> f64 {
+        self.mean_ms
+    }
+
+    /// Median latency (within one bucket, about 9%)
+    #[wasm_bindgen(getter)]
+    pub fn p50_ms(&self) -> f64 {
+        self.p50_ms
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn p90_ms(&self) -> f64 {
+        self.p90_ms
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn p99_ms(&self) -> f64 {
+        self.p99_ms
+    }
+
+    /// Slowest call, exactly
+    #[wasm_bindgen(getter)]
+    pub fn max_ms(&self) -> f64 {
+        self.max_ms
+    }
+
+    /// Calls per second of wall time since the first call
+    #[wasm_bindgen(getter)]
+    pub fn calls_per_second(&self) -> f64 {
+        self.calls_per_second
+    }
+
+    /// Texts per second of wall time since the first call
+    #[wasm_bindgen(getter)]
+    pub fn texts_per_second(&self) -> f64 {
+        self.texts_per_second
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Latency percentiles and throughput of the embedding calls since the
+    /// engine was created or `reset_latency_stats()`
+    #[wasm_bindgen]
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.latency.borrow().stats(clock::now_ms())
+    }
+
+    /// Start the latency histogram over
+    #[wasm_bindgen]
+    pub fn reset_latency_stats(&self) {
+        *self.latency.borrow_mut() = LatencyHistogram::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_bound_latencies() {
+        for ms in [0.005, 0.01, 0.37, 1.0, 12.5, 480.0, 1e9] {
+            let b = bucket(ms);
+            assert!(ms <= upper_bound(b) || b == BUCKET_COUNT - 1);
+            assert!(b == 0 || ms > upper_bound(b - 1));
+        }
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut histogram = LatencyHistogram::default();
+        for i in 1..=100 {
+            histogram.record(i as f64, 2, 1000.0 + i as f64);
+        }
+        let stats = histogram.stats(2000.0);
+        assert_eq!((stats.calls, stats.texts), (100, 200));
+        assert_eq!(stats.mean_ms, 50.5);
+        assert_eq!(stats.max_ms, 100.0);
+        for (p, exact) in [
+            (stats.p50_ms, 50.0),
+            (stats.p90_ms, 90.0),
+            (stats.p99_ms, 99.0),
+        ] {
+            assert!(p >= exact && p <= exact * 1.1, "{} vs {}", p, exact);
+        }
+        // The first call started at 1000ms, 1s before now
+        assert_eq!(stats.calls_per_second, 100.0);
+        assert_eq!(LatencyHistogram::default().stats(0.0).p99_ms, 0.0);
+    }
+}
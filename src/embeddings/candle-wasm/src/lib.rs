@@ -7,6 +7,69 @@
 //! - Model weights embedded at compile time (zero runtime downloads)
 //! - Single WASM file contains everything
 //! - Works in all environments: Node.js, Bun, Bun compile, browsers
+//! - `StaticEmbedder` fast path for Model2Vec static embeddings (same API shape)
+//! - `HashEmbedder` model-free fallback (`hash-embedder` feature)
+//! - ONNX-exported models via `load_onnx()` (`onnx` feature)
+//! - Attention maps and per-token salience via `embed_with_attentions()`
+//! - Token-level match attribution via `explain_similarity()`
+//! - Query-relevant snippet spans via `best_spans()`
+//! - `encode_with_offsets()` and `embed_tokens()` with per-token source offsets
+//! - `compatibility_fingerprint()` to detect vectors from a different model
+//! - `self_test()` numerics check against built-in golden vectors
+//! - `on_inference(callback)` per-call stats for your own metrics pipeline
+//! - `set_log_level()` console diagnostics (`logging` feature)
+//! - `install_panic_hook()` readable panic messages (`panic-hook` feature)
+//! - `embed_batch_flat()`/`embed_batch_into()` for one contiguous row-major buffer
+//! - `set_memory_budget()` to split large batches instead of growing memory
+//! - `set_max_tokens_per_forward()` to pack forward passes by token count
+//! - `warmup()` primes the model and can auto-tune the micro-batch size
+//! - `autotune({ target_latency_ms })` picks micro-batch size and token cap per device
+//! - `export_model()` re-saves the weights, optionally as f16
+//! - `set_weight_precision("f16")` keeps weights in f16 with f32 accumulation
+//! - 4-bit group-quantized weights via `set_weight_precision("q4")` and
+//!   `export_model({ dtype: "q4" })`; `model_info()` reports what is loaded
+//! - `snapshot()`/`restore()` to bring back a configured engine after a reload
+//! - `set_normalization()` lowercasing/accent/Unicode overrides for tokenizer.json
+//! - `load_with_options()` special-token fixes and config.json overrides at load time
+//! - `set_preprocessing()` HTML, URL, emoji and whitespace cleanup before embedding
+//! - `for_preset()` pooling, prefixes and limits for known models, with
+//!   `embed_query()` for the query side; instruction prefixes are tokenized once
+//! - `embed_batch_with_tokens()` per-text token counts, truncation flags and ids
+//! - `embed_ids()` for token ids produced by another tokenizer or cache
+//! - `embed_utf8()`/`embed_batch_utf8()` for text already held as UTF-8 bytes
+//! - `BulkEmbedJob` chunked corpus embedding with checkpoints that survive a reload
+//! - `on_progress()` callback with done/total/elapsed/ETA between micro-batches
+//! - `set_limits()` batch-size and tokens-per-second limits with typed errors
+//!   for shared services
+//! - `embeddings_close()`/`max_abs_diff()` tolerance checks for parity between
+//!   builds, backends and reference outputs
+//! - `capabilities()` runtime SIMD/shared-memory detection, with
+//!   `js/capabilities.js` `select_artifact()` to load the SIMD build where it runs
+//! - RoBERTa-family checkpoints detected from config.json, and
+//!   `apply_sentence_transformers_config()` pooling/length/lowercasing from
+//!   sentence-transformers files (read automatically by `load_from_path()`)
+//! - `int8_scores()`/`int8_dot()` scoring of int8-quantized vectors without dequantizing
+//! - `pairwise_distances()` tiled distance matrices, optionally streamed per tile
+//! - `pairwise_top_k()` nearest neighbours per row without the full matrix
+//! - `search_batch()` top-k corpus matches for many queries in one blocked pass
+//! - `search_range()` every corpus vector above a similarity threshold
+//! - `ScoreCalibration` Platt scaling from raw similarity to match probability
+//! - `is_similar()` yes/no similarity with per-preset cutoffs or a fitted calibration
+//! - `Matcher` duplicate detection against existing items, tuned by confirm/reject feedback
+//! - `OnlineCentroid` decaying weighted-mean profile vectors with JSON state
+//! - `latency_stats()` p50/p90/p99 latency and throughput of embedding calls
+//! - `DocumentEmbedder` chunk/section/document embedding trees with parent links and spans
+//! - `cosine_similarity_normalized()` and `assume_normalized` dot-product scoring for unit vectors
+//! - `PqCodec` product quantization (train/encode/decode/asymmetric distance), 16-32x smaller vectors
+//! - `RandomProjection` seeded Gaussian/sparse dimension reduction, identical across clients
+//! - `LshIndex` random-hyperplane signatures and banded candidate lookup before exact rescoring, with a portable byte format
+//! - `simhash()` 64-bit text/embedding fingerprints and `hamming_near()` near-duplicate pairs
+//! - `benchmark_index()` recall@k, QPS and latency percentiles for any index
+//! - `ndcg_at_k()`, `mrr()` and `recall_at_k()` retrieval metrics over qrels
+//! - `evaluate_sts()` Pearson/Spearman correlation against STS gold scores
+//! - `embed_document()` one vector for long texts, stitching overlapping windows
+//! - `load_with_vocab()`, `load_with_sentencepiece()` and `load_with_merges()` for
+//!   checkpoints without a tokenizer.json
 //!
 //! ## Usage from JavaScript
 //! ```js
@@ -18,14 +81,166 @@
 //! const embedding = engine.embed("Hello world");
 //! const embeddings = engine.embed_batch(["Hello", "World"]);
 //! ```
+//!
+//! ## Edge runtimes (Cloudflare Workers)
+//! Workers cap isolate memory at 128MB, so the model should be streamed in
+//! rather than passed to `load()` as one buffer:
+//! ```js
+//! const engine = new EmbeddingEngine();
+//! engine.begin_streaming_load();
+//! const reader = (await env.MODELS.get("model.safetensors")).body.getReader();
+//! for (let r = await reader.read(); !r.done; r = await reader.read()) {
+//!     engine.push_model_chunk(r.value);
+//! }
+//! engine.finish_streaming_load(tokenizerBytes, configBytes);
+//! ```
+//!
+//! ## Browser file inputs
+//! Dropped or selected files can be passed directly; they are read in slices
+//! rather than as one large `ArrayBuffer`:
+//! ```js
+//! const engine = await EmbeddingEngine.load_from_blobs(modelFile, tokenizerFile, configFile);
+//! ```
+//!
+//! ## Deno
+//! The `--target web` bindings work unmodified; `load_from_path()` reads the
+//! model files with `Deno.readFile` (or `fetch` for URLs):
+//! ```js
+//! await init();
+//! const engine = await EmbeddingEngine.load_from_path('./models/all-MiniLM-L6-v2');
+//! ```
+//!
+//! ## Native GPU builds
+//! Server-side indexing jobs can run the same engine on a GPU by building with
+//! the `cuda` or `metal` feature:
+//! ```no_run
+//! use candle_core::Device;
+//! use candle_embeddings::EmbeddingEngine;
+//!
+//! let engine = EmbeddingEngine::with_device(Device::new_cuda(0)?);
+//! # Ok::<(), candle_core::Error>(())
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 use candle_core::{DType, Device, Tensor};
 use candle_nn::VarBuilder;
-use candle_transformers::models::bert::{BertModel, Config as BertConfig};
 use js_sys::{Array, Float32Array};
-use tokenizers::Tokenizer;
+use tokenizers::{Encoding, Tokenizer};
 use wasm_bindgen::prelude::*;
 
+#[macro_use]
+mod logging;
+
+mod attention;
+mod attribution;
+mod autodetect;
+mod batching;
+mod benchmark;
+mod bert;
+mod bulk;
+mod calibration;
+mod capabilities;
+mod centroid;
+mod clock;
+mod compare;
+mod document;
+mod errors;
+mod eval;
+mod export;
+mod fingerprint;
+#[cfg(feature = "hash-embedder")]
+mod hash_embedder;
+mod hierarchy;
+mod js;
+mod kernels;
+mod latency;
+mod limits;
+mod linear;
+mod load_options;
+mod loaders;
+mod long_text;
+mod lsh;
+mod matcher;
+mod memory;
+mod model_info;
+mod normalization;
+#[cfg(feature = "onnx")]
+mod onnx;
+mod pairwise;
+mod pq;
+mod prefix_tokens;
+mod preprocess;
+mod presets;
+mod pretokenized;
+mod progress;
+mod projection;
+mod q4;
+mod quantized;
+mod rng;
+mod self_test;
+mod simhash;
+mod snapshot;
+mod spans;
+mod static_embedder;
+mod streaming;
+mod telemetry;
+mod tfidf;
+mod token_cache;
+mod token_usage;
+mod tokenizer_files;
+mod tokens;
+mod tuning;
+mod utf8;
+
+pub use attention::AttentionEmbedding;
+pub use attribution::SimilarityExplanation;
+use batching::EmbeddingMatrix;
+pub use benchmark::{benchmark_index, BenchmarkReport};
+use bert::{BertModel, Config as BertConfig, LayerSelection};
+pub use bulk::{BulkChunk, BulkEmbedJob};
+pub use calibration::ScoreCalibration;
+pub use capabilities::{capabilities, Capabilities};
+pub use centroid::OnlineCentroid;
+pub use compare::{embeddings_close, max_abs_diff};
+#[cfg(feature = "panic-hook")]
+pub use errors::install_panic_hook;
+use errors::{js_error, ErrorKind};
+pub use eval::{mrr, ndcg_at_k, recall_at_k, StsReport};
+use fingerprint::{hash_bytes, ModelHasher};
+#[cfg(feature = "hash-embedder")]
+pub use hash_embedder::HashEmbedder;
+pub use hierarchy::{DocumentEmbedder, DocumentTree};
+pub use latency::LatencyStats;
+use linear::WeightPrecision;
+pub use loaders::ModelAssets;
+#[cfg(feature = "logging")]
+pub use logging::set_log_level;
+pub use lsh::LshIndex;
+pub use matcher::{Matcher, Matches};
+pub use model_info::ModelInfo;
+pub use pairwise::{
+    pairwise_distances, pairwise_top_k, search_batch, search_range, NearestNeighbors, RangeMatches,
+};
+pub use pq::PqCodec;
+pub use presets::model_presets;
+use presets::TextRole;
+pub use projection::RandomProjection;
+pub use quantized::{int8_dot, int8_scale, int8_scores, quantize_int8};
+pub use self_test::SelfTestReport;
+pub use simhash::{hamming_near, simhash};
+pub use spans::TextSpan;
+pub use static_embedder::StaticEmbedder;
+use streaming::SafetensorsStream;
+pub use telemetry::InferenceStats;
+pub use tfidf::{SparseVector, TfIdfVectorizer};
+pub use token_cache::CacheStats;
+use token_cache::TokenCache;
+pub use token_usage::EmbeddingsWithTokens;
+pub use tokens::{TokenEmbeddings, TokenizedText};
+pub use tuning::{TuningProfile, WarmupReport};
+
 // Model weights are NO LONGER embedded in WASM
 //
 // Previous design: 90MB WASM with model weights embedded via include_bytes!()
@@ -38,7 +253,7 @@ use wasm_bindgen::prelude::*;
 // The load() method accepts external model bytes for all environments:
 // - Node.js: Load from filesystem
 // - Bun: Load from filesystem
-// - Bun --compile: Load from embedded assets
+// - Bun --compile: Load from embedded assets (load_from_embedded_assets())
 // - Browser: Fetch from server
 
 /// Model configuration constants for all-MiniLM-L6-v2
@@ -52,15 +267,220 @@ pub enum PoolingStrategy {
     Mean,
     /// Use the [CLS] token embedding
     Cls,
+    /// Position-weighted mean (SGPT): token i gets weight i + 1, so later
+    /// tokens count more. Required by some decoder-based embedders.
+    WeightedMean,
+    /// Weight tokens by the final layer's attention from [CLS], averaged
+    /// over heads. Only available for the built-in BERT encoder.
+    Attention,
+}
+
+impl PoolingStrategy {
+    /// Parse a strategy name as accepted by `set_pooling()`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mean" => Some(PoolingStrategy::Mean),
+            "cls" => Some(PoolingStrategy::Cls),
+            "weighted_mean" => Some(PoolingStrategy::WeightedMean),
+            "attention" => Some(PoolingStrategy::Attention),
+            _ => None,
+        }
+    }
+
+    /// Name of the strategy as accepted by `set_pooling()`
+    pub fn name(&self) -> &'static str {
+        match self {
+            PoolingStrategy::Mean => "mean",
+            PoolingStrategy::Cls => "cls",
+            PoolingStrategy::WeightedMean => "weighted_mean",
+            PoolingStrategy::Attention => "attention",
+        }
+    }
+}
+
+/// Loaded encoder backend producing `[batch, seq, hidden]` token embeddings
+// One per engine, so variant size differences don't matter
+#[allow(clippy::large_enum_variant)]
+enum Encoder {
+    Bert(BertModel),
+    #[cfg(feature = "onnx")]
+    Onnx(onnx::OnnxEncoder),
+}
+
+impl Encoder {
+    /// Whether the model needs `token_type_ids`
+    fn uses_token_types(&self) -> bool {
+        match self {
+            Encoder::Bert(model) => model.config().uses_token_types(),
+            #[cfg(feature = "onnx")]
+            Encoder::Onnx(model) => model.uses_token_types(),
+        }
+    }
+
+    /// Hidden size (the embedding dimension)
+    fn hidden_size(&self) -> usize {
+        match self {
+            Encoder::Bert(model) => model.config().hidden_size,
+            #[cfg(feature = "onnx")]
+            Encoder::Onnx(model) => model.hidden_size(),
+        }
+    }
+
+    /// Number of token ids the model accepts, when known
+    fn vocab_size(&self) -> Option<usize> {
+        match self {
+            Encoder::Bert(model) => Some(model.config().vocab_size),
+            #[cfg(feature = "onnx")]
+            Encoder::Onnx(_) => None,
+        }
+    }
+
+    /// Id filled into padding positions
+    fn pad_token_id(&self) -> u32 {
+        match self {
+            Encoder::Bert(model) => model.config().pad_token_id as u32,
+            #[cfg(feature = "onnx")]
+            Encoder::Onnx(_) => 0,
+        }
+    }
+
+    /// Dimensions used to estimate activation memory
+    fn memory_shape(&self) -> memory::ModelShape {
+        match self {
+            Encoder::Bert(model) => memory::ModelShape::from_config(model.config()),
+            #[cfg(feature = "onnx")]
+            Encoder::Onnx(model) => memory::ModelShape::from_hidden_size(model.hidden_size()),
+        }
+    }
+
+    fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: Option<&Tensor>,
+        attention_mask: &Tensor,
+    ) -> candle_core::Result<Tensor> {
+        match self {
+            Encoder::Bert(model) => model.forward(input_ids, token_type_ids, Some(attention_mask)),
+            #[cfg(feature = "onnx")]
+            Encoder::Onnx(model) => model.forward(input_ids, token_type_ids, attention_mask),
+        }
+    }
+
+    /// Run the model, returning the hidden state selected by `layer` and, if
+    /// `want_attentions`, the final layer's attention probabilities
+    /// `[batch, heads, seq, seq]`
+    fn forward_selected(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: Option<&Tensor>,
+        attention_mask: &Tensor,
+        layer: LayerSelection,
+        want_attentions: bool,
+    ) -> candle_core::Result<(Tensor, Option<Tensor>)> {
+        if layer == LayerSelection::Last && !want_attentions {
+            return Ok((
+                self.forward(input_ids, token_type_ids, attention_mask)?,
+                None,
+            ));
+        }
+        match self {
+            Encoder::Bert(model) => {
+                let outputs = model.forward_with_outputs(
+                    input_ids,
+                    token_type_ids,
+                    Some(attention_mask),
+                    layer != LayerSelection::Last,
+                )?;
+                let hidden = match layer {
+                    LayerSelection::Last => outputs.last_hidden_state,
+                    _ => layer.select(&outputs.hidden_states)?,
+                };
+                Ok((hidden, want_attentions.then_some(outputs.attentions)))
+            }
+            #[cfg(feature = "onnx")]
+            Encoder::Onnx(_) => {
+                candle_core::bail!(
+                    "ONNX models only expose the final hidden state (no attentions or intermediate layers)"
+                )
+            }
+        }
+    }
+
+    /// Check that `layer` can be selected from this model
+    fn validate_layer(&self, layer: LayerSelection) -> candle_core::Result<()> {
+        match self {
+            Encoder::Bert(model) => layer.validate(model.config().num_hidden_layers),
+            #[cfg(feature = "onnx")]
+            Encoder::Onnx(_) => match layer {
+                LayerSelection::Last => Ok(()),
+                _ => candle_core::bail!("ONNX models only expose the final hidden state"),
+            },
+        }
+    }
+}
+
+/// Result of running the model on one tokenized batch
+struct BatchOutput {
+    /// Pooled, L2-normalized sentence embeddings
+    embeddings: EmbeddingMatrix,
+    /// Token embeddings `[batch, seq, hidden]` that were pooled (None for an
+    /// empty batch)
+    token_embeddings: Option<Tensor>,
+    /// Final layer attentions `[batch, heads, seq, seq]`, if requested
+    attentions: Option<Tensor>,
+    /// Time spent in the forward pass
+    inference_ms: f64,
+    /// Time spent pooling, normalizing and copying out
+    pooling_ms: f64,
 }
 
 /// WASM-compatible embedding engine
 #[wasm_bindgen]
 pub struct EmbeddingEngine {
-    model: Option<BertModel>,
+    model: Option<Encoder>,
     tokenizer: Option<Tokenizer>,
     device: Device,
     pooling: PoolingStrategy,
+    /// Hidden state the pooling runs over (final layer by default)
+    pooling_layer: LayerSelection,
+    /// In-progress chunked model load (see `begin_streaming_load`)
+    pending_model: Option<SafetensorsStream>,
+    /// Running hash of the chunks pushed so far
+    pending_hash: ModelHasher,
+    /// Tensors of the loaded SafeTensors model (shared with the model, kept
+    /// for `export_model`)
+    weights: Option<HashMap<String, Tensor>>,
+    /// Hash of the loaded weights, tokenizer and config (see `fingerprint`)
+    model_hash: Option<u64>,
+    /// Normalizer JSON set by `set_normalization`, replacing tokenizer.json's
+    custom_normalizer: Option<String>,
+    /// Recently used tokenizer encodings
+    token_cache: RefCell<TokenCache>,
+    /// Callback receiving `InferenceStats` (see `on_inference`)
+    on_inference: Option<js_sys::Function>,
+    /// Callback receiving batch progress (see `on_progress`)
+    on_progress: Option<js_sys::Function>,
+    /// Maximum working set per forward pass (see `set_memory_budget`)
+    memory_budget: Option<usize>,
+    /// Maximum padded tokens per forward pass (see `set_max_tokens_per_forward`)
+    max_tokens_per_forward: Option<usize>,
+    /// Maximum texts per forward pass (see `set_micro_batch_size`)
+    micro_batch_size: Option<usize>,
+    /// Text cleanup applied before embedding (see `set_preprocessing`)
+    preprocessing: Option<preprocess::PreprocessOptions>,
+    /// Storage type for the weight matrices of models loaded from now on
+    /// (see `set_weight_precision`)
+    weight_precision: WeightPrecision,
+    /// Model the engine was configured for (see `for_preset`)
+    preset: Option<&'static presets::ModelPreset>,
+    /// Per-call limits (see `set_limits`)
+    limits: limits::ResourceLimits,
+    /// Token bucket for `limits.max_tokens_per_second`
+    rate_limiter: RefCell<Option<limits::TokenBucket>>,
+    /// Score mapping for `is_similar` (see `set_calibration`)
+    calibration: Option<calibration::Platt>,
+    /// Timings of the calls that ran the model (see `latency_stats`)
+    latency: RefCell<latency::LatencyHistogram>,
 }
 
 #[wasm_bindgen]
@@ -73,6 +493,25 @@ impl EmbeddingEngine {
             tokenizer: None,
             device: Device::Cpu,
             pooling: PoolingStrategy::Mean,
+            pooling_layer: LayerSelection::Last,
+            pending_model: None,
+            pending_hash: ModelHasher::new(),
+            weights: None,
+            model_hash: None,
+            custom_normalizer: None,
+            token_cache: RefCell::new(TokenCache::new(token_cache::DEFAULT_CAPACITY)),
+            on_inference: None,
+            on_progress: None,
+            memory_budget: None,
+            max_tokens_per_forward: None,
+            micro_batch_size: None,
+            preprocessing: None,
+            weight_precision: WeightPrecision::F32,
+            preset: None,
+            limits: limits::ResourceLimits::default(),
+            rate_limiter: RefCell::new(None),
+            calibration: None,
+            latency: RefCell::new(latency::LatencyHistogram::default()),
         }
     }
 
@@ -92,25 +531,158 @@ impl EmbeddingEngine {
         tokenizer_bytes: &[u8],
         config_bytes: &[u8],
     ) -> Result<(), JsValue> {
-        // Parse config
-        let config: BertConfig = serde_json::from_slice(config_bytes)
-            .map_err(|e| JsValue::from_str(&format!("Failed to parse config: {}", e)))?;
-
         // Load model from SafeTensors
         let tensors = candle_core::safetensors::load_buffer(model_bytes, &self.device)
             .map_err(|e| JsValue::from_str(&format!("Failed to load safetensors: {}", e)))?;
 
+        let weights_hash = hash_bytes(model_bytes);
+        self.install(tensors, weights_hash, tokenizer_bytes, config_bytes)
+    }
+
+    /// Start a chunked model load
+    ///
+    /// For memory-capped runtimes such as Cloudflare Workers. Instead of holding
+    /// the whole SafeTensors file in memory, feed it piece by piece with
+    /// `push_model_chunk()` (e.g. from an R2/KV/fetch stream) and complete with
+    /// `finish_streaming_load()`. Each tensor is built as soon as its bytes arrive,
+    /// so peak memory stays close to the size of the weights themselves.
+    #[wasm_bindgen]
+    pub fn begin_streaming_load(&mut self) {
+        self.pending_model = Some(SafetensorsStream::new(&self.device));
+        self.pending_hash = ModelHasher::new();
+    }
+
+    /// Feed the next chunk of model.safetensors to a streaming load
+    #[wasm_bindgen]
+    pub fn push_model_chunk(&mut self, chunk: &[u8]) -> Result<(), JsValue> {
+        let stream = self.pending_model.as_mut().ok_or_else(|| {
+            JsValue::from_str("No streaming load in progress. Call begin_streaming_load() first.")
+        })?;
+
+        stream.push(chunk).map_err(|e| {
+            self.pending_model = None;
+            JsValue::from_str(&format!("Failed to load safetensors chunk: {}", e))
+        })?;
+        self.pending_hash.update(chunk);
+        Ok(())
+    }
+
+    /// Complete a streaming load with the tokenizer and config
+    ///
+    /// # Arguments
+    /// * `tokenizer_bytes` - tokenizer.json contents
+    /// * `config_bytes` - config.json contents
+    #[wasm_bindgen]
+    pub fn finish_streaming_load(
+        &mut self,
+        tokenizer_bytes: &[u8],
+        config_bytes: &[u8],
+    ) -> Result<(), JsValue> {
+        let stream = self.pending_model.take().ok_or_else(|| {
+            JsValue::from_str("No streaming load in progress. Call begin_streaming_load() first.")
+        })?;
+
+        let tensors = stream
+            .finish()
+            .map_err(|e| JsValue::from_str(&format!("Failed to load safetensors: {}", e)))?;
+
+        let weights_hash = std::mem::replace(&mut self.pending_hash, ModelHasher::new()).finish();
+        self.install(tensors, weights_hash, tokenizer_bytes, config_bytes)
+    }
+
+    /// Store the weight matrices of the next model loaded as "f32" (default),
+    /// "f16" or "q4"
+    ///
+    /// f16 halves the memory the weights take, for browsers where that is the
+    /// limit. Inference still accumulates in f32, so vectors differ from f32
+    /// storage only by the rounding of the weights. "q4" quantizes the dense
+    /// layers to 4 bits (see `export_model()` to serve them that way too) and
+    /// trades noticeable accuracy for a seventh of the size; `model_info()`
+    /// reports when it is in use. Either gets its own
+    /// `compatibility_fingerprint()`. Call before `load()` (or a streaming
+    /// load); the loaded model is not changed.
+    #[wasm_bindgen]
+    pub fn set_weight_precision(&mut self, precision: &str) -> Result<(), JsValue> {
+        self.weight_precision =
+            WeightPrecision::parse(precision).map_err(|e| JsValue::from_str(&e))?;
+        Ok(())
+    }
+
+    /// Build the model from loaded tensors and install it with the tokenizer
+    fn install(
+        &mut self,
+        mut tensors: HashMap<String, Tensor>,
+        weights_hash: u64,
+        tokenizer_bytes: &[u8],
+        config_bytes: &[u8],
+    ) -> Result<(), JsValue> {
+        // Parse config
+        let mut config: BertConfig = serde_json::from_slice(config_bytes)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse config: {}", e)))?;
+        config.weight_precision = self.weight_precision;
+        config
+            .family()
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        if let Some(preset) = self.preset {
+            preset
+                .check_dimension(config.hidden_size)
+                .map_err(|e| JsValue::from_str(&e))?;
+        }
+
+        // Convert matrices in place, so each f32 original is freed as soon as
+        // its copy exists and `weights` shares storage with the model
+        if self.weight_precision == WeightPrecision::Q4 {
+            q4::quantize_tensors(&mut tensors)
+                .map_err(|e| JsValue::from_str(&format!("Failed to quantize weights: {}", e)))?;
+        }
+        let storage = self.weight_precision.dtype();
+        for (name, tensor) in tensors.iter_mut() {
+            if tensor.rank() == 2
+                && tensor.dtype().is_float()
+                && tensor.dtype() != storage
+                && !name.ends_with(q4::SCALE_SUFFIX)
+            {
+                *tensor = tensor
+                    .to_dtype(storage)
+                    .map_err(|e| JsValue::from_str(&format!("Failed to convert weights: {}", e)))?;
+            }
+        }
+        let weights_hash = fingerprint::with_weight_precision(weights_hash, self.weight_precision);
+
+        let weights = tensors.clone();
         let vb = VarBuilder::from_tensors(tensors, DType::F32, &self.device);
 
         let model = BertModel::load(vb, &config)
+            .map(Encoder::Bert)
             .map_err(|e| JsValue::from_str(&format!("Failed to create model: {}", e)))?;
+        model
+            .validate_layer(self.pooling_layer)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
         // Load tokenizer
-        let tokenizer = Tokenizer::from_bytes(tokenizer_bytes)
-            .map_err(|e| JsValue::from_str(&format!("Failed to load tokenizer: {:?}", e)))?;
+        let mut tokenizer = load_tokenizer(tokenizer_bytes)?;
+        if let Some(preset) = self.preset {
+            preset
+                .apply_max_length(&mut tokenizer)
+                .map_err(|e| JsValue::from_str(&e))?;
+        }
 
+        info_log!(
+            "loaded {} layer BERT model (hidden size {}, {} position embeddings)",
+            config.num_hidden_layers,
+            config.hidden_size,
+            config.position_embedding_type
+        );
         self.model = Some(model);
+        self.weights = Some(weights);
         self.tokenizer = Some(tokenizer);
+        self.custom_normalizer = None;
+        self.token_cache.borrow_mut().clear();
+        self.model_hash = Some(fingerprint::model_hash(
+            weights_hash,
+            tokenizer_bytes,
+            config_bytes,
+        ));
 
         Ok(())
     }
@@ -126,13 +698,10 @@ impl EmbeddingEngine {
     /// Returns a Float32Array of 384 dimensions
     #[wasm_bindgen]
     pub fn embed(&self, text: &str) -> Result<Float32Array, JsValue> {
-        let texts = vec![text.to_string()];
-        let embeddings = self.embed_internal(&texts)?;
+        let embeddings = self.embed_matrix(&[text.to_string()])?;
 
-        if let Some(first) = embeddings.into_iter().next() {
-            let arr = Float32Array::new_with_length(first.len() as u32);
-            arr.copy_from(&first);
-            Ok(arr)
+        if embeddings.len() > 0 {
+            Ok(Float32Array::from(embeddings.row(0)))
         } else {
             Err(JsValue::from_str("No embedding generated"))
         }
@@ -142,55 +711,271 @@ impl EmbeddingEngine {
     ///
     /// Takes a JavaScript Array of strings
     /// Returns a JavaScript Array of Float32Array
+    /// Duplicate texts are embedded once and share the result.
     #[wasm_bindgen]
     pub fn embed_batch(&self, texts: &Array) -> Result<Array, JsValue> {
-        // Convert JS Array to Vec<String>
-        let mut rust_texts: Vec<String> = Vec::with_capacity(texts.length() as usize);
-        for i in 0..texts.length() {
-            let item = texts.get(i);
-            let text = item
-                .as_string()
-                .ok_or_else(|| JsValue::from_str(&format!("Item at index {} is not a string", i)))?;
-            rust_texts.push(text);
-        }
+        let rust_texts = js_array_to_strings(texts)?;
 
         if rust_texts.is_empty() {
             return Ok(Array::new());
         }
 
-        // Get embeddings
-        let embeddings = self.embed_internal(&rust_texts)?;
+        // Run inference once per unique text
+        let (unique, positions) = batching::dedup_texts(rust_texts);
+        let embeddings = self.embed_matrix(&unique)?;
+
+        Ok(positions
+            .iter()
+            .map(|&i| JsValue::from(Float32Array::from(embeddings.row(i))))
+            .collect())
+    }
 
-        // Convert to JS Array of Float32Array
-        let result = Array::new_with_length(embeddings.len() as u32);
-        for (i, embedding) in embeddings.into_iter().enumerate() {
-            let arr = Float32Array::new_with_length(embedding.len() as u32);
-            arr.copy_from(&embedding);
-            result.set(i as u32, arr.into());
+    /// Generate embeddings for multiple texts as one row-major Float32Array
+    /// of `texts.length * dimension()` values
+    ///
+    /// One allocation and one copy for the whole batch, instead of an array
+    /// per text.
+    #[wasm_bindgen]
+    pub fn embed_batch_flat(&self, texts: &Array) -> Result<Float32Array, JsValue> {
+        Ok(Float32Array::from(self.embed_texts_flat(texts)?.as_slice()))
+    }
+
+    /// Write the embeddings of `texts` into `out`, row-major, starting at
+    /// element `offset`; returns the number of values written
+    ///
+    /// Lets callers fill a preallocated buffer (e.g. a vector index) a batch
+    /// at a time without intermediate arrays.
+    #[wasm_bindgen]
+    pub fn embed_batch_into(
+        &self,
+        texts: &Array,
+        out: &Float32Array,
+        offset: u32,
+    ) -> Result<u32, JsValue> {
+        let embeddings = self.embed_texts_flat(texts)?;
+        let values = embeddings.as_slice();
+        let end = offset as usize + values.len();
+        if end > out.length() as usize {
+            return Err(JsValue::from_str(&format!(
+                "Output buffer holds {} values; {} are needed from offset {}",
+                out.length(),
+                values.len(),
+                offset
+            )));
         }
+        out.subarray(offset, end as u32).copy_from(values);
+        Ok(values.len() as u32)
+    }
 
-        Ok(result)
+    /// `embed_batch()` as one matrix, in input order
+    fn embed_texts_flat(&self, texts: &Array) -> Result<EmbeddingMatrix, JsValue> {
+        let (unique, positions) = batching::dedup_texts(js_array_to_strings(texts)?);
+        let embeddings = self.embed_matrix(&unique)?;
+        if unique.len() == positions.len() {
+            return Ok(embeddings);
+        }
+        Ok(embeddings.gather(&positions))
     }
 
     /// Internal embedding function that works with Rust types
     fn embed_internal(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, JsValue> {
+        Ok(self.embed_matrix(texts)?.into_rows())
+    }
+
+    /// Embed texts into one contiguous matrix
+    fn embed_matrix(&self, texts: &[String]) -> Result<EmbeddingMatrix, JsValue> {
+        self.embed_matrix_as(texts, TextRole::Document)
+    }
+
+    /// `embed_matrix()` with the preset's prefix for `role`
+    fn embed_matrix_as(
+        &self,
+        texts: &[String],
+        role: TextRole,
+    ) -> Result<EmbeddingMatrix, JsValue> {
+        Ok(self.embed_matrix_with_encodings(texts, role)?.0)
+    }
+
+    /// `embed_matrix_as()`, also returning the encodings the model ran on
+    fn embed_matrix_with_encodings(
+        &self,
+        texts: &[String],
+        role: TextRole,
+    ) -> Result<(EmbeddingMatrix, Vec<Encoding>), JsValue> {
+        let start = clock::now_ms();
+        let hits_before = self.token_cache.borrow().hit_count();
+        let prefix = self.role_prefix(role);
+        let encodings =
+            self.tokenize_with_prefix(&self.prefixed(&self.preprocessed(texts), role), prefix)?;
+        let tokenize_ms = clock::now_ms() - start;
+
+        let output = self.embed_encodings_within_budget(&encodings)?;
+        let end = clock::now_ms();
+        self.latency
+            .borrow_mut()
+            .record(end - start, texts.len(), end);
+
+        if self.on_inference.is_some() {
+            let mut stats = InferenceStats::for_batch(&encodings);
+            stats.set_cache_hits((self.token_cache.borrow().hit_count() - hits_before) as usize);
+            stats.set_timings(tokenize_ms, output.inference_ms, output.pooling_ms);
+            stats.set_total_ms(clock::now_ms() - start);
+            self.report_inference(stats);
+        }
+        Ok((output.embeddings, encodings))
+    }
+
+    /// Check encodings against `set_limits()` and embed them, splitting them
+    /// into micro-batches that fit the memory budget, token limit and
+    /// micro-batch size and reporting progress after each (token embeddings
+    /// are not kept when the batch was split)
+    fn embed_encodings_within_budget(
+        &self,
+        encodings: &[Encoding],
+    ) -> Result<BatchOutput, JsValue> {
+        self.admit(encodings)?;
+        let start = clock::now_ms();
+        let memory = self.memory_budget.zip(self.batch_cost());
+        let max_tokens = self.max_tokens_per_forward;
+        let max_batch = self.effective_micro_batch_size();
+        if memory.is_none() && max_tokens.is_none() && max_batch.is_none() {
+            return self.embed_encodings(encodings, false);
+        }
+        let lens: Vec<usize> = encodings
+            .iter()
+            .map(|e| e.len().min(MAX_SEQUENCE_LENGTH))
+            .collect();
+        if let Some((budget, cost)) = &memory {
+            if let Some(i) = lens.iter().position(|&len| cost(1, len) > *budget) {
+                return Err(budget_error(
+                    cost(1, lens[i]),
+                    *budget,
+                    &format!("text {}", i),
+                ));
+            }
+        }
+        let batches = batching::pack_batches(&lens, |batch, seq_len| {
+            memory
+                .as_ref()
+                .is_none_or(|(budget, cost)| cost(batch, seq_len) <= *budget)
+                && max_tokens.is_none_or(|max| batch * seq_len <= max)
+                && max_batch.is_none_or(|max| batch <= max)
+        });
+        if batches.len() == 1 {
+            let output = self.embed_encodings(encodings, false)?;
+            self.report_progress(encodings.len(), encodings.len(), clock::now_ms() - start)?;
+            return Ok(output);
+        }
+
+        debug_log!(
+            "splitting batch of {} into {} micro-batches",
+            encodings.len(),
+            batches.len()
+        );
+        let mut merged = BatchOutput {
+            embeddings: EmbeddingMatrix::default(),
+            token_embeddings: None,
+            attentions: None,
+            inference_ms: 0.0,
+            pooling_ms: 0.0,
+        };
+        for range in batches {
+            let output = self.embed_encodings(&encodings[range], false)?;
+            merged.embeddings.append(output.embeddings);
+            merged.inference_ms += output.inference_ms;
+            merged.pooling_ms += output.pooling_ms;
+            self.report_progress(
+                merged.embeddings.len(),
+                encodings.len(),
+                clock::now_ms() - start,
+            )?;
+        }
+        Ok(merged)
+    }
+
+    /// Tokenize texts with special tokens added, using the encoding cache
+    fn tokenize(&self, texts: &[String]) -> Result<Vec<Encoding>, JsValue> {
+        self.tokenize_with_prefix(texts, "")
+    }
+
+    /// `tokenize()` for texts that all start with `prefix`, reusing the
+    /// prefix's tokens where the tokenizer allows
+    fn tokenize_with_prefix(
+        &self,
+        texts: &[String],
+        prefix: &str,
+    ) -> Result<Vec<Encoding>, JsValue> {
+        let tokenizer = self.tokenizer.as_ref().ok_or_else(|| {
+            JsValue::from_str("Tokenizer not loaded. Call load_embedded() first.")
+        })?;
+
+        // Borrows of the cache are kept short so a trap inside the tokenizer
+        // can't leave it borrowed and fail every later call
+        let prefix_tokens = self.prefix_tokens(tokenizer, prefix);
+        let long = texts.iter().any(|t| t.len() > long_text::LONG_TEXT_BYTES);
+        if self.token_cache.borrow().capacity() == 0 && !long && prefix_tokens.is_none() {
+            return tokenizer
+                .encode_batch(texts.to_vec(), true)
+                .map_err(|e| JsValue::from_str(&format!("Tokenization failed: {:?}", e)));
+        }
+
+        let mut encodings = Vec::with_capacity(texts.len());
+        let mut misses = 0;
+        for text in texts {
+            let cached = self.token_cache.borrow_mut().get(text);
+            let encoding = match cached {
+                Some(encoding) => encoding,
+                None => {
+                    misses += 1;
+                    let rest = text
+                        .strip_prefix(prefix)
+                        .filter(|rest| rest.len() <= long_text::LONG_TEXT_BYTES);
+                    let encoding = match (&prefix_tokens, rest) {
+                        (Some(tokens), Some(rest)) => tokens.encode(tokenizer, rest),
+                        _ => long_text::encode_prefix(tokenizer, text, MAX_SEQUENCE_LENGTH),
+                    }
+                    .map_err(|e| JsValue::from_str(&format!("Tokenization failed: {:?}", e)))?;
+                    self.token_cache.borrow_mut().insert(text, encoding.clone());
+                    encoding
+                }
+            };
+            encodings.push(encoding);
+        }
+
+        debug_log!(
+            "tokenized {} texts ({} from cache)",
+            texts.len(),
+            texts.len() - misses
+        );
+
+        // Batch padding, as encode_batch would apply
+        if let Some(params) = tokenizer.get_padding() {
+            tokenizers::utils::padding::pad_encodings(&mut encodings, params)
+                .map_err(|e| JsValue::from_str(&format!("Padding failed: {:?}", e)))?;
+        }
+        Ok(encodings)
+    }
+
+    /// Run the model on tokenized inputs and pool
+    fn embed_encodings(
+        &self,
+        encodings: &[Encoding],
+        want_attentions: bool,
+    ) -> Result<BatchOutput, JsValue> {
         let model = self
             .model
             .as_ref()
             .ok_or_else(|| JsValue::from_str("Model not loaded. Call load_embedded() first."))?;
-        let tokenizer = self
-            .tokenizer
-            .as_ref()
-            .ok_or_else(|| JsValue::from_str("Tokenizer not loaded. Call load_embedded() first."))?;
-
-        // Tokenize all texts
-        let encodings = tokenizer
-            .encode_batch(texts.to_vec(), true)
-            .map_err(|e| JsValue::from_str(&format!("Tokenization failed: {:?}", e)))?;
 
         let batch_size = encodings.len();
         if batch_size == 0 {
-            return Ok(vec![]);
+            return Ok(BatchOutput {
+                embeddings: EmbeddingMatrix::default(),
+                token_embeddings: None,
+                attentions: None,
+                inference_ms: 0.0,
+                pooling_ms: 0.0,
+            });
         }
 
         // Find max sequence length in batch
@@ -200,13 +985,56 @@ impl EmbeddingEngine {
             .max()
             .unwrap_or(0)
             .min(MAX_SEQUENCE_LENGTH);
+        for (i, encoding) in encodings.iter().enumerate() {
+            if !encoding.get_overflowing().is_empty() {
+                debug_log!(
+                    "text {}: tokenizer truncated to {} tokens",
+                    i,
+                    encoding.len()
+                );
+            }
+            if encoding.len() > MAX_SEQUENCE_LENGTH {
+                debug_log!(
+                    "text {}: {} tokens truncated to {}",
+                    i,
+                    encoding.len(),
+                    MAX_SEQUENCE_LENGTH
+                );
+            }
+        }
+        debug_log!(
+            "running batch of {} at sequence length {}",
+            batch_size,
+            max_len
+        );
 
-        // Prepare input tensors
-        let mut input_ids: Vec<i64> = Vec::with_capacity(batch_size * max_len);
-        let mut attention_mask: Vec<i64> = Vec::with_capacity(batch_size * max_len);
-        let mut token_type_ids: Vec<i64> = Vec::with_capacity(batch_size * max_len);
+        // Callers that can't split (attentions, attribution) are refused here
+        if let (Some(budget), Some(cost)) = (self.memory_budget, self.batch_cost()) {
+            let needed = cost(batch_size, max_len);
+            if needed > budget {
+                return Err(budget_error(
+                    needed,
+                    budget,
+                    &format!("a batch of {} x {} tokens", batch_size, max_len),
+                ));
+            }
+        }
 
-        for encoding in &encodings {
+        // Segment ids are only needed by architectures with more than one token type
+        let use_token_types = model.uses_token_types();
+        let pad_token_id = model.pad_token_id();
+
+        // Prepare input tensors: ids as u32 (what the embedding lookups index
+        // with) and the mask as u8, widened to f32 only where it is applied
+        let mut input_ids: Vec<u32> = Vec::with_capacity(batch_size * max_len);
+        let mut attention_mask: Vec<u8> = Vec::with_capacity(batch_size * max_len);
+        let mut token_type_ids: Vec<u32> = if use_token_types {
+            Vec::with_capacity(batch_size * max_len)
+        } else {
+            Vec::new()
+        };
+
+        for encoding in encodings {
             let ids = encoding.get_ids();
             let mask = encoding.get_attention_mask();
             let types = encoding.get_type_ids();
@@ -215,16 +1043,20 @@ impl EmbeddingEngine {
 
             // Add tokens
             for i in 0..seq_len {
-                input_ids.push(ids[i] as i64);
-                attention_mask.push(mask[i] as i64);
-                token_type_ids.push(types[i] as i64);
+                input_ids.push(ids[i]);
+                attention_mask.push(u8::from(mask[i] != 0));
+                if use_token_types {
+                    token_type_ids.push(types[i]);
+                }
             }
 
             // Pad to max_len
             for _ in seq_len..max_len {
-                input_ids.push(0);
+                input_ids.push(pad_token_id);
                 attention_mask.push(0);
-                token_type_ids.push(0);
+                if use_token_types {
+                    token_type_ids.push(0);
+                }
             }
         }
 
@@ -232,26 +1064,57 @@ impl EmbeddingEngine {
         let input_ids = Tensor::from_vec(input_ids, (batch_size, max_len), &self.device)
             .map_err(|e| JsValue::from_str(&format!("Failed to create input_ids tensor: {}", e)))?;
 
-        let attention_mask_tensor =
-            Tensor::from_vec(attention_mask.clone(), (batch_size, max_len), &self.device)
+        let attention_mask = Tensor::from_vec(attention_mask, (batch_size, max_len), &self.device)
+            .map_err(|e| {
+                JsValue::from_str(&format!("Failed to create attention_mask tensor: {}", e))
+            })?;
+
+        let token_type_ids = if use_token_types {
+            let tensor = Tensor::from_vec(token_type_ids, (batch_size, max_len), &self.device)
                 .map_err(|e| {
-                    JsValue::from_str(&format!("Failed to create attention_mask tensor: {}", e))
+                    JsValue::from_str(&format!("Failed to create token_type_ids tensor: {}", e))
                 })?;
+            Some(tensor)
+        } else {
+            None
+        };
 
-        let token_type_ids = Tensor::from_vec(token_type_ids, (batch_size, max_len), &self.device)
+        // Run model inference; attention pooling also needs the attention maps
+        let inference_start = clock::now_ms();
+        let (output, attentions) = model
+            .forward_selected(
+                &input_ids,
+                token_type_ids.as_ref(),
+                &attention_mask,
+                self.pooling_layer,
+                want_attentions || self.pooling == PoolingStrategy::Attention,
+            )
             .map_err(|e| {
-                JsValue::from_str(&format!("Failed to create token_type_ids tensor: {}", e))
+                js_error(
+                    ErrorKind::Internal,
+                    &format!("Model inference failed: {}", e),
+                )
             })?;
+        check_output_shapes(&output, attentions.as_ref(), batch_size, max_len)
+            .map_err(|message| js_error(ErrorKind::Internal, &message))?;
 
-        // Run model inference
-        let output = model
-            .forward(&input_ids, &token_type_ids, Some(&attention_mask_tensor))
-            .map_err(|e| JsValue::from_str(&format!("Model inference failed: {}", e)))?;
+        let pooling_start = clock::now_ms();
+
+        // Pooling weights: the mask as f32 [batch, seq], converted once
+        let mask = attention_mask
+            .to_dtype(DType::F32)
+            .map_err(|e| JsValue::from_str(&format!("Dtype conversion failed: {}", e)))?;
 
         // Apply pooling
         let embeddings = match self.pooling {
             PoolingStrategy::Mean => {
-                self.mean_pooling(&output, &attention_mask_tensor, batch_size, max_len)?
+                // One pass over the hidden states where they are in CPU memory
+                let fused = kernels::mean_pool(&output, &attention_mask)
+                    .map_err(|e| JsValue::from_str(&format!("Mean pooling failed: {}", e)))?;
+                match fused {
+                    Some(pooled) => pooled,
+                    None => self.mean_pooling(&output, &mask)?,
+                }
             }
             PoolingStrategy::Cls => {
                 // Get [CLS] token (first token) embeddings
@@ -261,57 +1124,126 @@ impl EmbeddingEngine {
                     .squeeze(1)
                     .map_err(|e| JsValue::from_str(&format!("Squeeze failed: {}", e)))?
             }
+            PoolingStrategy::WeightedMean => self.weighted_mean_pooling(&output, &mask, max_len)?,
+            PoolingStrategy::Attention => match &attentions {
+                Some(attentions) => self.attention_pooling(&output, attentions, &mask)?,
+                None => return Err(js_error(ErrorKind::Internal, "Attention outputs missing")),
+            },
         };
 
         // Normalize embeddings (L2 normalization)
         let embeddings = self.l2_normalize(&embeddings)?;
 
-        // Convert to Vec<Vec<f32>>
-        let embeddings_flat = embeddings
-            .to_vec2::<f32>()
+        // Copy out in one contiguous extraction
+        let dimension = embeddings
+            .dim(1)
+            .map_err(|e| JsValue::from_str(&format!("Unexpected embedding shape: {}", e)))?;
+        let values = embeddings
+            .flatten_all()
+            .and_then(|e| e.to_vec1::<f32>())
             .map_err(|e| JsValue::from_str(&format!("Failed to extract embeddings: {}", e)))?;
 
-        Ok(embeddings_flat)
+        Ok(BatchOutput {
+            embeddings: EmbeddingMatrix::new(values, dimension),
+            token_embeddings: Some(output),
+            attentions,
+            inference_ms: pooling_start - inference_start,
+            pooling_ms: clock::now_ms() - pooling_start,
+        })
     }
 
-    /// Mean pooling over token embeddings, weighted by attention mask
-    fn mean_pooling(
+    /// Mean pooling over token embeddings, weighted by the f32 attention
+    /// mask `[batch, seq]`
+    fn mean_pooling(&self, token_embeddings: &Tensor, mask: &Tensor) -> Result<Tensor, JsValue> {
+        // Broadcast the mask over the hidden dimension rather than expanding it
+        let summed = mask
+            .unsqueeze(2)
+            .and_then(|m| token_embeddings.broadcast_mul(&m))
+            .and_then(|t| t.sum(1))
+            .map_err(|e| JsValue::from_str(&format!("Masked sum failed: {}", e)))?;
+
+        // Token counts for normalization
+        let counts = mask
+            .sum_keepdim(1)
+            .and_then(|c| c.clamp(1e-9, f64::INFINITY))
+            .map_err(|e| JsValue::from_str(&format!("Mask sum failed: {}", e)))?;
+
+        summed
+            .broadcast_div(&counts)
+            .map_err(|e| JsValue::from_str(&format!("Division failed: {}", e)))
+    }
+
+    /// Position-weighted mean pooling (SGPT), weighted by the f32 attention
+    /// mask
+    ///
+    /// Token at position i gets weight (i + 1); padding gets weight 0.
+    fn weighted_mean_pooling(
         &self,
         token_embeddings: &Tensor,
-        attention_mask: &Tensor,
-        batch_size: usize,
+        mask: &Tensor,
         seq_len: usize,
     ) -> Result<Tensor, JsValue> {
-        // Expand attention mask to match embedding dimensions
-        // attention_mask: [batch, seq] -> [batch, seq, hidden]
-        let mask = attention_mask
-            .unsqueeze(2)
-            .map_err(|e| JsValue::from_str(&format!("Unsqueeze failed: {}", e)))?
-            .expand((batch_size, seq_len, HIDDEN_SIZE))
-            .map_err(|e| JsValue::from_str(&format!("Expand failed: {}", e)))?
-            .to_dtype(DType::F32)
-            .map_err(|e| JsValue::from_str(&format!("Dtype conversion failed: {}", e)))?;
+        // weights: [batch, seq] = (position + 1) * mask
+        let positions = Tensor::arange(1u32, seq_len as u32 + 1, &self.device)
+            .and_then(|p| p.to_dtype(DType::F32))
+            .and_then(|p| p.unsqueeze(0))
+            .map_err(|e| JsValue::from_str(&format!("Position weights failed: {}", e)))?;
+        let weights = mask
+            .broadcast_mul(&positions)
+            .map_err(|e| JsValue::from_str(&format!("Weight computation failed: {}", e)))?;
+
+        // Weighted sum over sequence dimension
+        let summed = token_embeddings
+            .broadcast_mul(
+                &weights
+                    .unsqueeze(2)
+                    .map_err(|e| JsValue::from_str(&format!("Unsqueeze failed: {}", e)))?,
+            )
+            .and_then(|t| t.sum(1))
+            .map_err(|e| JsValue::from_str(&format!("Weighted sum failed: {}", e)))?;
 
-        // Multiply embeddings by mask
-        let masked = token_embeddings
-            .mul(&mask)
-            .map_err(|e| JsValue::from_str(&format!("Mask multiplication failed: {}", e)))?;
+        let weight_sum = weights
+            .sum_keepdim(1)
+            .and_then(|w| w.clamp(1e-9, f64::INFINITY))
+            .map_err(|e| JsValue::from_str(&format!("Weight sum failed: {}", e)))?;
+
+        summed
+            .broadcast_div(&weight_sum)
+            .map_err(|e| JsValue::from_str(&format!("Division failed: {}", e)))
+    }
+
+    /// Attention pooling: weight tokens by how much [CLS] attends to them in
+    /// the final layer (averaged over heads), renormalized over the real
+    /// tokens of the f32 attention mask
+    fn attention_pooling(
+        &self,
+        token_embeddings: &Tensor,
+        attentions: &Tensor,
+        mask: &Tensor,
+    ) -> Result<Tensor, JsValue> {
+        // attentions: [batch, heads, seq, seq] -> row of query 0: [batch, seq]
+        let cls_attention = attentions
+            .narrow(2, 0, 1)
+            .and_then(|a| a.squeeze(2))
+            .and_then(|a| a.mean(1))
+            .map_err(|e| JsValue::from_str(&format!("CLS attention extraction failed: {}", e)))?;
+        let weights = cls_attention
+            .mul(mask)
+            .map_err(|e| JsValue::from_str(&format!("Weight computation failed: {}", e)))?;
 
-        // Sum over sequence dimension
-        let summed = masked
-            .sum(1)
-            .map_err(|e| JsValue::from_str(&format!("Sum failed: {}", e)))?;
+        let summed = weights
+            .unsqueeze(2)
+            .and_then(|w| token_embeddings.broadcast_mul(&w))
+            .and_then(|t| t.sum(1))
+            .map_err(|e| JsValue::from_str(&format!("Weighted sum failed: {}", e)))?;
 
-        // Sum attention mask for normalization
-        let mask_sum = mask
-            .sum(1)
-            .map_err(|e| JsValue::from_str(&format!("Mask sum failed: {}", e)))?
-            .clamp(1e-9, f64::INFINITY)
-            .map_err(|e| JsValue::from_str(&format!("Clamp failed: {}", e)))?;
+        let weight_sum = weights
+            .sum_keepdim(1)
+            .and_then(|w| w.clamp(1e-9, f64::INFINITY))
+            .map_err(|e| JsValue::from_str(&format!("Weight sum failed: {}", e)))?;
 
-        // Divide by mask sum
         summed
-            .div(&mask_sum)
+            .broadcast_div(&weight_sum)
             .map_err(|e| JsValue::from_str(&format!("Division failed: {}", e)))
     }
 
@@ -332,10 +1264,62 @@ impl EmbeddingEngine {
             .map_err(|e| JsValue::from_str(&format!("Normalize division failed: {}", e)))
     }
 
+    /// Select the pooling strategy: "mean" (default), "cls", "weighted_mean"
+    /// or "attention"
+    #[wasm_bindgen]
+    pub fn set_pooling(&mut self, strategy: &str) -> Result<(), JsValue> {
+        self.pooling = PoolingStrategy::from_name(strategy).ok_or_else(|| {
+            JsValue::from_str(&format!(
+                "Unknown pooling strategy '{}' (expected mean, cls, weighted_mean or attention)",
+                strategy
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Get the current pooling strategy name
+    #[wasm_bindgen]
+    pub fn pooling(&self) -> String {
+        self.pooling.name().to_string()
+    }
+
+    /// Select the hidden state to pool over: a layer index (`-2` is the layer
+    /// before last, `0` the embedding output), `"avg_last_N"` to average the
+    /// last N layers, or `"last"` (default)
+    #[wasm_bindgen]
+    pub fn set_pooling_layer(&mut self, layer: &JsValue) -> Result<(), JsValue> {
+        let spec = match layer.as_f64() {
+            Some(index) if index.fract() == 0.0 => (index as isize).to_string(),
+            Some(index) => {
+                return Err(JsValue::from_str(&format!(
+                    "Pooling layer must be an integer, got {}",
+                    index
+                )))
+            }
+            None => layer
+                .as_string()
+                .ok_or_else(|| JsValue::from_str("Pooling layer must be a number or a string"))?,
+        };
+        let layer = LayerSelection::parse(&spec).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        if let Some(model) = &self.model {
+            model
+                .validate_layer(layer)
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        }
+        self.pooling_layer = layer;
+        Ok(())
+    }
+
+    /// Get the current pooling layer ("last", an index, or "avg_last_N")
+    #[wasm_bindgen]
+    pub fn pooling_layer(&self) -> String {
+        self.pooling_layer.name()
+    }
+
     /// Get the embedding dimension (384 for all-MiniLM-L6-v2)
     #[wasm_bindgen]
     pub fn dimension(&self) -> usize {
-        HIDDEN_SIZE
+        self.model.as_ref().map_or(HIDDEN_SIZE, |m| m.hidden_size())
     }
 
     /// Get the maximum sequence length
@@ -351,6 +1335,99 @@ impl Default for EmbeddingEngine {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl EmbeddingEngine {
+    /// Create an engine (not loaded) that runs on `device`
+    ///
+    /// Build with the `cuda` or `metal` feature for GPU devices. Tokenization,
+    /// pooling and normalization are the same code as in the browser; only
+    /// the matrix kernels differ, so vectors agree with WASM clients to float
+    /// rounding.
+    pub fn with_device(device: Device) -> Self {
+        EmbeddingEngine {
+            device,
+            ..Self::new()
+        }
+    }
+
+    /// Device the model runs on
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+}
+
+/// Parse tokenizer.json contents
+pub(crate) fn load_tokenizer(tokenizer_bytes: &[u8]) -> Result<Tokenizer, JsValue> {
+    Tokenizer::from_bytes(tokenizer_bytes)
+        .map_err(|e| JsValue::from_str(&format!("Failed to load tokenizer: {:?}", e)))
+}
+
+/// Convert a JS Array of strings to Vec<String>
+pub(crate) fn js_array_to_strings(texts: &Array) -> Result<Vec<String>, JsValue> {
+    let mut rust_texts: Vec<String> = Vec::with_capacity(texts.length() as usize);
+    for i in 0..texts.length() {
+        let item = texts.get(i);
+        let text = item
+            .as_string()
+            .ok_or_else(|| JsValue::from_str(&format!("Item at index {} is not a string", i)))?;
+        rust_texts.push(text);
+    }
+    Ok(rust_texts)
+}
+
+/// Convert embeddings to a JS Array of Float32Array
+pub(crate) fn embeddings_to_js(embeddings: Vec<Vec<f32>>) -> Array {
+    let result = Array::new_with_length(embeddings.len() as u32);
+    for (i, embedding) in embeddings.into_iter().enumerate() {
+        let arr = Float32Array::new_with_length(embedding.len() as u32);
+        arr.copy_from(&embedding);
+        result.set(i as u32, arr.into());
+    }
+    result
+}
+
+/// `MemoryBudgetError` for an input whose estimated working set is too large
+fn budget_error(needed: usize, budget: usize, what: &str) -> JsValue {
+    js_error(
+        ErrorKind::MemoryBudget,
+        &format!(
+            "Embedding {} needs about {} bytes, over the memory budget of {} bytes",
+            what, needed, budget
+        ),
+    )
+}
+
+/// Check model outputs against the batch shape before anything indexes them:
+/// hidden states `[batch, seq, hidden]`, attentions `[batch, heads, seq, seq]`
+fn check_output_shapes(
+    hidden: &Tensor,
+    attentions: Option<&Tensor>,
+    batch_size: usize,
+    seq_len: usize,
+) -> Result<(), String> {
+    match hidden.dims() {
+        &[b, s, h] if b == batch_size && s == seq_len && h > 0 => {}
+        dims => {
+            return Err(format!(
+                "Model returned hidden states of shape {:?} for a batch of {} x {} tokens",
+                dims, batch_size, seq_len
+            ))
+        }
+    }
+    if let Some(attentions) = attentions {
+        match attentions.dims() {
+            &[b, _, q, k] if b == batch_size && q == seq_len && k == seq_len => {}
+            dims => {
+                return Err(format!(
+                    "Model returned attentions of shape {:?} for a batch of {} x {} tokens",
+                    dims, batch_size, seq_len
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Calculate cosine similarity between two embeddings
 #[wasm_bindgen]
 pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
@@ -375,6 +1452,18 @@ pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     dot / (norm_a.sqrt() * norm_b.sqrt())
 }
 
+/// Cosine similarity of two L2-normalized embeddings (such as `embed()`
+/// output): their dot product, without computing norms
+///
+/// Returns 0 for vectors of different lengths, like `cosine_similarity`.
+#[wasm_bindgen]
+pub fn cosine_similarity_normalized(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -389,10 +1478,104 @@ mod tests {
         assert!(cosine_similarity(&a, &c).abs() < 1e-6);
     }
 
+    #[test]
+    fn test_cosine_similarity_normalized() {
+        let a = vec![1.0, 0.0, 0.0];
+        let b = vec![1.0, 0.0, 0.0];
+        assert_eq!(cosine_similarity_normalized(&a, &b), 1.0);
+
+        let c = vec![0.0, 1.0, 0.0];
+        assert_eq!(cosine_similarity_normalized(&a, &c), 0.0);
+        assert_eq!(cosine_similarity_normalized(&a, &[1.0]), 0.0);
+    }
+
+    #[test]
+    fn test_mean_pooling() {
+        let engine = EmbeddingEngine::new();
+        let hidden = Tensor::new(&[[[1.0f32, 0.0], [0.0, 1.0], [9.0, 9.0]]], &Device::Cpu).unwrap();
+        let mask = Tensor::new(&[[1.0f32, 1.0, 0.0]], &Device::Cpu).unwrap();
+
+        let pooled = engine
+            .mean_pooling(&hidden, &mask)
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        assert_eq!(pooled, vec![vec![0.5, 0.5]]);
+    }
+
+    #[test]
+    fn test_weighted_mean_pooling() {
+        let engine = EmbeddingEngine::new();
+        // batch of 1, seq 3 (last token is padding), hidden 2
+        let hidden = Tensor::new(&[[[1.0f32, 0.0], [0.0, 1.0], [9.0, 9.0]]], &Device::Cpu).unwrap();
+        let mask = Tensor::new(&[[1.0f32, 1.0, 0.0]], &Device::Cpu).unwrap();
+
+        let pooled = engine
+            .weighted_mean_pooling(&hidden, &mask, 3)
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        // weights 1 and 2 over the two real tokens
+        assert!((pooled[0][0] - 1.0 / 3.0).abs() < 1e-6);
+        assert!((pooled[0][1] - 2.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_attention_pooling() {
+        let engine = EmbeddingEngine::new();
+        let hidden = Tensor::new(&[[[1.0f32, 0.0], [0.0, 1.0], [9.0, 9.0]]], &Device::Cpu).unwrap();
+        let mask = Tensor::new(&[[1.0f32, 1.0, 0.0]], &Device::Cpu).unwrap();
+        // two heads; [CLS] attends 0.25/0.75 on average to the real tokens
+        let row = |a: f32, b: f32| [[a, b, 0.0], [0.0; 3], [0.0; 3]];
+        let attentions = Tensor::new(&[[row(0.5, 0.5), row(0.0, 1.0)]], &Device::Cpu).unwrap();
+
+        let pooled = engine
+            .attention_pooling(&hidden, &attentions, &mask)
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap();
+        assert!((pooled[0][0] - 0.25).abs() < 1e-6);
+        assert!((pooled[0][1] - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_pooling_names_round_trip() {
+        for strategy in [
+            PoolingStrategy::Mean,
+            PoolingStrategy::Cls,
+            PoolingStrategy::WeightedMean,
+            PoolingStrategy::Attention,
+        ] {
+            assert_eq!(PoolingStrategy::from_name(strategy.name()), Some(strategy));
+        }
+        assert_eq!(PoolingStrategy::from_name("max"), None);
+    }
+
+    #[test]
+    fn test_output_shape_check() {
+        let hidden = Tensor::zeros((2, 5, 4), DType::F32, &Device::Cpu).unwrap();
+        let attentions = Tensor::zeros((2, 3, 5, 5), DType::F32, &Device::Cpu).unwrap();
+        assert!(check_output_shapes(&hidden, Some(&attentions), 2, 5).is_ok());
+        assert!(check_output_shapes(&hidden, None, 3, 5).is_err());
+        assert!(check_output_shapes(&hidden, None, 2, 4).is_err());
+
+        let short = Tensor::zeros((2, 3, 5, 4), DType::F32, &Device::Cpu).unwrap();
+        assert!(check_output_shapes(&hidden, Some(&short), 2, 5).is_err());
+        let flat = Tensor::zeros((2, 4), DType::F32, &Device::Cpu).unwrap();
+        assert!(check_output_shapes(&flat, None, 2, 5).is_err());
+    }
+
     #[test]
     fn test_engine_creation() {
         let engine = EmbeddingEngine::new();
         assert!(!engine.is_ready());
         assert_eq!(engine.dimension(), 384);
     }
+
+    #[test]
+    fn test_with_device() {
+        let engine = EmbeddingEngine::with_device(Device::Cpu);
+        assert!(engine.device().is_cpu());
+        assert!(!engine.is_ready());
+    }
 }
@@ -0,0 +1,258 @@
+//! Per-engine resource limits (`set_limits`)
+//!
+//! A service sharing one engine between callers needs a way to stop any one
+//! of them from monopolizing it. Limits are checked after tokenization and
+//! before any inference, so a rejected request costs no model time:
+//!
+//! - `max_batch_size` caps the texts in one call; larger calls fail with a
+//!   `LimitExceededError` (retrying won't help).
+//! - `max_tokens_per_second` is a token bucket holding up to `burst_tokens`
+//!   (one second's worth by default), refilled continuously. A call needing
+//!   more tokens than are left fails with a `RateLimitError` whose
+//!   `retry_after_ms` says when it would fit; one needing more than the
+//!   bucket can ever hold fails with a `LimitExceededError`.
+//!
+//! There is no concurrency limit: calls on one instance already run one at a
+//! time.
+
+use serde::Deserialize;
+use tokenizers::Encoding;
+use wasm_bindgen::prelude::*;
+
+use crate::errors::{js_error, ErrorKind};
+use crate::js::parse_options;
+use crate::telemetry::attended_tokens;
+use crate::{clock, EmbeddingEngine, MAX_SEQUENCE_LENGTH};
+
+/// Options for `set_limits`; 0 (or unset) means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub(crate) struct ResourceLimits {
+    max_batch_size: usize,
+    max_tokens_per_second: f64,
+    /// Size of the token bucket (defaults to `max_tokens_per_second`)
+    burst_tokens: f64,
+}
+
+impl ResourceLimits {
+    fn bucket(&self) -> Option<TokenBucket> {
+        (self.max_tokens_per_second > 0.0).then(|| {
+            let capacity = if self.burst_tokens > 0.0 {
+                self.burst_tokens
+            } else {
+                self.max_tokens_per_second
+            };
+            TokenBucket::new(self.max_tokens_per_second, capacity, clock::now_ms())
+        })
+    }
+}
+
+/// Why a request was refused
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Refusal {
+    /// The request can never fit; the payload is the limit it exceeds
+    TooLarge(f64),
+    /// The request fits once `retry_after_ms` have passed
+    RateLimited { available: f64, retry_after_ms: f64 },
+}
+
+/// Tokens per second, with bursts of up to `capacity`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TokenBucket {
+    /// Tokens added per millisecond
+    rate_per_ms: f64,
+    capacity: f64,
+    available: f64,
+    updated_ms: f64,
+}
+
+impl TokenBucket {
+    /// A full bucket
+    fn new(tokens_per_second: f64, capacity: f64, now_ms: f64) -> Self {
+        TokenBucket {
+            rate_per_ms: tokens_per_second / 1000.0,
+            capacity,
+            available: capacity,
+            updated_ms: now_ms,
+        }
+    }
+
+    /// Take `tokens` from the bucket, or leave it untouched and say why not
+    fn take(&mut self, tokens: f64, now_ms: f64) -> Result<(), Refusal> {
+        if tokens > self.capacity {
+            return Err(Refusal::TooLarge(self.capacity));
+        }
+        let elapsed = (now_ms - self.updated_ms).max(0.0);
+        self.available = (self.available + elapsed * self.rate_per_ms).min(self.capacity);
+        self.updated_ms = now_ms;
+        if tokens > self.available {
+            return Err(Refusal::RateLimited {
+                available: self.available,
+                retry_after_ms: (tokens - self.available) / self.rate_per_ms,
+            });
+        }
+        self.available -= tokens;
+        Ok(())
+    }
+}
+
+/// A `RateLimitError` carrying `retry_after_ms`
+fn rate_limit_error(tokens: usize, available: f64, retry_after_ms: f64) -> JsValue {
+    let error = js_error(
+        ErrorKind::RateLimit,
+        &format!(
+            "Rate limit exceeded: {} tokens requested, {:.0} available; retry in {:.0} ms",
+            tokens, available, retry_after_ms
+        ),
+    );
+    // Setting a property on a fresh Error object cannot fail
+    let _ = js_sys::Reflect::set(
+        &error,
+        &JsValue::from_str("retry_after_ms"),
+        &JsValue::from_f64(retry_after_ms.ceil()),
+    );
+    error
+}
+
+impl EmbeddingEngine {
+    /// Check a tokenized request against the limits, charging its tokens to
+    /// the rate limit when it is admitted
+    pub(crate) fn admit(&self, encodings: &[Encoding]) -> Result<(), JsValue> {
+        let max_batch = self.limits.max_batch_size;
+        if max_batch > 0 && encodings.len() > max_batch {
+            return Err(js_error(
+                ErrorKind::LimitExceeded,
+                &format!(
+                    "Batch of {} texts exceeds the limit of {}",
+                    encodings.len(),
+                    max_batch
+                ),
+            ));
+        }
+        let mut bucket = self.rate_limiter.borrow_mut();
+        let Some(bucket) = bucket.as_mut() else {
+            return Ok(());
+        };
+        // Padding isn't charged, matching the counts telemetry reports
+        let tokens: usize = encodings
+            .iter()
+            .map(|e| attended_tokens(e).min(MAX_SEQUENCE_LENGTH))
+            .sum();
+        match bucket.take(tokens as f64, clock::now_ms()) {
+            Ok(()) => Ok(()),
+            Err(Refusal::TooLarge(capacity)) => Err(js_error(
+                ErrorKind::LimitExceeded,
+                &format!(
+                    "Request of {} tokens exceeds the rate limit's burst size of {:.0}",
+                    tokens, capacity
+                ),
+            )),
+            Err(Refusal::RateLimited {
+                available,
+                retry_after_ms,
+            }) => Err(rate_limit_error(tokens, available, retry_after_ms)),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Limit what one call may use
+    ///
+    /// Options: `{ max_batch_size?, max_tokens_per_second?, burst_tokens? }`;
+    /// 0 or unset means unlimited, and `undefined` removes all limits.
+    /// Refused calls throw a `LimitExceededError` (too large to ever run) or a
+    /// `RateLimitError` with `retry_after_ms`. Setting limits refills the
+    /// token bucket.
+    #[wasm_bindgen]
+    pub fn set_limits(&mut self, options: &JsValue) -> Result<(), JsValue> {
+        let limits: ResourceLimits = parse_options(options)?;
+        if !(limits.max_tokens_per_second >= 0.0 && limits.burst_tokens >= 0.0) {
+            return Err(JsValue::from_str(
+                "max_tokens_per_second and burst_tokens must be non-negative",
+            ));
+        }
+        self.limits = limits;
+        *self.rate_limiter.borrow_mut() = limits.bucket();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js::options_from_json;
+    use std::str::FromStr;
+    use tokenizers::Tokenizer;
+
+    #[test]
+    fn test_bucket_refills_and_refuses() {
+        let mut bucket = TokenBucket::new(100.0, 50.0, 0.0);
+        assert_eq!(bucket.take(40.0, 0.0), Ok(()));
+        let Err(Refusal::RateLimited {
+            available,
+            retry_after_ms,
+        }) = bucket.take(30.0, 0.0)
+        else {
+            panic!("expected a rate limit");
+        };
+        assert_eq!(available, 10.0);
+        assert!((retry_after_ms - 200.0).abs() < 1e-9);
+        // A refusal takes nothing, and waiting the suggested time is enough
+        assert_eq!(bucket.take(30.0, 200.0), Ok(()));
+        // Refill stops at the burst size
+        assert_eq!(bucket.take(51.0, 10_000.0), Err(Refusal::TooLarge(50.0)));
+        assert_eq!(bucket.take(50.0, 10_000.0), Ok(()));
+    }
+
+    #[test]
+    fn test_limit_options() {
+        let limits: ResourceLimits =
+            options_from_json(r#"{"max_batch_size": 16, "max_tokens_per_second": 2000}"#).unwrap();
+        assert_eq!(limits.max_batch_size, 16);
+        let bucket = limits.bucket().unwrap();
+        assert_eq!(bucket.capacity, 2000.0);
+        assert!(ResourceLimits::default().bucket().is_none());
+    }
+
+    #[test]
+    fn test_admits_within_limits() {
+        let mut engine = EmbeddingEngine::new();
+        engine.limits = ResourceLimits {
+            max_batch_size: 2,
+            ..Default::default()
+        };
+        assert!(engine
+            .admit(&[Encoding::default(), Encoding::default()])
+            .is_ok());
+        engine.limits.max_tokens_per_second = 10.0;
+        *engine.rate_limiter.borrow_mut() = engine.limits.bucket();
+        // Empty encodings cost nothing
+        assert!(engine.admit(&[Encoding::default()]).is_ok());
+    }
+
+    #[test]
+    fn test_padding_not_charged() {
+        let tokenizer = Tokenizer::from_str(
+            r#"{
+                "version": "1.0", "truncation": null, "added_tokens": [],
+                "padding": {"strategy": {"Fixed": 128}, "direction": "Right",
+                            "pad_to_multiple_of": null, "pad_id": 0, "pad_type_id": 0,
+                            "pad_token": "[PAD]"},
+                "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+                "post_processor": null, "decoder": null,
+                "model": {"type": "WordLevel", "vocab": {"[PAD]": 0, "cat": 1},
+                          "unk_token": "[PAD]"}
+            }"#,
+        )
+        .unwrap();
+        let encoding = tokenizer.encode("cat cat cat", false).unwrap();
+        assert_eq!(encoding.len(), 128);
+
+        let engine = EmbeddingEngine::new();
+        *engine.rate_limiter.borrow_mut() = Some(TokenBucket::new(1e-9, 10.0, clock::now_ms()));
+        assert!(engine.admit(&[encoding]).is_ok());
+        let available = engine.rate_limiter.borrow().unwrap().available;
+        assert!((available - 7.0).abs() < 1e-3, "{}", available);
+    }
+}
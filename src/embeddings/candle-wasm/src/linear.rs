@@ -0,0 +1,354 @@
+//! Encoder matmuls
+//!
+//! In WASM the dense layers and attention products run on
+//! `kernels::matmul_bt()` instead of candle's matmul; other targets, and any
+//! tensor that isn't contiguous f32 in CPU memory, use candle as before.
+//!
+//! Weight matrices can also be kept in f16 (`WeightPrecision::F16`) to halve
+//! the memory they take. Activations stay f32: the kernel upcasts the weights
+//! block by block as it goes, and f16 layers on any target use it so the
+//! matrices are never converted whole. Q4 weights (`crate::q4`) work the same
+//! way.
+
+use candle_core::{DType, Module, Result, Tensor, D};
+use candle_nn::{Embedding, VarBuilder};
+use half::f16;
+
+use crate::kernels::{matmul_bt, matmul_bt_f16, with_cpu_slice};
+use crate::q4::Q4Matrix;
+
+/// Whether to use the blocked kernel where the operands allow it
+const BLOCKED: bool = cfg!(target_arch = "wasm32");
+
+/// Storage type of the weight matrices (dense layers and embedding tables)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum WeightPrecision {
+    #[default]
+    F32,
+    /// Half the memory; products are still computed and summed in f32
+    F16,
+    /// Dense layers 4-bit quantized (`crate::q4`), other matrices f16;
+    /// vectors only approximate the original model's
+    Q4,
+}
+
+impl WeightPrecision {
+    pub(crate) fn parse(name: &str) -> std::result::Result<Self, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "f32" => Ok(WeightPrecision::F32),
+            "f16" => Ok(WeightPrecision::F16),
+            "q4" => Ok(WeightPrecision::Q4),
+            _ => Err(format!(
+                "Unknown weight precision: {} (expected \"f32\", \"f16\" or \"q4\")",
+                name
+            )),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            WeightPrecision::F32 => "f32",
+            WeightPrecision::F16 => "f16",
+            WeightPrecision::Q4 => "q4",
+        }
+    }
+
+    /// dtype of the weight matrices that aren't kept quantized
+    pub(crate) fn dtype(&self) -> DType {
+        match self {
+            WeightPrecision::F32 => DType::F32,
+            WeightPrecision::F16 | WeightPrecision::Q4 => DType::F16,
+        }
+    }
+}
+
+/// Weight matrix of a `Linear`, `[out, in]`
+enum Weight {
+    /// f32, or f16 storage
+    Dense(Tensor),
+    Q4(Q4Matrix),
+}
+
+/// `x · Wᵀ + b`, as `candle_nn::Linear`
+pub(crate) struct Linear {
+    weight: Weight,
+    bias: Option<Tensor>,
+}
+
+impl Linear {
+    #[cfg(test)]
+    pub(crate) fn new(weight: Tensor, bias: Option<Tensor>) -> Self {
+        Linear {
+            weight: Weight::Dense(weight),
+            bias,
+        }
+    }
+
+    /// `(out, in)`
+    fn dims(&self) -> Result<(usize, usize)> {
+        match &self.weight {
+            Weight::Dense(weight) => weight.dims2(),
+            Weight::Q4(weight) => Ok(weight.dims()),
+        }
+    }
+
+    /// The layer on the blocked kernel, or None if the operands aren't
+    /// contiguous CPU tensors (f32 input; f32, f16 or Q4 weight)
+    fn forward_blocked(&self, xs: &Tensor) -> Result<Option<Tensor>> {
+        let (out_dim, in_dim) = self.dims()?;
+        let Some((&last, batch_dims)) = xs.dims().split_last() else {
+            return Ok(None);
+        };
+        if last != in_dim {
+            return Ok(None);
+        }
+        let rows = xs.elem_count() / in_dim.max(1);
+        let values = with_cpu_slice::<f32, _>(xs, |x| match &self.weight {
+            Weight::Dense(weight) if weight.dtype() == DType::F16 => {
+                with_cpu_slice::<f16, _>(weight, |w| matmul_bt_f16(x, w, rows, out_dim, in_dim))
+            }
+            Weight::Dense(weight) => {
+                with_cpu_slice::<f32, _>(weight, |w| matmul_bt(x, w, rows, out_dim, in_dim))
+            }
+            Weight::Q4(weight) => weight.matmul_bt(x, rows),
+        })
+        .flatten();
+        let Some(values) = values else {
+            return Ok(None);
+        };
+        let mut shape = batch_dims.to_vec();
+        shape.push(out_dim);
+        let ys = Tensor::from_vec(values, shape, xs.device())?;
+        match &self.bias {
+            Some(bias) => ys.broadcast_add(bias).map(Some),
+            None => Ok(Some(ys)),
+        }
+    }
+}
+
+impl Module for Linear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let weight = match &self.weight {
+            Weight::Dense(weight) if weight.dtype() == xs.dtype() => {
+                if BLOCKED {
+                    if let Some(ys) = self.forward_blocked(xs)? {
+                        return Ok(ys);
+                    }
+                }
+                weight.clone()
+            }
+            stored => {
+                // Compact weights always use the kernel on the CPU, and are
+                // only expanded in full for this call elsewhere
+                if let Some(ys) = self.forward_blocked(xs)? {
+                    return Ok(ys);
+                }
+                match stored {
+                    Weight::Dense(weight) => weight.to_dtype(xs.dtype())?,
+                    Weight::Q4(weight) => weight.dequantize(xs.dtype())?,
+                }
+            }
+        };
+        candle_nn::Linear::new(weight, self.bias.clone()).forward(xs)
+    }
+}
+
+/// Load a linear layer, as `candle_nn::linear`, with the weight matrix stored
+/// at `precision` (the bias stays at the VarBuilder's dtype)
+///
+/// Q4 weights from the checkpoint are kept quantized for
+/// `WeightPrecision::Q4` and dequantized otherwise.
+pub(crate) fn linear(
+    in_dim: usize,
+    out_dim: usize,
+    precision: WeightPrecision,
+    vb: VarBuilder,
+) -> Result<Linear> {
+    let bias = Some(vb.get(out_dim, "bias")?);
+    let weight = match Q4Matrix::load(&vb, "weight", (out_dim, in_dim))? {
+        Some(weight) if precision == WeightPrecision::Q4 => Weight::Q4(weight),
+        Some(weight) => Weight::Dense(weight.dequantize(precision.dtype())?),
+        None => Weight::Dense(vb.get_with_hints_dtype(
+            (out_dim, in_dim),
+            "weight",
+            Default::default(),
+            precision.dtype(),
+        )?),
+    };
+    Ok(Linear { weight, bias })
+}
+
+/// Load an embedding table, as `candle_nn::embedding`, stored at `precision`
+///
+/// Lookups return the stored dtype; callers upcast the gathered rows. Q4
+/// tables are dequantized, since a lookup only touches a few rows.
+pub(crate) fn embedding(
+    rows: usize,
+    dim: usize,
+    precision: WeightPrecision,
+    vb: VarBuilder,
+) -> Result<Embedding> {
+    let table = match Q4Matrix::load(&vb, "weight", (rows, dim))? {
+        Some(table) => table.dequantize(precision.dtype())?,
+        None => {
+            vb.get_with_hints_dtype((rows, dim), "weight", Default::default(), precision.dtype())?
+        }
+    };
+    Ok(Embedding::new(table, dim))
+}
+
+/// `a · bᵀ` over the last two dimensions of `[..., n, k]` and `[..., m, k]`
+/// with matching leading dimensions, on the blocked kernel
+fn matmul_bt_blocked(a: &Tensor, b: &Tensor) -> Result<Option<Tensor>> {
+    let (Some((&k, a_lead)), Some((&b_k, b_lead))) = (a.dims().split_last(), b.dims().split_last())
+    else {
+        return Ok(None);
+    };
+    let (Some((&n, a_batch)), Some((&m, b_batch))) = (a_lead.split_last(), b_lead.split_last())
+    else {
+        return Ok(None);
+    };
+    if k != b_k || a_batch != b_batch {
+        return Ok(None);
+    }
+    let batches: usize = a_batch.iter().product();
+    let values = with_cpu_slice::<f32, _>(a, |a| {
+        with_cpu_slice::<f32, _>(b, |b| {
+            let mut out = Vec::with_capacity(batches * n * m);
+            for i in 0..batches {
+                out.extend(matmul_bt(
+                    &a[i * n * k..(i + 1) * n * k],
+                    &b[i * m * k..(i + 1) * m * k],
+                    n,
+                    m,
+                    k,
+                ));
+            }
+            out
+        })
+    })
+    .flatten();
+    let Some(values) = values else {
+        return Ok(None);
+    };
+    let mut shape = a_batch.to_vec();
+    shape.extend([n, m]);
+    Tensor::from_vec(values, shape, a.device()).map(Some)
+}
+
+/// Batched `a · bᵀ`, e.g. attention scores from queries and keys
+pub(crate) fn matmul_transposed(a: &Tensor, b: &Tensor) -> Result<Tensor> {
+    if BLOCKED {
+        if let Some(out) = matmul_bt_blocked(a, b)? {
+            return Ok(out);
+        }
+    }
+    a.matmul(&b.t()?)
+}
+
+/// Batched `a · b`, e.g. attention probabilities applied to values
+pub(crate) fn matmul(a: &Tensor, b: &Tensor) -> Result<Tensor> {
+    if BLOCKED {
+        // The kernel reads both operands along rows, so b is transposed once
+        let b_t = b.transpose(D::Minus1, D::Minus2)?.contiguous()?;
+        if let Some(out) = matmul_bt_blocked(a, &b_t)? {
+            return Ok(out);
+        }
+    }
+    a.matmul(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+
+    fn assert_close(a: &Tensor, b: &Tensor) {
+        assert_eq!(a.dims(), b.dims());
+        let diff = (a - b)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .flatten_all()
+            .unwrap()
+            .max(0)
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!(diff < 1e-4, "max difference {}", diff);
+    }
+
+    fn tensor(shape: &[usize], scale: f64) -> Tensor {
+        let count: usize = shape.iter().product();
+        Tensor::arange(0f32, count as f32, &Device::Cpu)
+            .unwrap()
+            .affine(scale, -1.0)
+            .unwrap()
+            .sin()
+            .unwrap()
+            .reshape(shape)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_blocked_linear_matches_candle() {
+        let (weight, bias) = (tensor(&[5, 6], 0.3), tensor(&[5], 0.7));
+        let layer = Linear::new(weight.clone(), Some(bias.clone()));
+        let xs = tensor(&[2, 3, 6], 0.11);
+        let blocked = layer.forward_blocked(&xs).unwrap().unwrap();
+        let reference = candle_nn::Linear::new(weight, Some(bias));
+        assert_close(&blocked, &reference.forward(&xs).unwrap());
+
+        // Strided input: left to candle
+        let strided = tensor(&[2, 6, 3], 0.11).transpose(1, 2).unwrap();
+        assert!(layer.forward_blocked(&strided).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_f16_weights_on_any_target() {
+        let weight = tensor(&[5, 6], 0.3);
+        let half = Linear::new(
+            weight.to_dtype(DType::F16).unwrap(),
+            Some(tensor(&[5], 0.7)),
+        );
+        let xs = tensor(&[2, 3, 6], 0.11);
+        let ys = half.forward(&xs).unwrap();
+        assert_eq!(ys.dtype(), DType::F32);
+
+        // Same as f32 weights up to f16 rounding of the weights
+        let full = Linear::new(weight, Some(tensor(&[5], 0.7)));
+        let diff = (ys - full.forward(&xs).unwrap())
+            .unwrap()
+            .abs()
+            .unwrap()
+            .flatten_all()
+            .unwrap()
+            .max(0)
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!(diff < 1e-2, "max difference {}", diff);
+
+        // Strided input takes the upcasting fallback
+        let strided = tensor(&[2, 6, 3], 0.11).transpose(1, 2).unwrap();
+        assert_eq!(half.forward(&strided).unwrap().dims(), &[2, 3, 5]);
+    }
+
+    #[test]
+    fn test_blocked_batched_matmuls() {
+        let q = tensor(&[2, 3, 4, 8], 0.05);
+        let k = tensor(&[2, 3, 5, 8], 0.09);
+        let scores = matmul_bt_blocked(&q, &k).unwrap().unwrap();
+        assert_close(&scores, &q.matmul(&k.t().unwrap()).unwrap());
+
+        let v = tensor(&[2, 3, 5, 8], 0.13);
+        let v_t = v.transpose(2, 3).unwrap().contiguous().unwrap();
+        let context = matmul_bt_blocked(&scores, &v_t).unwrap().unwrap();
+        assert_close(&context, &scores.matmul(&v).unwrap());
+
+        // Mismatched batch dimensions are left to candle
+        assert!(matmul_bt_blocked(&q, &tensor(&[3, 5, 8], 0.1))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,304 @@
+//! Load-time overrides (`load_with_options`)
+//!
+//! Some fine-tuned checkpoints ship a tokenizer.json whose special tokens
+//! don't match the ids the model was trained with. Rather than patching files
+//! by hand, callers pass the ids to use and the tokenizer and config JSON are
+//! rewritten before loading, so the fingerprint reflects the change.
+//!
+//! Extra special tokens must already exist in the vocabulary: the embedding
+//! matrix has no rows for new ids.
+//!
+//! A partial `config` is merged over config.json the same way, e.g.
+//! `{ num_hidden_layers: 4 }` to run only the first layers of a layer-dropped
+//! checkpoint.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::EmbeddingEngine;
+
+/// Options for `load_with_options`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct LoadOptions {
+    /// Id of the token prepended to every sequence
+    cls_token_id: Option<u32>,
+    /// Id of the token closing every sequence
+    sep_token_id: Option<u32>,
+    /// Id used to pad batches
+    pad_token_id: Option<u32>,
+    /// Vocabulary entries to treat as special (never split or normalized)
+    special_tokens: Vec<String>,
+    /// Fields replacing those in config.json
+    config: serde_json::Map<String, Value>,
+}
+
+/// Vocabulary entry for an id (WordPiece/BPE map or Unigram list)
+fn vocab_token(tokenizer: &Value, id: u32) -> Option<String> {
+    match &tokenizer["model"]["vocab"] {
+        Value::Object(vocab) => vocab
+            .iter()
+            .find(|(_, v)| v.as_u64() == Some(id as u64))
+            .map(|(token, _)| token.clone()),
+        Value::Array(vocab) => vocab.get(id as usize)?[0].as_str().map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Id of a vocabulary entry
+fn vocab_id(tokenizer: &Value, token: &str) -> Option<u64> {
+    match &tokenizer["model"]["vocab"] {
+        Value::Object(vocab) => vocab.get(token)?.as_u64(),
+        Value::Array(vocab) => vocab
+            .iter()
+            .position(|entry| entry[0].as_str() == Some(token))
+            .map(|id| id as u64),
+        _ => None,
+    }
+}
+
+/// Which end of the sequence a special token sits at
+#[derive(Clone, Copy)]
+enum Role {
+    Cls,
+    Sep,
+}
+
+/// Point the post-processor's CLS or SEP token at `id`
+fn set_role_token(tokenizer: &mut Value, role: Role, id: u32) -> Result<(), String> {
+    let token = vocab_token(tokenizer, id)
+        .ok_or_else(|| format!("Token id {} is not in the vocabulary", id))?;
+    let processor = &mut tokenizer["post_processor"];
+    let kind = processor["type"].as_str().unwrap_or_default().to_string();
+    match kind.as_str() {
+        "BertProcessing" | "RobertaProcessing" => {
+            let key = match role {
+                Role::Cls => "cls",
+                Role::Sep => "sep",
+            };
+            processor[key] = json!([token, id]);
+        }
+        "TemplateProcessing" => {
+            // The first special token of the single-sequence template is the
+            // CLS role, the last the SEP role
+            let specials: Vec<String> = processor["single"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|piece| piece["SpecialToken"]["id"].as_str())
+                .map(str::to_string)
+                .collect();
+            let old = match role {
+                Role::Cls => specials.first(),
+                Role::Sep => specials.last(),
+            }
+            .cloned()
+            .ok_or("Post-processor template has no special tokens")?;
+
+            for template in ["single", "pair"] {
+                for piece in processor[template].as_array_mut().into_iter().flatten() {
+                    if piece["SpecialToken"]["id"].as_str() == Some(old.as_str()) {
+                        piece["SpecialToken"]["id"] = json!(token);
+                    }
+                }
+            }
+            let specials = processor["special_tokens"]
+                .as_object_mut()
+                .ok_or("Post-processor template has no special_tokens")?;
+            specials.remove(&old);
+            specials.insert(
+                token.clone(),
+                json!({"id": token, "ids": [id], "tokens": [token]}),
+            );
+        }
+        "" => return Err("Tokenizer has no post-processor to set special tokens on".to_string()),
+        other => {
+            return Err(format!(
+                "Unsupported post-processor for special tokens: {}",
+                other
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Make a vocabulary entry an added special token
+fn add_special_token(tokenizer: &mut Value, token: &str) -> Result<(), String> {
+    let id = vocab_id(tokenizer, token)
+        .ok_or_else(|| format!("Special token {} is not in the vocabulary", token))?;
+    if !tokenizer["added_tokens"].is_array() {
+        tokenizer["added_tokens"] = json!([]);
+    }
+    let Some(added) = tokenizer["added_tokens"].as_array_mut() else {
+        return Err("Tokenizer has malformed added_tokens".to_string());
+    };
+    match added
+        .iter_mut()
+        .find(|entry| entry["content"].as_str() == Some(token))
+    {
+        Some(entry) => entry["special"] = json!(true),
+        None => added.push(json!({
+            "id": id,
+            "content": token,
+            "single_word": false,
+            "lstrip": false,
+            "rstrip": false,
+            "normalized": false,
+            "special": true,
+        })),
+    }
+    Ok(())
+}
+
+impl LoadOptions {
+    /// Rewrite tokenizer.json and config.json according to the options
+    pub(crate) fn apply(
+        &self,
+        tokenizer_bytes: &[u8],
+        config_bytes: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>), String> {
+        // Unchanged files keep their fingerprint
+        if self.cls_token_id.is_none()
+            && self.sep_token_id.is_none()
+            && self.pad_token_id.is_none()
+            && self.special_tokens.is_empty()
+            && self.config.is_empty()
+        {
+            return Ok((tokenizer_bytes.to_vec(), config_bytes.to_vec()));
+        }
+        let mut tokenizer: Value = serde_json::from_slice(tokenizer_bytes)
+            .map_err(|e| format!("Failed to parse tokenizer: {}", e))?;
+        let mut config: Value = serde_json::from_slice(config_bytes)
+            .map_err(|e| format!("Failed to parse config: {}", e))?;
+
+        if let Some(id) = self.cls_token_id {
+            set_role_token(&mut tokenizer, Role::Cls, id)?;
+        }
+        if let Some(id) = self.sep_token_id {
+            set_role_token(&mut tokenizer, Role::Sep, id)?;
+        }
+        if let Some(id) = self.pad_token_id {
+            let token = vocab_token(&tokenizer, id)
+                .ok_or_else(|| format!("Token id {} is not in the vocabulary", id))?;
+            if tokenizer["padding"].is_object() {
+                tokenizer["padding"]["pad_id"] = json!(id);
+                tokenizer["padding"]["pad_token"] = json!(token);
+            }
+            config["pad_token_id"] = json!(id);
+        }
+        for token in &self.special_tokens {
+            add_special_token(&mut tokenizer, token)?;
+        }
+        let fields = config
+            .as_object_mut()
+            .ok_or("config.json must be a JSON object")?;
+        fields.extend(self.config.clone());
+
+        Ok((
+            tokenizer.to_string().into_bytes(),
+            config.to_string().into_bytes(),
+        ))
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Load the model with special-token and config overrides
+    ///
+    /// Options: `{ cls_token_id?, sep_token_id?, pad_token_id?, special_tokens?:
+    /// string[], config?: object }`. `special_tokens` names vocabulary entries
+    /// (e.g. `"[unused0]"`) to keep whole as domain markers; `config` fields
+    /// (`num_hidden_layers`, `layer_norm_eps`, ...) replace those in
+    /// config.json.
+    #[wasm_bindgen]
+    pub fn load_with_options(
+        &mut self,
+        model_bytes: &[u8],
+        tokenizer_bytes: &[u8],
+        config_bytes: &[u8],
+        options: &JsValue,
+    ) -> Result<(), JsValue> {
+        let options: LoadOptions = parse_options(options)?;
+        let (tokenizer, config) = options
+            .apply(tokenizer_bytes, config_bytes)
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.load(model_bytes, &tokenizer, &config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js::options_from_json;
+
+    fn tokenizer_json() -> Value {
+        json!({
+            "added_tokens": [{"id": 0, "content": "[PAD]", "special": true,
+                              "single_word": false, "lstrip": false, "rstrip": false,
+                              "normalized": false}],
+            "padding": {"pad_id": 0, "pad_token": "[PAD]"},
+            "post_processor": {
+                "type": "TemplateProcessing",
+                "single": [{"SpecialToken": {"id": "[CLS]", "type_id": 0}},
+                           {"Sequence": {"id": "A", "type_id": 0}},
+                           {"SpecialToken": {"id": "[SEP]", "type_id": 0}}],
+                "pair": [],
+                "special_tokens": {
+                    "[CLS]": {"id": "[CLS]", "ids": [3], "tokens": ["[CLS]"]},
+                    "[SEP]": {"id": "[SEP]", "ids": [4], "tokens": ["[SEP]"]}
+                }
+            },
+            "model": {"type": "WordPiece", "vocab": {"[PAD]": 0, "<s>": 1, "</s>": 2,
+                      "[CLS]": 3, "[SEP]": 4, "[unused0]": 5}}
+        })
+    }
+
+    #[test]
+    fn test_rewrites_template_and_padding() {
+        let options: LoadOptions = options_from_json(
+            r#"{"cls_token_id": 1, "sep_token_id": 2, "pad_token_id": 5, "special_tokens": ["[unused0]"]}"#,
+        )
+        .unwrap();
+        let (tokenizer, config) = options
+            .apply(tokenizer_json().to_string().as_bytes(), b"{}")
+            .unwrap();
+        let tokenizer: Value = serde_json::from_slice(&tokenizer).unwrap();
+        let processor = &tokenizer["post_processor"];
+        assert_eq!(processor["single"][0]["SpecialToken"]["id"], "<s>");
+        assert_eq!(processor["single"][2]["SpecialToken"]["id"], "</s>");
+        assert_eq!(processor["special_tokens"]["</s>"]["ids"], json!([2]));
+        assert!(processor["special_tokens"].get("[CLS]").is_none());
+        assert_eq!(tokenizer["padding"]["pad_token"], "[unused0]");
+        assert_eq!(tokenizer["added_tokens"][1]["id"], 5);
+
+        let config: Value = serde_json::from_slice(&config).unwrap();
+        assert_eq!(config["pad_token_id"], 5);
+    }
+
+    #[test]
+    fn test_unknown_tokens_rejected() {
+        let bytes = tokenizer_json().to_string();
+        let options: LoadOptions = options_from_json(r#"{"cls_token_id": 99}"#).unwrap();
+        assert!(options.apply(bytes.as_bytes(), b"{}").is_err());
+        let options: LoadOptions = options_from_json(r#"{"special_tokens": ["<ent>"]}"#).unwrap();
+        assert!(options.apply(bytes.as_bytes(), b"{}").is_err());
+    }
+
+    #[test]
+    fn test_config_override_merged() {
+        let options: LoadOptions =
+            options_from_json(r#"{"config": {"num_hidden_layers": 4, "layer_norm_eps": 1e-6}}"#)
+                .unwrap();
+        let (tokenizer, config) = options
+            .apply(b"{}", br#"{"hidden_size": 384, "num_hidden_layers": 6}"#)
+            .unwrap();
+        let config: Value = serde_json::from_slice(&config).unwrap();
+        assert_eq!(config["num_hidden_layers"], 4);
+        assert_eq!(config["layer_norm_eps"], 1e-6);
+        assert_eq!(config["hidden_size"], 384);
+        assert_eq!(tokenizer, b"{}");
+        assert!(options.apply(b"{}", b"[1]").is_err());
+    }
+}
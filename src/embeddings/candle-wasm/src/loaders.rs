@@ -0,0 +1,287 @@
+//! Runtime-specific model loading helpers
+//!
+//! These wrap the JS snippet in `js/loaders.js` so that model files can be read
+//! from inside the WASM bindings, without relying on the Node-only glue that
+//! callers would otherwise have to write (or patch) themselves.
+
+use js_sys::{ArrayBuffer, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::Blob;
+
+use crate::autodetect::SentenceTransformersFiles;
+use crate::EmbeddingEngine;
+
+#[wasm_bindgen(module = "/js/loaders.js")]
+extern "C" {
+    #[wasm_bindgen(catch)]
+    async fn read_asset(path: &str) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(catch)]
+    async fn read_embedded_asset(path: &str) -> Result<JsValue, JsValue>;
+}
+
+/// Read a file or URL through the JS snippet and copy it into WASM memory
+async fn read_bytes(path: String) -> Result<Vec<u8>, JsValue> {
+    let value = read_asset(&path).await?;
+    Ok(Uint8Array::new(&value).to_vec())
+}
+
+/// Read a Bun compiled-binary asset and copy it into WASM memory
+async fn read_embedded_bytes(path: String) -> Result<Vec<u8>, JsValue> {
+    let value = read_embedded_asset(&path).await?;
+    Ok(Uint8Array::new(&value).to_vec())
+}
+
+/// Bytes copied into WASM memory per read when streaming a model file
+const SOURCE_CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+
+/// Pass a file given as a `Blob`/`File`, `ArrayBuffer` or `Uint8Array` to
+/// `push` in chunks, so only one chunk at a time is copied into WASM memory
+async fn read_source(
+    source: &JsValue,
+    mut push: impl FnMut(&[u8]) -> Result<(), JsValue>,
+) -> Result<(), JsValue> {
+    if let Some(blob) = source.dyn_ref::<Blob>() {
+        let size = blob.size();
+        let mut start = 0.0;
+        while start < size {
+            let end = (start + SOURCE_CHUNK_SIZE as f64).min(size);
+            let slice = blob.slice_with_f64_and_f64(start, end)?;
+            let buffer = JsFuture::from(slice.array_buffer()).await?;
+            push(&Uint8Array::new(&buffer).to_vec())?;
+            start = end;
+        }
+        return Ok(());
+    }
+
+    let bytes = if let Some(bytes) = source.dyn_ref::<Uint8Array>() {
+        bytes.clone()
+    } else if source.is_instance_of::<ArrayBuffer>() {
+        Uint8Array::new(source)
+    } else {
+        return Err(JsValue::from_str(
+            "Expected a Blob, File, ArrayBuffer or Uint8Array",
+        ));
+    };
+    let len = bytes.length();
+    let mut start = 0;
+    while start < len {
+        let end = start.saturating_add(SOURCE_CHUNK_SIZE).min(len);
+        push(&bytes.subarray(start, end).to_vec())?;
+        start = end;
+    }
+    Ok(())
+}
+
+/// Read a whole `Blob`/`File`, `ArrayBuffer` or `Uint8Array` into WASM memory
+async fn read_source_bytes(source: &JsValue) -> Result<Vec<u8>, JsValue> {
+    let mut bytes = Vec::new();
+    read_source(source, |chunk| {
+        bytes.extend_from_slice(chunk);
+        Ok(())
+    })
+    .await?;
+    Ok(bytes)
+}
+
+/// The three files that make up a model, held in WASM memory
+///
+/// Lets the bytes be fetched once (e.g. from Bun's embedded assets) and then
+/// loaded into one or more engines without another round trip through JS.
+#[wasm_bindgen]
+pub struct ModelAssets {
+    model: Vec<u8>,
+    tokenizer: Vec<u8>,
+    config: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl ModelAssets {
+    /// Wrap already-loaded model, tokenizer and config bytes
+    #[wasm_bindgen(constructor)]
+    pub fn new(model: Vec<u8>, tokenizer: Vec<u8>, config: Vec<u8>) -> Self {
+        ModelAssets {
+            model,
+            tokenizer,
+            config,
+        }
+    }
+
+    /// Read model files embedded in a `bun build --compile` binary
+    ///
+    /// Takes the paths produced by static `with { type: 'file' }` imports.
+    #[wasm_bindgen]
+    pub async fn from_embedded(
+        model_path: String,
+        tokenizer_path: String,
+        config_path: String,
+    ) -> Result<ModelAssets, JsValue> {
+        let (model, tokenizer, config) = futures::try_join!(
+            read_embedded_bytes(model_path),
+            read_embedded_bytes(tokenizer_path),
+            read_embedded_bytes(config_path),
+        )?;
+        Ok(ModelAssets::new(model, tokenizer, config))
+    }
+
+    /// Total size of all three files in bytes
+    #[wasm_bindgen]
+    pub fn total_bytes(&self) -> usize {
+        self.model.len() + self.tokenizer.len() + self.config.len()
+    }
+}
+
+impl ModelAssets {
+    /// SafeTensors model weights
+    pub fn model(&self) -> &[u8] {
+        &self.model
+    }
+
+    /// tokenizer.json contents
+    pub fn tokenizer(&self) -> &[u8] {
+        &self.tokenizer
+    }
+
+    /// config.json contents
+    pub fn config(&self) -> &[u8] {
+        &self.config
+    }
+}
+
+/// Join a model directory (or base URL) and a file name
+fn join(base: &str, file: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), file)
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Create an engine and load it from a model directory or base URL
+    ///
+    /// Reads `model.safetensors`, `tokenizer.json` and `config.json` from `base`,
+    /// then applies the sentence-transformers files (`modules.json`,
+    /// `1_Pooling/config.json`, `sentence_bert_config.json`) that exist there;
+    /// see `apply_sentence_transformers_config()`.
+    /// Under Deno plain paths use `Deno.readFile` (requires `--allow-read`) and
+    /// URLs use `fetch`; other runtimes always use `fetch`.
+    ///
+    /// ```js
+    /// import init, { EmbeddingEngine } from './candle_embeddings.js';
+    ///
+    /// await init();
+    /// const engine = await EmbeddingEngine.load_from_path('./assets/models/all-MiniLM-L6-v2');
+    /// ```
+    #[wasm_bindgen]
+    pub async fn load_from_path(base: String) -> Result<EmbeddingEngine, JsValue> {
+        let (model, tokenizer, config) = futures::try_join!(
+            read_bytes(join(&base, "model.safetensors")),
+            read_bytes(join(&base, "tokenizer.json")),
+            read_bytes(join(&base, "config.json")),
+        )?;
+
+        let mut engine = EmbeddingEngine::new();
+        engine.load(&model, &tokenizer, &config)?;
+
+        // Sentence-transformers files are optional; any that can't be read
+        // are taken to be absent
+        let (modules, sentence_bert_config) = futures::join!(
+            read_bytes(join(&base, "modules.json")),
+            read_bytes(join(&base, "sentence_bert_config.json")),
+        );
+        let files = SentenceTransformersFiles::from_bytes(
+            modules.ok().as_deref(),
+            sentence_bert_config.ok().as_deref(),
+        )
+        .map_err(|e| JsValue::from_str(&e))?;
+        let pooling = read_bytes(join(&join(&base, files.pooling_path()), "config.json"))
+            .await
+            .ok();
+        let files = files
+            .with_pooling(pooling.as_deref())
+            .map_err(|e| JsValue::from_str(&e))?;
+        engine.apply_sentence_transformers(&files)?;
+        Ok(engine)
+    }
+
+    /// Create an engine from model files embedded in a `bun build --compile` binary
+    ///
+    /// Bun only embeds files that are statically imported, so the caller passes
+    /// the paths those imports resolve to:
+    ///
+    /// ```js
+    /// import modelPath from './assets/models/all-MiniLM-L6-v2/model.safetensors' with { type: 'file' };
+    /// import tokenizerPath from './assets/models/all-MiniLM-L6-v2/tokenizer.json' with { type: 'file' };
+    /// import configPath from './assets/models/all-MiniLM-L6-v2/config.json' with { type: 'file' };
+    ///
+    /// const engine = await EmbeddingEngine.load_from_embedded_assets(modelPath, tokenizerPath, configPath);
+    /// ```
+    #[wasm_bindgen]
+    pub async fn load_from_embedded_assets(
+        model_path: String,
+        tokenizer_path: String,
+        config_path: String,
+    ) -> Result<EmbeddingEngine, JsValue> {
+        let assets = ModelAssets::from_embedded(model_path, tokenizer_path, config_path).await?;
+
+        let mut engine = EmbeddingEngine::new();
+        engine.load_assets(&assets)?;
+        Ok(engine)
+    }
+
+    /// Create an engine from `Blob`/`File` objects (or `ArrayBuffer`/`Uint8Array`)
+    ///
+    /// For "bring your own model" UIs: files from a drop event or
+    /// `<input type="file">` are read slice by slice and streamed into the
+    /// model, so the weights never have to exist as one JS `ArrayBuffer`.
+    ///
+    /// ```js
+    /// const [model, tokenizer, config] = event.dataTransfer.files;
+    /// const engine = await EmbeddingEngine.load_from_blobs(model, tokenizer, config);
+    /// ```
+    #[wasm_bindgen]
+    pub async fn load_from_blobs(
+        model: JsValue,
+        tokenizer: JsValue,
+        config: JsValue,
+    ) -> Result<EmbeddingEngine, JsValue> {
+        let mut engine = EmbeddingEngine::new();
+        engine.begin_streaming_load();
+        read_source(&model, |chunk| engine.push_model_chunk(chunk)).await?;
+        let tokenizer = read_source_bytes(&tokenizer).await?;
+        let config = read_source_bytes(&config).await?;
+        engine.finish_streaming_load(&tokenizer, &config)?;
+        Ok(engine)
+    }
+
+    /// Load the model from a `ModelAssets` bundle
+    #[wasm_bindgen]
+    pub fn load_assets(&mut self, assets: &ModelAssets) -> Result<(), JsValue> {
+        self.load(assets.model(), assets.tokenizer(), assets.config())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_assets_accessors() {
+        let assets = ModelAssets::new(vec![1, 2, 3], vec![4], vec![5, 6]);
+        assert_eq!(assets.model(), &[1, 2, 3]);
+        assert_eq!(assets.config(), &[5, 6]);
+        assert_eq!(assets.total_bytes(), 6);
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(
+            join("models/minilm", "config.json"),
+            "models/minilm/config.json"
+        );
+        assert_eq!(
+            join("https://cdn/x/", "config.json"),
+            "https://cdn/x/config.json"
+        );
+    }
+}
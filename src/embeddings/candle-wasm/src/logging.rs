@@ -0,0 +1,115 @@
+//! Leveled diagnostics through the `log` crate (`logging` feature)
+//!
+//! Messages go to the JS console. Nothing is emitted until `set_log_level()`
+//! is called, so production builds stay silent. Without the feature the
+//! `debug_log!`/`info_log!` macros compile to nothing.
+
+/// Debug-level trace; a no-op unless the `logging` feature is enabled
+macro_rules! debug_log {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "logging")]
+        log::debug!($($arg)*);
+        // Keep the arguments "used" so disabled traces don't cause warnings
+        #[cfg(not(feature = "logging"))]
+        let _ = format_args!($($arg)*);
+    };
+}
+
+/// Info-level trace; a no-op unless the `logging` feature is enabled
+macro_rules! info_log {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "logging")]
+        log::info!($($arg)*);
+        // Keep the arguments "used" so disabled traces don't cause warnings
+        #[cfg(not(feature = "logging"))]
+        let _ = format_args!($($arg)*);
+    };
+}
+
+#[cfg(feature = "logging")]
+mod console {
+    use log::{LevelFilter, Log, Metadata, Record};
+    use wasm_bindgen::prelude::*;
+
+    /// Logger writing to `console.debug/info/warn/error`
+    struct ConsoleLogger;
+
+    static LOGGER: ConsoleLogger = ConsoleLogger;
+
+    impl Log for ConsoleLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= log::max_level()
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+            let message = format!("[candle-embeddings] {}", record.args());
+            #[cfg(target_arch = "wasm32")]
+            {
+                use log::Level;
+
+                let message = JsValue::from_str(&message);
+                match record.level() {
+                    Level::Error => web_sys::console::error_1(&message),
+                    Level::Warn => web_sys::console::warn_1(&message),
+                    Level::Info => web_sys::console::info_1(&message),
+                    Level::Debug | Level::Trace => web_sys::console::debug_1(&message),
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            eprintln!("{} {}", record.level(), message);
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Parse a level name as accepted by `set_log_level()`
+    pub(super) fn parse_level(level: &str) -> Option<LevelFilter> {
+        match level.to_ascii_lowercase().as_str() {
+            "off" => Some(LevelFilter::Off),
+            "error" => Some(LevelFilter::Error),
+            "warn" => Some(LevelFilter::Warn),
+            "info" => Some(LevelFilter::Info),
+            "debug" => Some(LevelFilter::Debug),
+            "trace" => Some(LevelFilter::Trace),
+            _ => None,
+        }
+    }
+
+    /// Set the log level: "off" (default), "error", "warn", "info", "debug"
+    /// or "trace"
+    ///
+    /// "debug" traces tokenization and truncation decisions, which helps when
+    /// diagnosing embedding quality issues.
+    #[wasm_bindgen]
+    pub fn set_log_level(level: &str) -> Result<(), JsValue> {
+        let filter = parse_level(level).ok_or_else(|| {
+            JsValue::from_str(&format!(
+                "Unknown log level '{}' (expected off, error, warn, info, debug or trace)",
+                level
+            ))
+        })?;
+        // Another logger may already be installed by the host; keep it
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(filter);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "logging")]
+pub use console::set_log_level;
+
+#[cfg(all(test, feature = "logging"))]
+mod tests {
+    use super::console::parse_level;
+    use log::LevelFilter;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("DEBUG"), Some(LevelFilter::Debug));
+        assert_eq!(parse_level("off"), Some(LevelFilter::Off));
+        assert_eq!(parse_level("verbose"), None);
+    }
+}
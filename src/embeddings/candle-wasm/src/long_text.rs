@@ -0,0 +1,138 @@
+//! Piecewise tokenization of very long inputs
+//!
+//! Tokenizing a multi-megabyte document in one call materializes every token
+//! (ids, token strings, offsets and, under truncation, an overflow `Encoding`
+//! per window) before any of it is discarded. Long texts are instead
+//! tokenized in pieces cut at whitespace, where tokenization doesn't depend on
+//! the neighbouring text: embedding tokenizes only as much of a text as
+//! truncation keeps, and `best_spans()` collects offsets one piece at a time.
+
+use tokenizers::{Encoding, Tokenizer};
+
+/// Texts longer than this many bytes are tokenized piecewise
+pub(crate) const LONG_TEXT_BYTES: usize = 16 * 1024;
+
+/// End of the longest prefix of at most `max` bytes that ends before
+/// whitespace, or at a character boundary when there is no whitespace
+fn cut_point(text: &str, max: usize) -> usize {
+    if max >= text.len() {
+        return text.len();
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    match text[..end].rfind(char::is_whitespace) {
+        Some(pos) if pos > 0 => pos,
+        _ => end,
+    }
+}
+
+/// Encode with special tokens, tokenizing only enough of a long text to fill
+/// `limit` tokens and still show that it was truncated
+///
+/// Tokens up to the limit are the same as from encoding the whole text, and
+/// the encoding is still marked as truncated: by overflow when the tokenizer
+/// truncates, or by being longer than `limit` when it doesn't.
+pub(crate) fn encode_prefix(
+    tokenizer: &Tokenizer,
+    text: &str,
+    limit: usize,
+) -> tokenizers::Result<Encoding> {
+    let mut len = LONG_TEXT_BYTES;
+    loop {
+        let end = cut_point(text, len);
+        let encoding = tokenizer.encode(&text[..end], true)?;
+        let truncated = !encoding.get_overflowing().is_empty() || encoding.len() > limit;
+        if truncated || end == text.len() {
+            return Ok(encoding);
+        }
+        len = len.saturating_mul(2);
+    }
+}
+
+/// Byte offsets of every non-empty token in `text`, tokenized a piece at a
+/// time without special tokens (use a tokenizer without truncation)
+pub(crate) fn token_offsets(
+    tokenizer: &Tokenizer,
+    text: &str,
+) -> tokenizers::Result<Vec<(usize, usize)>> {
+    let mut offsets = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let end = start + cut_point(&text[start..], LONG_TEXT_BYTES);
+        let encoding = tokenizer.encode(&text[start..end], false)?;
+        offsets.extend(
+            encoding
+                .get_offsets()
+                .iter()
+                .filter(|(s, e)| e > s)
+                .map(|&(s, e)| (start + s, start + e)),
+        );
+        start = end;
+    }
+    Ok(offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn tokenizer(truncation: Option<usize>) -> Tokenizer {
+        let truncation = match truncation {
+            Some(max) => format!(
+                r#"{{"direction": "Right", "max_length": {}, "strategy": "LongestFirst", "stride": 0}}"#,
+                max
+            ),
+            None => "null".to_string(),
+        };
+        Tokenizer::from_str(&format!(
+            r#"{{
+                "version": "1.0", "truncation": {}, "padding": null, "added_tokens": [],
+                "normalizer": null, "pre_tokenizer": {{"type": "Whitespace"}},
+                "post_processor": null, "decoder": null,
+                "model": {{"type": "WordLevel", "vocab": {{"[UNK]": 0, "cat": 1, "dog": 2}},
+                           "unk_token": "[UNK]"}}
+            }}"#,
+            truncation
+        ))
+        .unwrap()
+    }
+
+    fn long_text() -> String {
+        "cat dog ".repeat(LONG_TEXT_BYTES / 4)
+    }
+
+    #[test]
+    fn test_cut_point() {
+        assert_eq!(cut_point("ab cd", 10), 5);
+        assert_eq!(cut_point("ab cd ef", 6), 5);
+        // No whitespace: cut at a character boundary
+        assert_eq!(cut_point("ééé", 3), 2);
+    }
+
+    #[test]
+    fn test_encode_prefix_matches_full() {
+        let text = long_text();
+        for truncation in [Some(8), None] {
+            let tokenizer = tokenizer(truncation);
+            let full = tokenizer.encode(text.as_str(), true).unwrap();
+            let prefix = encode_prefix(&tokenizer, &text, 8).unwrap();
+            assert_eq!(&prefix.get_ids()[..8], &full.get_ids()[..8]);
+            assert!(!prefix.get_overflowing().is_empty() || prefix.len() > 8);
+            assert!(prefix.len() < full.len() || truncation.is_some());
+        }
+        let short = encode_prefix(&tokenizer(None), "cat dog", 8).unwrap();
+        assert_eq!(short.get_ids(), &[1, 2]);
+    }
+
+    #[test]
+    fn test_token_offsets_cover_document() {
+        let text = long_text();
+        let tokenizer = tokenizer(None);
+        let offsets = token_offsets(&tokenizer, &text).unwrap();
+        let full = tokenizer.encode(text.as_str(), false).unwrap();
+        assert_eq!(offsets, full.get_offsets());
+    }
+}
@@ -0,0 +1,395 @@
+//! Random-hyperplane LSH (`LshIndex`)
+//!
+//! Each signature bit records which side of a random hyperplane an embedding
+//! falls on, so two embeddings agree on a bit with probability
+//! `1 - angle / pi`. Bits are grouped into `bands` of `rows` bits; an
+//! embedding is a candidate for a query when all bits of at least one band
+//! match, which finds close neighbours with high probability while looking
+//! at a small part of the corpus. Candidates are meant to be rescored
+//! exactly; more bands raise recall, more rows per band cut candidates.
+//!
+//! Hyperplanes come from the seed, so signatures computed by different
+//! clients with the same `(dim, bands, rows, seed)` are comparable. For the
+//! same reason `to_bytes()` stores only the settings and each entry's id and
+//! signature: an index built natively loads in a browser, and the other way
+//! round, with its hyperplanes regenerated from the seed.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::rng::Rng;
+
+/// First bytes of `to_bytes()` output
+const MAGIC: &[u8; 4] = b"LSH1";
+
+/// Header: magic, then dim, bands, rows, seed and entry count as u32
+const HEADER_LEN: usize = 24;
+
+/// Most hyperplane values (`bands * rows * dim`) an index may hold
+const MAX_PLANE_VALUES: usize = 1 << 24;
+
+/// Options for `new LshIndex()`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct LshOptions {
+    bands: usize,
+    /// Bits per band, at most 64
+    rows: usize,
+    seed: u32,
+}
+
+impl Default for LshOptions {
+    fn default() -> Self {
+        LshOptions {
+            bands: 16,
+            rows: 8,
+            seed: 0,
+        }
+    }
+}
+
+/// Signature bits packed into 32-bit words, bit `i` in word `i / 32`
+fn signature_words(planes: &[f32], dim: usize, embedding: &[f32]) -> Vec<u32> {
+    let bits = planes.len() / dim;
+    let mut words = vec![0u32; bits.div_ceil(32)];
+    for (i, plane) in planes.chunks_exact(dim).enumerate() {
+        let side: f32 = plane.iter().zip(embedding).map(|(p, v)| p * v).sum();
+        if side >= 0.0 {
+            words[i / 32] |= 1 << (i % 32);
+        }
+    }
+    words
+}
+
+/// Check index settings before any hyperplanes are drawn for them
+fn check_shape(dim: usize, bands: usize, rows: usize) -> Result<(), String> {
+    if dim == 0 || bands == 0 || !(1..=64).contains(&rows) {
+        return Err("dim and bands must be greater than 0 and rows between 1 and 64".to_string());
+    }
+    match bands
+        .checked_mul(rows)
+        .and_then(|bits| bits.checked_mul(dim))
+    {
+        Some(values) if values <= MAX_PLANE_VALUES => Ok(()),
+        _ => Err(format!(
+            "bands * rows * dim must be at most {}",
+            MAX_PLANE_VALUES
+        )),
+    }
+}
+
+/// Bits `start..start + len` of a signature (`len <= 64`)
+fn band_key(words: &[u32], start: usize, len: usize) -> u64 {
+    (0..len).fold(0u64, |key, j| {
+        let bit = start + j;
+        key | ((((words[bit / 32] >> (bit % 32)) & 1) as u64) << j)
+    })
+}
+
+/// Banded LSH buckets over caller-chosen ids
+#[wasm_bindgen]
+pub struct LshIndex {
+    dim: usize,
+    bands: usize,
+    rows: usize,
+    seed: u32,
+    /// `bands * rows` hyperplanes of `dim` values, row-major
+    planes: Vec<f32>,
+    /// Per band, ids by band key
+    buckets: Vec<HashMap<u64, Vec<u32>>>,
+    /// Ids in the order added, for `to_bytes()`
+    ids: Vec<u32>,
+    /// Signature words of each entry of `ids`, back to back
+    signatures: Vec<u32>,
+}
+
+impl LshIndex {
+    /// Empty index with hyperplanes drawn from `seed`
+    fn empty(dim: usize, bands: usize, rows: usize, seed: u32) -> Self {
+        let mut rng = Rng::new(seed as u64);
+        let planes = (0..bands * rows * dim)
+            .map(|_| rng.normal() as f32)
+            .collect();
+        LshIndex {
+            dim,
+            bands,
+            rows,
+            seed,
+            planes,
+            buckets: vec![HashMap::new(); bands],
+            ids: Vec::new(),
+            signatures: Vec::new(),
+        }
+    }
+
+    fn words_per_signature(&self) -> usize {
+        (self.bands * self.rows).div_ceil(32)
+    }
+
+    fn check_dim(&self, len: usize) -> Result<(), JsValue> {
+        if len != self.dim {
+            return Err(JsValue::from_str(&format!(
+                "Embedding has {} values, expected {}",
+                len, self.dim
+            )));
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, id: u32, embedding: &[f32]) {
+        let words = signature_words(&self.planes, self.dim, embedding);
+        self.insert_signature(id, &words);
+    }
+
+    fn insert_signature(&mut self, id: u32, words: &[u32]) {
+        for (band, buckets) in self.buckets.iter_mut().enumerate() {
+            let key = band_key(words, band * self.rows, self.rows);
+            buckets.entry(key).or_default().push(id);
+        }
+        self.ids.push(id);
+        self.signatures.extend_from_slice(words);
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let header = bytes
+            .get(..HEADER_LEN)
+            .filter(|header| header.starts_with(MAGIC))
+            .ok_or("Not an LshIndex (missing LSH1 header)")?;
+        let field = |i: usize| u32::from_le_bytes(header[4 + i * 4..8 + i * 4].try_into().unwrap());
+        let (dim, bands, rows) = (field(0) as usize, field(1) as usize, field(2) as usize);
+        let (seed, count) = (field(3), field(4) as usize);
+        check_shape(dim, bands, rows).map_err(|e| format!("Invalid LshIndex header: {}", e))?;
+        let entry_len = 4 * (1 + (bands * rows).div_ceil(32));
+        let entries = &bytes[HEADER_LEN..];
+        if count.checked_mul(entry_len) != Some(entries.len()) {
+            return Err(format!(
+                "LshIndex data has {} bytes, expected {} entries of {}",
+                entries.len(),
+                count,
+                entry_len
+            ));
+        }
+        let mut index = LshIndex::empty(dim, bands, rows, seed);
+        for entry in entries.chunks_exact(entry_len) {
+            let words: Vec<u32> = entry
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+            index.insert_signature(words[0], &words[1..]);
+        }
+        Ok(index)
+    }
+
+    /// Sorted, distinct ids sharing at least one band with `embedding`
+    fn lookup(&self, embedding: &[f32]) -> Vec<u32> {
+        let words = signature_words(&self.planes, self.dim, embedding);
+        let mut ids: Vec<u32> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter_map(|(band, buckets)| {
+                buckets.get(&band_key(&words, band * self.rows, self.rows))
+            })
+            .flatten()
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+}
+
+#[wasm_bindgen]
+impl LshIndex {
+    /// Empty index for `dim`-dimension embeddings
+    ///
+    /// Options: `{ bands = 16, rows = 8, seed = 0 }`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(dim: usize, options: &JsValue) -> Result<LshIndex, JsValue> {
+        let options: LshOptions = parse_options(options)?;
+        check_shape(dim, options.bands, options.rows).map_err(|e| JsValue::from_str(&e))?;
+        Ok(LshIndex::empty(
+            dim,
+            options.bands,
+            options.rows,
+            options.seed,
+        ))
+    }
+
+    /// Signature of `embedding`: `bands * rows` bits in 32-bit words
+    #[wasm_bindgen]
+    pub fn signature(&self, embedding: &[f32]) -> Result<Vec<u32>, JsValue> {
+        self.check_dim(embedding.len())?;
+        Ok(signature_words(&self.planes, self.dim, embedding))
+    }
+
+    /// Add `embedding` under `id`
+    #[wasm_bindgen]
+    pub fn add(&mut self, id: u32, embedding: &[f32]) -> Result<(), JsValue> {
+        self.check_dim(embedding.len())?;
+        self.insert(id, embedding);
+        Ok(())
+    }
+
+    /// Add row-major `embeddings`, one per id
+    #[wasm_bindgen]
+    pub fn add_batch(&mut self, ids: &[u32], embeddings: &[f32]) -> Result<(), JsValue> {
+        if embeddings.len() != ids.len() * self.dim {
+            return Err(JsValue::from_str(&format!(
+                "Expected {} values for {} ids, got {}",
+                ids.len() * self.dim,
+                ids.len(),
+                embeddings.len()
+            )));
+        }
+        for (&id, embedding) in ids.iter().zip(embeddings.chunks_exact(self.dim)) {
+            self.insert(id, embedding);
+        }
+        Ok(())
+    }
+
+    /// Ids sharing a band with `embedding`, ascending, to rescore exactly
+    #[wasm_bindgen]
+    pub fn candidates(&self, embedding: &[f32]) -> Result<Vec<u32>, JsValue> {
+        self.check_dim(embedding.len())?;
+        Ok(self.lookup(embedding))
+    }
+
+    /// Serialize the index: `LSH1`, then dim, bands, rows, seed and the
+    /// entry count as little-endian u32, then per entry its id and signature
+    /// words (as from `signature()`), all u32
+    #[wasm_bindgen]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(HEADER_LEN + (self.ids.len() + self.signatures.len()) * 4);
+        bytes.extend_from_slice(MAGIC);
+        for field in [
+            self.dim as u32,
+            self.bands as u32,
+            self.rows as u32,
+            self.seed,
+            self.ids.len() as u32,
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        let words = self.words_per_signature();
+        for (&id, signature) in self.ids.iter().zip(self.signatures.chunks_exact(words)) {
+            bytes.extend_from_slice(&id.to_le_bytes());
+            for word in signature {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Restore an index saved with `to_bytes()`
+    #[wasm_bindgen]
+    pub fn from_bytes(bytes: &[u8]) -> Result<LshIndex, JsValue> {
+        LshIndex::parse(bytes).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Number of embeddings added
+    #[wasm_bindgen(getter)]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn bands(&self) -> usize {
+        self.bands
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_key() {
+        let words = [0b1011_0000u32, 1];
+        assert_eq!(band_key(&words, 4, 4), 0b1011);
+        assert_eq!(band_key(&words, 30, 4), 0b0100);
+    }
+
+    #[test]
+    fn test_candidates_are_close() {
+        let dim = 32;
+        let mut index = LshIndex::empty(dim, 8, 6, 3);
+        let base: Vec<f32> = (0..dim).map(|i| (i as f32 * 0.7).sin()).collect();
+        let near: Vec<f32> = base.iter().map(|v| v + 0.01).collect();
+        let far: Vec<f32> = base.iter().map(|v| -v).collect();
+        index.insert(1, &near);
+        index.insert(2, &far);
+
+        assert_eq!(index.lookup(&base), vec![1]);
+        assert_eq!(index.lookup(&far), vec![2]);
+        assert_eq!(signature_words(&index.planes, dim, &base).len(), 2);
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let dim = 16;
+        let mut index = LshIndex::empty(dim, 5, 10, 7);
+        let vectors: Vec<Vec<f32>> = (0..20)
+            .map(|i| {
+                (0..dim)
+                    .map(|d| ((i * dim + d) as f32 * 0.37).sin())
+                    .collect()
+            })
+            .collect();
+        for (i, v) in vectors.iter().enumerate() {
+            index.insert(i as u32 * 3, v);
+        }
+        let bytes = index.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 20 * 4 * 3);
+        let restored = LshIndex::parse(&bytes).unwrap();
+        assert_eq!(restored.planes, index.planes);
+        assert_eq!(restored.len(), 20);
+        for v in &vectors {
+            assert_eq!(restored.lookup(v), index.lookup(v));
+        }
+        assert_eq!(restored.to_bytes(), bytes);
+
+        assert!(LshIndex::parse(b"LSH1").is_err());
+        assert!(LshIndex::parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(LshIndex::parse(b"PQC1\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0").is_err());
+    }
+
+    #[test]
+    fn test_rejects_oversized_header() {
+        let header = |dim: u32, bands: u32, rows: u32| {
+            let mut bytes = MAGIC.to_vec();
+            for field in [dim, bands, rows, 0, 0] {
+                bytes.extend_from_slice(&field.to_le_bytes());
+            }
+            bytes
+        };
+        assert!(LshIndex::parse(&header(16, 5, 10)).is_ok());
+        assert!(LshIndex::parse(&header(u32::MAX, 16, 8)).is_err());
+        assert!(LshIndex::parse(&header(384, u32::MAX, 64)).is_err());
+        assert!(LshIndex::parse(&header(1 << 20, 1 << 10, 8)).is_err());
+    }
+}
@@ -0,0 +1,328 @@
+//! Matching new items against existing ones (`Matcher`)
+//!
+//! Support-ticket dedup and duplicate-question detection all ask the same
+//! thing: does this new text match something already seen? A `Matcher`
+//! keeps the embeddings of the existing items, answers `match()` with the
+//! ones above its threshold, and learns from feedback: `confirm()` and
+//! `reject()` label the scores of the last `match()`, and once both kinds of
+//! label exist a calibration is fitted to them (see `calibration`). The
+//! threshold then follows the fitted match probability for the matcher's
+//! strictness, and matches carry that probability.
+
+use std::collections::HashMap;
+
+use js_sys::{Array, Float32Array, Uint32Array};
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::calibration::{Platt, Strictness};
+use crate::js::parse_options;
+use crate::{cosine_similarity, js_array_to_strings, EmbeddingEngine};
+
+/// Options for `new Matcher()`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct MatcherOptions {
+    /// "loose", "normal" or "strict"
+    strictness: String,
+}
+
+impl Default for MatcherOptions {
+    fn default() -> Self {
+        MatcherOptions {
+            strictness: "normal".to_string(),
+        }
+    }
+}
+
+/// Existing items scoring above the threshold, from `Matcher.match()`
+#[wasm_bindgen]
+pub struct Matches {
+    ids: Vec<u32>,
+    scores: Vec<f32>,
+    probabilities: Option<Vec<f32>>,
+}
+
+#[wasm_bindgen]
+impl Matches {
+    /// Ids of the matching items, best first
+    #[wasm_bindgen(getter)]
+    pub fn ids(&self) -> Uint32Array {
+        Uint32Array::from(&self.ids[..])
+    }
+
+    /// Cosine similarities matching `ids`
+    #[wasm_bindgen(getter)]
+    pub fn scores(&self) -> Float32Array {
+        Float32Array::from(&self.scores[..])
+    }
+
+    /// Match probabilities, or `undefined` without a calibration
+    #[wasm_bindgen(getter)]
+    pub fn probabilities(&self) -> Option<Float32Array> {
+        self.probabilities.as_deref().map(Float32Array::from)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// Embedded items to match new texts against, with a threshold tuned by
+/// feedback
+#[wasm_bindgen]
+pub struct Matcher {
+    strictness: Strictness,
+    threshold: f32,
+    /// Engine calibration at creation, then the one fitted to feedback
+    calibration: Option<Platt>,
+    ids: Vec<u32>,
+    /// Row of each id in `vectors`
+    rows: HashMap<u32, usize>,
+    vectors: Vec<f32>,
+    dim: usize,
+    /// Scores of the last `match()`, by id
+    last_scores: HashMap<u32, f32>,
+    /// Labeled scores from `confirm()`/`reject()`
+    feedback: Vec<(f32, bool)>,
+}
+
+impl Matcher {
+    fn insert(&mut self, id: u32, vector: &[f32]) -> Result<(), JsValue> {
+        if self.ids.is_empty() {
+            self.dim = vector.len();
+        } else if vector.len() != self.dim {
+            return Err(JsValue::from_str(&format!(
+                "Embedding has {} values but the matcher holds {}",
+                vector.len(),
+                self.dim
+            )));
+        }
+        match self.rows.get(&id) {
+            Some(&row) => {
+                self.vectors[row * self.dim..(row + 1) * self.dim].copy_from_slice(vector)
+            }
+            None => {
+                self.rows.insert(id, self.ids.len());
+                self.ids.push(id);
+                self.vectors.extend_from_slice(vector);
+            }
+        }
+        Ok(())
+    }
+
+    /// Up to `k` `(id, score)` at or above `threshold`, best first (ties go
+    /// to the lower id)
+    fn ranked(&self, vector: &[f32], k: usize, threshold: f32) -> Vec<(u32, f32)> {
+        if self.dim == 0 {
+            return Vec::new();
+        }
+        let mut scored: Vec<(u32, f32)> = self
+            .vectors
+            .chunks_exact(self.dim)
+            .zip(&self.ids)
+            .map(|(row, &id)| (id, cosine_similarity(vector, row)))
+            .filter(|&(_, score)| score >= threshold)
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored.truncate(k);
+        scored
+    }
+
+    /// Record feedback on `id` from the last `match()` and refit
+    fn label(&mut self, id: u32, matched: bool) -> Result<(), JsValue> {
+        let score = *self.last_scores.get(&id).ok_or_else(|| {
+            JsValue::from_str(&format!("Item {} was not in the last match() results", id))
+        })?;
+        self.feedback.push((score, matched));
+        self.refit();
+        Ok(())
+    }
+
+    /// Fit the calibration to the feedback, keeping the previous one when
+    /// there aren't both kinds of label or matches don't score higher
+    fn refit(&mut self) {
+        let (scores, labels): (Vec<f32>, Vec<bool>) = self.feedback.iter().copied().unzip();
+        if let Ok(platt) = Platt::fit(&scores, &labels) {
+            self.calibration = Some(platt);
+            self.threshold = platt.threshold(self.strictness.probability());
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Matcher {
+    /// Empty matcher starting from `engine`'s threshold at `strictness`
+    ///
+    /// Options: `{ strictness: "loose" | "normal" | "strict" }` (default
+    /// "normal").
+    #[wasm_bindgen(constructor)]
+    pub fn new(engine: &EmbeddingEngine, options: &JsValue) -> Result<Matcher, JsValue> {
+        let options: MatcherOptions = parse_options(options)?;
+        let strictness =
+            Strictness::from_name(&options.strictness).map_err(|e| JsValue::from_str(&e))?;
+        Ok(Matcher {
+            strictness,
+            threshold: engine.similarity_threshold(&options.strictness)?,
+            calibration: engine.calibration,
+            ids: Vec::new(),
+            rows: HashMap::new(),
+            vectors: Vec::new(),
+            dim: 0,
+            last_scores: HashMap::new(),
+            feedback: Vec::new(),
+        })
+    }
+
+    /// Add an existing item, replacing any with the same id
+    #[wasm_bindgen]
+    pub fn add(&mut self, engine: &EmbeddingEngine, id: u32, text: &str) -> Result<(), JsValue> {
+        let embeddings = engine.embed_matrix(&[text.to_string()])?;
+        self.insert(id, embeddings.row(0))
+    }
+
+    /// Add existing items, one id per text
+    #[wasm_bindgen]
+    pub fn add_batch(
+        &mut self,
+        engine: &EmbeddingEngine,
+        ids: &[u32],
+        texts: &Array,
+    ) -> Result<(), JsValue> {
+        let texts = js_array_to_strings(texts)?;
+        if texts.len() != ids.len() {
+            return Err(JsValue::from_str(&format!(
+                "Got {} ids for {} texts",
+                ids.len(),
+                texts.len()
+            )));
+        }
+        if texts.is_empty() {
+            return Ok(());
+        }
+        let embeddings = engine.embed_matrix(&texts)?;
+        for (i, &id) in ids.iter().enumerate() {
+            self.insert(id, embeddings.row(i))?;
+        }
+        Ok(())
+    }
+
+    /// Up to `k` existing items matching `text`, best first
+    ///
+    /// `threshold` overrides the matcher's own for this call. The results
+    /// are what `confirm()` and `reject()` refer to.
+    #[wasm_bindgen(js_name = "match")]
+    pub fn match_text(
+        &mut self,
+        engine: &EmbeddingEngine,
+        text: &str,
+        k: usize,
+        threshold: Option<f32>,
+    ) -> Result<Matches, JsValue> {
+        let embeddings = engine.embed_matrix(&[text.to_string()])?;
+        let ranked = self.ranked(embeddings.row(0), k, threshold.unwrap_or(self.threshold));
+        self.last_scores = ranked.iter().copied().collect();
+        let probabilities = self
+            .calibration
+            .map(|platt| ranked.iter().map(|&(_, s)| platt.probability(s)).collect());
+        Ok(Matches {
+            ids: ranked.iter().map(|&(id, _)| id).collect(),
+            scores: ranked.iter().map(|&(_, s)| s).collect(),
+            probabilities,
+        })
+    }
+
+    /// Mark `id` from the last `match()` as a true match
+    #[wasm_bindgen]
+    pub fn confirm(&mut self, id: u32) -> Result<(), JsValue> {
+        self.label(id, true)
+    }
+
+    /// Mark `id` from the last `match()` as not a match
+    #[wasm_bindgen]
+    pub fn reject(&mut self, id: u32) -> Result<(), JsValue> {
+        self.label(id, false)
+    }
+
+    /// Cosine similarity a match needs, as tuned so far
+    #[wasm_bindgen(getter)]
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// Number of items held
+    #[wasm_bindgen(getter)]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Number of `confirm()`/`reject()` labels recorded
+    #[wasm_bindgen(getter)]
+    pub fn feedback_count(&self) -> usize {
+        self.feedback.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher() -> Matcher {
+        Matcher {
+            strictness: Strictness::Normal,
+            threshold: 0.5,
+            calibration: None,
+            ids: Vec::new(),
+            rows: HashMap::new(),
+            vectors: Vec::new(),
+            dim: 0,
+            last_scores: HashMap::new(),
+            feedback: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_ranked_above_threshold() {
+        let mut matcher = matcher();
+        matcher.insert(7, &[1.0, 0.0]).unwrap();
+        matcher.insert(3, &[0.6, 0.8]).unwrap();
+        matcher.insert(9, &[0.0, 1.0]).unwrap();
+        assert_eq!(
+            matcher.ranked(&[1.0, 0.0], 5, 0.5),
+            vec![(7, 1.0), (3, 0.6)]
+        );
+        assert_eq!(matcher.ranked(&[1.0, 0.0], 1, 0.5), vec![(7, 1.0)]);
+
+        // Re-adding an id replaces its vector
+        matcher.insert(7, &[0.0, 1.0]).unwrap();
+        assert_eq!(matcher.len(), 3);
+        assert_eq!(matcher.ranked(&[1.0, 0.0], 5, 0.5), vec![(3, 0.6)]);
+    }
+
+    #[test]
+    fn test_feedback_moves_threshold() {
+        let mut matcher = matcher();
+        for (i, score) in [0.55f32, 0.6, 0.65, 0.8, 0.85, 0.9].iter().enumerate() {
+            matcher.last_scores.insert(i as u32, *score);
+        }
+        matcher.label(0, false).unwrap();
+        // One kind of label isn't enough to fit
+        assert_eq!(matcher.threshold, 0.5);
+        for (id, matched) in [(1, false), (2, false), (3, true), (4, true), (5, true)] {
+            matcher.label(id, matched).unwrap();
+        }
+        assert!(matcher.calibration.is_some());
+        assert!(matcher.threshold > 0.65 && matcher.threshold < 0.8);
+    }
+}
@@ -0,0 +1,149 @@
+//! Working-set budget for inference
+//!
+//! A forward pass allocates activations proportional to batch x sequence
+//! length, plus batch x heads x sequence² for the attention scores. WASM linear
+//! memory never shrinks, so one large `embed_batch` call can permanently grow
+//! the instance by hundreds of MB, which is enough to get a mobile Safari tab
+//! killed. With a budget set, batches are split into micro-batches whose
+//! estimated working set fits, and inputs that can't fit even one at a time are
+//! refused with a `MemoryBudgetError` before anything is allocated.
+
+use wasm_bindgen::prelude::*;
+
+use crate::bert::Config as BertConfig;
+use crate::EmbeddingEngine;
+
+/// Dimensions that determine activation memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ModelShape {
+    hidden: usize,
+    heads: usize,
+    intermediate: usize,
+    layers: usize,
+}
+
+impl ModelShape {
+    pub(crate) fn from_config(config: &BertConfig) -> Self {
+        ModelShape {
+            hidden: config.hidden_size,
+            heads: config.num_attention_heads,
+            intermediate: config.intermediate_size,
+            layers: config.num_hidden_layers,
+        }
+    }
+
+    /// Assume standard BERT proportions for models that only report their
+    /// hidden size
+    #[cfg(any(feature = "onnx", test))]
+    pub(crate) fn from_hidden_size(hidden: usize) -> Self {
+        ModelShape {
+            hidden,
+            heads: (hidden / 64).max(1),
+            intermediate: 4 * hidden,
+            layers: 12,
+        }
+    }
+
+    /// Estimated peak bytes allocated while embedding `batch` sequences of
+    /// `seq_len` tokens, excluding the weights
+    ///
+    /// Counts the live f32 tensors of one encoder layer (q/k/v, context and
+    /// output projections, the feed-forward intermediate before and after the
+    /// activation, scores and softmax), every layer's hidden state when an
+    /// intermediate layer is pooled, and the masked copy of the hidden state
+    /// that weighted pooling modes build.
+    pub(crate) fn working_set_bytes(
+        &self,
+        batch: usize,
+        seq_len: usize,
+        keep_hidden_states: bool,
+    ) -> usize {
+        let tokens = batch.saturating_mul(seq_len);
+        let per_token = 6 * self.hidden + 2 * self.intermediate;
+        let scores = 2 * self.heads * seq_len.saturating_mul(seq_len);
+        let kept = if keep_hidden_states {
+            (self.layers + 1) * self.hidden
+        } else {
+            0
+        };
+        let pooling = self.hidden;
+        let floats = tokens
+            .saturating_mul(per_token + kept + pooling)
+            .saturating_add(batch.saturating_mul(scores));
+        floats.saturating_mul(std::mem::size_of::<f32>())
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Cap the memory a single inference may allocate, in bytes (0 removes the
+    /// cap)
+    ///
+    /// Covers activations only, not the loaded weights. Larger batches are
+    /// split automatically; a text too long to fit on its own fails with an
+    /// error whose `name` is `MemoryBudgetError`.
+    #[wasm_bindgen]
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = (bytes > 0).then_some(bytes);
+    }
+
+    /// Current memory budget in bytes (0 when unlimited)
+    #[wasm_bindgen]
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget.unwrap_or(0)
+    }
+
+    /// Cap the padded tokens (batch size x longest sequence) processed in one
+    /// forward pass (0 removes the cap)
+    ///
+    /// Batches are packed greedily in input order, so short texts share a
+    /// pass and long ones get fewer companions. A text longer than the cap
+    /// runs on its own.
+    #[wasm_bindgen]
+    pub fn set_max_tokens_per_forward(&mut self, tokens: usize) {
+        self.max_tokens_per_forward = (tokens > 0).then_some(tokens);
+    }
+
+    /// Current token cap per forward pass (0 when unlimited)
+    #[wasm_bindgen]
+    pub fn max_tokens_per_forward(&self) -> usize {
+        self.max_tokens_per_forward.unwrap_or(0)
+    }
+
+    /// How many sequences of `seq_len` tokens fit in one batch under the
+    /// budget (0 if not even one fits; unlimited without a budget or model)
+    #[wasm_bindgen]
+    pub fn safe_batch_size(&self, seq_len: usize) -> usize {
+        let (Some(budget), Some(cost)) = (self.memory_budget, self.batch_cost()) else {
+            return usize::MAX;
+        };
+        let per_sequence = cost(1, seq_len).max(1);
+        budget / per_sequence
+    }
+}
+
+impl EmbeddingEngine {
+    /// Working-set estimate `(batch, seq_len) -> bytes` for the loaded model
+    /// and current pooling settings
+    pub(crate) fn batch_cost(&self) -> Option<impl Fn(usize, usize) -> usize> {
+        let shape = self.model.as_ref()?.memory_shape();
+        let keep_hidden_states = self.pooling_layer != crate::bert::LayerSelection::Last;
+        Some(move |batch, seq_len| shape.working_set_bytes(batch, seq_len, keep_hidden_states))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_working_set_estimate() {
+        let shape = ModelShape::from_hidden_size(384);
+        assert_eq!(shape.heads, 6);
+        let one = shape.working_set_bytes(1, 128, false);
+        assert_eq!(shape.working_set_bytes(4, 128, false), 4 * one);
+        assert!(shape.working_set_bytes(1, 128, true) > one);
+        // Attention scores make cost superlinear in sequence length
+        assert!(shape.working_set_bytes(1, 256, false) > 2 * one);
+    }
+}
@@ -0,0 +1,99 @@
+//! Description of the loaded model (`model_info()`)
+//!
+//! Mostly for diagnostics and UIs: what kind of encoder is running, how big
+//! it is, and whether its weights were quantized, which changes how closely
+//! its vectors match the original model's.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{q4, EmbeddingEngine, Encoder};
+
+/// The loaded model, as returned by `model_info()`
+#[wasm_bindgen]
+pub struct ModelInfo {
+    architecture: String,
+    dimension: usize,
+    layers: usize,
+    weight_precision: String,
+    quantized: bool,
+    weight_bytes: usize,
+}
+
+#[wasm_bindgen]
+impl ModelInfo {
+    /// `model_type` from config.json ("bert" if unset), or "onnx"
+    #[wasm_bindgen(getter)]
+    pub fn architecture(&self) -> String {
+        self.architecture.clone()
+    }
+
+    /// Length of the embedding vectors
+    #[wasm_bindgen(getter)]
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// Number of encoder layers (0 if unknown)
+    #[wasm_bindgen(getter)]
+    pub fn layers(&self) -> usize {
+        self.layers
+    }
+
+    /// Storage of the weight matrices: "f32", "f16" or "q4" (see
+    /// `set_weight_precision()`)
+    #[wasm_bindgen(getter)]
+    pub fn weight_precision(&self) -> String {
+        self.weight_precision.clone()
+    }
+
+    /// Whether the weights went through 4-bit quantization, either at load
+    /// time or in the checkpoint itself, so vectors only approximate the
+    /// original model's
+    #[wasm_bindgen(getter)]
+    pub fn quantized(&self) -> bool {
+        self.quantized
+    }
+
+    /// Bytes of the loaded weights as stored (0 for ONNX models)
+    #[wasm_bindgen(getter)]
+    pub fn weight_bytes(&self) -> usize {
+        self.weight_bytes
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Describe the loaded model
+    #[wasm_bindgen]
+    pub fn model_info(&self) -> Result<ModelInfo, JsValue> {
+        let model = self
+            .model
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Model not loaded. Call load() first."))?;
+        let (architecture, layers, weight_precision) = match model {
+            Encoder::Bert(model) => {
+                let config = model.config();
+                (
+                    config.model_type.as_deref().unwrap_or("bert").to_string(),
+                    config.num_hidden_layers,
+                    config.weight_precision.name(),
+                )
+            }
+            #[cfg(feature = "onnx")]
+            Encoder::Onnx(_) => ("onnx".to_string(), 0, "f32"),
+        };
+        let weights = self.weights.as_ref();
+        Ok(ModelInfo {
+            architecture,
+            dimension: self.dimension(),
+            layers,
+            weight_precision: weight_precision.to_string(),
+            quantized: weights.is_some_and(q4::is_quantized),
+            weight_bytes: weights
+                .into_iter()
+                .flat_map(|weights| weights.values())
+                .map(|tensor| tensor.elem_count() * tensor.dtype().size_in_bytes())
+                .sum(),
+        })
+    }
+}
@@ -0,0 +1,258 @@
+//! Overrides for the tokenizer's text normalization
+//!
+//! tokenizer.json fixes whether text is lowercased, accent-stripped or
+//! Unicode-normalized. `set_normalization()` toggles those behaviours on top of
+//! what the file specifies, leaving every other normalization step alone. The
+//! edits are made on the normalizer's JSON form: a `BertNormalizer` has its
+//! flags flipped, and other normalizers get steps added or removed.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokenizers::NormalizerWrapper;
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::EmbeddingEngine;
+
+/// Options for `set_normalization`; unset fields keep the current behaviour
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct NormalizationOptions {
+    lowercase: Option<bool>,
+    strip_accents: Option<bool>,
+    /// "nfc", "nfd", "nfkc", "nfkd" or "none"
+    unicode: Option<String>,
+}
+
+const UNICODE_FORMS: [&str; 4] = ["NFC", "NFD", "NFKC", "NFKD"];
+
+fn is_type(step: &Value, name: &str) -> bool {
+    step.get("type").and_then(Value::as_str) == Some(name)
+}
+
+/// Whether a normalizer config lowercases its input
+fn lowercases(config: &Value) -> bool {
+    if is_type(config, "Sequence") {
+        return config["normalizers"]
+            .as_array()
+            .is_some_and(|steps| steps.iter().any(lowercases));
+    }
+    is_type(config, "Lowercase")
+        || (is_type(config, "BertNormalizer") && config["lowercase"].as_bool() == Some(true))
+}
+
+/// Apply the options to a normalizer config, returning the new config (None
+/// for no normalization)
+fn updated_config(
+    current: Option<Value>,
+    options: &NormalizationOptions,
+) -> Result<Option<Value>, String> {
+    let mut steps = match current {
+        None => Vec::new(),
+        Some(mut config) if is_type(&config, "Sequence") => {
+            match config.get_mut("normalizers").map(Value::take) {
+                Some(Value::Array(steps)) => steps,
+                _ => return Err("Malformed Sequence normalizer".to_string()),
+            }
+        }
+        Some(step) => vec![step],
+    };
+    let bert = steps.iter().position(|s| is_type(s, "BertNormalizer"));
+
+    if let Some(index) = bert {
+        let step = &mut steps[index];
+        let lowercase = step["lowercase"].as_bool().unwrap_or(false);
+        // Unset strip_accents follows lowercase; pin it so toggling one flag
+        // doesn't silently change the other
+        if step["strip_accents"].is_null() {
+            step["strip_accents"] = json!(lowercase);
+        }
+        if let Some(lowercase) = options.lowercase {
+            step["lowercase"] = json!(lowercase);
+        }
+        if let Some(strip) = options.strip_accents {
+            step["strip_accents"] = json!(strip);
+        }
+    } else {
+        match options.lowercase {
+            Some(true) if !steps.iter().any(|s| is_type(s, "Lowercase")) => {
+                steps.push(json!({"type": "Lowercase"}))
+            }
+            Some(false) => steps.retain(|s| !is_type(s, "Lowercase")),
+            _ => {}
+        }
+        match options.strip_accents {
+            Some(true) if !steps.iter().any(|s| is_type(s, "StripAccents")) => {
+                // Accents can only be removed once decomposed into combining marks
+                steps.push(json!({"type": "NFD"}));
+                steps.push(json!({"type": "StripAccents"}));
+            }
+            Some(false) => steps.retain(|s| !is_type(s, "StripAccents")),
+            _ => {}
+        }
+    }
+
+    if let Some(form) = &options.unicode {
+        let form = form.to_ascii_uppercase();
+        if form != "NONE" && !UNICODE_FORMS.contains(&form.as_str()) {
+            return Err(format!(
+                "Unknown Unicode normalization form: {} (expected nfc, nfd, nfkc, nfkd or none)",
+                form.to_ascii_lowercase()
+            ));
+        }
+        steps.retain(|s| !UNICODE_FORMS.iter().any(|f| is_type(s, f)));
+        if form != "NONE" {
+            steps.insert(0, json!({ "type": form }));
+        }
+    }
+
+    Ok(match steps.len() {
+        0 => None,
+        1 => steps.pop(),
+        _ => Some(json!({"type": "Sequence", "normalizers": steps})),
+    })
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Change how text is normalized before tokenization
+    ///
+    /// Options: `{ lowercase?: boolean, strip_accents?: boolean, unicode?:
+    /// "nfc" | "nfd" | "nfkc" | "nfkd" | "none" }`. Unset options keep the
+    /// tokenizer.json behaviour. Vectors change accordingly, and so does
+    /// `compatibility_fingerprint()`.
+    #[wasm_bindgen]
+    pub fn set_normalization(&mut self, options: &JsValue) -> Result<(), JsValue> {
+        let options: NormalizationOptions = parse_options(options)?;
+        let tokenizer = self.tokenizer.as_ref().ok_or_else(|| {
+            JsValue::from_str("Tokenizer not loaded. Call load_embedded() first.")
+        })?;
+        let current = tokenizer
+            .get_normalizer()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|e| JsValue::from_str(&format!("Failed to read normalizer: {}", e)))?;
+        let updated = updated_config(current, &options).map_err(|e| JsValue::from_str(&e))?;
+        self.apply_normalizer(updated.map(|config| config.to_string()))
+    }
+
+    /// Current normalizer configuration as JSON (`null` for none)
+    #[wasm_bindgen]
+    pub fn normalization(&self) -> Result<String, JsValue> {
+        let tokenizer = self.tokenizer.as_ref().ok_or_else(|| {
+            JsValue::from_str("Tokenizer not loaded. Call load_embedded() first.")
+        })?;
+        serde_json::to_string(&tokenizer.get_normalizer())
+            .map_err(|e| JsValue::from_str(&format!("Failed to read normalizer: {}", e)))
+    }
+}
+
+impl EmbeddingEngine {
+    /// Lowercase text before tokenization, unless the tokenizer already does
+    pub(crate) fn lowercase_input(&mut self) -> Result<(), JsValue> {
+        let tokenizer = self.tokenizer.as_ref().ok_or_else(|| {
+            JsValue::from_str("Tokenizer not loaded. Call load_embedded() first.")
+        })?;
+        let current = tokenizer
+            .get_normalizer()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|e| JsValue::from_str(&format!("Failed to read normalizer: {}", e)))?;
+        if current.as_ref().is_some_and(lowercases) {
+            return Ok(());
+        }
+        let options = NormalizationOptions {
+            lowercase: Some(true),
+            ..Default::default()
+        };
+        let updated = updated_config(current, &options).map_err(|e| JsValue::from_str(&e))?;
+        self.apply_normalizer(updated.map(|config| config.to_string()))
+    }
+
+    /// Install a normalizer given as JSON, recording it as an override of
+    /// tokenizer.json
+    pub(crate) fn apply_normalizer(&mut self, config: Option<String>) -> Result<(), JsValue> {
+        let normalizer: Option<NormalizerWrapper> = config
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()
+            .map_err(|e| JsValue::from_str(&format!("Invalid normalizer: {}", e)))?;
+        let tokenizer = self.tokenizer.as_mut().ok_or_else(|| {
+            JsValue::from_str("Tokenizer not loaded. Call load_embedded() first.")
+        })?;
+        tokenizer.with_normalizer(normalizer);
+        self.custom_normalizer = Some(config.unwrap_or_else(|| "null".to_string()));
+        self.token_cache.borrow_mut().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(json: &str) -> NormalizationOptions {
+        crate::js::options_from_json(json).unwrap()
+    }
+
+    fn bert() -> Value {
+        json!({"type": "BertNormalizer", "clean_text": true, "handle_chinese_chars": true,
+               "strip_accents": null, "lowercase": true})
+    }
+
+    #[test]
+    fn test_bert_flags_toggled() {
+        let updated = updated_config(Some(bert()), &options(r#"{"lowercase": false}"#))
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated["lowercase"], json!(false));
+        // Accent stripping was implied by lowercase and stays on
+        assert_eq!(updated["strip_accents"], json!(true));
+
+        let updated = updated_config(Some(bert()), &options(r#"{"unicode": "nfkc"}"#))
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated["type"], "Sequence");
+        assert_eq!(updated["normalizers"][0], json!({"type": "NFKC"}));
+        let _: NormalizerWrapper = serde_json::from_value(updated).unwrap();
+    }
+
+    #[test]
+    fn test_detects_lowercasing() {
+        assert!(lowercases(&bert()));
+        assert!(lowercases(
+            &json!({"type": "Sequence", "normalizers": [{"type": "NFC"}, {"type": "Lowercase"}]})
+        ));
+        assert!(!lowercases(&json!({"type": "NFKC"})));
+        let cased = updated_config(Some(bert()), &options(r#"{"lowercase": false}"#))
+            .unwrap()
+            .unwrap();
+        assert!(!lowercases(&cased));
+    }
+
+    #[test]
+    fn test_steps_added_and_removed() {
+        let updated = updated_config(
+            None,
+            &options(r#"{"lowercase": true, "strip_accents": true}"#),
+        )
+        .unwrap()
+        .unwrap();
+        let steps: Vec<&str> = updated["normalizers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(steps, vec!["Lowercase", "NFD", "StripAccents"]);
+        let _: NormalizerWrapper = serde_json::from_value(updated.clone()).unwrap();
+
+        let removed = updated_config(
+            Some(updated),
+            &options(r#"{"lowercase": false, "strip_accents": false, "unicode": "none"}"#),
+        )
+        .unwrap();
+        assert_eq!(removed, None);
+        assert!(updated_config(None, &options(r#"{"unicode": "nfx"}"#)).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+//! ONNX model loading (`onnx` feature)
+//!
+//! Many sentence-transformers are distributed as ONNX exports. `load_onnx()`
+//! evaluates them with candle-onnx and feeds the resulting token embeddings into
+//! the same pooling/normalization pipeline as the SafeTensors path, so callers
+//! do not need to convert checkpoints first.
+
+use std::collections::HashMap;
+
+use candle_core::{DType, Tensor};
+use candle_onnx::onnx::{tensor_shape_proto, type_proto, ModelProto, ValueInfoProto};
+use prost::Message;
+use wasm_bindgen::prelude::*;
+
+use crate::{fingerprint, load_tokenizer, EmbeddingEngine, Encoder};
+
+/// Preferred output name in sentence-transformers exports
+const HIDDEN_STATE_OUTPUT: &str = "last_hidden_state";
+
+/// An ONNX graph producing `[batch, seq, hidden]` token embeddings
+pub(crate) struct OnnxEncoder {
+    model: ModelProto,
+    output: String,
+    uses_token_types: bool,
+    hidden_size: usize,
+}
+
+impl OnnxEncoder {
+    fn from_bytes(bytes: &[u8]) -> candle_core::Result<Self> {
+        let model = ModelProto::decode(bytes).map_err(candle_core::Error::wrap)?;
+        let graph = model
+            .graph
+            .as_ref()
+            .ok_or_else(|| candle_core::Error::Msg("ONNX model has no graph".to_string()))?;
+
+        let input_names: Vec<&str> = graph.input.iter().map(|i| i.name.as_str()).collect();
+        for required in ["input_ids", "attention_mask"] {
+            if !input_names.contains(&required) {
+                candle_core::bail!(
+                    "ONNX model has no '{}' input (inputs: {:?})",
+                    required,
+                    input_names
+                );
+            }
+        }
+        let uses_token_types = input_names.contains(&"token_type_ids");
+
+        let output = graph
+            .output
+            .iter()
+            .find(|o| o.name == HIDDEN_STATE_OUTPUT)
+            .or_else(|| graph.output.first())
+            .ok_or_else(|| candle_core::Error::Msg("ONNX model has no outputs".to_string()))?;
+
+        let static_hidden_size = last_static_dim(output);
+        let mut encoder = OnnxEncoder {
+            hidden_size: static_hidden_size.unwrap_or(0),
+            output: output.name.clone(),
+            uses_token_types,
+            model,
+        };
+        if static_hidden_size.is_none() {
+            encoder.hidden_size = encoder.probe_hidden_size().map_err(|e| {
+                candle_core::Error::Msg(format!(
+                    "could not determine the hidden size of ONNX output '{}': {}",
+                    encoder.output, e
+                ))
+            })?;
+        }
+        Ok(encoder)
+    }
+
+    /// Run a one-token input through the graph to read the size of a hidden
+    /// dimension the export left symbolic
+    fn probe_hidden_size(&self) -> candle_core::Result<usize> {
+        let device = candle_core::Device::Cpu;
+        let input_ids = Tensor::zeros((1, 1), DType::I64, &device)?;
+        let attention_mask = Tensor::ones((1, 1), DType::I64, &device)?;
+        let hidden = self.forward(&input_ids, None, &attention_mask)?;
+        hidden.dim(2)
+    }
+
+    pub(crate) fn uses_token_types(&self) -> bool {
+        self.uses_token_types
+    }
+
+    pub(crate) fn hidden_size(&self) -> usize {
+        self.hidden_size
+    }
+
+    /// Stand-in for config.json in the model fingerprint: the graph itself is
+    /// hashed with the weights, this records how it is driven
+    fn config_bytes(&self) -> Vec<u8> {
+        serde_json::json!({
+            "format": "onnx",
+            "output": self.output,
+            "uses_token_types": self.uses_token_types,
+            "hidden_size": self.hidden_size,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    pub(crate) fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: Option<&Tensor>,
+        attention_mask: &Tensor,
+    ) -> candle_core::Result<Tensor> {
+        // ONNX exports of BERT-style models take int64 inputs
+        let mut inputs = HashMap::new();
+        inputs.insert("input_ids".to_string(), input_ids.to_dtype(DType::I64)?);
+        inputs.insert(
+            "attention_mask".to_string(),
+            attention_mask.to_dtype(DType::I64)?,
+        );
+        if self.uses_token_types {
+            let token_type_ids = match token_type_ids {
+                Some(ids) => ids.to_dtype(DType::I64)?,
+                None => input_ids.zeros_like()?.to_dtype(DType::I64)?,
+            };
+            inputs.insert("token_type_ids".to_string(), token_type_ids);
+        }
+
+        let mut outputs = candle_onnx::simple_eval(&self.model, inputs)?;
+        let hidden = outputs.remove(&self.output).ok_or_else(|| {
+            candle_core::Error::Msg(format!("ONNX output '{}' was not produced", self.output))
+        })?;
+        if hidden.rank() != 3 {
+            candle_core::bail!(
+                "ONNX output '{}' has shape {:?}; expected [batch, seq, hidden] token embeddings",
+                self.output,
+                hidden.dims()
+            );
+        }
+        hidden.to_dtype(DType::F32)
+    }
+}
+
+/// Static size of the last dimension of a graph output, if declared
+fn last_static_dim(info: &ValueInfoProto) -> Option<usize> {
+    let tensor = match info.r#type.as_ref()?.value.as_ref()? {
+        type_proto::Value::TensorType(tensor) => tensor,
+        _ => return None,
+    };
+    match tensor.shape.as_ref()?.dim.last()?.value.as_ref()? {
+        tensor_shape_proto::dimension::Value::DimValue(v) if *v > 0 => Some(*v as usize),
+        _ => None,
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Load an ONNX-exported model and its tokenizer
+    ///
+    /// The graph must take `input_ids` and `attention_mask` (and optionally
+    /// `token_type_ids`) and output token embeddings, preferably named
+    /// `last_hidden_state`. Pooling and normalization then run exactly as for
+    /// SafeTensors models, and the same preset and pooling layer checks apply.
+    ///
+    /// # Arguments
+    /// * `model_bytes` - model.onnx contents
+    /// * `tokenizer_bytes` - tokenizer.json contents
+    #[wasm_bindgen]
+    pub fn load_onnx(&mut self, model_bytes: &[u8], tokenizer_bytes: &[u8]) -> Result<(), JsValue> {
+        let encoder = OnnxEncoder::from_bytes(model_bytes)
+            .map_err(|e| JsValue::from_str(&format!("Failed to load ONNX model: {}", e)))?;
+        if let Some(preset) = self.preset {
+            preset
+                .check_dimension(encoder.hidden_size())
+                .map_err(|e| JsValue::from_str(&e))?;
+        }
+        let config = encoder.config_bytes();
+        info_log!(
+            "loaded ONNX model (hidden size {}, output '{}')",
+            encoder.hidden_size,
+            encoder.output
+        );
+        let model = Encoder::Onnx(encoder);
+        model
+            .validate_layer(self.pooling_layer)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let mut tokenizer = load_tokenizer(tokenizer_bytes)?;
+        if let Some(preset) = self.preset {
+            preset
+                .apply_max_length(&mut tokenizer)
+                .map_err(|e| JsValue::from_str(&e))?;
+        }
+
+        self.model = Some(model);
+        self.weights = None;
+        self.tokenizer = Some(tokenizer);
+        self.custom_normalizer = None;
+        self.token_cache.borrow_mut().clear();
+        self.model_hash = Some(fingerprint::model_hash(
+            fingerprint::hash_bytes(model_bytes),
+            tokenizer_bytes,
+            &config,
+        ));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_onnx::onnx::{
+        tensor_proto::DataType, GraphProto, NodeProto, TensorProto, TensorShapeProto, TypeProto,
+    };
+
+    const TOKENIZER: &[u8] = br#"{
+        "version": "1.0", "truncation": null, "padding": null, "added_tokens": [],
+        "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+        "post_processor": null, "decoder": null,
+        "model": {"type": "WordLevel", "vocab": {"[UNK]": 0, "hello": 1, "world": 2},
+                  "unk_token": "[UNK]"}
+    }"#;
+
+    fn value_info(name: &str, elem_type: DataType, dims: Option<&[i64]>) -> ValueInfoProto {
+        let shape = dims.map(|dims| TensorShapeProto {
+            dim: dims
+                .iter()
+                .map(|&d| tensor_shape_proto::Dimension {
+                    value: Some(if d > 0 {
+                        tensor_shape_proto::dimension::Value::DimValue(d)
+                    } else {
+                        tensor_shape_proto::dimension::Value::DimParam("n".to_string())
+                    }),
+                    ..Default::default()
+                })
+                .collect(),
+        });
+        ValueInfoProto {
+            name: name.to_string(),
+            r#type: Some(TypeProto {
+                value: Some(type_proto::Value::TensorType(type_proto::Tensor {
+                    elem_type: elem_type as i32,
+                    shape,
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// A graph that looks each token up in a `[3, 4]` embedding table
+    fn lookup_model(output_dims: Option<&[i64]>) -> Vec<u8> {
+        let table = TensorProto {
+            name: "table".to_string(),
+            dims: vec![3, 4],
+            data_type: DataType::Float as i32,
+            float_data: (0..12).map(|v| v as f32).collect(),
+            ..Default::default()
+        };
+        let gather = NodeProto {
+            op_type: "Gather".to_string(),
+            input: vec!["table".to_string(), "input_ids".to_string()],
+            output: vec![HIDDEN_STATE_OUTPUT.to_string()],
+            ..Default::default()
+        };
+        ModelProto {
+            graph: Some(GraphProto {
+                node: vec![gather],
+                initializer: vec![table],
+                input: vec![
+                    value_info("input_ids", DataType::Int64, None),
+                    value_info("attention_mask", DataType::Int64, None),
+                ],
+                output: vec![value_info(
+                    HIDDEN_STATE_OUTPUT,
+                    DataType::Float,
+                    output_dims,
+                )],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn test_hidden_size_from_declared_shape() {
+        let encoder = OnnxEncoder::from_bytes(&lookup_model(Some(&[0, 0, 4]))).unwrap();
+        assert_eq!(encoder.hidden_size(), 4);
+        assert!(!encoder.uses_token_types());
+    }
+
+    #[test]
+    fn test_hidden_size_probed_when_symbolic() {
+        for dims in [None, Some(&[0, 0, 0][..])] {
+            let encoder = OnnxEncoder::from_bytes(&lookup_model(dims)).unwrap();
+            assert_eq!(encoder.hidden_size(), 4);
+        }
+    }
+
+    #[test]
+    fn test_load_onnx_embeds() {
+        let mut engine = EmbeddingEngine::new();
+        engine.load_onnx(&lookup_model(None), TOKENIZER).unwrap();
+        assert_eq!(engine.dimension(), 4);
+
+        let embeddings = engine.embed_internal(&["hello world".to_string()]).unwrap();
+        // Mean of rows 1 and 2 of the table, normalized
+        let expected = [6.0f32, 7.0, 8.0, 9.0];
+        let norm = expected.iter().map(|v| v * v).sum::<f32>().sqrt();
+        for (got, want) in embeddings[0].iter().zip(expected) {
+            assert!((got - want / norm).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,704 @@
+//! Blocked pairwise distances
+//!
+//! A full distance matrix is n² floats: 1.6GB for 20k embeddings, far beyond
+//! what a tab can allocate. `pairwise_distances()` works in square tiles of
+//! `block_size` rows and columns. Given a callback, it hands each tile over
+//! as soon as it is computed and never holds more than one; without one, it
+//! assembles the full matrix for inputs small enough to afford it.
+//!
+//! `pairwise_top_k()` keeps only the k nearest neighbours of each row, which
+//! fits in memory where the matrix doesn't (10k rows with k = 10 is 100k
+//! entries instead of 100M). `search_batch()` does the same for a set of
+//! queries against a corpus, reading each block of corpus rows once per
+//! block of queries instead of rescanning the corpus for every query.
+//! `search_range()` returns every corpus vector above a similarity threshold
+//! instead of a fixed k.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::convert::Infallible;
+
+use js_sys::{Float32Array, Uint32Array};
+use wasm_bindgen::prelude::*;
+
+/// Tile edge used when `block_size` is 0
+const DEFAULT_BLOCK_SIZE: usize = 256;
+
+/// Most entries `pairwise_distances()` returns as a full matrix: 256MB of
+/// f32, 8192 embeddings; larger inputs must stream tiles through `on_tile`
+const MAX_MATRIX_ENTRIES: usize = 1 << 26;
+
+/// Distance between two embeddings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    /// `1 - cosine similarity`
+    Cosine,
+    /// `1 - dot product`: cosine for vectors already L2-normalized
+    NormalizedCosine,
+    Euclidean,
+    /// Sum of absolute differences
+    Manhattan,
+}
+
+impl Metric {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cosine" => Some(Metric::Cosine),
+            "euclidean" => Some(Metric::Euclidean),
+            "manhattan" => Some(Metric::Manhattan),
+            _ => None,
+        }
+    }
+
+    /// The metric to compute, given whether the vectors are L2-normalized
+    fn for_vectors(self, assume_normalized: bool) -> Self {
+        match self {
+            Metric::Cosine if assume_normalized => Metric::NormalizedCosine,
+            metric => metric,
+        }
+    }
+}
+
+/// Row-major embeddings with precomputed norms for the cosine metric
+struct Embeddings<'a> {
+    data: &'a [f32],
+    dim: usize,
+    norms: Vec<f32>,
+}
+
+impl<'a> Embeddings<'a> {
+    fn new(data: &'a [f32], dim: usize, metric: Metric) -> Self {
+        let norms = if metric == Metric::Cosine {
+            data.chunks_exact(dim)
+                .map(|row| row.iter().map(|v| v * v).sum::<f32>().sqrt())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Embeddings { data, dim, norms }
+    }
+
+    fn len(&self) -> usize {
+        self.data.len() / self.dim
+    }
+
+    fn row(&self, i: usize) -> &[f32] {
+        &self.data[i * self.dim..(i + 1) * self.dim]
+    }
+
+    fn distance(&self, metric: Metric, i: usize, j: usize) -> f32 {
+        self.distance_to(self, metric, i, j)
+    }
+
+    /// Distance from row `i` to row `j` of `other`, which has the same
+    /// dimension and metric
+    fn distance_to(&self, other: &Embeddings, metric: Metric, i: usize, j: usize) -> f32 {
+        let (a, b) = (self.row(i), other.row(j));
+        match metric {
+            Metric::Cosine => {
+                let norm = self.norms[i] * other.norms[j];
+                if norm == 0.0 {
+                    return 1.0;
+                }
+                let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+                1.0 - dot / norm
+            }
+            Metric::NormalizedCosine => 1.0 - a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>(),
+            Metric::Euclidean => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt(),
+            Metric::Manhattan => a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum(),
+        }
+    }
+}
+
+/// Compute the tiles on and above the diagonal, passing each to `emit` as
+/// `(row_start, col_start, rows x cols distances)`
+fn for_each_tile<E>(
+    embeddings: &Embeddings,
+    metric: Metric,
+    block_size: usize,
+    mut emit: impl FnMut(usize, usize, &[f32]) -> Result<(), E>,
+) -> Result<(), E> {
+    let n = embeddings.len();
+    let mut tile = Vec::with_capacity(block_size * block_size);
+    for row_start in (0..n).step_by(block_size) {
+        let rows = row_start..(row_start + block_size).min(n);
+        for col_start in (row_start..n).step_by(block_size) {
+            let cols = col_start..(col_start + block_size).min(n);
+            tile.clear();
+            for i in rows.clone() {
+                tile.extend(cols.clone().map(|j| embeddings.distance(metric, i, j)));
+            }
+            emit(row_start, col_start, &tile)?;
+        }
+    }
+    Ok(())
+}
+
+/// Entries in the full matrix of `n` embeddings, if within
+/// `MAX_MATRIX_ENTRIES`
+fn matrix_entries(n: usize) -> Result<usize, String> {
+    n.checked_mul(n)
+        .filter(|&entries| entries <= MAX_MATRIX_ENTRIES)
+        .ok_or_else(|| {
+            format!(
+                "A full matrix of {} embeddings exceeds the limit of {} entries; pass on_tile to receive it in tiles",
+                n, MAX_MATRIX_ENTRIES
+            )
+        })
+}
+
+/// Full n x n matrix, mirroring the computed upper tiles
+fn distance_matrix(
+    embeddings: &Embeddings,
+    metric: Metric,
+    block_size: usize,
+) -> Result<Vec<f32>, String> {
+    let n = embeddings.len();
+    let mut matrix = vec![0.0f32; matrix_entries(n)?];
+    let _ = for_each_tile::<Infallible>(embeddings, metric, block_size, |r0, c0, tile| {
+        let cols = block_size.min(n - c0);
+        for (k, &d) in tile.iter().enumerate() {
+            let (i, j) = (r0 + k / cols, c0 + k % cols);
+            matrix[i * n + j] = d;
+            matrix[j * n + i] = d;
+        }
+        Ok(())
+    });
+    Ok(matrix)
+}
+
+/// A neighbour candidate, ordered by distance and then index so the heap's
+/// top is the worst one kept
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    distance: f32,
+    index: u32,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+/// Offer `candidate` to a heap holding the best `k`
+fn offer(heap: &mut BinaryHeap<Candidate>, k: usize, candidate: Candidate) {
+    if heap.len() < k {
+        heap.push(candidate);
+    } else if heap.peek().is_some_and(|worst| candidate < *worst) {
+        heap.pop();
+        heap.push(candidate);
+    }
+}
+
+/// The `k` nearest neighbours of every row, nearest first, as
+/// `(indices, distances)` of `n * k` values; `k` must be at most the number
+/// of candidates per row
+fn top_k(
+    embeddings: &Embeddings,
+    metric: Metric,
+    block_size: usize,
+    k: usize,
+    exclude_self: bool,
+) -> (Vec<u32>, Vec<f32>) {
+    let n = embeddings.len();
+    let mut heaps: Vec<BinaryHeap<Candidate>> =
+        (0..n).map(|_| BinaryHeap::with_capacity(k + 1)).collect();
+    // Each upper tile serves both rows of a pair
+    let _ = for_each_tile::<Infallible>(embeddings, metric, block_size, |r0, c0, tile| {
+        let cols = block_size.min(n - c0);
+        for (offset, &distance) in tile.iter().enumerate() {
+            let (i, j) = (r0 + offset / cols, c0 + offset % cols);
+            if j < i || (j == i && exclude_self) {
+                continue;
+            }
+            offer(
+                &mut heaps[i],
+                k,
+                Candidate {
+                    distance,
+                    index: j as u32,
+                },
+            );
+            if j != i {
+                offer(
+                    &mut heaps[j],
+                    k,
+                    Candidate {
+                        distance,
+                        index: i as u32,
+                    },
+                );
+            }
+        }
+        Ok(())
+    });
+    flatten(heaps)
+}
+
+/// The `k` nearest `corpus` rows of every query, nearest first, as for
+/// `top_k()`; works in blocks of `block_size` queries and corpus rows
+fn search_top_k(
+    queries: &Embeddings,
+    corpus: &Embeddings,
+    metric: Metric,
+    block_size: usize,
+    k: usize,
+) -> (Vec<u32>, Vec<f32>) {
+    let (n, m) = (queries.len(), corpus.len());
+    let mut heaps: Vec<BinaryHeap<Candidate>> =
+        (0..n).map(|_| BinaryHeap::with_capacity(k + 1)).collect();
+    for row_start in (0..n).step_by(block_size) {
+        let rows = row_start..(row_start + block_size).min(n);
+        for col_start in (0..m).step_by(block_size) {
+            let cols = col_start..(col_start + block_size).min(m);
+            for i in rows.clone() {
+                for j in cols.clone() {
+                    let distance = queries.distance_to(corpus, metric, i, j);
+                    offer(
+                        &mut heaps[i],
+                        k,
+                        Candidate {
+                            distance,
+                            index: j as u32,
+                        },
+                    );
+                }
+            }
+        }
+    }
+    flatten(heaps)
+}
+
+/// Each heap's candidates nearest first, back to back
+fn flatten(heaps: Vec<BinaryHeap<Candidate>>) -> (Vec<u32>, Vec<f32>) {
+    let len = heaps.iter().map(|h| h.len()).sum();
+    let mut indices = Vec::with_capacity(len);
+    let mut distances = Vec::with_capacity(len);
+    for heap in heaps {
+        for candidate in heap.into_sorted_vec() {
+            indices.push(candidate.index);
+            distances.push(candidate.distance);
+        }
+    }
+    (indices, distances)
+}
+
+/// Nearest neighbours of each row from `pairwise_top_k()` or
+/// `search_batch()`
+#[wasm_bindgen]
+pub struct NearestNeighbors {
+    k: usize,
+    indices: Vec<u32>,
+    distances: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl NearestNeighbors {
+    /// Neighbours per row (the requested k, or fewer if there aren't enough
+    /// rows)
+    #[wasm_bindgen(getter)]
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Neighbour row indices, `k` per row, nearest first
+    #[wasm_bindgen(getter)]
+    pub fn indices(&self) -> Uint32Array {
+        Uint32Array::from(&self.indices[..])
+    }
+
+    /// Distances matching `indices`
+    #[wasm_bindgen(getter)]
+    pub fn distances(&self) -> Float32Array {
+        Float32Array::from(&self.distances[..])
+    }
+}
+
+/// Corpus vectors within a similarity threshold, from `search_range()`
+#[wasm_bindgen]
+pub struct RangeMatches {
+    indices: Vec<u32>,
+    scores: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl RangeMatches {
+    /// Number of matches
+    #[wasm_bindgen(getter)]
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Corpus row indices, most similar first
+    #[wasm_bindgen(getter)]
+    pub fn indices(&self) -> Uint32Array {
+        Uint32Array::from(&self.indices[..])
+    }
+
+    /// Cosine similarities matching `indices`
+    #[wasm_bindgen(getter)]
+    pub fn scores(&self) -> Float32Array {
+        Float32Array::from(&self.scores[..])
+    }
+}
+
+/// Corpus rows whose similarity to query row 0 is at least `min_score`, most
+/// similar first (ties go to the lower index)
+fn within_range(
+    query: &Embeddings,
+    corpus: &Embeddings,
+    metric: Metric,
+    min_score: f32,
+) -> RangeMatches {
+    let mut matches: Vec<(f32, u32)> = (0..corpus.len())
+        .map(|j| (1.0 - query.distance_to(corpus, metric, 0, j), j as u32))
+        .filter(|&(score, _)| score >= min_score)
+        .collect();
+    matches.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    RangeMatches {
+        indices: matches.iter().map(|&(_, j)| j).collect(),
+        scores: matches.iter().map(|&(score, _)| score).collect(),
+    }
+}
+
+/// Parse the metric name and check the embeddings' shape
+fn checked_input(
+    embeddings: &[f32],
+    dim: usize,
+    metric: &str,
+    assume_normalized: Option<bool>,
+) -> Result<Metric, JsValue> {
+    let metric = Metric::from_name(metric).ok_or_else(|| {
+        JsValue::from_str(&format!(
+            "Unknown metric: {} (expected cosine, euclidean or manhattan)",
+            metric
+        ))
+    })?;
+    if dim == 0 || !embeddings.len().is_multiple_of(dim) {
+        return Err(JsValue::from_str(&format!(
+            "Embeddings length {} is not a multiple of dimension {}",
+            embeddings.len(),
+            dim
+        )));
+    }
+    Ok(metric.for_vectors(assume_normalized.unwrap_or(false)))
+}
+
+/// Distances between all pairs of embeddings, computed in tiles
+///
+/// `embeddings` holds the vectors back to back, `dim` values each. `metric`
+/// is `"cosine"` (1 - similarity), `"euclidean"` or `"manhattan"`;
+/// `block_size` is the tile edge (0 for 256). Pass `assume_normalized` for
+/// unit vectors (such as `embed()` output) to compute cosine distances from
+/// dot products alone, skipping the norms.
+///
+/// With `on_tile`, nothing is returned: the callback receives
+/// `(row_start, col_start, distances)` for each tile on or above the
+/// diagonal (the matrix is symmetric), where `distances` is row-major with
+/// `min(block_size, n - col_start)` columns. Without it, the full n x n
+/// matrix is returned, for up to 8192 embeddings (64M entries).
+#[wasm_bindgen]
+pub fn pairwise_distances(
+    embeddings: &[f32],
+    dim: usize,
+    metric: &str,
+    block_size: usize,
+    on_tile: Option<js_sys::Function>,
+    assume_normalized: Option<bool>,
+) -> Result<Option<Vec<f32>>, JsValue> {
+    let metric = checked_input(embeddings, dim, metric, assume_normalized)?;
+    let block_size = if block_size == 0 {
+        DEFAULT_BLOCK_SIZE
+    } else {
+        block_size
+    };
+    let embeddings = Embeddings::new(embeddings, dim, metric);
+
+    match on_tile {
+        Some(callback) => {
+            for_each_tile(
+                &embeddings,
+                metric,
+                block_size,
+                |row_start, col_start, tile| {
+                    callback
+                        .call3(
+                            &JsValue::NULL,
+                            &JsValue::from(row_start as u32),
+                            &JsValue::from(col_start as u32),
+                            &Float32Array::from(tile),
+                        )
+                        .map(|_| ())
+                },
+            )?;
+            Ok(None)
+        }
+        None => distance_matrix(&embeddings, metric, block_size)
+            .map(Some)
+            .map_err(|e| JsValue::from_str(&e)),
+    }
+}
+
+/// The `k` nearest neighbours of every embedding, without building the full
+/// distance matrix
+///
+/// `embeddings`, `dim`, `metric` and `assume_normalized` are as for
+/// `pairwise_distances()`. With `exclude_self`, a row is
+/// not its own neighbour. Returns `k` indices and distances per row, nearest
+/// first (ties go to the lower index); `k` is reduced when there are fewer
+/// candidates.
+#[wasm_bindgen]
+pub fn pairwise_top_k(
+    embeddings: &[f32],
+    dim: usize,
+    metric: &str,
+    k: usize,
+    exclude_self: bool,
+    assume_normalized: Option<bool>,
+) -> Result<NearestNeighbors, JsValue> {
+    let metric = checked_input(embeddings, dim, metric, assume_normalized)?;
+    let embeddings = Embeddings::new(embeddings, dim, metric);
+    let candidates = embeddings.len().saturating_sub(exclude_self as usize);
+    let k = k.min(candidates);
+    let (indices, distances) = top_k(&embeddings, metric, DEFAULT_BLOCK_SIZE, k, exclude_self);
+    Ok(NearestNeighbors {
+        k,
+        indices,
+        distances,
+    })
+}
+
+/// The `k` nearest `corpus` embeddings of each query, scoring all queries in
+/// one blocked pass over the corpus
+///
+/// `queries` and `corpus` hold vectors back to back, `dim` values each;
+/// `metric` and `assume_normalized` are as for `pairwise_distances()`.
+/// Returns `k` corpus indices and distances per query, nearest first (ties
+/// go to the lower index); `k` is reduced when the corpus is smaller.
+#[wasm_bindgen]
+pub fn search_batch(
+    queries: &[f32],
+    corpus: &[f32],
+    dim: usize,
+    metric: &str,
+    k: usize,
+    assume_normalized: Option<bool>,
+) -> Result<NearestNeighbors, JsValue> {
+    let metric = checked_input(queries, dim, metric, assume_normalized)?;
+    if !corpus.len().is_multiple_of(dim) {
+        return Err(JsValue::from_str(&format!(
+            "Corpus length {} is not a multiple of dimension {}",
+            corpus.len(),
+            dim
+        )));
+    }
+    let queries = Embeddings::new(queries, dim, metric);
+    let corpus = Embeddings::new(corpus, dim, metric);
+    let k = k.min(corpus.len());
+    let (indices, distances) = search_top_k(&queries, &corpus, metric, DEFAULT_BLOCK_SIZE, k);
+    Ok(NearestNeighbors {
+        k,
+        indices,
+        distances,
+    })
+}
+
+/// Every `corpus` embedding with cosine similarity to `query` of at least
+/// `min_score`, most similar first
+///
+/// `corpus` holds vectors back to back, `query.len()` values each. Pass
+/// `assume_normalized` for unit vectors (such as `embed()` output) to score
+/// with dot products alone.
+#[wasm_bindgen]
+pub fn search_range(
+    query: &[f32],
+    corpus: &[f32],
+    min_score: f32,
+    assume_normalized: Option<bool>,
+) -> Result<RangeMatches, JsValue> {
+    let dim = query.len();
+    let metric = checked_input(corpus, dim, "cosine", assume_normalized)?;
+    let query = Embeddings::new(query, dim, metric);
+    let corpus = Embeddings::new(corpus, dim, metric);
+    Ok(within_range(&query, &corpus, metric, min_score))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> Vec<f32> {
+        (0..7 * 3)
+            .map(|i| ((i * 5 % 9) as f32 - 4.0) / 4.0)
+            .collect()
+    }
+
+    #[test]
+    fn test_tiled_matrix_matches_direct() {
+        let data = data();
+        for metric in [Metric::Cosine, Metric::Euclidean, Metric::Manhattan] {
+            let embeddings = Embeddings::new(&data, 3, metric);
+            let full = distance_matrix(&embeddings, metric, 7).unwrap();
+            assert_eq!(distance_matrix(&embeddings, metric, 3).unwrap(), full);
+            for i in 0..7 {
+                assert!(full[i * 7 + i].abs() < 1e-6);
+                for j in 0..7 {
+                    assert_eq!(full[i * 7 + j], embeddings.distance(metric, i, j));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_full_matrix_size_capped() {
+        assert_eq!(matrix_entries(8192), Ok(MAX_MATRIX_ENTRIES));
+        assert!(matrix_entries(8193).unwrap_err().contains("on_tile"));
+        // n * n overflows usize here
+        assert!(matrix_entries(usize::MAX / 2).is_err());
+    }
+
+    #[test]
+    fn test_tiles_cover_upper_triangle() {
+        let data = data();
+        let embeddings = Embeddings::new(&data, 3, Metric::Euclidean);
+        let mut tiles = Vec::new();
+        for_each_tile::<Infallible>(&embeddings, Metric::Euclidean, 3, |r, c, tile| {
+            tiles.push((r, c, tile.len()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            tiles,
+            vec![
+                (0, 0, 9),
+                (0, 3, 9),
+                (0, 6, 3),
+                (3, 3, 9),
+                (3, 6, 3),
+                (6, 6, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_top_k_matches_sorted_matrix() {
+        let data = data();
+        let embeddings = Embeddings::new(&data, 3, Metric::Euclidean);
+        let full = distance_matrix(&embeddings, Metric::Euclidean, 7).unwrap();
+        for exclude_self in [false, true] {
+            let (indices, distances) = top_k(&embeddings, Metric::Euclidean, 3, 2, exclude_self);
+            assert_eq!(indices.len(), 14);
+            for i in 0..7 {
+                let mut expected: Vec<(f32, u32)> = (0..7)
+                    .filter(|&j| !(exclude_self && j == i))
+                    .map(|j| (full[i * 7 + j], j as u32))
+                    .collect();
+                expected.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+                for r in 0..2 {
+                    assert_eq!(indices[i * 2 + r], expected[r].1);
+                    assert_eq!(distances[i * 2 + r], expected[r].0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_matches_per_query_scan() {
+        let data = data();
+        let (queries, corpus) = data.split_at(2 * 3);
+        let queries = Embeddings::new(queries, 3, Metric::Cosine);
+        let corpus = Embeddings::new(corpus, 3, Metric::Cosine);
+        let (indices, distances) = search_top_k(&queries, &corpus, Metric::Cosine, 2, 3);
+        assert_eq!(indices.len(), 6);
+        for i in 0..2 {
+            let mut expected: Vec<(f32, u32)> = (0..5)
+                .map(|j| (queries.distance_to(&corpus, Metric::Cosine, i, j), j as u32))
+                .collect();
+            expected.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+            for r in 0..3 {
+                assert_eq!(indices[i * 3 + r], expected[r].1);
+                assert_eq!(distances[i * 3 + r], expected[r].0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_range_keeps_all_above_threshold() {
+        let data = data();
+        let (query, corpus) = data.split_at(3);
+        let query = Embeddings::new(query, 3, Metric::Cosine);
+        let corpus = Embeddings::new(corpus, 3, Metric::Cosine);
+        let scores: Vec<f32> = (0..6)
+            .map(|j| 1.0 - query.distance_to(&corpus, Metric::Cosine, 0, j))
+            .collect();
+        let matches = within_range(&query, &corpus, Metric::Cosine, 0.0);
+        let expected = scores.iter().filter(|&&score| score >= 0.0).count();
+        assert_eq!(matches.indices.len(), expected);
+        assert!(matches.scores.windows(2).all(|w| w[0] >= w[1]));
+        for (&j, &score) in matches.indices.iter().zip(&matches.scores) {
+            assert_eq!(scores[j as usize], score);
+        }
+        assert!(within_range(&query, &corpus, Metric::Cosine, 1.1)
+            .indices
+            .is_empty());
+    }
+
+    #[test]
+    fn test_normalized_cosine_skips_norms() {
+        let data: Vec<f32> = data()
+            .chunks_exact(3)
+            .flat_map(|row| {
+                let norm = row.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-6);
+                row.iter().map(move |v| v / norm).collect::<Vec<_>>()
+            })
+            .collect();
+        let metric = Metric::Cosine.for_vectors(true);
+        assert_eq!(metric, Metric::NormalizedCosine);
+        let fast = Embeddings::new(&data, 3, metric);
+        assert!(fast.norms.is_empty());
+        let slow = Embeddings::new(&data, 3, Metric::Cosine);
+        for (i, j) in [(0, 1), (2, 5), (3, 3)] {
+            let (a, b) = (
+                fast.distance(metric, i, j),
+                slow.distance(Metric::Cosine, i, j),
+            );
+            assert!((a - b).abs() < 1e-5);
+        }
+        assert_eq!(Metric::Euclidean.for_vectors(true), Metric::Euclidean);
+    }
+
+    #[test]
+    fn test_zero_vector_cosine() {
+        let data = [0.0, 0.0, 1.0, 0.0];
+        let embeddings = Embeddings::new(&data, 2, Metric::Cosine);
+        assert_eq!(embeddings.distance(Metric::Cosine, 0, 1), 1.0);
+        assert_eq!(Metric::from_name("hamming"), None);
+    }
+}
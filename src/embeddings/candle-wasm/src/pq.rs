@@ -0,0 +1,460 @@
+//! Product quantization (`PqCodec`)
+//!
+//! Each vector is split into `m` equal subvectors and each subvector is
+//! replaced by the index of its nearest centroid in a per-subspace codebook of
+//! `2^bits` centroids learned with k-means. A 384-dimension f32 vector (1536
+//! bytes) becomes `m` one-byte codes: 48 bytes at `m = 48` is 32x smaller.
+//!
+//! The codec is independent of any index, so apps with their own storage can
+//! compress vectors and score a query against codes directly: asymmetric
+//! distance compares the exact query with the decoded vectors through one
+//! lookup table per subspace, without decoding anything.
+
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::rng::Rng;
+
+/// First bytes of `to_bytes()` output
+const MAGIC: &[u8; 4] = b"PQC1";
+
+/// Options for `PqCodec.train`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct TrainOptions {
+    /// k-means iterations per subspace
+    iterations: usize,
+    /// Seed for centroid initialization
+    seed: u32,
+}
+
+impl Default for TrainOptions {
+    fn default() -> Self {
+        TrainOptions {
+            iterations: 10,
+            seed: 0,
+        }
+    }
+}
+
+/// Squared Euclidean distance
+fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// Index of the centroid (rows of `centroids`) nearest to `point`
+fn nearest(centroids: &[f32], point: &[f32]) -> usize {
+    centroids
+        .chunks_exact(point.len())
+        .map(|c| squared_distance(c, point))
+        .enumerate()
+        .fold(
+            (0, f32::INFINITY),
+            |best, (i, d)| {
+                if d < best.1 {
+                    (i, d)
+                } else {
+                    best
+                }
+            },
+        )
+        .0
+}
+
+/// `k` centroids of `points` (rows of `dim` values) by k-means with
+/// k-means++ initialization
+fn kmeans(points: &[f32], dim: usize, k: usize, iterations: usize, rng: &mut Rng) -> Vec<f32> {
+    let n = points.len() / dim;
+    let point = |i: usize| &points[i * dim..(i + 1) * dim];
+
+    // k-means++: each next centroid is drawn with probability proportional
+    // to its squared distance from the nearest one so far
+    let mut centroids = point(rng.below(n)).to_vec();
+    let mut closest: Vec<f32> = (0..n)
+        .map(|i| squared_distance(point(i), &centroids))
+        .collect();
+    while centroids.len() < k * dim {
+        let total: f64 = closest.iter().map(|&d| d as f64).sum();
+        let next = if total > 0.0 {
+            let mut target = rng.next_f64() * total;
+            closest
+                .iter()
+                .position(|&d| {
+                    target -= d as f64;
+                    target < 0.0
+                })
+                .unwrap_or(n - 1)
+        } else {
+            rng.below(n)
+        };
+        let start = centroids.len();
+        centroids.extend_from_slice(point(next));
+        for (i, d) in closest.iter_mut().enumerate() {
+            *d = d.min(squared_distance(point(i), &centroids[start..]));
+        }
+    }
+
+    let mut sums = vec![0.0f64; k * dim];
+    let mut counts = vec![0usize; k];
+    for _ in 0..iterations {
+        sums.iter_mut().for_each(|s| *s = 0.0);
+        counts.iter_mut().for_each(|c| *c = 0);
+        for i in 0..n {
+            let c = nearest(&centroids, point(i));
+            counts[c] += 1;
+            for (s, &v) in sums[c * dim..(c + 1) * dim].iter_mut().zip(point(i)) {
+                *s += v as f64;
+            }
+        }
+        // An empty cluster keeps its centroid
+        for c in (0..k).filter(|&c| counts[c] > 0) {
+            for d in 0..dim {
+                centroids[c * dim + d] = (sums[c * dim + d] / counts[c] as f64) as f32;
+            }
+        }
+    }
+    centroids
+}
+
+/// Product quantizer: `m` codebooks of `2^bits` centroids
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PqCodec {
+    dim: usize,
+    m: usize,
+    bits: u32,
+    /// `m` codebooks back to back, each `2^bits` centroids of `dim / m` values
+    centroids: Vec<f32>,
+}
+
+impl PqCodec {
+    fn sub_dim(&self) -> usize {
+        self.dim / self.m
+    }
+
+    fn centroid_count(&self) -> usize {
+        1 << self.bits
+    }
+
+    /// Codebook of subspace `s`
+    fn codebook(&self, s: usize) -> &[f32] {
+        let size = self.centroid_count() * self.sub_dim();
+        &self.centroids[s * size..(s + 1) * size]
+    }
+
+    fn fit(
+        vectors: &[f32],
+        dim: usize,
+        m: usize,
+        bits: u32,
+        options: &TrainOptions,
+    ) -> Result<Self, String> {
+        if m == 0 || dim == 0 || !dim.is_multiple_of(m) {
+            return Err(format!(
+                "Dimension {} is not divisible into {} subspaces",
+                dim, m
+            ));
+        }
+        if !(1..=8).contains(&bits) {
+            return Err(format!("bits must be 1 to 8, got {}", bits));
+        }
+        check_rows(vectors, dim, "Training vectors")?;
+        let k = 1usize << bits;
+        let n = vectors.len() / dim;
+        if n < k {
+            return Err(format!(
+                "Training needs at least {} vectors for {} bits, got {}",
+                k, bits, n
+            ));
+        }
+
+        let sub_dim = dim / m;
+        let mut rng = Rng::new(options.seed as u64);
+        let mut centroids = Vec::with_capacity(m * k * sub_dim);
+        let mut subvectors = Vec::with_capacity(n * sub_dim);
+        for s in 0..m {
+            subvectors.clear();
+            for row in vectors.chunks_exact(dim) {
+                subvectors.extend_from_slice(&row[s * sub_dim..(s + 1) * sub_dim]);
+            }
+            centroids.extend(kmeans(
+                &subvectors,
+                sub_dim,
+                k,
+                options.iterations,
+                &mut rng,
+            ));
+        }
+        Ok(PqCodec {
+            dim,
+            m,
+            bits,
+            centroids,
+        })
+    }
+
+    fn encode_rows(&self, vectors: &[f32]) -> Vec<u8> {
+        let sub_dim = self.sub_dim();
+        vectors
+            .chunks_exact(self.dim)
+            .flat_map(|row| {
+                (0..self.m).map(move |s| {
+                    nearest(self.codebook(s), &row[s * sub_dim..(s + 1) * sub_dim]) as u8
+                })
+            })
+            .collect()
+    }
+
+    fn decode_rows(&self, codes: &[u8]) -> Vec<f32> {
+        let sub_dim = self.sub_dim();
+        codes
+            .chunks_exact(self.m)
+            .flat_map(|row| {
+                row.iter().enumerate().flat_map(move |(s, &code)| {
+                    let start = code as usize * sub_dim;
+                    self.codebook(s)[start..start + sub_dim].iter().copied()
+                })
+            })
+            .collect()
+    }
+
+    /// Squared distances from `query` to each encoded vector, via a table of
+    /// query-to-centroid distances per subspace
+    fn distances(&self, query: &[f32], codes: &[u8]) -> Vec<f32> {
+        let sub_dim = self.sub_dim();
+        let k = self.centroid_count();
+        let table: Vec<f32> = (0..self.m)
+            .flat_map(|s| {
+                let sub = &query[s * sub_dim..(s + 1) * sub_dim];
+                self.codebook(s)
+                    .chunks_exact(sub_dim)
+                    .map(move |c| squared_distance(sub, c))
+            })
+            .collect();
+        codes
+            .chunks_exact(self.m)
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(s, &code)| table[s * k + code as usize])
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let header = bytes
+            .get(..16)
+            .filter(|header| header.starts_with(MAGIC))
+            .ok_or("Not a PqCodec (missing PQC1 header)")?;
+        let field = |i: usize| {
+            u32::from_le_bytes(header[4 + i * 4..8 + i * 4].try_into().unwrap()) as usize
+        };
+        let (dim, m, bits) = (field(0), field(1), field(2) as u32);
+        if m == 0 || dim == 0 || !dim.is_multiple_of(m) || !(1..=8).contains(&bits) {
+            return Err("Invalid PqCodec header".to_string());
+        }
+        let values = &bytes[16..];
+        let expected = dim << bits;
+        if values.len() != expected * 4 {
+            return Err(format!(
+                "PqCodec data has {} bytes, expected {}",
+                values.len(),
+                expected * 4
+            ));
+        }
+        let centroids = values
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        Ok(PqCodec {
+            dim,
+            m,
+            bits,
+            centroids,
+        })
+    }
+}
+
+/// Check that `values` holds whole rows of `dim`
+fn check_rows(values: &[f32], dim: usize, what: &str) -> Result<(), String> {
+    if values.len().is_multiple_of(dim) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} length {} is not a multiple of dimension {}",
+            what,
+            values.len(),
+            dim
+        ))
+    }
+}
+
+#[wasm_bindgen]
+impl PqCodec {
+    /// Learn codebooks from training vectors (`embeddings`, row-major with
+    /// `dim` values each)
+    ///
+    /// `dim` must be divisible by `m`; codes are one byte per subspace, so
+    /// `bits` is 1 to 8 and at least `2^bits` training vectors are needed.
+    /// Options: `{ iterations = 10, seed = 0 }`. Training is deterministic
+    /// for a seed. It costs about `n * 2^bits * dim * iterations` operations,
+    /// so train on a sample of a large corpus.
+    #[wasm_bindgen]
+    pub fn train(
+        embeddings: &[f32],
+        dim: usize,
+        m: usize,
+        bits: u32,
+        options: &JsValue,
+    ) -> Result<PqCodec, JsValue> {
+        let options: TrainOptions = parse_options(options)?;
+        PqCodec::fit(embeddings, dim, m, bits, &options).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Codes for row-major vectors: `m` bytes per vector
+    #[wasm_bindgen]
+    pub fn encode(&self, vectors: &[f32]) -> Result<Vec<u8>, JsValue> {
+        check_rows(vectors, self.dim, "Vectors").map_err(|e| JsValue::from_str(&e))?;
+        Ok(self.encode_rows(vectors))
+    }
+
+    /// Approximate vectors for codes from `encode()`
+    #[wasm_bindgen]
+    pub fn decode(&self, codes: &[u8]) -> Result<Vec<f32>, JsValue> {
+        self.check_codes(codes)?;
+        Ok(self.decode_rows(codes))
+    }
+
+    /// Squared Euclidean distance from `query` to each vector in `codes`
+    ///
+    /// For unit vectors this is `2 - 2 * cosine`, so ranking by it ranks by
+    /// cosine similarity.
+    #[wasm_bindgen]
+    pub fn asymmetric_distance(&self, query: &[f32], codes: &[u8]) -> Result<Vec<f32>, JsValue> {
+        if query.len() != self.dim {
+            return Err(JsValue::from_str(&format!(
+                "Query has {} values, expected {}",
+                query.len(),
+                self.dim
+            )));
+        }
+        self.check_codes(codes)?;
+        Ok(self.distances(query, codes))
+    }
+
+    /// Serialize the codebooks
+    #[wasm_bindgen]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + self.centroids.len() * 4);
+        bytes.extend_from_slice(MAGIC);
+        for field in [self.dim as u32, self.m as u32, self.bits] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        for value in &self.centroids {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Restore a codec saved with `to_bytes()`
+    #[wasm_bindgen]
+    pub fn from_bytes(bytes: &[u8]) -> Result<PqCodec, JsValue> {
+        PqCodec::parse(bytes).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Dimension of the vectors
+    #[wasm_bindgen(getter)]
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Number of subspaces (bytes per code)
+    #[wasm_bindgen(getter)]
+    pub fn m(&self) -> usize {
+        self.m
+    }
+
+    /// Bits per subspace code
+    #[wasm_bindgen(getter)]
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+}
+
+impl PqCodec {
+    fn check_codes(&self, codes: &[u8]) -> Result<(), JsValue> {
+        if !codes.len().is_multiple_of(self.m) {
+            return Err(JsValue::from_str(&format!(
+                "Codes length {} is not a multiple of {} subspaces",
+                codes.len(),
+                self.m
+            )));
+        }
+        let k = self.centroid_count();
+        if let Some(code) = codes.iter().find(|&&c| c as usize >= k) {
+            return Err(JsValue::from_str(&format!(
+                "Code {} is out of range for {} bits",
+                code, self.bits
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vectors of two 2-d subspaces, each near one of four square corners
+    fn clustered(n: usize) -> Vec<f32> {
+        let mut rng = Rng::new(3);
+        let mut data = Vec::with_capacity(n * 4);
+        for i in 0..n {
+            for s in 0..2 {
+                let corner = (i + s) % 4;
+                for axis in [corner % 2, corner / 2] {
+                    data.push(axis as f32 * 10.0 + (rng.next_f64() as f32 - 0.5) * 0.1);
+                }
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_codes_round_trip_clusters() {
+        let data = clustered(64);
+        let codec = PqCodec::fit(&data, 4, 2, 2, &TrainOptions::default()).unwrap();
+        let codes = codec.encode_rows(&data);
+        assert_eq!(codes.len(), 64 * 2);
+        let decoded = codec.decode_rows(&codes);
+        let error = decoded
+            .iter()
+            .zip(&data)
+            .fold(0.0f32, |max, (a, b)| max.max((a - b).abs()));
+        assert!(error < 0.1, "max error {}", error);
+
+        // Asymmetric distances match distances to the decoded vectors
+        let query = &data[..4];
+        let distances = codec.distances(query, &codes);
+        for (row, distance) in decoded.chunks_exact(4).zip(&distances) {
+            assert!((squared_distance(query, row) - distance).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_training_is_seeded_and_serializes() {
+        let data = clustered(32);
+        let options = TrainOptions::default();
+        let a = PqCodec::fit(&data, 4, 2, 2, &options).unwrap();
+        assert_eq!(a, PqCodec::fit(&data, 4, 2, 2, &options).unwrap());
+        assert_eq!(PqCodec::parse(&a.to_bytes()).unwrap(), a);
+
+        assert!(PqCodec::parse(b"PQC1").is_err());
+        assert!(PqCodec::parse(&a.to_bytes()[..20]).is_err());
+        assert!(PqCodec::fit(&data, 4, 3, 2, &options).is_err());
+        assert!(PqCodec::fit(&data, 4, 2, 9, &options).is_err());
+        assert!(PqCodec::fit(&data[..8], 4, 2, 2, &options).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Tokenizing instruction prefixes once
+//!
+//! Instruction-tuned models (e5, bge) put the same prefix ("query: ",
+//! "passage: ") in front of every text. When the tokenizer splits on
+//! whitespace, the prefix tokenizes the same whatever follows it, so it is
+//! tokenized once per tokenizer and joined to each text's own tokens. Whether
+//! that holds is checked once per prefix, against tokenizing probe texts whole;
+//! tokenizers where it doesn't (byte-level BPE, Metaspace) tokenize the full
+//! text as before. Texts are tokenized with a copy of the tokenizer without
+//! truncation or padding, made on first use; both are applied to the joined
+//! tokens.
+//!
+//! Only tokenization is cached. BERT-style encoders attend in both directions,
+//! so the hidden states of the prefix tokens depend on the text after them:
+//! unlike a decoder's KV cache there is no prefix state to reuse, and every
+//! forward pass still runs over the whole sequence.
+
+use std::rc::Rc;
+
+use tokenizers::{Encoding, Tokenizer};
+
+use crate::EmbeddingEngine;
+
+/// Texts joined to a prefix to check that joining matches whole tokenization
+const PROBE_TEXTS: [&str; 2] = ["hello world", "Ünïcode, punctuation: 42!"];
+
+/// `tokenizer` without truncation or padding, for encoding the parts of a
+/// text; both apply once the parts are joined
+pub(crate) fn segment_tokenizer(tokenizer: &Tokenizer) -> Option<Tokenizer> {
+    let mut segments = tokenizer.clone();
+    segments.with_truncation(None).ok()?.with_padding(None);
+    Some(segments)
+}
+
+/// `prefix` tokens followed by `text` tokens, with the text's offsets and
+/// word ids moved past the prefix as if both were tokenized together
+fn join(prefix: &Encoding, prefix_len: usize, mut text: Encoding) -> Encoding {
+    let words = prefix
+        .get_word_ids()
+        .iter()
+        .flatten()
+        .max()
+        .map_or(0, |w| w + 1);
+    for (start, end) in text.get_offsets_mut() {
+        *start += prefix_len;
+        *end += prefix_len;
+    }
+    for word in text.get_word_ids_mut().iter_mut().flatten() {
+        *word += words;
+    }
+    let mut joined = prefix.clone();
+    joined.merge_with(text, false);
+    joined
+}
+
+/// Tokens of an instruction prefix, to put in front of each text's own
+pub(crate) struct PrefixTokens {
+    tokens: Encoding,
+    /// Length of the prefix in bytes
+    len: usize,
+    /// `segment_tokenizer()` of the engine's tokenizer
+    segments: Rc<Tokenizer>,
+}
+
+impl PrefixTokens {
+    /// Tokenize `prefix`, or `None` if texts after it can't be tokenized on
+    /// their own
+    pub(crate) fn new(
+        tokenizer: &Tokenizer,
+        segments: Rc<Tokenizer>,
+        prefix: &str,
+    ) -> Option<Self> {
+        if !prefix.ends_with(char::is_whitespace) {
+            return None;
+        }
+        let prefix_tokens = PrefixTokens {
+            tokens: segments.encode(prefix, false).ok()?,
+            len: prefix.len(),
+            segments,
+        };
+        prefix_tokens
+            .joins_cleanly(tokenizer, prefix)
+            .then_some(prefix_tokens)
+    }
+
+    /// Encode `text` with special tokens as if it followed the prefix;
+    /// `tokenizer`'s truncation and padding apply to the joined tokens
+    pub(crate) fn encode(&self, tokenizer: &Tokenizer, text: &str) -> tokenizers::Result<Encoding> {
+        let text = self.segments.encode(text, false)?;
+        tokenizer.post_process(join(&self.tokens, self.len, text), None, true)
+    }
+
+    /// Whether joining gives the same encoding as tokenizing the joined
+    /// string
+    fn joins_cleanly(&self, tokenizer: &Tokenizer, prefix: &str) -> bool {
+        PROBE_TEXTS.iter().all(|probe| {
+            let joined = self.encode(tokenizer, probe);
+            let whole = tokenizer.encode(format!("{}{}", prefix, probe), true);
+            match (joined, whole) {
+                (Ok(joined), Ok(whole)) => {
+                    joined.get_ids() == whole.get_ids()
+                        && joined.get_type_ids() == whole.get_type_ids()
+                        && joined.get_attention_mask() == whole.get_attention_mask()
+                        && joined.get_offsets() == whole.get_offsets()
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// The tokenizer used for the text after the prefix
+    pub(crate) fn segments(&self) -> Rc<Tokenizer> {
+        self.segments.clone()
+    }
+}
+
+impl EmbeddingEngine {
+    /// Cached tokens of `prefix`, or `None` when there is no prefix or it
+    /// has to be tokenized with each text
+    pub(crate) fn prefix_tokens(
+        &self,
+        tokenizer: &Tokenizer,
+        prefix: &str,
+    ) -> Option<Rc<PrefixTokens>> {
+        if prefix.is_empty() {
+            return None;
+        }
+        let (cached, segments) = {
+            let cache = self.token_cache.borrow();
+            (cache.prefix_tokens(prefix), cache.prefix_segments())
+        };
+        if let Some(cached) = cached {
+            return cached;
+        }
+        let tokens = segments
+            .or_else(|| segment_tokenizer(tokenizer).map(Rc::new))
+            .and_then(|segments| PrefixTokens::new(tokenizer, segments, prefix))
+            .map(Rc::new);
+        self.token_cache
+            .borrow_mut()
+            .insert_prefix_tokens(prefix, tokens.clone());
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn wordpiece_tokenizer() -> Tokenizer {
+        Tokenizer::from_str(
+            r###"{
+                "version": "1.0",
+                "added_tokens": [],
+                "normalizer": {"type": "BertNormalizer", "clean_text": true,
+                    "handle_chinese_chars": true, "strip_accents": null, "lowercase": true},
+                "pre_tokenizer": {"type": "BertPreTokenizer"},
+                "post_processor": {"type": "BertProcessing",
+                    "sep": ["[SEP]", 102], "cls": ["[CLS]", 101]},
+                "model": {"type": "WordPiece", "unk_token": "[UNK]",
+                    "continuing_subword_prefix": "##", "max_input_chars_per_word": 100,
+                    "vocab": {"[UNK]": 0, "[CLS]": 101, "[SEP]": 102, "query": 3, ":": 4,
+                        "hello": 5, "world": 6, "un": 7, "##ic": 8, "##ode": 9, ",": 10,
+                        "!": 11, "42": 12}}
+            }"###,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_prefix_joined_like_whole_text() {
+        let tokenizer = wordpiece_tokenizer();
+        let segments = Rc::new(segment_tokenizer(&tokenizer).unwrap());
+        let prefix = PrefixTokens::new(&tokenizer, segments.clone(), "query: ").unwrap();
+        assert_eq!(prefix.tokens.get_ids(), &[3, 4]);
+
+        let joined = prefix.encode(&tokenizer, "Hello, world!").unwrap();
+        let whole = tokenizer.encode("query: Hello, world!", true).unwrap();
+        assert_eq!(joined.get_ids(), whole.get_ids());
+        assert_eq!(joined.get_offsets(), whole.get_offsets());
+        assert_eq!(joined.get_word_ids(), whole.get_word_ids());
+
+        // A prefix that runs into the text can't be tokenized separately
+        assert!(PrefixTokens::new(&tokenizer, segments, "query:").is_none());
+    }
+}
@@ -0,0 +1,288 @@
+//! Optional text cleanup before tokenization
+//!
+//! Web-scraped text embeds noticeably better after consistent cleanup: markup
+//! and tracking URLs carry no meaning but use up the token budget. The stage is
+//! off by default; `set_preprocessing()` enables it for everything that goes
+//! through `embed`/`embed_batch`. APIs that report offsets into the input
+//! (`explain_similarity`, `embed_with_attentions`) see the raw text.
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::EmbeddingEngine;
+
+/// What to do with URLs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum UrlMode {
+    #[default]
+    Keep,
+    Remove,
+    /// Replace with the host name, e.g. `example.com`
+    Domain,
+}
+
+/// What to do with emoji
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EmojiMode {
+    #[default]
+    Keep,
+    Remove,
+}
+
+/// Options for `set_preprocessing`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct PreprocessOptions {
+    /// Drop tags (and `<script>`/`<style>` contents) and decode entities
+    strip_html: bool,
+    /// Turn runs of whitespace into single spaces and trim the ends
+    collapse_whitespace: bool,
+    urls: UrlMode,
+    emoji: EmojiMode,
+}
+
+impl PreprocessOptions {
+    fn is_noop(&self) -> bool {
+        *self == PreprocessOptions::default()
+    }
+
+    /// Apply the enabled steps in order: HTML, URLs, emoji, whitespace
+    pub(crate) fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        if self.strip_html && text.contains(['<', '&']) {
+            text = Cow::Owned(strip_html(&text));
+        }
+        if self.urls != UrlMode::Keep {
+            text = Cow::Owned(replace_urls(&text, self.urls));
+        }
+        if self.emoji == EmojiMode::Remove && text.chars().any(is_emoji) {
+            text = Cow::Owned(text.chars().filter(|&c| !is_emoji(c)).collect());
+        }
+        if self.collapse_whitespace {
+            text = Cow::Owned(text.split_whitespace().collect::<Vec<_>>().join(" "));
+        }
+        text
+    }
+}
+
+/// Remove tags, replacing them with a space so words on either side of a
+/// block boundary stay separate, and decode character entities
+fn strip_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(pos) = rest.find(['<', '&']) {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if rest.starts_with('<') {
+            let Some(end) = rest.find('>') else {
+                // Unterminated tag: keep the text as is
+                out.push_str(rest);
+                return out;
+            };
+            let inner = &rest[1..end];
+            let closing = inner.starts_with('/');
+            let name = inner
+                .trim_start_matches('/')
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            rest = &rest[end + 1..];
+            if !closing && (name == "script" || name == "style") {
+                // Skip straight to the closing tag; the contents aren't text
+                let close = format!("</{}", name);
+                rest = match rest.to_ascii_lowercase().find(&close) {
+                    Some(at) => &rest[at..],
+                    None => "",
+                };
+            }
+            out.push(' ');
+        } else {
+            let (decoded, used) = decode_entity(rest);
+            out.push_str(&decoded);
+            rest = &rest[used..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decode the entity at the start of `text`, returning the replacement and
+/// the number of bytes consumed (just the `&` if it isn't a known entity)
+fn decode_entity(text: &str) -> (Cow<'static, str>, usize) {
+    let Some(end) = text.bytes().take(12).position(|b| b == b';') else {
+        return (Cow::Borrowed("&"), 1);
+    };
+    let name = &text[1..end];
+    let decoded = match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" | "#39" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = if let Some(hex) = name.strip_prefix("#x").or(name.strip_prefix("#X")) {
+                u32::from_str_radix(hex, 16).ok()
+            } else {
+                name.strip_prefix('#').and_then(|dec| dec.parse().ok())
+            };
+            code.and_then(char::from_u32)
+        }
+    };
+    match decoded {
+        Some(c) => (Cow::Owned(c.to_string()), end + 1),
+        None => (Cow::Borrowed("&"), 1),
+    }
+}
+
+/// Replace `http(s)://` and `www.` URLs according to `mode`
+fn replace_urls(text: &str, mode: UrlMode) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = find_url(rest) {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        let len = tail
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"'))
+            .unwrap_or(tail.len());
+        // Sentence punctuation directly after a URL isn't part of it
+        let url = tail[..len].trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+        if mode == UrlMode::Domain {
+            out.push_str(url_host(url));
+        }
+        rest = &tail[url.len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Byte position of the first URL that starts at a word boundary
+fn find_url(text: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(pos) = ["http://", "https://", "www."]
+        .iter()
+        .filter_map(|prefix| text[from..].find(prefix).map(|p| p + from))
+        .min()
+    {
+        let at_boundary = text[..pos]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_alphanumeric());
+        if at_boundary {
+            return Some(pos);
+        }
+        from = pos + 1;
+    }
+    None
+}
+
+/// Host of a URL, without scheme, `www.`, port, path or credentials
+fn url_host(url: &str) -> &str {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = url.split(['/', '?', '#']).next().unwrap_or(url);
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    let host = host.split(':').next().unwrap_or(host);
+    host.strip_prefix("www.").unwrap_or(host)
+}
+
+/// Emoji and the joiners/modifiers that combine them
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF // pictographs, emoticons, transport, flags, symbols
+        | 0x2600..=0x27BF // miscellaneous symbols and dingbats
+        | 0x2B00..=0x2BFF // arrows and stars such as ⭐
+        | 0x200D          // zero width joiner
+        | 0xFE0F          // emoji presentation selector
+        | 0x20E3          // keycap
+        | 0xE0020..=0xE007F // tag sequences (subdivision flags)
+    )
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Enable text cleanup before embedding
+    ///
+    /// Options: `{ strip_html = false, collapse_whitespace = false, urls =
+    /// "keep" | "remove" | "domain", emoji = "keep" | "remove" }`. Pass
+    /// `undefined` to turn preprocessing off.
+    #[wasm_bindgen]
+    pub fn set_preprocessing(&mut self, options: &JsValue) -> Result<(), JsValue> {
+        let options: PreprocessOptions = parse_options(options)?;
+        self.preprocessing = (!options.is_noop()).then_some(options);
+        Ok(())
+    }
+
+    /// Show what the preprocessing stage does to a text
+    #[wasm_bindgen]
+    pub fn preprocess(&self, text: &str) -> String {
+        match &self.preprocessing {
+            Some(options) => options.apply(text).into_owned(),
+            None => text.to_string(),
+        }
+    }
+}
+
+impl EmbeddingEngine {
+    /// Texts after preprocessing, borrowed when it is disabled
+    pub(crate) fn preprocessed<'a>(&self, texts: &'a [String]) -> Cow<'a, [String]> {
+        match &self.preprocessing {
+            Some(options) => Cow::Owned(
+                texts
+                    .iter()
+                    .map(|t| options.apply(t).into_owned())
+                    .collect(),
+            ),
+            None => Cow::Borrowed(texts),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js::options_from_json;
+
+    fn options(json: &str) -> PreprocessOptions {
+        options_from_json(json).unwrap()
+    }
+
+    #[test]
+    fn test_strip_html() {
+        let html = "<p>Fish &amp; chips</p><script>var x = 1 < 2;</script><b>caf&#233;</b> &nosuch";
+        let clean = options(r#"{"strip_html": true, "collapse_whitespace": true}"#).apply(html);
+        assert_eq!(clean, "Fish & chips café &nosuch");
+        assert_eq!(strip_html("a < b"), "a < b");
+    }
+
+    #[test]
+    fn test_urls() {
+        let text = "See https://www.example.com/a?b=1, or www.rust-lang.org. Not xwww.a";
+        assert_eq!(
+            options(r#"{"urls": "domain"}"#).apply(text),
+            "See example.com, or rust-lang.org. Not xwww.a"
+        );
+        assert_eq!(
+            options(r#"{"urls": "remove", "collapse_whitespace": true}"#).apply(text),
+            "See , or . Not xwww.a"
+        );
+        assert_eq!(url_host("http://user@host.io:8080/x"), "host.io");
+    }
+
+    #[test]
+    fn test_emoji_and_noop() {
+        let text = "Great job 👍🏽! 🇺🇸 ❤️ done";
+        assert_eq!(
+            options(r#"{"emoji": "remove", "collapse_whitespace": true}"#).apply(text),
+            "Great job ! done"
+        );
+        let noop = options("{}");
+        assert!(noop.is_noop());
+        assert!(matches!(noop.apply(text), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,325 @@
+//! Known-good settings for popular checkpoints
+//!
+//! Switching models usually means switching pooling and input prefixes too,
+//! and getting either wrong still produces vectors, just worse ones. A preset
+//! carries what the model was trained with: `for_preset()` applies its
+//! pooling, and after `load()` the engine checks the model's dimension
+//! against it, truncates at its maximum length (within the engine's own
+//! limit) and adds its prefixes: the document prefix to everything embedded
+//! through `embed()`/`embed_batch*()`, the query prefix through
+//! `embed_query()`.
+
+use std::borrow::Cow;
+
+use js_sys::{Array, Float32Array};
+use tokenizers::{Encoding, Tokenizer, TruncationParams};
+use wasm_bindgen::prelude::*;
+
+use crate::{EmbeddingEngine, PoolingStrategy, MAX_SEQUENCE_LENGTH};
+
+/// Settings a checkpoint was trained with
+#[derive(Debug, PartialEq)]
+pub(crate) struct ModelPreset {
+    /// Hugging Face repository id
+    pub(crate) name: &'static str,
+    pub(crate) dimension: usize,
+    /// Tokens the model was trained on
+    pub(crate) max_length: usize,
+    pub(crate) pooling: PoolingStrategy,
+    /// Prepended to queries (`embed_query()`)
+    pub(crate) query_prefix: &'static str,
+    /// Prepended to everything else
+    pub(crate) document_prefix: &'static str,
+    /// Cosine cutoffs for `is_similar()` when loose, normal and strict: rough
+    /// starting points from where the model's scores usually fall, until a
+    /// fitted `ScoreCalibration` replaces them
+    pub(crate) similarity_thresholds: [f32; 3],
+}
+
+/// `similarity_thresholds` without a preset, for MiniLM-style models
+pub(crate) const DEFAULT_SIMILARITY_THRESHOLDS: [f32; 3] = [0.5, 0.6, 0.75];
+
+pub(crate) const PRESETS: &[ModelPreset] = &[
+    ModelPreset {
+        name: "sentence-transformers/all-MiniLM-L6-v2",
+        dimension: 384,
+        max_length: 256,
+        pooling: PoolingStrategy::Mean,
+        query_prefix: "",
+        document_prefix: "",
+        similarity_thresholds: [0.5, 0.6, 0.75],
+    },
+    ModelPreset {
+        name: "sentence-transformers/all-MiniLM-L12-v2",
+        dimension: 384,
+        max_length: 128,
+        pooling: PoolingStrategy::Mean,
+        query_prefix: "",
+        document_prefix: "",
+        similarity_thresholds: [0.5, 0.6, 0.75],
+    },
+    ModelPreset {
+        name: "intfloat/e5-small-v2",
+        dimension: 384,
+        max_length: 512,
+        pooling: PoolingStrategy::Mean,
+        query_prefix: "query: ",
+        document_prefix: "passage: ",
+        similarity_thresholds: [0.8, 0.85, 0.9],
+    },
+    ModelPreset {
+        name: "BAAI/bge-small-en-v1.5",
+        dimension: 384,
+        max_length: 512,
+        pooling: PoolingStrategy::Cls,
+        query_prefix: "Represent this sentence for searching relevant passages: ",
+        document_prefix: "",
+        similarity_thresholds: [0.75, 0.8, 0.88],
+    },
+    ModelPreset {
+        name: "intfloat/multilingual-e5-small",
+        dimension: 384,
+        max_length: 512,
+        pooling: PoolingStrategy::Mean,
+        query_prefix: "query: ",
+        document_prefix: "passage: ",
+        similarity_thresholds: [0.82, 0.87, 0.92],
+    },
+];
+
+impl ModelPreset {
+    /// Look up a preset by repository id, with or without the organization
+    /// (`"bge-small-en-v1.5"`), ignoring case
+    pub(crate) fn find(name: &str) -> Option<&'static ModelPreset> {
+        let name = name.trim();
+        PRESETS.iter().find(|preset| {
+            let short = preset.name.rsplit('/').next().unwrap_or(preset.name);
+            preset.name.eq_ignore_ascii_case(name) || short.eq_ignore_ascii_case(name)
+        })
+    }
+
+    /// Check a loaded model's hidden size against the preset
+    pub(crate) fn check_dimension(&self, dimension: usize) -> Result<(), String> {
+        if dimension == self.dimension {
+            return Ok(());
+        }
+        Err(format!(
+            "Model has dimension {} but preset {} expects {}; was the right model loaded?",
+            dimension, self.name, self.dimension
+        ))
+    }
+
+    /// Truncate the tokenizer at the preset's maximum length
+    pub(crate) fn apply_max_length(&self, tokenizer: &mut Tokenizer) -> Result<(), String> {
+        limit_max_length(tokenizer, self.max_length)
+    }
+}
+
+/// `set_max_length()` within `MAX_SEQUENCE_LENGTH`
+///
+/// The engine cuts ids at `MAX_SEQUENCE_LENGTH` without re-adding special
+/// tokens, so truncating any longer would drop the closing `[SEP]`/`</s>`
+/// of long texts.
+pub(crate) fn limit_max_length(tokenizer: &mut Tokenizer, max_length: usize) -> Result<(), String> {
+    set_max_length(tokenizer, max_length.min(MAX_SEQUENCE_LENGTH))
+}
+
+/// Truncate at `max_length` tokens, keeping the rest of the truncation settings
+fn set_max_length(tokenizer: &mut Tokenizer, max_length: usize) -> Result<(), String> {
+    let params = TruncationParams {
+        max_length,
+        ..tokenizer.get_truncation().cloned().unwrap_or_default()
+    };
+    tokenizer
+        .with_truncation(Some(params))
+        .map(|_| ())
+        .map_err(|e| format!("Failed to set truncation: {}", e))
+}
+
+/// Which side of a retrieval pair a text is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TextRole {
+    Query,
+    Document,
+}
+
+/// `texts` with `prefix` prepended, borrowed when there is none
+fn with_prefix<'a>(texts: &'a [String], prefix: &str) -> Cow<'a, [String]> {
+    if prefix.is_empty() {
+        return Cow::Borrowed(texts);
+    }
+    Cow::Owned(texts.iter().map(|t| format!("{}{}", prefix, t)).collect())
+}
+
+/// A token's byte offsets in the text after a `prefix_len`-byte prefix, or
+/// `None` for the prefix's own tokens
+pub(crate) fn unprefixed_offsets(
+    (start, end): (usize, usize),
+    prefix_len: usize,
+) -> Option<(usize, usize)> {
+    (start >= prefix_len).then(|| (start - prefix_len, end - prefix_len))
+}
+
+/// Names accepted by `EmbeddingEngine.for_preset()`
+#[wasm_bindgen]
+pub fn model_presets() -> Array {
+    PRESETS
+        .iter()
+        .map(|preset| JsValue::from_str(preset.name))
+        .collect()
+}
+
+impl EmbeddingEngine {
+    /// The preset's prefix for `role` ("" without a preset)
+    pub(crate) fn role_prefix(&self, role: TextRole) -> &'static str {
+        match (self.preset, role) {
+            (Some(preset), TextRole::Query) => preset.query_prefix,
+            (Some(preset), TextRole::Document) => preset.document_prefix,
+            (None, _) => "",
+        }
+    }
+
+    /// Texts with the preset's prefix for `role`
+    pub(crate) fn prefixed<'a>(&self, texts: &'a [String], role: TextRole) -> Cow<'a, [String]> {
+        with_prefix(texts, self.role_prefix(role))
+    }
+
+    /// Tokenize `texts` with the preset's prefix for `role`, as the
+    /// embedding calls do; offsets then index the prefixed text, see
+    /// `unprefixed_offsets()`
+    pub(crate) fn tokenize_as(
+        &self,
+        texts: &[String],
+        role: TextRole,
+    ) -> Result<Vec<Encoding>, JsValue> {
+        let prefix = self.role_prefix(role);
+        self.tokenize_with_prefix(&self.prefixed(texts, role), prefix)
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Create an engine (not loaded) configured for a known model
+    ///
+    /// See `model_presets()` for the names; the organization part can be
+    /// left out, e.g. `EmbeddingEngine.for_preset("bge-small-en-v1.5")`.
+    /// Pooling is set from the preset and can still be changed.
+    #[wasm_bindgen]
+    pub fn for_preset(name: &str) -> Result<EmbeddingEngine, JsValue> {
+        let preset = ModelPreset::find(name).ok_or_else(|| {
+            JsValue::from_str(&format!(
+                "Unknown model preset: {} (see model_presets())",
+                name
+            ))
+        })?;
+        let mut engine = EmbeddingEngine::new();
+        engine.pooling = preset.pooling;
+        engine.preset = Some(preset);
+        Ok(engine)
+    }
+
+    /// Repository id of the preset the engine was created with, if any
+    #[wasm_bindgen]
+    pub fn preset(&self) -> Option<String> {
+        self.preset.map(|preset| preset.name.to_string())
+    }
+
+    /// Embed a search query, with the preset's query prefix
+    ///
+    /// Without a preset, or for models that use no prefix, this is `embed()`.
+    #[wasm_bindgen]
+    pub fn embed_query(&self, text: &str) -> Result<Float32Array, JsValue> {
+        let embeddings = self.embed_matrix_as(&[text.to_string()], TextRole::Query)?;
+        if embeddings.len() > 0 {
+            Ok(Float32Array::from(embeddings.row(0)))
+        } else {
+            Err(JsValue::from_str("No embedding generated"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_find_preset() {
+        let bge = ModelPreset::find("bge-small-en-v1.5").unwrap();
+        assert_eq!(bge.name, "BAAI/bge-small-en-v1.5");
+        assert_eq!(bge.pooling, PoolingStrategy::Cls);
+        assert_eq!(
+            ModelPreset::find("INTFLOAT/E5-small-v2").unwrap().dimension,
+            384
+        );
+        assert!(ModelPreset::find("e5").is_none());
+        assert!(bge.check_dimension(384).is_ok());
+        assert!(bge.check_dimension(768).is_err());
+    }
+
+    #[test]
+    fn test_prefixes_by_role() {
+        let mut engine = EmbeddingEngine::new();
+        let texts = vec!["how do magnets work".to_string()];
+        assert_eq!(engine.prefixed(&texts, TextRole::Query)[0], texts[0]);
+
+        engine.preset = ModelPreset::find("multilingual-e5-small");
+        assert_eq!(
+            engine.prefixed(&texts, TextRole::Query)[0],
+            "query: how do magnets work"
+        );
+        assert_eq!(
+            engine.prefixed(&texts, TextRole::Document)[0],
+            "passage: how do magnets work"
+        );
+        engine.preset = ModelPreset::find("bge-small-en-v1.5");
+        assert!(matches!(
+            engine.prefixed(&texts, TextRole::Document),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_max_length_applied() {
+        let mut tokenizer = Tokenizer::from_str(
+            r#"{
+                "version": "1.0", "truncation": null, "padding": null, "added_tokens": [],
+                "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+                "post_processor": null, "decoder": null,
+                "model": {"type": "WordLevel", "vocab": {"[UNK]": 0, "cat": 1},
+                          "unk_token": "[UNK]"}
+            }"#,
+        )
+        .unwrap();
+        let preset = ModelPreset::find("all-MiniLM-L12-v2").unwrap();
+        preset.apply_max_length(&mut tokenizer).unwrap();
+        let text = "cat ".repeat(300);
+        assert_eq!(tokenizer.encode(text.as_str(), false).unwrap().len(), 128);
+    }
+
+    #[test]
+    fn test_max_length_keeps_sep_within_engine_limit() {
+        let mut tokenizer = Tokenizer::from_str(
+            r#"{
+                "version": "1.0", "truncation": null, "padding": null, "added_tokens": [],
+                "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+                "post_processor": {"type": "BertProcessing",
+                                   "sep": ["[SEP]", 2], "cls": ["[CLS]", 1]},
+                "decoder": null,
+                "model": {"type": "WordLevel",
+                          "vocab": {"[UNK]": 0, "[CLS]": 1, "[SEP]": 2, "cat": 3},
+                          "unk_token": "[UNK]"}
+            }"#,
+        )
+        .unwrap();
+        let preset = ModelPreset::find("e5-small-v2").unwrap();
+        assert_eq!(preset.max_length, 512);
+        preset.apply_max_length(&mut tokenizer).unwrap();
+        let text = "cat ".repeat(300);
+        let encoding = tokenizer.encode(text.as_str(), true).unwrap();
+        // What the engine keeps: at most MAX_SEQUENCE_LENGTH ids
+        let ids = &encoding.get_ids()[..encoding.len().min(MAX_SEQUENCE_LENGTH)];
+        assert_eq!(ids.len(), MAX_SEQUENCE_LENGTH);
+        assert_eq!(ids.last(), Some(&2));
+    }
+}
@@ -0,0 +1,150 @@
+//! Embedding pre-tokenized input (`embed_ids`)
+//!
+//! Callers that tokenize elsewhere, cache token ids, or need to reproduce
+//! encodings from another toolchain exactly can pass ids straight to the
+//! model. The tokenizer, preprocessing and preset prefixes are skipped; ids
+//! must already include special tokens (`[CLS] ... [SEP]`). Limits, memory
+//! budget and micro-batching apply as for text.
+
+use js_sys::{Array, Float32Array, Uint32Array};
+use tokenizers::Encoding;
+use wasm_bindgen::prelude::*;
+
+use crate::{EmbeddingEngine, MAX_SEQUENCE_LENGTH};
+
+/// Rows of an array of id arrays (plain arrays or typed arrays)
+fn rows(value: &Array) -> Vec<Vec<u32>> {
+    value
+        .iter()
+        .map(|row| Uint32Array::new(&row).to_vec())
+        .collect()
+}
+
+/// Encodings for rows of token ids, validated against the model
+///
+/// Without `masks` every token is attended to. Padding between rows of
+/// different lengths is added when the batch runs.
+fn encodings_from_ids(
+    ids: Vec<Vec<u32>>,
+    masks: Option<Vec<Vec<u32>>>,
+    vocab_size: Option<usize>,
+) -> Result<Vec<Encoding>, String> {
+    if let Some(masks) = &masks {
+        if masks.len() != ids.len() {
+            return Err(format!(
+                "attention_mask has {} rows but input_ids has {}",
+                masks.len(),
+                ids.len()
+            ));
+        }
+    }
+    let mut masks = masks.map(Vec::into_iter);
+    let mut encodings = Vec::with_capacity(ids.len());
+    for (row, ids) in ids.into_iter().enumerate() {
+        let len = ids.len();
+        if len == 0 || len > MAX_SEQUENCE_LENGTH {
+            return Err(format!(
+                "Row {} has {} tokens (expected 1 to {})",
+                row, len, MAX_SEQUENCE_LENGTH
+            ));
+        }
+        if let Some(vocab_size) = vocab_size {
+            if let Some(id) = ids.iter().find(|&&id| id as usize >= vocab_size) {
+                return Err(format!(
+                    "Token id {} in row {} is outside the vocabulary of {}",
+                    id, row, vocab_size
+                ));
+            }
+        }
+        let mask = match masks.as_mut().and_then(Iterator::next) {
+            Some(mask) if mask.len() != len => {
+                return Err(format!(
+                    "attention_mask row {} has {} values for {} tokens",
+                    row,
+                    mask.len(),
+                    len
+                ))
+            }
+            Some(mask) => mask,
+            None => vec![1; len],
+        };
+        encodings.push(Encoding::new(
+            ids,
+            vec![0; len],
+            vec![String::new(); len],
+            vec![None; len],
+            vec![(0, 0); len],
+            vec![0; len],
+            mask,
+            Vec::new(),
+            Default::default(),
+        ));
+    }
+    Ok(encodings)
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Embed token ids produced elsewhere
+    ///
+    /// `input_ids` is an array of rows (arrays or `Uint32Array`s), one per
+    /// text, with special tokens included; `attention_mask`, if given, has the
+    /// same shape (0 marks padding). Returns one Float32Array per row.
+    #[wasm_bindgen]
+    pub fn embed_ids(
+        &self,
+        input_ids: &Array,
+        attention_mask: Option<Array>,
+    ) -> Result<Array, JsValue> {
+        let model = self
+            .model
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Model not loaded. Call load_embedded() first."))?;
+        let encodings = encodings_from_ids(
+            rows(input_ids),
+            attention_mask.as_ref().map(rows),
+            model.vocab_size(),
+        )
+        .map_err(|e| JsValue::from_str(&e))?;
+        if encodings.is_empty() {
+            return Ok(Array::new());
+        }
+
+        let embeddings = self.embed_encodings_within_budget(&encodings)?.embeddings;
+        Ok((0..embeddings.len())
+            .map(|i| JsValue::from(Float32Array::from(embeddings.row(i))))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings_from_ids() {
+        let encodings = encodings_from_ids(
+            vec![vec![101, 7592, 102], vec![101, 102]],
+            None,
+            Some(30522),
+        )
+        .unwrap();
+        assert_eq!(encodings[0].get_ids(), &[101, 7592, 102]);
+        assert_eq!(encodings[1].get_attention_mask(), &[1, 1]);
+        assert_eq!(encodings[1].get_type_ids(), &[0, 0]);
+
+        let masked =
+            encodings_from_ids(vec![vec![101, 102, 0]], Some(vec![vec![1, 1, 0]]), None).unwrap();
+        assert_eq!(masked[0].get_attention_mask(), &[1, 1, 0]);
+    }
+
+    #[test]
+    fn test_invalid_ids_rejected() {
+        let err = encodings_from_ids(vec![vec![101, 40000]], None, Some(30522)).unwrap_err();
+        assert!(err.contains("40000"));
+        assert!(encodings_from_ids(vec![vec![]], None, None).is_err());
+        assert!(encodings_from_ids(vec![vec![1; MAX_SEQUENCE_LENGTH + 1]], None, None).is_err());
+        assert!(encodings_from_ids(vec![vec![1, 2]], Some(vec![vec![1]]), None).is_err());
+        assert!(encodings_from_ids(vec![vec![1, 2]], Some(vec![]), None).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Progress reporting for large batches (`on_progress`)
+//!
+//! A big `embed_batch` call runs for seconds without returning. With a
+//! progress callback registered, batches are embedded in micro-batches (of
+//! `set_micro_batch_size()` texts, or `PROGRESS_BATCH_SIZE` when none is set)
+//! and the callback runs after each one with
+//! `(done, total, elapsed_ms, eta_ms)`. Splitting doesn't change the vectors:
+//! padding is masked out of attention and pooling.
+//!
+//! Unlike `on_inference`, an error thrown by the callback is not swallowed: it
+//! stops the call and is rethrown, so a UI can cancel a long job.
+
+use wasm_bindgen::prelude::*;
+
+use crate::EmbeddingEngine;
+
+/// Texts per micro-batch when reporting progress without an explicit
+/// micro-batch size
+pub(crate) const PROGRESS_BATCH_SIZE: usize = 32;
+
+/// Remaining time extrapolated from the average time per text so far
+fn eta_ms(done: usize, total: usize, elapsed_ms: f64) -> f64 {
+    if done == 0 {
+        return 0.0;
+    }
+    elapsed_ms / done as f64 * total.saturating_sub(done) as f64
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Register a callback `(done, total, elapsed_ms, eta_ms)` run after each
+    /// micro-batch of an embedding call (replaces any previous callback)
+    ///
+    /// `done` and `total` count the distinct texts of the call. Throwing from
+    /// the callback cancels the call.
+    #[wasm_bindgen]
+    pub fn on_progress(&mut self, callback: js_sys::Function) {
+        self.on_progress = Some(callback);
+    }
+
+    /// Remove the `on_progress` callback
+    #[wasm_bindgen]
+    pub fn clear_on_progress(&mut self) {
+        self.on_progress = None;
+    }
+}
+
+impl EmbeddingEngine {
+    /// Texts per micro-batch: the configured size, or `PROGRESS_BATCH_SIZE`
+    /// while a progress callback needs batches to report between
+    pub(crate) fn effective_micro_batch_size(&self) -> Option<usize> {
+        self.micro_batch_size
+            .or_else(|| self.on_progress.as_ref().map(|_| PROGRESS_BATCH_SIZE))
+    }
+
+    /// Report progress to the registered callback, if any, passing on any
+    /// error it throws
+    pub(crate) fn report_progress(
+        &self,
+        done: usize,
+        total: usize,
+        elapsed_ms: f64,
+    ) -> Result<(), JsValue> {
+        let Some(callback) = &self.on_progress else {
+            return Ok(());
+        };
+        callback.call4(
+            &JsValue::NULL,
+            &JsValue::from(done as u32),
+            &JsValue::from(total as u32),
+            &JsValue::from_f64(elapsed_ms),
+            &JsValue::from_f64(eta_ms(done, total, elapsed_ms)),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eta_extrapolates_rate() {
+        assert_eq!(eta_ms(0, 100, 0.0), 0.0);
+        assert_eq!(eta_ms(25, 100, 500.0), 1500.0);
+        assert_eq!(eta_ms(100, 100, 2000.0), 0.0);
+    }
+
+    #[test]
+    fn test_micro_batches_only_when_needed() {
+        let mut engine = EmbeddingEngine::new();
+        assert_eq!(engine.effective_micro_batch_size(), None);
+        assert!(engine.report_progress(1, 2, 10.0).is_ok());
+        engine.micro_batch_size = Some(8);
+        assert_eq!(engine.effective_micro_batch_size(), Some(8));
+    }
+}
@@ -0,0 +1,196 @@
+//! Seeded random projection (`RandomProjection`)
+//!
+//! Multiplying by a random matrix approximately preserves distances
+//! (Johnson-Lindenstrauss), so vectors can be shrunk without fitting PCA on a
+//! corpus first. The matrix is generated from the seed alone, using only
+//! correctly rounded arithmetic, so every client that uses the same
+//! `(input_dim, output_dim, seed)` projects identically and their vectors
+//! stay comparable.
+//!
+//! Entries are Gaussian (an Irwin-Hall approximation) with variance
+//! `1 / output_dim`, or with `{ sparse: true }` the Achlioptas distribution:
+//! `±sqrt(3 / output_dim)` with probability 1/6 each, 0 otherwise.
+
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::rng::Rng;
+
+/// Options for `new RandomProjection()`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct ProjectionOptions {
+    /// Use the sparse (Achlioptas) distribution instead of Gaussian
+    sparse: bool,
+}
+
+/// `output_dim x input_dim` row-major projection matrix for `seed`
+fn projection_matrix(input_dim: usize, output_dim: usize, seed: u32, sparse: bool) -> Vec<f32> {
+    let mut rng = Rng::new(seed as u64);
+    let scale = if sparse {
+        (3.0 / output_dim as f64).sqrt()
+    } else {
+        (1.0 / output_dim as f64).sqrt()
+    };
+    (0..input_dim * output_dim)
+        .map(|_| {
+            let value = if sparse {
+                match rng.below(6) {
+                    0 => 1.0,
+                    1 => -1.0,
+                    _ => 0.0,
+                }
+            } else {
+                rng.normal()
+            };
+            (value * scale) as f32
+        })
+        .collect()
+}
+
+/// Fixed random linear map from `input_dim` to `output_dim` dimensions
+#[wasm_bindgen]
+pub struct RandomProjection {
+    input_dim: usize,
+    output_dim: usize,
+    seed: u32,
+    sparse: bool,
+    matrix: Vec<f32>,
+}
+
+impl RandomProjection {
+    /// `matrix * vector` for a vector of `input_dim` values
+    fn project(&self, vector: &[f32]) -> Vec<f32> {
+        self.matrix
+            .chunks_exact(self.input_dim)
+            .map(|row| row.iter().zip(vector).map(|(w, v)| w * v).sum())
+            .collect()
+    }
+}
+
+#[wasm_bindgen]
+impl RandomProjection {
+    /// Create the projection for `seed`
+    ///
+    /// Options: `{ sparse = false }`. The same arguments give the same
+    /// projection in every client.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        input_dim: usize,
+        output_dim: usize,
+        seed: u32,
+        options: &JsValue,
+    ) -> Result<RandomProjection, JsValue> {
+        let options: ProjectionOptions = parse_options(options)?;
+        if input_dim == 0 || output_dim == 0 {
+            return Err(JsValue::from_str("Dimensions must be greater than 0"));
+        }
+        Ok(RandomProjection {
+            input_dim,
+            output_dim,
+            seed,
+            sparse: options.sparse,
+            matrix: projection_matrix(input_dim, output_dim, seed, options.sparse),
+        })
+    }
+
+    /// Project one vector of `input_dim` values
+    #[wasm_bindgen]
+    pub fn apply(&self, vector: &[f32]) -> Result<Vec<f32>, JsValue> {
+        if vector.len() != self.input_dim {
+            return Err(JsValue::from_str(&format!(
+                "Vector has {} values, expected {}",
+                vector.len(),
+                self.input_dim
+            )));
+        }
+        Ok(self.project(vector))
+    }
+
+    /// Project row-major vectors, `input_dim` values each
+    #[wasm_bindgen]
+    pub fn apply_batch(&self, vectors: &[f32]) -> Result<Vec<f32>, JsValue> {
+        if !vectors.len().is_multiple_of(self.input_dim) {
+            return Err(JsValue::from_str(&format!(
+                "Vectors length {} is not a multiple of dimension {}",
+                vectors.len(),
+                self.input_dim
+            )));
+        }
+        Ok(vectors
+            .chunks_exact(self.input_dim)
+            .flat_map(|vector| self.project(vector))
+            .collect())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn input_dim(&self) -> usize {
+        self.input_dim
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn output_dim(&self) -> usize {
+        self.output_dim
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Whether the matrix uses the sparse distribution
+    #[wasm_bindgen(getter)]
+    pub fn sparse(&self) -> bool {
+        self.sparse
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn projection(sparse: bool) -> RandomProjection {
+        RandomProjection {
+            input_dim: 64,
+            output_dim: 256,
+            seed: 42,
+            sparse,
+            matrix: projection_matrix(64, 256, 42, sparse),
+        }
+    }
+
+    #[test]
+    fn test_seeded_matrix() {
+        assert_eq!(
+            projection_matrix(8, 4, 1, false),
+            projection_matrix(8, 4, 1, false)
+        );
+        assert_ne!(
+            projection_matrix(8, 4, 1, false),
+            projection_matrix(8, 4, 2, false)
+        );
+        let sparse = projection_matrix(64, 64, 1, true);
+        let zeros = sparse.iter().filter(|&&v| v == 0.0).count();
+        assert!((2400..3100).contains(&zeros), "{} zeros", zeros);
+    }
+
+    #[test]
+    fn test_preserves_distances() {
+        let a: Vec<f32> = (0..64).map(|i| (i as f32 * 0.37).sin()).collect();
+        let b: Vec<f32> = (0..64).map(|i| (i as f32 * 0.11).cos()).collect();
+        let distance = |x: &[f32], y: &[f32]| {
+            x.iter()
+                .zip(y)
+                .map(|(p, q)| (p - q) * (p - q))
+                .sum::<f32>()
+                .sqrt()
+        };
+        for sparse in [false, true] {
+            let projection = projection(sparse);
+            let ratio =
+                distance(&projection.project(&a), &projection.project(&b)) / distance(&a, &b);
+            assert!((0.85..1.15).contains(&ratio), "ratio {}", ratio);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! One-call numerics sanity check
+//!
+//! `self_test()` embeds a small built-in reference set and checks invariants
+//! that hold for any model (finite, unit norm, deterministic, batch results
+//! equal single results). When the loaded model is the stock
+//! all-MiniLM-L6-v2 with default pooling, it also compares against golden
+//! values produced by the native build, which match sentence-transformers.
+
+use js_sys::Array;
+use wasm_bindgen::prelude::*;
+
+use crate::bert::LayerSelection;
+use crate::{EmbeddingEngine, PoolingStrategy};
+
+/// Reference texts embedded by the self-test
+const REFERENCE_TEXTS: [&str; 3] = [
+    "Hello world",
+    "A cat sits on the mat",
+    "The quick brown fox jumps over the lazy dog",
+];
+
+/// Model hash (see `fingerprint`) of the stock all-MiniLM-L6-v2 files
+const REFERENCE_MODEL_HASH: u64 = 0x70de_7f85_5098_2a1f;
+
+/// First dimensions of each reference embedding for all-MiniLM-L6-v2
+const GOLDEN_PREFIXES: [[f32; 8]; 3] = [
+    [
+        -0.0344773,
+        0.031023242,
+        0.0067349747,
+        0.026108963,
+        -0.039362025,
+        -0.16030249,
+        0.066924065,
+        -0.006441427,
+    ],
+    [
+        0.1287247,
+        -0.03267293,
+        -0.022915695,
+        0.04023047,
+        -0.03545385,
+        0.03155845,
+        0.04999072,
+        0.028023796,
+    ],
+    [
+        0.035496827,
+        0.061286278,
+        0.05269208,
+        0.07070504,
+        0.033101406,
+        -0.03066961,
+        0.006620607,
+        -0.061183304,
+    ],
+];
+
+/// Cosine similarity of reference pairs (0, 1), (0, 2), (1, 2)
+const GOLDEN_COSINES: [f32; 3] = [-0.064278856, 0.13322034, 0.18380636];
+
+/// Allowed absolute error against golden values; covers differences in
+/// float summation order between runtimes, not numerics bugs
+const GOLDEN_TOLERANCE: f32 = 1e-3;
+
+/// Allowed difference between batched and single-text results
+const CONSISTENCY_TOLERANCE: f32 = 1e-4;
+
+/// Result of `self_test()`
+#[wasm_bindgen]
+pub struct SelfTestReport {
+    failures: Vec<String>,
+    golden_compared: bool,
+    max_golden_error: f32,
+}
+
+#[wasm_bindgen]
+impl SelfTestReport {
+    /// True when every check passed
+    #[wasm_bindgen(getter)]
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Whether golden vectors were compared (stock all-MiniLM-L6-v2 with
+    /// mean pooling only); other models get the invariant checks alone
+    #[wasm_bindgen(getter)]
+    pub fn golden_compared(&self) -> bool {
+        self.golden_compared
+    }
+
+    /// Largest absolute deviation from the golden values (0 if not compared)
+    #[wasm_bindgen(getter)]
+    pub fn max_golden_error(&self) -> f32 {
+        self.max_golden_error
+    }
+
+    /// Description of each failed check
+    #[wasm_bindgen(getter)]
+    pub fn failures(&self) -> Array {
+        self.failures.iter().map(|f| JsValue::from_str(f)).collect()
+    }
+
+    /// One-line human-readable summary
+    #[wasm_bindgen]
+    pub fn summary(&self) -> String {
+        let golden = if self.golden_compared {
+            format!(
+                "golden vectors matched within {:.1e}",
+                self.max_golden_error
+            )
+        } else {
+            "no golden vectors for this model".to_string()
+        };
+        if self.failures.is_empty() {
+            format!("self-test passed ({})", golden)
+        } else {
+            format!("self-test failed: {}", self.failures.join("; "))
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Embed a built-in reference set and check the numerics
+    ///
+    /// Use after deploying to a new runtime to confirm the WASM build produces
+    /// correct vectors. Never throws for a failed check; inspect `passed`.
+    #[wasm_bindgen]
+    pub fn self_test(&self) -> Result<SelfTestReport, JsValue> {
+        let texts: Vec<String> = REFERENCE_TEXTS.iter().map(|t| t.to_string()).collect();
+        let batch = self.embed_internal(&texts)?;
+        let repeat = self.embed_internal(&texts)?;
+        let singles = texts
+            .iter()
+            .map(|text| {
+                self.embed_internal(std::slice::from_ref(text))
+                    .map(|mut v| v.remove(0))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut failures = invariant_failures(&batch, &repeat, &singles);
+
+        let golden_compared = self.model_hash == Some(REFERENCE_MODEL_HASH)
+            && self.custom_normalizer.is_none()
+            && self.pooling == PoolingStrategy::Mean
+            && self.pooling_layer == LayerSelection::Last;
+        let max_golden_error = if golden_compared {
+            let (error, golden_failures) = golden_failures(&batch);
+            failures.extend(golden_failures);
+            error
+        } else {
+            0.0
+        };
+
+        Ok(SelfTestReport {
+            failures,
+            golden_compared,
+            max_golden_error,
+        })
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn max_abs_diff(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).abs())
+        .fold(0.0, f32::max)
+}
+
+/// Checks that hold for any model
+fn invariant_failures(
+    batch: &[Vec<f32>],
+    repeat: &[Vec<f32>],
+    singles: &[Vec<f32>],
+) -> Vec<String> {
+    let mut failures = Vec::new();
+    for (i, vector) in batch.iter().enumerate() {
+        if vector.iter().any(|v| !v.is_finite()) {
+            failures.push(format!("embedding {} contains NaN or infinity", i));
+            continue;
+        }
+        let norm = dot(vector, vector).sqrt();
+        if (norm - 1.0).abs() > 1e-3 {
+            failures.push(format!("embedding {} has norm {} (expected 1)", i, norm));
+        }
+        if vector != &repeat[i] {
+            failures.push(format!("embedding {} differs between identical runs", i));
+        }
+        let diff = max_abs_diff(vector, &singles[i]);
+        if diff > CONSISTENCY_TOLERANCE {
+            failures.push(format!(
+                "embedding {} differs by {} between batched and single-text runs",
+                i, diff
+            ));
+        }
+    }
+    failures
+}
+
+/// Compare against the all-MiniLM-L6-v2 golden values, returning the largest
+/// error and any failures
+fn golden_failures(batch: &[Vec<f32>]) -> (f32, Vec<String>) {
+    let mut failures = Vec::new();
+    let mut max_error = 0.0f32;
+    for (i, (vector, golden)) in batch.iter().zip(&GOLDEN_PREFIXES).enumerate() {
+        let error = max_abs_diff(&vector[..golden.len().min(vector.len())], golden);
+        max_error = max_error.max(error);
+        if error > GOLDEN_TOLERANCE {
+            failures.push(format!(
+                "embedding {} deviates from golden values by {}",
+                i, error
+            ));
+        }
+    }
+    for (&(i, j), &golden) in [(0, 1), (0, 2), (1, 2)].iter().zip(&GOLDEN_COSINES) {
+        let error = (dot(&batch[i], &batch[j]) - golden).abs();
+        max_error = max_error.max(error);
+        if error > GOLDEN_TOLERANCE {
+            failures.push(format!(
+                "similarity of texts {} and {} deviates from golden value by {}",
+                i, j, error
+            ));
+        }
+    }
+    (max_error, failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The golden prefixes themselves, as truncated 8-dimensional vectors
+    fn golden_batch() -> Vec<Vec<f32>> {
+        GOLDEN_PREFIXES.iter().map(|g| g.to_vec()).collect()
+    }
+
+    #[test]
+    fn test_invariants() {
+        let unit = vec![vec![0.6, 0.8], vec![1.0, 0.0]];
+        assert!(invariant_failures(&unit, &unit, &unit).is_empty());
+
+        let not_unit = vec![vec![0.5, 0.5], vec![f32::NAN, 0.0]];
+        let failures = invariant_failures(&not_unit, &not_unit, &not_unit);
+        assert_eq!(failures.len(), 2);
+        assert!(failures[0].contains("norm"));
+        assert!(failures[1].contains("NaN"));
+
+        let drifted = vec![vec![0.6, 0.8], vec![0.999, 0.0447]];
+        let failures = invariant_failures(&unit, &unit, &drifted);
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].contains("batched"));
+    }
+
+    #[test]
+    fn test_golden_prefix_comparison() {
+        let (error, failures) = golden_failures(&golden_batch());
+        // Prefixes match exactly; only the cosines (of truncated vectors) differ
+        assert!(failures.iter().all(|f| f.contains("similarity")));
+        assert!(error > 0.0);
+
+        let mut shifted = golden_batch();
+        shifted[1][0] += 0.01;
+        let (_, failures) = golden_failures(&shifted);
+        assert!(failures.iter().any(|f| f.starts_with("embedding 1")));
+    }
+}
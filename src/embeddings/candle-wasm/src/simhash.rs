@@ -0,0 +1,181 @@
+//! SimHash fingerprints (`simhash`, `hamming_near`)
+//!
+//! A 64-bit fingerprint where similar inputs differ in few bits: each text
+//! shingle (three consecutive lowercased words) or embedding dimension votes
+//! on every bit through its hash or a fixed random sign, and a bit is set when
+//! the votes sum above zero. Comparing fingerprints by Hamming distance is
+//! far cheaper than comparing embeddings, which makes it a pre-filter for
+//! near duplicates on ingestion where exact dedup only catches identical
+//! texts.
+//!
+//! `hamming_near` avoids comparing all pairs with the pigeonhole principle:
+//! two fingerprints within `threshold` bits agree exactly on at least one of
+//! `threshold + 1` blocks, so only fingerprints sharing a block are compared.
+
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::fingerprint::hash_bytes;
+use crate::rng::Rng;
+
+/// Words per text shingle
+const SHINGLE_WORDS: usize = 3;
+
+/// Seed of the per-dimension signs, fixed so fingerprints match everywhere
+const EMBEDDING_SEED: u64 = 0x5348_5348;
+
+/// Fingerprint with bit `i` set where `votes[i] > 0`
+fn from_votes(votes: &[f32; 64]) -> u64 {
+    votes
+        .iter()
+        .enumerate()
+        .filter(|(_, &v)| v > 0.0)
+        .fold(0, |hash, (bit, _)| hash | (1 << bit))
+}
+
+/// Add `weight` to the bits set in `hash` and subtract it from the rest
+fn vote(votes: &mut [f32; 64], hash: u64, weight: f32) {
+    for (bit, v) in votes.iter_mut().enumerate() {
+        *v += if (hash >> bit) & 1 == 1 {
+            weight
+        } else {
+            -weight
+        };
+    }
+}
+
+fn text_simhash(text: &str) -> u64 {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let mut votes = [0.0; 64];
+    for shingle in words.windows(SHINGLE_WORDS.min(words.len()).max(1)) {
+        vote(&mut votes, hash_bytes(shingle.join(" ").as_bytes()), 1.0);
+    }
+    from_votes(&votes)
+}
+
+fn embedding_simhash(embedding: &[f32]) -> u64 {
+    // One random word per dimension gives that dimension's sign in each of
+    // the 64 hyperplanes
+    let mut rng = Rng::new(EMBEDDING_SEED);
+    let mut votes = [0.0; 64];
+    for &value in embedding {
+        vote(&mut votes, rng.next_u64(), value);
+    }
+    from_votes(&votes)
+}
+
+/// Pairs `(i, j)`, `i < j`, of `signatures` within `threshold` bits
+fn near_pairs(signatures: &[u64], threshold: u32) -> Vec<(u32, u32)> {
+    let close = |i: usize, j: usize| (signatures[i] ^ signatures[j]).count_ones() <= threshold;
+    if threshold >= 64 {
+        return (0..signatures.len())
+            .flat_map(|i| (i + 1..signatures.len()).map(move |j| (i as u32, j as u32)))
+            .collect();
+    }
+
+    let blocks = threshold as usize + 1;
+    let block = |signature: u64, b: usize| {
+        let (start, end) = (b * 64 / blocks, (b + 1) * 64 / blocks);
+        (signature >> start) & (u64::MAX >> (64 - (end - start)))
+    };
+    let mut pairs = Vec::new();
+    for b in 0..blocks {
+        let mut groups: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, &signature) in signatures.iter().enumerate() {
+            groups.entry(block(signature, b)).or_default().push(i);
+        }
+        for group in groups.values() {
+            for (n, &i) in group.iter().enumerate() {
+                for &j in &group[n + 1..] {
+                    // Report each pair only at the first block it shares
+                    let first = (0..b).all(|e| block(signatures[i], e) != block(signatures[j], e));
+                    if first && close(i, j) {
+                        pairs.push((i as u32, j as u32));
+                    }
+                }
+            }
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// 64-bit SimHash of a string or a Float32Array embedding
+///
+/// Texts hash their word shingles, so small edits flip few bits; embeddings
+/// use fixed random hyperplanes, so nearby vectors flip few bits. The two
+/// kinds are not comparable with each other.
+#[wasm_bindgen]
+pub fn simhash(input: &JsValue) -> Result<u64, JsValue> {
+    if let Some(text) = input.as_string() {
+        Ok(text_simhash(&text))
+    } else if let Some(embedding) = input.dyn_ref::<js_sys::Float32Array>() {
+        Ok(embedding_simhash(&embedding.to_vec()))
+    } else {
+        Err(JsValue::from_str("Expected a string or Float32Array"))
+    }
+}
+
+/// Pairs of `signatures` differing in at most `threshold` bits, flattened as
+/// `[i0, j0, i1, j1, ...]` with `i < j`, ascending
+#[wasm_bindgen]
+pub fn hamming_near(signatures: &[u64], threshold: u32) -> Vec<u32> {
+    near_pairs(signatures, threshold)
+        .into_iter()
+        .flat_map(|(i, j)| [i, j])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similar_texts_share_bits() {
+        let a =
+            text_simhash("The quick brown fox jumps over the lazy dog near the river bank today");
+        let b =
+            text_simhash("the quick brown fox jumps over the lazy dog near the river bank, today!");
+        let c =
+            text_simhash("The quick brown fox leaps over the lazy dog near the river bank today");
+        let d = text_simhash("Quarterly revenue grew eight percent on strong cloud demand");
+        assert_eq!(a, b);
+        assert!((a ^ c).count_ones() < (a ^ d).count_ones());
+        assert_eq!(text_simhash(""), 0);
+
+        let e: Vec<f32> = (0..64).map(|i| (i as f32 * 0.3).sin()).collect();
+        let near: Vec<f32> = e.iter().map(|v| v + 0.01).collect();
+        let far: Vec<f32> = e.iter().map(|v| -v).collect();
+        let (e, near, far) = (
+            embedding_simhash(&e),
+            embedding_simhash(&near),
+            embedding_simhash(&far),
+        );
+        assert!((e ^ near).count_ones() <= 4);
+        assert_eq!(e ^ far, u64::MAX);
+    }
+
+    #[test]
+    fn test_near_pairs_matches_all_pairs() {
+        let mut rng = Rng::new(9);
+        let mut signatures: Vec<u64> = (0..200).map(|_| rng.next_u64()).collect();
+        for i in 0..50 {
+            let flips = (0..rng.below(6)).fold(0u64, |f, _| f | (1 << rng.below(64)));
+            signatures.push(signatures[i] ^ flips);
+        }
+        for threshold in [0, 3, 5, 20] {
+            let expected: Vec<(u32, u32)> = (0..signatures.len())
+                .flat_map(|i| (i + 1..signatures.len()).map(move |j| (i, j)))
+                .filter(|&(i, j)| (signatures[i] ^ signatures[j]).count_ones() <= threshold)
+                .map(|(i, j)| (i as u32, j as u32))
+                .collect();
+            assert_eq!(near_pairs(&signatures, threshold), expected);
+        }
+        assert_eq!(hamming_near(&[0, 1, 3], 1), vec![0, 1, 1, 2]);
+    }
+}
@@ -0,0 +1,179 @@
+//! Engine configuration snapshots
+//!
+//! `snapshot()` captures everything about an engine except the model itself
+//! (pooling, pooled layer, preset, normalization and preprocessing, cache, memory and
+//! batching settings, and which texts are in the tokenization cache) as a
+//! small JSON document. After a page reload, load the model and `restore()`
+//! the snapshot to get the same engine back.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::bert::LayerSelection;
+use crate::preprocess::PreprocessOptions;
+use crate::presets::ModelPreset;
+use crate::{EmbeddingEngine, PoolingStrategy};
+
+/// Snapshot format version; bump when fields change meaning
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Serialized engine settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct EngineSettings {
+    version: u32,
+    pooling: String,
+    pooling_layer: String,
+    token_cache_size: usize,
+    #[serde(default)]
+    memory_budget: Option<usize>,
+    #[serde(default)]
+    max_tokens_per_forward: Option<usize>,
+    #[serde(default)]
+    micro_batch_size: Option<usize>,
+    /// Normalizer JSON from `set_normalization`, if it was used
+    #[serde(default)]
+    normalizer: Option<String>,
+    #[serde(default)]
+    preprocessing: Option<PreprocessOptions>,
+    /// Repository id from `for_preset`
+    #[serde(default)]
+    preset: Option<String>,
+    /// Texts in the tokenization cache, least recently used first
+    #[serde(default)]
+    cached_texts: Vec<String>,
+}
+
+impl EngineSettings {
+    fn from_json(bytes: &[u8]) -> Result<Self, String> {
+        let settings: EngineSettings =
+            serde_json::from_slice(bytes).map_err(|e| format!("Invalid snapshot: {}", e))?;
+        if settings.version != SNAPSHOT_VERSION {
+            return Err(format!(
+                "Unsupported snapshot version {} (expected {})",
+                settings.version, SNAPSHOT_VERSION
+            ));
+        }
+        Ok(settings)
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Capture the engine configuration (not the weights) as JSON bytes
+    #[wasm_bindgen]
+    pub fn snapshot(&self) -> Vec<u8> {
+        let cache = self.token_cache.borrow();
+        let settings = EngineSettings {
+            version: SNAPSHOT_VERSION,
+            pooling: self.pooling.name().to_string(),
+            pooling_layer: self.pooling_layer.name(),
+            token_cache_size: cache.capacity(),
+            memory_budget: self.memory_budget,
+            max_tokens_per_forward: self.max_tokens_per_forward,
+            micro_batch_size: self.micro_batch_size,
+            normalizer: self.custom_normalizer.clone(),
+            preprocessing: self.preprocessing.clone(),
+            preset: self.preset(),
+            cached_texts: cache.texts(),
+        };
+        serde_json::to_vec(&settings).unwrap_or_default()
+    }
+
+    /// Apply a `snapshot()`; load the model first
+    ///
+    /// The snapshot is checked against the loaded model before anything is
+    /// changed, so a failed restore leaves the engine as it was. Cached texts
+    /// are re-tokenized, which does not count towards the cache statistics.
+    #[wasm_bindgen]
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), JsValue> {
+        let settings = EngineSettings::from_json(snapshot).map_err(|e| JsValue::from_str(&e))?;
+        let pooling = PoolingStrategy::from_name(&settings.pooling).ok_or_else(|| {
+            JsValue::from_str(&format!("Unknown pooling strategy: {}", settings.pooling))
+        })?;
+        let layer = LayerSelection::parse(&settings.pooling_layer)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let preset = match &settings.preset {
+            Some(name) => Some(
+                ModelPreset::find(name)
+                    .ok_or_else(|| JsValue::from_str(&format!("Unknown model preset: {}", name)))?,
+            ),
+            None => None,
+        };
+        if let (Some(preset), Some(dimension)) =
+            (preset, self.model.as_ref().map(|m| m.hidden_size()))
+        {
+            preset
+                .check_dimension(dimension)
+                .map_err(|e| JsValue::from_str(&e))?;
+        }
+        if let Some(model) = &self.model {
+            model
+                .validate_layer(layer)
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        }
+
+        if let Some(normalizer) = settings.normalizer {
+            let config = (normalizer != "null").then_some(normalizer);
+            self.apply_normalizer(config)?;
+        }
+        self.pooling = pooling;
+        self.pooling_layer = layer;
+        self.memory_budget = settings.memory_budget;
+        self.max_tokens_per_forward = settings.max_tokens_per_forward;
+        self.micro_batch_size = settings.micro_batch_size;
+        self.preprocessing = settings.preprocessing;
+        self.preset = preset;
+        if let (Some(preset), Some(tokenizer)) = (preset, self.tokenizer.as_mut()) {
+            preset
+                .apply_max_length(tokenizer)
+                .map_err(|e| JsValue::from_str(&e))?;
+        }
+        {
+            let mut cache = self.token_cache.borrow_mut();
+            cache.clear();
+            cache.resize(settings.token_cache_size);
+        }
+        if self.tokenizer.is_some() && settings.token_cache_size > 0 {
+            self.tokenize(&settings.cached_texts)?;
+        }
+        self.token_cache.borrow_mut().reset_stats();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut engine = EmbeddingEngine::new();
+        engine.set_pooling("cls").unwrap();
+        engine.pooling_layer = LayerSelection::MeanOfLast(2);
+        engine.set_memory_budget(1 << 20);
+        engine.set_max_tokens_per_forward(512);
+        engine.set_micro_batch_size(8);
+        engine.set_tokenization_cache_size(16);
+
+        let snapshot = engine.snapshot();
+        let mut restored = EmbeddingEngine::new();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.pooling(), "cls");
+        assert_eq!(restored.pooling_layer(), "avg_last_2");
+        assert_eq!(restored.memory_budget(), 1 << 20);
+        assert_eq!(restored.max_tokens_per_forward(), 512);
+        assert_eq!(restored.micro_batch_size(), 8);
+        assert_eq!(restored.tokenization_cache_stats().capacity(), 16);
+        assert_eq!(restored.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_version_checked() {
+        let json =
+            br#"{"version":99,"pooling":"mean","pooling_layer":"last","token_cache_size":1}"#;
+        assert!(EngineSettings::from_json(json)
+            .unwrap_err()
+            .contains("version"));
+        assert!(EngineSettings::from_json(b"not json").is_err());
+    }
+}
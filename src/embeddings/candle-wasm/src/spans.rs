@@ -0,0 +1,211 @@
+//! Query-relevant span highlighting for retrieved passages
+//!
+//! The document is cut into overlapping windows of content tokens, each window
+//! is embedded as text, and the windows most similar to the query are returned
+//! as character spans, without overlaps, best first.
+
+use js_sys::Array;
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::attribution::utf16_table;
+use crate::document::window_ranges;
+use crate::js::parse_options;
+use crate::long_text;
+use crate::EmbeddingEngine;
+
+/// Options for `best_spans`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct SpanOptions {
+    /// Content tokens per window
+    window_tokens: usize,
+    /// Tokens between window starts (defaults to half a window)
+    stride: Option<usize>,
+    /// Maximum number of spans to return
+    top_k: usize,
+}
+
+impl Default for SpanOptions {
+    fn default() -> Self {
+        SpanOptions {
+            window_tokens: 32,
+            stride: None,
+            top_k: 3,
+        }
+    }
+}
+
+/// A scored span of the document
+#[wasm_bindgen]
+pub struct TextSpan {
+    start: u32,
+    end: u32,
+    score: f32,
+    text: String,
+}
+
+#[wasm_bindgen]
+impl TextSpan {
+    /// Start of the span as a UTF-16 index (usable with `String.prototype.slice`)
+    #[wasm_bindgen(getter)]
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// End of the span (exclusive) as a UTF-16 index
+    #[wasm_bindgen(getter)]
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    /// Cosine similarity between the span and the query
+    #[wasm_bindgen(getter)]
+    pub fn score(&self) -> f32 {
+        self.score
+    }
+
+    /// The span's text
+    #[wasm_bindgen(getter)]
+    pub fn text(&self) -> String {
+        self.text.clone()
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Find the document spans that best match a query
+    ///
+    /// Options: `{ window_tokens = 32, stride = window_tokens / 2, top_k = 3 }`.
+    /// Returns an array of `TextSpan`, best first, that do not overlap.
+    #[wasm_bindgen]
+    pub fn best_spans(
+        &self,
+        query: &str,
+        document: &str,
+        options: &JsValue,
+    ) -> Result<Array, JsValue> {
+        let options: SpanOptions = parse_options(options)?;
+        if options.window_tokens == 0 {
+            return Err(JsValue::from_str("window_tokens must be at least 1"));
+        }
+        let stride = options.stride.unwrap_or(options.window_tokens / 2).max(1);
+
+        // Offsets come from the tokenizer; drop any window that doesn't map to
+        // a valid slice rather than panicking on it
+        let mut windows = token_windows(
+            &self.document_offsets(document)?,
+            options.window_tokens,
+            stride,
+        );
+        windows.retain(|&(s, e)| document.get(s..e).is_some());
+        if windows.is_empty() || options.top_k == 0 {
+            return Ok(Array::new());
+        }
+
+        let mut texts = Vec::with_capacity(windows.len() + 1);
+        texts.push(query.to_string());
+        texts.extend(windows.iter().map(|&(s, e)| document[s..e].to_string()));
+        let embeddings = self.embed_internal(&texts)?;
+
+        let scored = windows.into_iter().zip(embeddings[1..].iter().map(|w| {
+            w.iter()
+                .zip(&embeddings[0])
+                .map(|(a, b)| a * b)
+                .sum::<f32>()
+        }));
+        let table = utf16_table(document);
+        Ok(select_spans(scored.collect(), options.top_k)
+            .into_iter()
+            .map(|((start, end), score)| {
+                JsValue::from(TextSpan {
+                    start: table[start],
+                    end: table[end],
+                    score,
+                    text: document[start..end].to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Byte offsets of every content token in `text`, ignoring the model's
+    /// truncation limit so long documents are covered end to end
+    pub(crate) fn document_offsets(&self, text: &str) -> Result<Vec<(usize, usize)>, JsValue> {
+        let mut tokenizer = self.tokenizer.clone().ok_or_else(|| {
+            JsValue::from_str("Tokenizer not loaded. Call load_embedded() first.")
+        })?;
+        tokenizer
+            .with_truncation(None)
+            .map_err(|e| JsValue::from_str(&format!("Tokenizer setup failed: {}", e)))?
+            .with_padding(None);
+        let mut offsets = long_text::token_offsets(&tokenizer, text)
+            .map_err(|e| JsValue::from_str(&format!("Tokenization failed: {:?}", e)))?;
+        offsets.retain(|&(_, end)| end <= text.len());
+        Ok(offsets)
+    }
+}
+
+/// Byte ranges of windows of `window` tokens, starting every `stride` tokens;
+/// the last window always reaches the final token
+fn token_windows(offsets: &[(usize, usize)], window: usize, stride: usize) -> Vec<(usize, usize)> {
+    window_ranges(offsets.len(), window, stride)
+        .into_iter()
+        .map(|w| (offsets[w.start].0, offsets[w.end - 1].1))
+        .collect()
+}
+
+/// Highest scoring spans that don't overlap an already chosen span
+fn select_spans(
+    mut scored: Vec<((usize, usize), f32)>,
+    top_k: usize,
+) -> Vec<((usize, usize), f32)> {
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let mut chosen: Vec<((usize, usize), f32)> = Vec::with_capacity(top_k);
+    for (span, score) in scored {
+        if chosen.len() == top_k {
+            break;
+        }
+        if chosen
+            .iter()
+            .all(|((s, e), _)| span.1 <= *s || span.0 >= *e)
+        {
+            chosen.push((span, score));
+        }
+    }
+    chosen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js::options_from_json;
+
+    #[test]
+    fn test_token_windows() {
+        let offsets: Vec<(usize, usize)> = (0..5).map(|i| (i * 2, i * 2 + 1)).collect();
+        assert_eq!(token_windows(&offsets, 2, 2), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(token_windows(&offsets, 4, 2), vec![(0, 7), (4, 9)]);
+        assert_eq!(token_windows(&offsets, 10, 5), vec![(0, 9)]);
+        assert!(token_windows(&[], 4, 2).is_empty());
+    }
+
+    #[test]
+    fn test_select_spans_skips_overlaps() {
+        let scored = vec![
+            ((0, 10), 0.5),
+            ((5, 15), 0.9),
+            ((15, 20), 0.7),
+            ((20, 30), 0.1),
+        ];
+        let chosen = select_spans(scored, 2);
+        assert_eq!(chosen, vec![((5, 15), 0.9), ((15, 20), 0.7)]);
+    }
+
+    #[test]
+    fn test_span_options_defaults() {
+        let options: SpanOptions = options_from_json(r#"{"top_k": 1}"#).unwrap();
+        assert_eq!(options.top_k, 1);
+        assert_eq!(options.window_tokens, 32);
+        assert_eq!(options.stride, None);
+    }
+}
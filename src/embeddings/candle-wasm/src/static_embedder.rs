@@ -0,0 +1,271 @@
+//! Static (Model2Vec-style) token embeddings
+//!
+//! Model2Vec distils a sentence-transformer into a single `[vocab, dim]` lookup
+//! table. Embedding a text is then just tokenize → average the rows → normalize,
+//! with no transformer forward pass at all, which makes it orders of magnitude
+//! faster than `EmbeddingEngine` at some cost in quality.
+//!
+//! `StaticEmbedder` mirrors the `EmbeddingEngine` API so callers can switch
+//! between the fast and accurate paths without changing their code.
+
+use candle_core::{DType, Device, Tensor};
+use js_sys::{Array, Float32Array};
+use serde::Deserialize;
+use tokenizers::Tokenizer;
+use wasm_bindgen::prelude::*;
+
+use crate::{embeddings_to_js, js_array_to_strings};
+
+/// Name of the embedding table in Model2Vec safetensors files
+const EMBEDDINGS_TENSOR: &str = "embeddings";
+
+/// The subset of a Model2Vec config.json we need
+#[derive(Debug, Deserialize)]
+struct StaticConfig {
+    #[serde(default = "default_normalize")]
+    normalize: bool,
+}
+
+fn default_normalize() -> bool {
+    true
+}
+
+/// Row-major `[vocab, dim]` embedding table
+pub(crate) struct StaticTable {
+    table: Vec<f32>,
+    dim: usize,
+    normalize: bool,
+}
+
+impl StaticTable {
+    fn from_tensor(tensor: &Tensor, normalize: bool) -> candle_core::Result<Self> {
+        let (vocab, dim) = tensor.dims2()?;
+        if vocab == 0 || dim == 0 {
+            candle_core::bail!("embedding table is empty ({} x {})", vocab, dim);
+        }
+        let table = tensor
+            .to_dtype(DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        Ok(StaticTable {
+            table,
+            dim,
+            normalize,
+        })
+    }
+
+    /// Average the rows for `ids`, skipping ids outside the table
+    fn pool(&self, ids: &[u32]) -> Vec<f32> {
+        let vocab = self.table.len() / self.dim;
+        let mut out = vec![0.0f32; self.dim];
+        let mut count = 0usize;
+
+        for &id in ids {
+            let id = id as usize;
+            if id >= vocab {
+                continue;
+            }
+            let row = &self.table[id * self.dim..(id + 1) * self.dim];
+            for (o, v) in out.iter_mut().zip(row) {
+                *o += v;
+            }
+            count += 1;
+        }
+
+        if count > 0 {
+            let scale = 1.0 / count as f32;
+            out.iter_mut().for_each(|v| *v *= scale);
+        }
+
+        if self.normalize {
+            let norm = out.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-12);
+            out.iter_mut().for_each(|v| *v /= norm);
+        }
+
+        out
+    }
+}
+
+/// WASM-compatible static embedder (no transformer forward pass)
+#[wasm_bindgen]
+pub struct StaticEmbedder {
+    table: Option<StaticTable>,
+    tokenizer: Option<Tokenizer>,
+}
+
+#[wasm_bindgen]
+impl StaticEmbedder {
+    /// Create a new static embedder instance (not loaded)
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        StaticEmbedder {
+            table: None,
+            tokenizer: None,
+        }
+    }
+
+    /// Load a Model2Vec model from bytes
+    ///
+    /// # Arguments
+    /// * `model_bytes` - SafeTensors file containing the `embeddings` table
+    /// * `tokenizer_bytes` - tokenizer.json contents
+    /// * `config_bytes` - config.json contents (only `normalize` is read)
+    #[wasm_bindgen]
+    pub fn load(
+        &mut self,
+        model_bytes: &[u8],
+        tokenizer_bytes: &[u8],
+        config_bytes: &[u8],
+    ) -> Result<(), JsValue> {
+        let config: StaticConfig = serde_json::from_slice(config_bytes)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse config: {}", e)))?;
+
+        let tensors = candle_core::safetensors::load_buffer(model_bytes, &Device::Cpu)
+            .map_err(|e| JsValue::from_str(&format!("Failed to load safetensors: {}", e)))?;
+        let embeddings = tensors.get(EMBEDDINGS_TENSOR).ok_or_else(|| {
+            JsValue::from_str("Model has no 'embeddings' tensor; is this a Model2Vec model?")
+        })?;
+
+        let table = StaticTable::from_tensor(embeddings, config.normalize)
+            .map_err(|e| JsValue::from_str(&format!("Invalid embeddings tensor: {}", e)))?;
+
+        // Padding would average [PAD] rows into every vector, and a static
+        // table has no sequence limit to truncate for
+        let mut tokenizer = Tokenizer::from_bytes(tokenizer_bytes)
+            .map_err(|e| JsValue::from_str(&format!("Failed to load tokenizer: {:?}", e)))?;
+        tokenizer
+            .with_truncation(None)
+            .map_err(|e| JsValue::from_str(&format!("Tokenizer setup failed: {}", e)))?
+            .with_padding(None);
+
+        self.table = Some(table);
+        self.tokenizer = Some(tokenizer);
+
+        Ok(())
+    }
+
+    /// Check if the embedder is ready for inference
+    #[wasm_bindgen]
+    pub fn is_ready(&self) -> bool {
+        self.table.is_some() && self.tokenizer.is_some()
+    }
+
+    /// Generate embedding for a single text
+    #[wasm_bindgen]
+    pub fn embed(&self, text: &str) -> Result<Float32Array, JsValue> {
+        let texts = vec![text.to_string()];
+        let embeddings = self.embed_internal(&texts)?;
+
+        let first = &embeddings[0];
+        let arr = Float32Array::new_with_length(first.len() as u32);
+        arr.copy_from(first);
+        Ok(arr)
+    }
+
+    /// Generate embeddings for multiple texts
+    ///
+    /// Takes a JavaScript Array of strings
+    /// Returns a JavaScript Array of Float32Array
+    #[wasm_bindgen]
+    pub fn embed_batch(&self, texts: &Array) -> Result<Array, JsValue> {
+        let rust_texts = js_array_to_strings(texts)?;
+        if rust_texts.is_empty() {
+            return Ok(Array::new());
+        }
+
+        let embeddings = self.embed_internal(&rust_texts)?;
+        Ok(embeddings_to_js(embeddings))
+    }
+
+    fn embed_internal(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, JsValue> {
+        let table = self
+            .table
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Model not loaded. Call load() first."))?;
+        let tokenizer = self
+            .tokenizer
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("Tokenizer not loaded. Call load() first."))?;
+
+        // Model2Vec tables have no use for [CLS]/[SEP]
+        let encodings = tokenizer
+            .encode_batch(texts.to_vec(), false)
+            .map_err(|e| JsValue::from_str(&format!("Tokenization failed: {:?}", e)))?;
+
+        Ok(encodings.iter().map(|e| table.pool(e.get_ids())).collect())
+    }
+
+    /// Get the embedding dimension (0 until loaded)
+    #[wasm_bindgen]
+    pub fn dimension(&self) -> usize {
+        self.table.as_ref().map_or(0, |t| t.dim)
+    }
+}
+
+impl Default for StaticEmbedder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(normalize: bool) -> StaticTable {
+        let tensor = Tensor::new(&[[1.0f32, 0.0], [0.0, 1.0], [3.0, 3.0]], &Device::Cpu).unwrap();
+        StaticTable::from_tensor(&tensor, normalize).unwrap()
+    }
+
+    #[test]
+    fn test_pool_averages_rows() {
+        let t = table(false);
+        assert_eq!(t.pool(&[0, 1]), vec![0.5, 0.5]);
+        // Out-of-vocabulary ids are ignored
+        assert_eq!(t.pool(&[2, 99]), vec![3.0, 3.0]);
+        assert_eq!(t.pool(&[]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_pool_normalizes() {
+        let v = table(true).pool(&[2]);
+        assert!((v[0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_load_ignores_tokenizer_padding() {
+        let tokenizer = br#"{
+            "version": "1.0", "added_tokens": [],
+            "truncation": {"direction": "Right", "max_length": 1, "strategy": "LongestFirst",
+                           "stride": 0},
+            "padding": {"strategy": {"Fixed": 16}, "direction": "Right",
+                        "pad_to_multiple_of": null, "pad_id": 0, "pad_type_id": 0,
+                        "pad_token": "[PAD]"},
+            "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+            "post_processor": null, "decoder": null,
+            "model": {"type": "WordLevel", "vocab": {"[PAD]": 0, "cat": 1, "mat": 2},
+                      "unk_token": "[PAD]"}
+        }"#;
+        let tensor = Tensor::new(&[[1.0f32, 0.0], [0.0, 1.0], [3.0, 3.0]], &Device::Cpu).unwrap();
+        let model = crate::export::serialize(
+            &[(EMBEDDINGS_TENSOR.to_string(), tensor)].into(),
+            DType::F32,
+        )
+        .unwrap();
+
+        let mut embedder = StaticEmbedder::new();
+        embedder
+            .load(&model, tokenizer, br#"{"normalize": false}"#)
+            .unwrap();
+        let embeddings = embedder.embed_internal(&["cat mat".to_string()]).unwrap();
+        assert_eq!(embeddings, vec![vec![1.5, 2.0]]);
+    }
+
+    #[test]
+    fn test_rejects_empty_table() {
+        for dims in [(0, 2), (3, 0)] {
+            let tensor = Tensor::zeros(dims, DType::F32, &Device::Cpu).unwrap();
+            assert!(StaticTable::from_tensor(&tensor, false).is_err());
+        }
+    }
+}
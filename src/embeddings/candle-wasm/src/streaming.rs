@@ -0,0 +1,318 @@
+//! Incremental SafeTensors loading
+//!
+//! `candle_core::safetensors::load_buffer` needs the whole file in memory and then
+//! copies every tensor out of it, so peak memory is roughly twice the model size.
+//! On memory-capped runtimes (Cloudflare Workers allow 128MB per isolate) that is
+//! enough to get the isolate killed for an ~88MB model.
+//!
+//! `SafetensorsStream` accepts the file in arbitrary chunks (e.g. straight from an
+//! R2/KV/fetch `ReadableStream`) and materializes each tensor as soon as its bytes
+//! have arrived. Only the tensor currently being assembled is buffered.
+
+use std::collections::HashMap;
+
+use candle_core::{DType, Device, Tensor};
+use serde::Deserialize;
+
+/// Upper bound on the JSON header size (matches the safetensors crate)
+const MAX_HEADER_SIZE: usize = 100_000_000;
+
+/// A single entry of the SafeTensors JSON header
+#[derive(Debug, Deserialize)]
+struct TensorInfo {
+    dtype: String,
+    shape: Vec<usize>,
+    data_offsets: (usize, usize),
+}
+
+/// Parsing position within the SafeTensors byte stream
+enum Phase {
+    /// Waiting for the 8-byte little-endian header length
+    HeaderLength,
+    /// Waiting for `len` bytes of JSON header
+    Header(usize),
+    /// Consuming the data section
+    Data,
+}
+
+/// Chunked SafeTensors parser that builds tensors as their bytes arrive
+pub struct SafetensorsStream {
+    device: Device,
+    phase: Phase,
+    /// Bytes of the header or of the tensor currently being assembled
+    pending: Vec<u8>,
+    /// Header entries sorted by data offset
+    entries: Vec<(String, TensorInfo)>,
+    /// Index of the next entry to materialize
+    next: usize,
+    /// Bytes of the data section consumed so far
+    position: usize,
+    tensors: HashMap<String, Tensor>,
+}
+
+impl SafetensorsStream {
+    /// Create an empty stream that places tensors on `device`
+    pub fn new(device: &Device) -> Self {
+        SafetensorsStream {
+            device: device.clone(),
+            phase: Phase::HeaderLength,
+            pending: Vec::new(),
+            entries: Vec::new(),
+            next: 0,
+            position: 0,
+            tensors: HashMap::new(),
+        }
+    }
+
+    /// Feed the next chunk of the file
+    pub fn push(&mut self, mut chunk: &[u8]) -> candle_core::Result<()> {
+        while !chunk.is_empty() {
+            match self.phase {
+                Phase::HeaderLength => {
+                    let taken = self.fill_pending(&mut chunk, 8);
+                    if taken {
+                        let mut len = [0u8; 8];
+                        len.copy_from_slice(&self.pending);
+                        let len = u64::from_le_bytes(len) as usize;
+                        if len > MAX_HEADER_SIZE {
+                            candle_core::bail!("SafeTensors header too large: {} bytes", len);
+                        }
+                        self.pending.clear();
+                        self.phase = Phase::Header(len);
+                    }
+                }
+                Phase::Header(len) => {
+                    if self.fill_pending(&mut chunk, len) {
+                        self.parse_header()?;
+                        self.pending = Vec::new();
+                        self.phase = Phase::Data;
+                    }
+                }
+                Phase::Data => self.consume_data(&mut chunk)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the stream, returning all tensors
+    ///
+    /// Fails if the stream ended before every tensor in the header was received.
+    pub fn finish(mut self) -> candle_core::Result<HashMap<String, Tensor>> {
+        // Zero-size tensors at the end of the data section have no bytes for
+        // push() to wait for
+        if matches!(self.phase, Phase::Data) {
+            while let Some((_, info)) = self.entries.get(self.next) {
+                let (start, end) = info.data_offsets;
+                if start != end || start > self.position {
+                    break;
+                }
+                self.materialize(&[])?;
+            }
+        }
+        if !matches!(self.phase, Phase::Data) || self.next < self.entries.len() {
+            candle_core::bail!(
+                "SafeTensors stream ended early: {} of {} tensors received",
+                self.tensors.len(),
+                self.entries.len()
+            );
+        }
+        Ok(self.tensors)
+    }
+
+    /// Move bytes from `chunk` into `pending` until it holds `target` bytes.
+    /// Returns true once `pending` is full.
+    fn fill_pending(&mut self, chunk: &mut &[u8], target: usize) -> bool {
+        let needed = target - self.pending.len();
+        let take = needed.min(chunk.len());
+        self.pending.extend_from_slice(&chunk[..take]);
+        *chunk = &chunk[take..];
+        self.pending.len() == target
+    }
+
+    fn parse_header(&mut self) -> candle_core::Result<()> {
+        let header: HashMap<String, serde_json::Value> = serde_json::from_slice(&self.pending)
+            .map_err(|e| candle_core::Error::Msg(format!("Invalid SafeTensors header: {}", e)))?;
+
+        let mut entries = Vec::with_capacity(header.len());
+        for (name, value) in header {
+            if name == "__metadata__" {
+                continue;
+            }
+            let info: TensorInfo = serde_json::from_value(value).map_err(|e| {
+                candle_core::Error::Msg(format!("Invalid header entry for {}: {}", name, e))
+            })?;
+            check_entry(&name, &info)?;
+            entries.push((name, info));
+        }
+        entries.sort_by_key(|(_, info)| info.data_offsets.0);
+        // Overlapping tensors would make the data section unparseable
+        for pair in entries.windows(2) {
+            let ((prev, a), (next, b)) = (&pair[0], &pair[1]);
+            if a.data_offsets.1 > b.data_offsets.0 {
+                candle_core::bail!("SafeTensors tensors {} and {} overlap", prev, next);
+            }
+        }
+
+        self.entries = entries;
+        Ok(())
+    }
+
+    fn consume_data(&mut self, chunk: &mut &[u8]) -> candle_core::Result<()> {
+        let Some((_, info)) = self.entries.get(self.next) else {
+            // Trailing bytes after the last tensor are ignored
+            self.position += chunk.len();
+            *chunk = &[];
+            return Ok(());
+        };
+        let (start, end) = info.data_offsets;
+
+        // Skip any gap before the next tensor
+        if self.position < start {
+            let skip = (start - self.position).min(chunk.len());
+            self.position += skip;
+            *chunk = &chunk[skip..];
+            return Ok(());
+        }
+
+        let size = end - start;
+        let remaining = size - self.pending.len();
+        if self.pending.is_empty() && chunk.len() >= size {
+            // Whole tensor is available in this chunk: build it without buffering
+            let (data, rest) = chunk.split_at(size);
+            self.materialize(data)?;
+            self.position += size;
+            *chunk = rest;
+        } else {
+            let take = remaining.min(chunk.len());
+            self.pending.extend_from_slice(&chunk[..take]);
+            self.position += take;
+            *chunk = &chunk[take..];
+            if self.pending.len() == size {
+                let data = std::mem::take(&mut self.pending);
+                self.materialize(&data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn materialize(&mut self, data: &[u8]) -> candle_core::Result<()> {
+        let (name, info) = &self.entries[self.next];
+        let dtype = parse_dtype(&info.dtype)?;
+        let tensor = Tensor::from_raw_buffer(data, dtype, &info.shape, &self.device)?;
+        self.tensors.insert(name.clone(), tensor);
+        self.next += 1;
+        Ok(())
+    }
+}
+
+/// Reject entries whose byte range doesn't match their dtype and shape, which
+/// would otherwise surface as an arithmetic overflow while streaming
+fn check_entry(name: &str, info: &TensorInfo) -> candle_core::Result<()> {
+    let dtype = parse_dtype(&info.dtype)?;
+    let (start, end) = info.data_offsets;
+    let expected = info
+        .shape
+        .iter()
+        .try_fold(dtype.size_in_bytes(), |acc, &d| acc.checked_mul(d));
+    if end < start || expected != Some(end - start) {
+        candle_core::bail!(
+            "SafeTensors entry {} has data offsets [{}, {}] but {} shape {:?}",
+            name,
+            start,
+            end,
+            info.dtype,
+            info.shape
+        );
+    }
+    Ok(())
+}
+
+/// Map a SafeTensors dtype tag to a candle dtype
+fn parse_dtype(tag: &str) -> candle_core::Result<DType> {
+    match tag {
+        "U8" => Ok(DType::U8),
+        "U32" => Ok(DType::U32),
+        "I64" => Ok(DType::I64),
+        "BF16" => Ok(DType::BF16),
+        "F16" => Ok(DType::F16),
+        "F32" => Ok(DType::F32),
+        "F64" => Ok(DType::F64),
+        other => candle_core::bail!("Unsupported SafeTensors dtype: {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a SafeTensors file with two small f32 tensors
+    fn sample_file() -> Vec<u8> {
+        let a: Vec<f32> = vec![1.0, 2.0, 3.0, 4.0];
+        let b: Vec<f32> = vec![5.0, 6.0];
+        let header = r#"{"__metadata__":{"format":"pt"},"b":{"dtype":"F32","shape":[2],"data_offsets":[16,24]},"a":{"dtype":"F32","shape":[2,2],"data_offsets":[0,16]}}"#;
+
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(header.as_bytes());
+        for v in a.iter().chain(b.iter()) {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_stream_in_small_chunks() {
+        let file = sample_file();
+        let mut stream = SafetensorsStream::new(&Device::Cpu);
+        for chunk in file.chunks(7) {
+            stream.push(chunk).unwrap();
+        }
+        let tensors = stream.finish().unwrap();
+
+        let a = tensors["a"].to_vec2::<f32>().unwrap();
+        assert_eq!(a, vec![vec![1.0, 2.0], vec![3.0, 4.0]]);
+        let b = tensors["b"].to_vec1::<f32>().unwrap();
+        assert_eq!(b, vec![5.0, 6.0]);
+    }
+
+    #[test]
+    fn test_malformed_header_rejected() {
+        let with_header = |header: &str| {
+            let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+            bytes.extend_from_slice(header.as_bytes());
+            bytes.extend_from_slice(&[0u8; 16]);
+            SafetensorsStream::new(&Device::Cpu).push(&bytes)
+        };
+        // Reversed offsets
+        assert!(with_header(r#"{"a":{"dtype":"F32","shape":[2],"data_offsets":[8,0]}}"#).is_err());
+        // Size doesn't match the shape
+        assert!(with_header(r#"{"a":{"dtype":"F32","shape":[3],"data_offsets":[0,8]}}"#).is_err());
+        // Overlapping tensors
+        assert!(with_header(
+            r#"{"a":{"dtype":"F32","shape":[2],"data_offsets":[0,8]},"b":{"dtype":"F32","shape":[2],"data_offsets":[4,12]}}"#
+        )
+        .is_err());
+        assert!(with_header(r#"{"a":{"dtype":"F32","shape":[4],"data_offsets":[0,16]}}"#).is_ok());
+    }
+
+    #[test]
+    fn test_trailing_empty_tensor() {
+        let header = r#"{"a":{"dtype":"F32","shape":[2],"data_offsets":[0,8]},"z":{"dtype":"F32","shape":[0,4],"data_offsets":[8,8]}}"#;
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend_from_slice(header.as_bytes());
+        file.extend_from_slice(&[0u8; 8]);
+
+        let mut stream = SafetensorsStream::new(&Device::Cpu);
+        stream.push(&file).unwrap();
+        let tensors = stream.finish().unwrap();
+        assert_eq!(tensors["z"].dims(), &[0, 4]);
+        assert_eq!(tensors["a"].dims(), &[2]);
+    }
+
+    #[test]
+    fn test_truncated_stream_fails() {
+        let file = sample_file();
+        let mut stream = SafetensorsStream::new(&Device::Cpu);
+        stream.push(&file[..file.len() - 4]).unwrap();
+        assert!(stream.finish().is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Per-call inference statistics delivered to a user callback
+//!
+//! Nothing is logged; apps register `on_inference(callback)` and forward the
+//! stats into their own metrics pipeline.
+
+use tokenizers::Encoding;
+use wasm_bindgen::prelude::*;
+
+use crate::{EmbeddingEngine, MAX_SEQUENCE_LENGTH};
+
+/// Tokens of an encoding that aren't padding, before the model's length limit
+pub(crate) fn attended_tokens(encoding: &Encoding) -> usize {
+    encoding
+        .get_attention_mask()
+        .iter()
+        .filter(|&&m| m != 0)
+        .count()
+}
+
+/// Whether the tokenizer or the model's length limit cut the text short
+pub(crate) fn was_truncated(encoding: &Encoding) -> bool {
+    attended_tokens(encoding) > MAX_SEQUENCE_LENGTH || !encoding.get_overflowing().is_empty()
+}
+
+/// Statistics for one embedding call
+#[wasm_bindgen]
+#[derive(Debug, Clone, Default)]
+pub struct InferenceStats {
+    batch_size: usize,
+    token_count: usize,
+    padded_token_count: usize,
+    truncated: usize,
+    cache_hits: usize,
+    tokenize_ms: f64,
+    inference_ms: f64,
+    pooling_ms: f64,
+    total_ms: f64,
+}
+
+#[wasm_bindgen]
+impl InferenceStats {
+    /// Number of texts run through the model
+    #[wasm_bindgen(getter)]
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Real (non-padding) tokens, including special tokens
+    #[wasm_bindgen(getter)]
+    pub fn token_count(&self) -> usize {
+        self.token_count
+    }
+
+    /// Tokens the model processed including padding (batch x sequence length)
+    #[wasm_bindgen(getter)]
+    pub fn padded_token_count(&self) -> usize {
+        self.padded_token_count
+    }
+
+    /// Texts that were cut off at the maximum sequence length
+    #[wasm_bindgen(getter)]
+    pub fn truncated(&self) -> usize {
+        self.truncated
+    }
+
+    /// Texts whose tokenization came from the tokenization cache
+    #[wasm_bindgen(getter)]
+    pub fn cache_hits(&self) -> usize {
+        self.cache_hits
+    }
+
+    /// Time spent tokenizing, in milliseconds
+    #[wasm_bindgen(getter)]
+    pub fn tokenize_ms(&self) -> f64 {
+        self.tokenize_ms
+    }
+
+    /// Time spent in the encoder forward pass, in milliseconds
+    #[wasm_bindgen(getter)]
+    pub fn inference_ms(&self) -> f64 {
+        self.inference_ms
+    }
+
+    /// Time spent pooling and normalizing, in milliseconds
+    #[wasm_bindgen(getter)]
+    pub fn pooling_ms(&self) -> f64 {
+        self.pooling_ms
+    }
+
+    /// End-to-end time of the call, in milliseconds
+    #[wasm_bindgen(getter)]
+    pub fn total_ms(&self) -> f64 {
+        self.total_ms
+    }
+}
+
+impl InferenceStats {
+    /// Token statistics of a tokenized batch, as the model will see it
+    pub(crate) fn for_batch(encodings: &[Encoding]) -> Self {
+        let seq_len = encodings
+            .iter()
+            .map(|e| e.len())
+            .max()
+            .unwrap_or(0)
+            .min(MAX_SEQUENCE_LENGTH);
+        let mut stats = InferenceStats {
+            batch_size: encodings.len(),
+            padded_token_count: encodings.len() * seq_len,
+            ..Default::default()
+        };
+        for encoding in encodings {
+            stats.token_count += attended_tokens(encoding).min(MAX_SEQUENCE_LENGTH);
+            if was_truncated(encoding) {
+                stats.truncated += 1;
+            }
+        }
+        stats
+    }
+
+    pub(crate) fn set_timings(&mut self, tokenize_ms: f64, inference_ms: f64, pooling_ms: f64) {
+        self.tokenize_ms = tokenize_ms;
+        self.inference_ms = inference_ms;
+        self.pooling_ms = pooling_ms;
+    }
+
+    pub(crate) fn set_cache_hits(&mut self, hits: usize) {
+        self.cache_hits = hits;
+    }
+
+    pub(crate) fn set_total_ms(&mut self, total_ms: f64) {
+        self.total_ms = total_ms;
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Register a callback receiving an `InferenceStats` after every
+    /// `embed`/`embed_batch` call (replaces any previous callback)
+    ///
+    /// Errors thrown by the callback are ignored so metrics can never break
+    /// embedding.
+    #[wasm_bindgen]
+    pub fn on_inference(&mut self, callback: js_sys::Function) {
+        self.on_inference = Some(callback);
+    }
+
+    /// Remove the `on_inference` callback
+    #[wasm_bindgen]
+    pub fn clear_on_inference(&mut self) {
+        self.on_inference = None;
+    }
+}
+
+impl EmbeddingEngine {
+    /// Deliver stats to the registered callback, if any
+    pub(crate) fn report_inference(&self, stats: InferenceStats) {
+        if let Some(callback) = &self.on_inference {
+            let _ = callback.call1(&JsValue::NULL, &JsValue::from(stats));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_token_counts() {
+        let short = Encoding::new(
+            vec![1, 2, 3, 0],
+            vec![0; 4],
+            vec![String::new(); 4],
+            vec![None; 4],
+            vec![(0, 0); 4],
+            vec![0; 4],
+            vec![1, 1, 1, 0],
+            vec![],
+            Default::default(),
+        );
+        let mut long = short.clone();
+        long.set_overflowing(vec![short.clone()]);
+
+        let stats = InferenceStats::for_batch(&[short, long]);
+        assert_eq!(stats.batch_size, 2);
+        assert_eq!(stats.token_count, 6);
+        assert_eq!(stats.padded_token_count, 8);
+        assert_eq!(stats.truncated, 1);
+    }
+}
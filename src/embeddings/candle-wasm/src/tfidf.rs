@@ -0,0 +1,241 @@
+//! TF-IDF sparse vectors for hybrid (lexical + dense) scoring
+//!
+//! Terms are the neural model's own token ids, so lexical and dense scores are
+//! computed over exactly the same tokenization.
+
+use std::collections::HashMap;
+
+use js_sys::{Array, Float32Array, Uint32Array};
+use serde::{Deserialize, Serialize};
+use tokenizers::Tokenizer;
+use wasm_bindgen::prelude::*;
+
+use crate::js_array_to_strings;
+
+/// Document-frequency statistics learned by `fit`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct TfIdfModel {
+    doc_count: u32,
+    /// token id -> number of documents containing it
+    df: HashMap<u32, u32>,
+}
+
+impl TfIdfModel {
+    fn fit(&mut self, docs: &[Vec<u32>]) {
+        for ids in docs {
+            let mut seen: Vec<u32> = ids.clone();
+            seen.sort_unstable();
+            seen.dedup();
+            for id in seen {
+                *self.df.entry(id).or_insert(0) += 1;
+            }
+            self.doc_count += 1;
+        }
+    }
+
+    /// Smoothed idf, as in scikit-learn: ln((1 + n) / (1 + df)) + 1
+    fn idf(&self, id: u32) -> f32 {
+        let df = self.df.get(&id).copied().unwrap_or(0) as f32;
+        ((1.0 + self.doc_count as f32) / (1.0 + df)).ln() + 1.0
+    }
+
+    /// L2-normalized tf-idf weights, sorted by token id
+    fn transform(&self, ids: &[u32]) -> (Vec<u32>, Vec<f32>) {
+        let mut counts: HashMap<u32, u32> = HashMap::new();
+        for &id in ids {
+            *counts.entry(id).or_insert(0) += 1;
+        }
+
+        let mut terms: Vec<(u32, f32)> = counts
+            .into_iter()
+            .map(|(id, tf)| (id, tf as f32 * self.idf(id)))
+            .collect();
+        terms.sort_unstable_by_key(|(id, _)| *id);
+
+        let norm = terms.iter().map(|(_, w)| w * w).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            terms.iter_mut().for_each(|(_, w)| *w /= norm);
+        }
+
+        terms.into_iter().unzip()
+    }
+}
+
+/// Sparse vector with sorted indices
+#[wasm_bindgen]
+pub struct SparseVector {
+    indices: Vec<u32>,
+    values: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl SparseVector {
+    /// Token ids with non-zero weight (ascending)
+    #[wasm_bindgen(getter)]
+    pub fn indices(&self) -> Uint32Array {
+        Uint32Array::from(&self.indices[..])
+    }
+
+    /// Weights matching `indices`
+    #[wasm_bindgen(getter)]
+    pub fn values(&self) -> Float32Array {
+        Float32Array::from(&self.values[..])
+    }
+
+    /// Dot product with another sparse vector (cosine, since both are normalized)
+    #[wasm_bindgen]
+    pub fn dot(&self, other: &SparseVector) -> f32 {
+        let (mut i, mut j, mut sum) = (0, 0, 0.0f32);
+        while i < self.indices.len() && j < other.indices.len() {
+            match self.indices[i].cmp(&other.indices[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => {
+                    sum += self.values[i] * other.values[j];
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        sum
+    }
+}
+
+/// TF-IDF vectorizer over the model tokenizer's vocabulary
+#[wasm_bindgen]
+pub struct TfIdfVectorizer {
+    tokenizer: Tokenizer,
+    model: TfIdfModel,
+}
+
+#[wasm_bindgen]
+impl TfIdfVectorizer {
+    /// Create an unfitted vectorizer using tokenizer.json contents
+    ///
+    /// The tokenizer's padding and truncation are turned off, so pad ids are
+    /// never counted as terms and long documents are counted in full.
+    #[wasm_bindgen(constructor)]
+    pub fn new(tokenizer_bytes: &[u8]) -> Result<TfIdfVectorizer, JsValue> {
+        let mut tokenizer = Tokenizer::from_bytes(tokenizer_bytes)
+            .map_err(|e| JsValue::from_str(&format!("Failed to load tokenizer: {:?}", e)))?;
+        tokenizer
+            .with_truncation(None)
+            .map_err(|e| JsValue::from_str(&format!("Tokenizer setup failed: {}", e)))?
+            .with_padding(None);
+        Ok(TfIdfVectorizer {
+            tokenizer,
+            model: TfIdfModel::default(),
+        })
+    }
+
+    /// Restore a vectorizer saved with `to_json()`
+    #[wasm_bindgen]
+    pub fn from_json(tokenizer_bytes: &[u8], json: &str) -> Result<TfIdfVectorizer, JsValue> {
+        let mut vectorizer = TfIdfVectorizer::new(tokenizer_bytes)?;
+        vectorizer.model = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid TF-IDF state: {}", e)))?;
+        Ok(vectorizer)
+    }
+
+    /// Serialize the fitted document frequencies
+    #[wasm_bindgen]
+    pub fn to_json(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.model)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize TF-IDF state: {}", e)))
+    }
+
+    /// Accumulate document frequencies from a corpus (may be called repeatedly)
+    #[wasm_bindgen]
+    pub fn fit(&mut self, texts: &Array) -> Result<(), JsValue> {
+        let texts = js_array_to_strings(texts)?;
+        let docs = self.tokenize(texts)?;
+        self.model.fit(&docs);
+        Ok(())
+    }
+
+    /// Convert a text to an L2-normalized sparse tf-idf vector
+    #[wasm_bindgen]
+    pub fn transform(&self, text: &str) -> Result<SparseVector, JsValue> {
+        let ids = self.tokenize(vec![text.to_string()])?;
+        let (indices, values) = self.model.transform(&ids[0]);
+        Ok(SparseVector { indices, values })
+    }
+
+    /// Number of documents seen by `fit`
+    #[wasm_bindgen]
+    pub fn document_count(&self) -> u32 {
+        self.model.doc_count
+    }
+}
+
+impl TfIdfVectorizer {
+    fn tokenize(&self, texts: Vec<String>) -> Result<Vec<Vec<u32>>, JsValue> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts, false)
+            .map_err(|e| JsValue::from_str(&format!("Tokenization failed: {:?}", e)))?;
+        Ok(encodings.iter().map(|e| e.get_ids().to_vec()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rare_terms_weigh_more() {
+        let mut model = TfIdfModel::default();
+        model.fit(&[vec![1, 2], vec![1, 3], vec![1, 2, 2]]);
+        assert_eq!(model.doc_count, 3);
+        assert!(model.idf(3) > model.idf(2));
+        assert!(model.idf(2) > model.idf(1));
+
+        let (indices, values) = model.transform(&[1, 3, 3]);
+        assert_eq!(indices, vec![1, 3]);
+        assert!(values[1] > values[0]);
+        let norm: f32 = values.iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sparse_dot() {
+        let a = SparseVector {
+            indices: vec![1, 4, 7],
+            values: vec![0.5, 0.5, 1.0],
+        };
+        let b = SparseVector {
+            indices: vec![4, 7, 9],
+            values: vec![2.0, 1.0, 3.0],
+        };
+        assert!((a.dot(&b) - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_ignores_tokenizer_padding_and_truncation() {
+        let tokenizer = br#"{
+            "version": "1.0", "added_tokens": [],
+            "truncation": {"direction": "Right", "max_length": 2, "strategy": "LongestFirst",
+                           "stride": 0},
+            "padding": {"strategy": {"Fixed": 16}, "direction": "Right",
+                        "pad_to_multiple_of": null, "pad_id": 0, "pad_type_id": 0,
+                        "pad_token": "[PAD]"},
+            "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+            "post_processor": null, "decoder": null,
+            "model": {"type": "WordLevel",
+                      "vocab": {"[PAD]": 0, "the": 1, "cat": 2, "sat": 3, "quantum": 4,
+                                "lattice": 5},
+                      "unk_token": "[PAD]"}
+        }"#;
+        let mut vectorizer = TfIdfVectorizer::new(tokenizer).unwrap();
+        let texts = ["the cat sat", "quantum lattice"]
+            .map(String::from)
+            .to_vec();
+        let docs = vectorizer.tokenize(texts).unwrap();
+        assert_eq!(docs, vec![vec![1, 2, 3], vec![4, 5]]);
+        vectorizer.model.fit(&docs);
+
+        let a = vectorizer.transform("the cat sat").unwrap();
+        let b = vectorizer.transform("quantum lattice").unwrap();
+        assert!(a.dot(&b).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,277 @@
+//! LRU cache of tokenizer encodings
+//!
+//! For short strings (tags, queries) tokenization is a surprising fraction of
+//! the total embedding latency, and the same strings recur constantly. Entries
+//! are keyed by a hash of the text; the text itself is kept to rule out
+//! collisions.
+
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use tokenizers::{Encoding, Tokenizer};
+use wasm_bindgen::prelude::*;
+
+use crate::fingerprint::hash_bytes;
+use crate::prefix_tokens::PrefixTokens;
+use crate::EmbeddingEngine;
+
+/// Default number of cached encodings
+pub(crate) const DEFAULT_CAPACITY: usize = 1024;
+
+struct Entry {
+    text: String,
+    encoding: Encoding,
+    /// Tick of the last lookup or insert
+    used: u64,
+}
+
+pub(crate) struct TokenCache {
+    capacity: usize,
+    entries: HashMap<u64, Entry>,
+    /// Last-used tick -> key, oldest first
+    recency: BTreeMap<u64, u64>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+    /// Tokens of instruction prefixes, `None` where a prefix has to be
+    /// tokenized with each text
+    prefixes: HashMap<String, Option<Rc<PrefixTokens>>>,
+}
+
+impl TokenCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        TokenCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+            prefixes: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Total hits so far
+    pub(crate) fn hit_count(&self) -> u64 {
+        self.hits
+    }
+
+    /// Look up a text, counting the hit or miss
+    pub(crate) fn get(&mut self, text: &str) -> Option<Encoding> {
+        let key = hash_bytes(text.as_bytes());
+        self.tick += 1;
+        match self.entries.get_mut(&key) {
+            Some(entry) if entry.text == text => {
+                self.recency.remove(&entry.used);
+                entry.used = self.tick;
+                self.recency.insert(self.tick, key);
+                self.hits += 1;
+                Some(entry.encoding.clone())
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Insert an encoding, evicting the least recently used entry when full
+    pub(crate) fn insert(&mut self, text: &str, encoding: Encoding) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = hash_bytes(text.as_bytes());
+        self.tick += 1;
+        if let Some(old) = self.entries.remove(&key) {
+            self.recency.remove(&old.used);
+        }
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.entries.insert(
+            key,
+            Entry {
+                text: text.to_string(),
+                encoding,
+                used: self.tick,
+            },
+        );
+        self.recency.insert(self.tick, key);
+    }
+
+    /// Change the capacity, evicting the oldest entries if it shrank
+    pub(crate) fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// Drop all entries and prefix tokens (e.g. after a tokenizer change);
+    /// stats are kept
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.prefixes.clear();
+    }
+
+    /// Cached `PrefixTokens::new()` result for `prefix`
+    pub(crate) fn prefix_tokens(&self, prefix: &str) -> Option<Option<Rc<PrefixTokens>>> {
+        self.prefixes.get(prefix).cloned()
+    }
+
+    /// Segment tokenizer of any cached prefix, shared between prefixes
+    pub(crate) fn prefix_segments(&self) -> Option<Rc<Tokenizer>> {
+        self.prefixes
+            .values()
+            .flatten()
+            .next()
+            .map(|p| p.segments())
+    }
+
+    pub(crate) fn insert_prefix_tokens(&mut self, prefix: &str, tokens: Option<Rc<PrefixTokens>>) {
+        self.prefixes.insert(prefix.to_string(), tokens);
+    }
+
+    /// Cached texts, least recently used first
+    pub(crate) fn texts(&self) -> Vec<String> {
+        self.recency
+            .values()
+            .filter_map(|key| self.entries.get(key))
+            .map(|entry| entry.text.clone())
+            .collect()
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    pub(crate) fn reset_stats(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+}
+
+/// Cache effectiveness counters
+#[wasm_bindgen]
+pub struct CacheStats {
+    hits: u64,
+    misses: u64,
+    entries: usize,
+    capacity: usize,
+}
+
+#[wasm_bindgen]
+impl CacheStats {
+    /// Lookups answered from the cache
+    #[wasm_bindgen(getter)]
+    pub fn hits(&self) -> f64 {
+        self.hits as f64
+    }
+
+    /// Lookups that had to tokenize
+    #[wasm_bindgen(getter)]
+    pub fn misses(&self) -> f64 {
+        self.misses as f64
+    }
+
+    /// Fraction of lookups that hit (0 when there were none)
+    #[wasm_bindgen(getter)]
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+
+    /// Entries currently cached
+    #[wasm_bindgen(getter)]
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    /// Maximum number of entries
+    #[wasm_bindgen(getter)]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Set how many tokenizer encodings to cache (default 1024; 0 disables)
+    #[wasm_bindgen]
+    pub fn set_tokenization_cache_size(&self, entries: usize) {
+        self.token_cache.borrow_mut().resize(entries);
+    }
+
+    /// Hit/miss counters for the tokenization cache
+    #[wasm_bindgen]
+    pub fn tokenization_cache_stats(&self) -> CacheStats {
+        self.token_cache.borrow().stats()
+    }
+
+    /// Empty the tokenization cache and reset its counters
+    #[wasm_bindgen]
+    pub fn clear_tokenization_cache(&self) {
+        let mut cache = self.token_cache.borrow_mut();
+        cache.clear();
+        cache.reset_stats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoding(id: u32) -> Encoding {
+        let mut encoding = Encoding::default();
+        encoding.set_type_ids(vec![id]);
+        encoding
+    }
+
+    #[test]
+    fn test_lru_eviction_and_stats() {
+        let mut cache = TokenCache::new(2);
+        cache.insert("a", encoding(1));
+        cache.insert("b", encoding(2));
+        // Touch "a" so "b" is the least recently used
+        assert_eq!(cache.get("a").unwrap().get_type_ids(), &[1]);
+        cache.insert("c", encoding(3));
+
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.get("c").unwrap().get_type_ids(), &[3]);
+        assert_eq!(cache.get("a").unwrap().get_type_ids(), &[1]);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (3, 1, 2));
+        assert!((stats.hit_rate() - 0.75).abs() < 1e-9);
+
+        assert_eq!(cache.texts(), vec!["c", "a"]);
+
+        cache.resize(1);
+        assert_eq!(cache.stats().entries, 1);
+        assert!(cache.get("a").is_some());
+
+        cache.resize(0);
+        cache.insert("d", encoding(4));
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
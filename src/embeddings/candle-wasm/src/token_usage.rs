@@ -0,0 +1,198 @@
+//! Token counts returned with embeddings (`embed_batch_with_tokens`)
+//!
+//! Logging cost, flagging inputs close to the model's limit and debugging
+//! truncation all need the tokens the model actually saw. Rather than
+//! tokenizing a second time, the embedding call hands back what it already
+//! computed: per-text token counts (special tokens and any preset prefix
+//! included), whether each text was truncated, and optionally the ids.
+
+use js_sys::{Array, Float32Array, Uint32Array};
+use serde::Deserialize;
+use tokenizers::Encoding;
+use wasm_bindgen::prelude::*;
+
+use crate::batching::{self, EmbeddingMatrix};
+use crate::js::parse_options;
+use crate::presets::TextRole;
+use crate::telemetry::{attended_tokens, was_truncated};
+use crate::{js_array_to_strings, EmbeddingEngine, MAX_SEQUENCE_LENGTH};
+
+/// Options for `embed_batch_with_tokens`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct TokenOptions {
+    /// Also return the token ids of each text
+    include_ids: bool,
+}
+
+/// Tokens of one text as the model ran on them
+#[derive(Debug, Clone, PartialEq)]
+struct TokenUsage {
+    count: u32,
+    truncated: bool,
+    ids: Option<Vec<u32>>,
+}
+
+impl TokenUsage {
+    /// Usage of a (possibly padded) encoding, up to the model's length limit
+    fn of(encoding: &Encoding, include_ids: bool) -> Self {
+        let ids = include_ids.then(|| {
+            encoding
+                .get_ids()
+                .iter()
+                .zip(encoding.get_attention_mask())
+                .filter(|(_, &mask)| mask != 0)
+                .map(|(&id, _)| id)
+                .take(MAX_SEQUENCE_LENGTH)
+                .collect()
+        });
+        TokenUsage {
+            count: attended_tokens(encoding).min(MAX_SEQUENCE_LENGTH) as u32,
+            truncated: was_truncated(encoding),
+            ids,
+        }
+    }
+}
+
+/// Embeddings of a batch with the tokens behind each one
+#[wasm_bindgen]
+pub struct EmbeddingsWithTokens {
+    embeddings: EmbeddingMatrix,
+    usage: Vec<TokenUsage>,
+    max_tokens: usize,
+}
+
+#[wasm_bindgen]
+impl EmbeddingsWithTokens {
+    /// One Float32Array per text, as from `embed_batch()`
+    #[wasm_bindgen(getter)]
+    pub fn embeddings(&self) -> Array {
+        (0..self.embeddings.len())
+            .map(|i| JsValue::from(Float32Array::from(self.embeddings.row(i))))
+            .collect()
+    }
+
+    /// Tokens per text, special tokens included
+    #[wasm_bindgen(getter)]
+    pub fn token_counts(&self) -> Uint32Array {
+        let counts: Vec<u32> = self.usage.iter().map(|u| u.count).collect();
+        Uint32Array::from(&counts[..])
+    }
+
+    /// Whether each text was cut to fit the model
+    #[wasm_bindgen(getter)]
+    pub fn truncated(&self) -> Array {
+        self.usage
+            .iter()
+            .map(|u| JsValue::from_bool(u.truncated))
+            .collect()
+    }
+
+    /// Number of truncated texts
+    #[wasm_bindgen(getter)]
+    pub fn truncated_count(&self) -> usize {
+        self.usage.iter().filter(|u| u.truncated).count()
+    }
+
+    /// One Uint32Array of token ids per text, or `undefined` unless
+    /// `include_ids` was set
+    #[wasm_bindgen(getter)]
+    pub fn token_ids(&self) -> Option<Array> {
+        self.usage
+            .iter()
+            .map(|u| {
+                u.ids
+                    .as_deref()
+                    .map(|ids| JsValue::from(Uint32Array::from(ids)))
+            })
+            .collect()
+    }
+
+    /// Most tokens a text can have, to compare counts against
+    #[wasm_bindgen(getter)]
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// `embed_batch()` that also returns each text's token count and
+    /// truncation flag, from the same tokenization
+    ///
+    /// Options: `{ include_ids?: boolean }` to also return the token ids.
+    #[wasm_bindgen]
+    pub fn embed_batch_with_tokens(
+        &self,
+        texts: &Array,
+        options: &JsValue,
+    ) -> Result<EmbeddingsWithTokens, JsValue> {
+        let options: TokenOptions = parse_options(options)?;
+        let (unique, positions) = batching::dedup_texts(js_array_to_strings(texts)?);
+        let (embeddings, encodings) = if unique.is_empty() {
+            (EmbeddingMatrix::default(), Vec::new())
+        } else {
+            self.embed_matrix_with_encodings(&unique, TextRole::Document)?
+        };
+        let usage: Vec<TokenUsage> = encodings
+            .iter()
+            .map(|e| TokenUsage::of(e, options.include_ids))
+            .collect();
+        let max_tokens = self
+            .tokenizer
+            .as_ref()
+            .and_then(|t| t.get_truncation())
+            .map_or(MAX_SEQUENCE_LENGTH, |t| {
+                t.max_length.min(MAX_SEQUENCE_LENGTH)
+            });
+
+        Ok(EmbeddingsWithTokens {
+            embeddings: embeddings.gather(&positions),
+            usage: batching::fan_out(usage, &positions),
+            max_tokens,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoding(ids: Vec<u32>, mask: Vec<u32>) -> Encoding {
+        let len = ids.len();
+        Encoding::new(
+            ids,
+            vec![0; len],
+            vec![String::new(); len],
+            vec![None; len],
+            vec![(0, 0); len],
+            vec![0; len],
+            mask,
+            Vec::new(),
+            Default::default(),
+        )
+    }
+
+    #[test]
+    fn test_usage_skips_padding() {
+        let padded = encoding(vec![101, 7592, 102, 0, 0], vec![1, 1, 1, 0, 0]);
+        assert_eq!(
+            TokenUsage::of(&padded, true),
+            TokenUsage {
+                count: 3,
+                truncated: false,
+                ids: Some(vec![101, 7592, 102]),
+            }
+        );
+        assert_eq!(TokenUsage::of(&padded, false).ids, None);
+
+        let long = encoding(
+            vec![1; MAX_SEQUENCE_LENGTH + 4],
+            vec![1; MAX_SEQUENCE_LENGTH + 4],
+        );
+        let usage = TokenUsage::of(&long, true);
+        assert_eq!(usage.count as usize, MAX_SEQUENCE_LENGTH);
+        assert!(usage.truncated);
+        assert_eq!(usage.ids.unwrap().len(), MAX_SEQUENCE_LENGTH);
+    }
+}
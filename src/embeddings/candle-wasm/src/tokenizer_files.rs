@@ -0,0 +1,794 @@
+//! Tokenizers from pre-tokenizer.json file formats
+//!
+//! Older checkpoints ship their vocabulary in the format of the library that
+//! trained them rather than as a tokenizer.json. These builders produce the
+//! tokenizer.json the Hugging Face conversion scripts would, so the rest of
+//! the engine (and the fingerprint) sees an ordinary tokenizer.
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokenizers::normalizers::Precompiled;
+use wasm_bindgen::prelude::*;
+
+use crate::js::parse_options;
+use crate::EmbeddingEngine;
+
+/// Options for `load_with_vocab`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct WordPieceOptions {
+    /// Lowercase input (uncased checkpoints)
+    lowercase: bool,
+    /// Remove accents; follows `lowercase` when unset, as in BERT
+    strip_accents: Option<bool>,
+    /// Truncation length including special tokens
+    max_length: usize,
+    unk_token: String,
+    cls_token: String,
+    sep_token: String,
+    pad_token: String,
+    mask_token: String,
+}
+
+impl Default for WordPieceOptions {
+    fn default() -> Self {
+        WordPieceOptions {
+            lowercase: true,
+            strip_accents: None,
+            max_length: 512,
+            unk_token: "[UNK]".to_string(),
+            cls_token: "[CLS]".to_string(),
+            sep_token: "[SEP]".to_string(),
+            pad_token: "[PAD]".to_string(),
+            mask_token: "[MASK]".to_string(),
+        }
+    }
+}
+
+/// Parse vocab.txt: one token per line, ids by line number (a repeated token
+/// keeps its last line, as in the reference loader)
+fn parse_vocab_txt(vocab: &str) -> Map<String, Value> {
+    let mut map = Map::new();
+    for (id, token) in vocab.lines().enumerate() {
+        map.insert(token.to_string(), json!(id));
+    }
+    map
+}
+
+/// `added_tokens` entries for the special tokens present in the vocabulary
+fn special_tokens(vocab: &Map<String, Value>, tokens: &[&str]) -> Vec<Value> {
+    let mut added: Vec<Value> = tokens
+        .iter()
+        .filter_map(|&token| {
+            let id = vocab.get(token)?.as_u64()?;
+            Some(json!({
+                "id": id,
+                "content": token,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": false,
+                "special": true,
+            }))
+        })
+        .collect();
+    added.sort_by_key(|entry| entry["id"].as_u64());
+    added.dedup_by_key(|entry| entry["id"].as_u64());
+    added
+}
+
+/// Template pieces from a spec like `"[CLS] $A [SEP] $B:1 [SEP]:1"`, where
+/// `$` marks a sequence and `:n` sets the type id
+fn template(spec: &str) -> Value {
+    let pieces: Vec<Value> = spec
+        .split_whitespace()
+        .map(|part| {
+            let (name, type_id) = match part.rsplit_once(':') {
+                Some((name, id)) if id.parse::<u32>().is_ok() => (name, id.parse::<u32>().unwrap()),
+                _ => (part, 0),
+            };
+            match name.strip_prefix('$') {
+                Some(sequence) => json!({"Sequence": {"id": sequence, "type_id": type_id}}),
+                None => json!({"SpecialToken": {"id": name, "type_id": type_id}}),
+            }
+        })
+        .collect();
+    Value::Array(pieces)
+}
+
+/// TemplateProcessing with the given templates; every special token they name
+/// must be in the vocabulary
+fn template_processor(
+    vocab: &Map<String, Value>,
+    single: &str,
+    pair: &str,
+) -> Result<Value, String> {
+    let mut special_tokens = Map::new();
+    for part in single.split_whitespace().chain(pair.split_whitespace()) {
+        let token = part.rsplit_once(':').map_or(part, |(name, _)| name);
+        if token.starts_with('$') || special_tokens.contains_key(token) {
+            continue;
+        }
+        let id = vocab
+            .get(token)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| format!("Special token {} is not in the vocabulary", token))?;
+        special_tokens.insert(
+            token.to_string(),
+            json!({"id": token, "ids": [id], "tokens": [token]}),
+        );
+    }
+    Ok(json!({
+        "type": "TemplateProcessing",
+        "single": template(single),
+        "pair": template(pair),
+        "special_tokens": special_tokens,
+    }))
+}
+
+fn truncation(max_length: usize) -> Value {
+    json!({"direction": "Right", "max_length": max_length, "strategy": "LongestFirst", "stride": 0})
+}
+
+/// tokenizer.json for a BERT WordPiece vocabulary
+fn wordpiece_tokenizer(vocab: &str, options: &WordPieceOptions) -> Result<Value, String> {
+    let vocab = parse_vocab_txt(vocab);
+    if !vocab.contains_key(&options.unk_token) {
+        return Err(format!(
+            "Unknown token {} is not in the vocabulary",
+            options.unk_token
+        ));
+    }
+    let specials = [
+        options.pad_token.as_str(),
+        &options.unk_token,
+        &options.cls_token,
+        &options.sep_token,
+        &options.mask_token,
+    ];
+    Ok(json!({
+        "version": "1.0",
+        "truncation": truncation(options.max_length),
+        "padding": null,
+        "added_tokens": special_tokens(&vocab, &specials),
+        "normalizer": {
+            "type": "BertNormalizer",
+            "clean_text": true,
+            "handle_chinese_chars": true,
+            "strip_accents": options.strip_accents,
+            "lowercase": options.lowercase,
+        },
+        "pre_tokenizer": {"type": "BertPreTokenizer"},
+        "post_processor": template_processor(
+            &vocab,
+            &format!("{cls} $A {sep}", cls = options.cls_token, sep = options.sep_token),
+            &format!("{cls} $A {sep} $B:1 {sep}:1", cls = options.cls_token, sep = options.sep_token),
+        )?,
+        "decoder": {"type": "WordPiece", "prefix": "##", "cleanup": true},
+        "model": {
+            "type": "WordPiece",
+            "unk_token": options.unk_token,
+            "continuing_subword_prefix": "##",
+            "max_input_chars_per_word": 100,
+            "vocab": vocab,
+        },
+    }))
+}
+
+/// Options for `load_with_sentencepiece`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct SentencePieceOptions {
+    /// fairseq id layout (XLM-R and its fine-tunes): `<s> <pad> </s> <unk>`
+    /// first, then the pieces, then `<mask>`
+    fairseq: bool,
+    /// Wrap each text as `<s> ... </s>`; `false` appends only `</s>`
+    add_bos: bool,
+    /// Truncation length including special tokens
+    max_length: usize,
+}
+
+impl Default for SentencePieceOptions {
+    fn default() -> Self {
+        SentencePieceOptions {
+            fairseq: false,
+            add_bos: true,
+            max_length: 512,
+        }
+    }
+}
+
+/// One field of a protobuf message
+enum ProtoField<'a> {
+    Varint(u64),
+    Fixed32(u32),
+    Bytes(&'a [u8]),
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or("Truncated varint")?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("Varint too long".to_string())
+}
+
+/// Top-level fields of a protobuf message, in order (64-bit fields, unused by
+/// the SentencePiece schema, are skipped)
+fn proto_fields(bytes: &[u8]) -> Result<Vec<(u64, ProtoField<'_>)>, String> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let key = read_varint(bytes, &mut pos)?;
+        let field = match key & 7 {
+            0 => ProtoField::Varint(read_varint(bytes, &mut pos)?),
+            1 => {
+                pos += 8;
+                continue;
+            }
+            2 => {
+                let len = read_varint(bytes, &mut pos)? as usize;
+                let end = pos.checked_add(len).filter(|&end| end <= bytes.len());
+                let end = end.ok_or("Truncated field")?;
+                let field = ProtoField::Bytes(&bytes[pos..end]);
+                pos = end;
+                field
+            }
+            5 => {
+                let raw = bytes.get(pos..pos + 4).ok_or("Truncated field")?;
+                pos += 4;
+                ProtoField::Fixed32(u32::from_le_bytes(raw.try_into().unwrap()))
+            }
+            wire => return Err(format!("Unsupported wire type {}", wire)),
+        };
+        fields.push((key >> 3, field));
+    }
+    if pos > bytes.len() {
+        return Err("Truncated field".to_string());
+    }
+    Ok(fields)
+}
+
+/// Piece types from sentencepiece_model.proto
+const PIECE_UNKNOWN: u64 = 2;
+const PIECE_CONTROL: u64 = 3;
+const PIECE_USER_DEFINED: u64 = 4;
+const MODEL_UNIGRAM: u64 = 1;
+
+struct Piece {
+    piece: String,
+    score: f32,
+    kind: u64,
+}
+
+/// The parts of a SentencePiece `ModelProto` the tokenizer needs
+struct SentencePieceModel {
+    pieces: Vec<Piece>,
+    model_type: u64,
+    byte_fallback: bool,
+    unk_id: usize,
+    bos_id: i64,
+    eos_id: i64,
+    precompiled_charsmap: Vec<u8>,
+    add_dummy_prefix: bool,
+    remove_extra_whitespaces: bool,
+}
+
+fn parse_sentencepiece(bytes: &[u8]) -> Result<SentencePieceModel, String> {
+    let mut model = SentencePieceModel {
+        pieces: Vec::new(),
+        model_type: MODEL_UNIGRAM,
+        byte_fallback: false,
+        unk_id: 0,
+        bos_id: 1,
+        eos_id: 2,
+        precompiled_charsmap: Vec::new(),
+        add_dummy_prefix: true,
+        remove_extra_whitespaces: true,
+    };
+    for (number, field) in proto_fields(bytes)? {
+        match (number, field) {
+            (1, ProtoField::Bytes(message)) => {
+                let mut piece = Piece {
+                    piece: String::new(),
+                    score: 0.0,
+                    kind: 1,
+                };
+                for field in proto_fields(message)? {
+                    match field {
+                        (1, ProtoField::Bytes(text)) => {
+                            piece.piece = String::from_utf8(text.to_vec())
+                                .map_err(|_| "Piece is not valid UTF-8")?;
+                        }
+                        (2, ProtoField::Fixed32(bits)) => piece.score = f32::from_bits(bits),
+                        (3, ProtoField::Varint(kind)) => piece.kind = kind,
+                        _ => {}
+                    }
+                }
+                model.pieces.push(piece);
+            }
+            // TrainerSpec
+            (2, ProtoField::Bytes(message)) => {
+                for field in proto_fields(message)? {
+                    match field {
+                        (3, ProtoField::Varint(kind)) => model.model_type = kind,
+                        (35, ProtoField::Varint(flag)) => model.byte_fallback = flag != 0,
+                        (40, ProtoField::Varint(id)) => model.unk_id = id as usize,
+                        // int32 fields: negative ids (disabled) are sign-extended
+                        (41, ProtoField::Varint(id)) => model.bos_id = id as i64,
+                        (42, ProtoField::Varint(id)) => model.eos_id = id as i64,
+                        _ => {}
+                    }
+                }
+            }
+            // NormalizerSpec
+            (3, ProtoField::Bytes(message)) => {
+                for field in proto_fields(message)? {
+                    match field {
+                        (2, ProtoField::Bytes(map)) => model.precompiled_charsmap = map.to_vec(),
+                        (3, ProtoField::Varint(flag)) => model.add_dummy_prefix = flag != 0,
+                        (4, ProtoField::Varint(flag)) => model.remove_extra_whitespaces = flag != 0,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if model.pieces.is_empty() {
+        return Err("No pieces found; is this a SentencePiece model?".to_string());
+    }
+    Ok(model)
+}
+
+/// tokenizer.json for a SentencePiece Unigram model, as converted by
+/// transformers
+fn sentencepiece_tokenizer(bytes: &[u8], options: &SentencePieceOptions) -> Result<Value, String> {
+    let model = parse_sentencepiece(bytes)?;
+    if model.model_type != MODEL_UNIGRAM {
+        return Err(format!(
+            "Only Unigram SentencePiece models are supported (model type {})",
+            model.model_type
+        ));
+    }
+
+    // (piece, score, special) in id order
+    let mut vocab: Vec<(String, f32, Option<bool>)> = Vec::with_capacity(model.pieces.len() + 2);
+    let (unk_id, bos, eos) = if options.fairseq {
+        for special in ["<s>", "<pad>", "</s>", "<unk>"] {
+            vocab.push((special.to_string(), 0.0, Some(true)));
+        }
+        vocab.extend(
+            model
+                .pieces
+                .iter()
+                .skip(3)
+                .map(|p| (p.piece.clone(), p.score, None)),
+        );
+        vocab.push(("<mask>".to_string(), 0.0, Some(true)));
+        (3, Some("<s>".to_string()), "</s>".to_string())
+    } else {
+        vocab.extend(model.pieces.iter().map(|p| {
+            let special = match p.kind {
+                PIECE_UNKNOWN | PIECE_CONTROL => Some(true),
+                PIECE_USER_DEFINED => Some(false),
+                _ => None,
+            };
+            (p.piece.clone(), p.score, special)
+        }));
+        let piece = |id: i64| {
+            usize::try_from(id)
+                .ok()
+                .and_then(|id| vocab.get(id))
+                .map(|p| p.0.clone())
+        };
+        let eos = piece(model.eos_id).ok_or("The model has no end-of-sequence piece")?;
+        (model.unk_id, piece(model.bos_id), eos)
+    };
+    if unk_id >= vocab.len() {
+        return Err(format!("Unknown piece id {} is out of range", unk_id));
+    }
+
+    let added_tokens: Vec<Value> = vocab
+        .iter()
+        .enumerate()
+        .filter_map(|(id, (piece, _, special))| {
+            let special = (*special)?;
+            Some(json!({
+                "id": id,
+                "content": piece,
+                "single_word": false,
+                "lstrip": false,
+                "rstrip": false,
+                "normalized": !special,
+                "special": special,
+            }))
+        })
+        .collect();
+    let ids: Map<String, Value> = vocab
+        .iter()
+        .enumerate()
+        .map(|(id, (piece, _, _))| (piece.clone(), json!(id)))
+        .collect();
+    let (single, pair) = match (&bos, options.add_bos) {
+        // fairseq separates pairs with a doubled </s>
+        (Some(bos), true) if options.fairseq => (
+            format!("{bos} $A {eos}"),
+            format!("{bos} $A {eos} {eos} $B:1 {eos}:1"),
+        ),
+        (Some(bos), true) => (
+            format!("{bos} $A {eos}"),
+            format!("{bos} $A {eos} $B:1 {eos}:1"),
+        ),
+        _ => (format!("$A {eos}"), format!("$A {eos} $B:1 {eos}:1")),
+    };
+
+    let mut normalizers = Vec::new();
+    if !model.precompiled_charsmap.is_empty() {
+        let precompiled = Precompiled::from(&model.precompiled_charsmap)
+            .map_err(|e| format!("Invalid normalization rules: {}", e))?;
+        normalizers.push(serde_json::to_value(precompiled).map_err(|e| e.to_string())?);
+    }
+    if model.remove_extra_whitespaces {
+        normalizers.push(json!({"type": "Strip", "strip_left": true, "strip_right": true}));
+        normalizers.push(json!({"type": "Replace", "pattern": {"Regex": " {2,}"}, "content": " "}));
+    }
+    let metaspace = json!({
+        "type": "Metaspace",
+        "replacement": "\u{2581}",
+        "prepend_scheme": if model.add_dummy_prefix { "always" } else { "never" },
+        "split": true,
+    });
+
+    Ok(json!({
+        "version": "1.0",
+        "truncation": truncation(options.max_length),
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": {"type": "Sequence", "normalizers": normalizers},
+        "pre_tokenizer": metaspace,
+        "post_processor": template_processor(&ids, &single, &pair)?,
+        "decoder": metaspace,
+        "model": {
+            "type": "Unigram",
+            "unk_id": unk_id,
+            "byte_fallback": model.byte_fallback,
+            "vocab": vocab.iter().map(|(piece, score, _)| json!([piece, score])).collect::<Vec<_>>(),
+        },
+    }))
+}
+
+/// Options for `load_with_merges`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct BpeOptions {
+    /// Treat the first word like any other by prefixing a space
+    add_prefix_space: bool,
+    /// Truncation length including special tokens
+    max_length: usize,
+    cls_token: String,
+    sep_token: String,
+    pad_token: String,
+    unk_token: String,
+    mask_token: String,
+}
+
+impl Default for BpeOptions {
+    fn default() -> Self {
+        BpeOptions {
+            add_prefix_space: false,
+            max_length: 512,
+            cls_token: "<s>".to_string(),
+            sep_token: "</s>".to_string(),
+            pad_token: "<pad>".to_string(),
+            unk_token: "<unk>".to_string(),
+            mask_token: "<mask>".to_string(),
+        }
+    }
+}
+
+/// Parse merges.txt: one space-separated pair per line, after an optional
+/// `#version` header
+fn parse_merges(merges: &str) -> Result<Vec<Value>, String> {
+    merges
+        .lines()
+        .enumerate()
+        .filter(|(i, line)| !line.is_empty() && (*i > 0 || !line.starts_with("#version")))
+        .map(|(i, line)| match line.split(' ').collect::<Vec<_>>()[..] {
+            [left, right] => Ok(json!([left, right])),
+            _ => Err(format!("Invalid merge on line {}: {:?}", i + 1, line)),
+        })
+        .collect()
+}
+
+/// tokenizer.json for a RoBERTa-style byte-level BPE vocabulary
+fn bpe_tokenizer(vocab: &str, merges: &str, options: &BpeOptions) -> Result<Value, String> {
+    let vocab: Map<String, Value> =
+        serde_json::from_str(vocab).map_err(|e| format!("Invalid vocab.json: {}", e))?;
+    let merges = parse_merges(merges)?;
+    let id = |token: &str| {
+        vocab
+            .get(token)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| format!("Special token {} is not in the vocabulary", token))
+    };
+    let cls = (options.cls_token.as_str(), id(&options.cls_token)?);
+    let sep = (options.sep_token.as_str(), id(&options.sep_token)?);
+    let specials = [
+        options.cls_token.as_str(),
+        &options.pad_token,
+        &options.sep_token,
+        &options.unk_token,
+        &options.mask_token,
+    ];
+    let mut added_tokens = special_tokens(&vocab, &specials);
+    // As in RoBERTa, `<mask>` takes the space before it
+    for token in &mut added_tokens {
+        if token["content"] == options.mask_token.as_str() {
+            token["lstrip"] = json!(true);
+        }
+    }
+    let byte_level = json!({
+        "type": "ByteLevel",
+        "add_prefix_space": options.add_prefix_space,
+        "trim_offsets": true,
+        "use_regex": true,
+    });
+    Ok(json!({
+        "version": "1.0",
+        "truncation": truncation(options.max_length),
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": null,
+        "pre_tokenizer": byte_level,
+        "post_processor": {
+            "type": "RobertaProcessing",
+            "sep": sep,
+            "cls": cls,
+            "trim_offsets": true,
+            "add_prefix_space": options.add_prefix_space,
+        },
+        "decoder": byte_level,
+        "model": {
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": vocab.contains_key(&options.unk_token).then_some(&options.unk_token),
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": false,
+            "byte_fallback": false,
+            "vocab": vocab,
+            "merges": merges,
+        },
+    }))
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Load a BERT checkpoint whose tokenizer is a WordPiece `vocab.txt`
+    ///
+    /// Options: `{ lowercase = true, strip_accents?, max_length = 512,
+    /// unk_token = "[UNK]", cls_token = "[CLS]", sep_token = "[SEP]",
+    /// pad_token = "[PAD]", mask_token = "[MASK]" }`. Set `lowercase: false`
+    /// for cased checkpoints (`do_lower_case` in their tokenizer_config.json).
+    #[wasm_bindgen]
+    pub fn load_with_vocab(
+        &mut self,
+        model_bytes: &[u8],
+        vocab: &str,
+        config_bytes: &[u8],
+        options: &JsValue,
+    ) -> Result<(), JsValue> {
+        let options: WordPieceOptions = parse_options(options)?;
+        let tokenizer = wordpiece_tokenizer(vocab, &options).map_err(|e| JsValue::from_str(&e))?;
+        self.load(model_bytes, tokenizer.to_string().as_bytes(), config_bytes)
+    }
+
+    /// Load a checkpoint whose tokenizer is a SentencePiece `.model` file
+    /// (`sentencepiece.bpe.model`, `spiece.model`)
+    ///
+    /// Options: `{ fairseq = false, add_bos = true, max_length = 512 }`. Set
+    /// `fairseq: true` for XLM-R based models, whose embedding ids are offset
+    /// from the SentencePiece ids. Unigram models only.
+    #[wasm_bindgen]
+    pub fn load_with_sentencepiece(
+        &mut self,
+        model_bytes: &[u8],
+        spm_bytes: &[u8],
+        config_bytes: &[u8],
+        options: &JsValue,
+    ) -> Result<(), JsValue> {
+        let options: SentencePieceOptions = parse_options(options)?;
+        let tokenizer =
+            sentencepiece_tokenizer(spm_bytes, &options).map_err(|e| JsValue::from_str(&e))?;
+        self.load(model_bytes, tokenizer.to_string().as_bytes(), config_bytes)
+    }
+
+    /// Load a RoBERTa-style checkpoint whose tokenizer is a byte-level BPE
+    /// `vocab.json` plus `merges.txt`
+    ///
+    /// Options: `{ add_prefix_space = false, max_length = 512,
+    /// cls_token = "<s>", sep_token = "</s>", pad_token = "<pad>",
+    /// unk_token = "<unk>", mask_token = "<mask>" }`.
+    #[wasm_bindgen]
+    pub fn load_with_merges(
+        &mut self,
+        model_bytes: &[u8],
+        vocab: &str,
+        merges: &str,
+        config_bytes: &[u8],
+        options: &JsValue,
+    ) -> Result<(), JsValue> {
+        let options: BpeOptions = parse_options(options)?;
+        let tokenizer =
+            bpe_tokenizer(vocab, merges, &options).map_err(|e| JsValue::from_str(&e))?;
+        self.load(model_bytes, tokenizer.to_string().as_bytes(), config_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use tokenizers::Tokenizer;
+
+    const VOCAB: &str = "[PAD]\n[UNK]\n[CLS]\n[SEP]\n[MASK]\nthe\nun\n##aff\n##able\n,\nhello\n";
+
+    #[test]
+    fn test_wordpiece_from_vocab() {
+        let json = wordpiece_tokenizer(VOCAB, &WordPieceOptions::default()).unwrap();
+        let tokenizer = Tokenizer::from_str(&json.to_string()).unwrap();
+        let encoding = tokenizer.encode("Héllo, the UNAFFABLE xyz", true).unwrap();
+        assert_eq!(
+            encoding.get_tokens(),
+            &["[CLS]", "hello", ",", "the", "un", "##aff", "##able", "[UNK]", "[SEP]"]
+        );
+        assert_eq!(encoding.get_ids()[..2], [2, 10]);
+        // Special tokens in the text are kept whole
+        let encoding = tokenizer.encode("the [MASK]", false).unwrap();
+        assert_eq!(encoding.get_ids(), &[5, 4]);
+    }
+
+    #[test]
+    fn test_cased_and_missing_tokens() {
+        let cased: WordPieceOptions =
+            crate::js::options_from_json(r#"{"lowercase": false}"#).unwrap();
+        let json = wordpiece_tokenizer(VOCAB, &cased).unwrap();
+        let tokenizer = Tokenizer::from_str(&json.to_string()).unwrap();
+        assert_eq!(tokenizer.encode("Hello", false).unwrap().get_ids(), &[1]);
+
+        let options: WordPieceOptions =
+            crate::js::options_from_json(r#"{"cls_token": "<s>"}"#).unwrap();
+        assert!(wordpiece_tokenizer(VOCAB, &options).is_err());
+        assert!(wordpiece_tokenizer("a\nb\n", &WordPieceOptions::default()).is_err());
+    }
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    fn message(number: u64, body: &[u8]) -> Vec<u8> {
+        let mut bytes = varint(number << 3 | 2);
+        bytes.extend(varint(body.len() as u64));
+        bytes.extend(body);
+        bytes
+    }
+
+    fn number(number: u64, value: u64) -> Vec<u8> {
+        let mut bytes = varint(number << 3);
+        bytes.extend(varint(value));
+        bytes
+    }
+
+    /// A Unigram ModelProto with the usual `<unk> <s> </s>` control pieces
+    fn spm_model(model_type: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let pieces = [
+            ("<unk>", 0.0, PIECE_UNKNOWN),
+            ("<s>", 0.0, PIECE_CONTROL),
+            ("</s>", 0.0, PIECE_CONTROL),
+            ("\u{2581}hello", -1.0, 1),
+            ("\u{2581}world", -2.0, 1),
+            ("\u{2581}", -3.0, 1),
+            ("h", -5.0, 1),
+        ];
+        for (piece, score, kind) in pieces {
+            let mut body = message(1, piece.as_bytes());
+            body.push(2 << 3 | 5);
+            body.extend(f32::to_le_bytes(score));
+            body.extend(number(3, kind));
+            bytes.extend(message(1, &body));
+        }
+        // Trainer spec with a disabled (-1) pad id, then a normalizer spec
+        let mut trainer = number(3, model_type);
+        trainer.extend(number(43, -1i64 as u64));
+        bytes.extend(message(2, &trainer));
+        bytes.extend(message(3, &message(1, b"identity")));
+        bytes
+    }
+
+    fn spm_tokenizer(options: &str) -> Tokenizer {
+        let options: SentencePieceOptions = crate::js::options_from_json(options).unwrap();
+        let json = sentencepiece_tokenizer(&spm_model(MODEL_UNIGRAM), &options).unwrap();
+        Tokenizer::from_str(&json.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_sentencepiece_unigram() {
+        let tokenizer = spm_tokenizer("{}");
+        let encoding = tokenizer.encode("  hello   world ", true).unwrap();
+        assert_eq!(encoding.get_ids(), &[1, 3, 4, 2]);
+        // Unknown characters map to <unk>
+        assert_eq!(
+            tokenizer.encode("hello z", false).unwrap().get_ids(),
+            &[3, 5, 0]
+        );
+        let tokenizer = spm_tokenizer(r#"{"add_bos": false}"#);
+        assert_eq!(tokenizer.encode("world", true).unwrap().get_ids(), &[4, 2]);
+    }
+
+    #[test]
+    fn test_sentencepiece_fairseq_layout() {
+        let tokenizer = spm_tokenizer(r#"{"fairseq": true}"#);
+        let encoding = tokenizer.encode("hello world z", true).unwrap();
+        assert_eq!(encoding.get_ids(), &[0, 4, 5, 6, 3, 2]);
+        assert_eq!(tokenizer.token_to_id("<mask>"), Some(8));
+        assert_eq!(
+            tokenizer.encode("hello <mask>", false).unwrap().get_ids(),
+            &[4, 8]
+        );
+    }
+
+    #[test]
+    fn test_sentencepiece_rejects_other_models() {
+        let options = SentencePieceOptions::default();
+        assert!(sentencepiece_tokenizer(&spm_model(2), &options)
+            .unwrap_err()
+            .contains("Unigram"));
+        assert!(sentencepiece_tokenizer(b"not a model", &options).is_err());
+        assert!(sentencepiece_tokenizer(&[], &options).is_err());
+    }
+
+    const BPE_VOCAB: &str = r#"{"<s>": 0, "<pad>": 1, "</s>": 2, "<unk>": 3, "h": 4, "e": 5,
+        "l": 6, "o": 7, "\u0120": 8, "he": 9, "ll": 10, "hell": 11, "hello": 12,
+        "\u0120hello": 13, "<mask>": 14}"#;
+    const MERGES: &str = "#version: 0.2\nh e\nl l\nhe ll\nhell o\n\u{120} hello\n";
+
+    #[test]
+    fn test_bpe_from_merges() {
+        let json = bpe_tokenizer(BPE_VOCAB, MERGES, &BpeOptions::default()).unwrap();
+        let tokenizer = Tokenizer::from_str(&json.to_string()).unwrap();
+        let encoding = tokenizer.encode("hello hello", true).unwrap();
+        assert_eq!(encoding.get_ids(), &[0, 12, 13, 2]);
+        assert_eq!(encoding.get_offsets()[2], (6, 11));
+        assert_eq!(
+            tokenizer.encode("hello <mask>", false).unwrap().get_ids(),
+            &[12, 14]
+        );
+
+        let options: BpeOptions =
+            crate::js::options_from_json(r#"{"add_prefix_space": true}"#).unwrap();
+        let json = bpe_tokenizer(BPE_VOCAB, MERGES, &options).unwrap();
+        let tokenizer = Tokenizer::from_str(&json.to_string()).unwrap();
+        assert_eq!(tokenizer.encode("hello", false).unwrap().get_ids(), &[13]);
+    }
+
+    #[test]
+    fn test_bpe_invalid_files() {
+        let options = BpeOptions::default();
+        assert!(bpe_tokenizer("[]", MERGES, &options).is_err());
+        assert!(bpe_tokenizer(BPE_VOCAB, "h e l\n", &options).is_err());
+        assert!(bpe_tokenizer(r#"{"a": 0}"#, "", &options).is_err());
+    }
+}
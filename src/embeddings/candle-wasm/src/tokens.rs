@@ -0,0 +1,242 @@
+//! Token ids and source offsets
+//!
+//! Highlighting and span extraction need to know which part of the input each
+//! token came from. The tokenizer tracks byte offsets into the original text;
+//! these are exposed as UTF-16 indices so they can be used with
+//! `String.prototype.slice` directly. A preset's document prefix is
+//! tokenized in front of the text as `embed()` does, and offsets are moved
+//! back past it so they still index the text as given.
+
+use js_sys::{Array, Float32Array, Uint32Array, Uint8Array};
+use tokenizers::Encoding;
+use wasm_bindgen::prelude::*;
+
+use crate::attribution::{content_tokens, drop_prefix_tokens, utf16_offsets};
+use crate::presets::{unprefixed_offsets, TextRole};
+use crate::{EmbeddingEngine, MAX_SEQUENCE_LENGTH};
+
+/// Tokens of a text as the model sees them, with their source spans
+#[wasm_bindgen]
+pub struct TokenizedText {
+    ids: Vec<u32>,
+    tokens: Vec<String>,
+    offsets: Vec<u32>,
+    special: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl TokenizedText {
+    /// Token ids, including special tokens
+    #[wasm_bindgen(getter)]
+    pub fn ids(&self) -> Uint32Array {
+        Uint32Array::from(&self.ids[..])
+    }
+
+    /// Token strings
+    #[wasm_bindgen(getter)]
+    pub fn tokens(&self) -> Array {
+        self.tokens.iter().map(|t| JsValue::from_str(t)).collect()
+    }
+
+    /// `[start, end]` pairs per token, as UTF-16 indices into the text
+    /// (special tokens have empty spans)
+    #[wasm_bindgen(getter)]
+    pub fn offsets(&self) -> Uint32Array {
+        Uint32Array::from(&self.offsets[..])
+    }
+
+    /// 0 for text tokens, 1 for the rest: special tokens added by the
+    /// tokenizer and the preset's document prefix
+    #[wasm_bindgen(getter)]
+    pub fn special_tokens_mask(&self) -> Uint8Array {
+        Uint8Array::from(&self.special[..])
+    }
+
+    /// Number of tokens
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.ids.len()
+    }
+}
+
+/// Per-token embeddings of a text, with their source spans
+#[wasm_bindgen]
+pub struct TokenEmbeddings {
+    ids: Vec<u32>,
+    tokens: Vec<String>,
+    offsets: Vec<u32>,
+    vectors: Vec<f32>,
+    dimension: usize,
+}
+
+#[wasm_bindgen]
+impl TokenEmbeddings {
+    /// Ids of the content tokens (special tokens removed)
+    #[wasm_bindgen(getter)]
+    pub fn ids(&self) -> Uint32Array {
+        Uint32Array::from(&self.ids[..])
+    }
+
+    /// Content token strings
+    #[wasm_bindgen(getter)]
+    pub fn tokens(&self) -> Array {
+        self.tokens.iter().map(|t| JsValue::from_str(t)).collect()
+    }
+
+    /// `[start, end]` pairs per token, as UTF-16 indices into the text
+    #[wasm_bindgen(getter)]
+    pub fn offsets(&self) -> Uint32Array {
+        Uint32Array::from(&self.offsets[..])
+    }
+
+    /// L2-normalized token vectors, `[tokens, dimension]` row-major
+    #[wasm_bindgen(getter)]
+    pub fn vectors(&self) -> Float32Array {
+        Float32Array::from(&self.vectors[..])
+    }
+
+    /// Length of each token vector
+    #[wasm_bindgen(getter)]
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+}
+
+/// Positions of the tokens the model runs on: not padding, and within the
+/// sequence limit
+fn model_positions(encoding: &Encoding) -> impl Iterator<Item = usize> + '_ {
+    encoding
+        .get_attention_mask()
+        .iter()
+        .take(MAX_SEQUENCE_LENGTH)
+        .enumerate()
+        .filter(|(_, &mask)| mask != 0)
+        .map(|(i, _)| i)
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Tokenize a text as `embed()` would, returning ids and source offsets
+    ///
+    /// Padding is dropped and truncation applied, so the result lists exactly
+    /// the tokens the model sees, including any preset document prefix
+    /// (with empty spans). `set_preprocessing()` is not applied, since its
+    /// output has no offsets into the original: pass `preprocess(text)` to
+    /// see the tokens of preprocessed text.
+    #[wasm_bindgen]
+    pub fn encode_with_offsets(&self, text: &str) -> Result<TokenizedText, JsValue> {
+        let encoding = self
+            .tokenize_as(&[text.to_string()], TextRole::Document)?
+            .remove(0);
+        let prefix_len = self.role_prefix(TextRole::Document).len();
+        let positions: Vec<usize> = model_positions(&encoding).collect();
+        let spans: Vec<Option<(usize, usize)>> = positions
+            .iter()
+            .map(|&i| {
+                let from_text = encoding.get_special_tokens_mask()[i] == 0;
+                from_text
+                    .then(|| unprefixed_offsets(encoding.get_offsets()[i], prefix_len))
+                    .flatten()
+            })
+            .collect();
+        Ok(TokenizedText {
+            ids: positions.iter().map(|&i| encoding.get_ids()[i]).collect(),
+            tokens: positions
+                .iter()
+                .map(|&i| encoding.get_tokens()[i].clone())
+                .collect(),
+            offsets: utf16_offsets(text, spans.iter().map(|span| span.unwrap_or((0, 0)))),
+            special: spans.iter().map(|span| span.is_none() as u8).collect(),
+        })
+    }
+
+    /// Embed each token of a text, with its source offsets
+    ///
+    /// Vectors come from the layer the engine pools over, as used by
+    /// `explain_similarity()`, with any preset document prefix in front as
+    /// in `embed()`; special and prefix tokens are omitted. As with
+    /// `encode_with_offsets()`, `set_preprocessing()` is not applied.
+    #[wasm_bindgen]
+    pub fn embed_tokens(&self, text: &str) -> Result<TokenEmbeddings, JsValue> {
+        let encodings = self.tokenize_as(&[text.to_string()], TextRole::Document)?;
+        let prefix_len = self.role_prefix(TextRole::Document).len();
+        self.admit(&encodings)?;
+        let output = self.embed_encodings(&encodings, false)?;
+        let token_embeddings = output
+            .token_embeddings
+            .ok_or_else(|| JsValue::from_str("No embedding generated"))?;
+        let mut tokens = content_tokens(&token_embeddings, 0, &encodings[0])
+            .map_err(|e| JsValue::from_str(&format!("Token extraction failed: {}", e)))?;
+        drop_prefix_tokens(&mut tokens, prefix_len);
+
+        Ok(TokenEmbeddings {
+            ids: tokens.iter().map(|t| t.id).collect(),
+            offsets: utf16_offsets(text, tokens.iter().map(|t| t.offsets)),
+            dimension: token_embeddings.dim(2).unwrap_or(0),
+            vectors: tokens
+                .iter()
+                .flat_map(|t| t.vector.iter().copied())
+                .collect(),
+            tokens: tokens.into_iter().map(|t| t.token).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use tokenizers::Tokenizer;
+
+    #[test]
+    fn test_model_positions_skip_padding() {
+        let tokenizer = Tokenizer::from_str(
+            r#"{
+                "version": "1.0", "truncation": null, "added_tokens": [],
+                "padding": {"strategy": {"Fixed": 6}, "direction": "Right", "pad_to_multiple_of": null,
+                            "pad_id": 0, "pad_type_id": 0, "pad_token": "[PAD]"},
+                "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+                "post_processor": null, "decoder": null,
+                "model": {"type": "WordLevel", "vocab": {"[PAD]": 0, "cat": 1, "dog": 2},
+                          "unk_token": "[PAD]"}
+            }"#,
+        )
+        .unwrap();
+        let encoding = tokenizer.encode("cat dog cat", false).unwrap();
+        assert_eq!(encoding.len(), 6);
+        assert_eq!(
+            model_positions(&encoding).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+
+        let long = "cat ".repeat(MAX_SEQUENCE_LENGTH + 10);
+        let encoding = tokenizer.encode(long.as_str(), false).unwrap();
+        assert_eq!(model_positions(&encoding).count(), MAX_SEQUENCE_LENGTH);
+    }
+
+    #[test]
+    fn test_offsets_skip_preset_prefix() {
+        let mut engine = EmbeddingEngine::new();
+        engine.tokenizer = Some(
+            Tokenizer::from_str(
+                r#"{
+                    "version": "1.0", "truncation": null, "padding": null, "added_tokens": [],
+                    "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+                    "post_processor": {"type": "BertProcessing",
+                                       "sep": ["[SEP]", 2], "cls": ["[CLS]", 1]},
+                    "decoder": null,
+                    "model": {"type": "WordLevel",
+                              "vocab": {"[UNK]": 0, "[CLS]": 1, "[SEP]": 2, "passage": 3,
+                                        ":": 4, "cat": 5, "dog": 6},
+                              "unk_token": "[UNK]"}
+                }"#,
+            )
+            .unwrap(),
+        );
+        engine.preset = crate::presets::ModelPreset::find("e5-small-v2");
+        let tokenized = engine.encode_with_offsets("cat dog").unwrap();
+        assert_eq!(tokenized.ids, vec![1, 3, 4, 5, 6, 2]);
+        assert_eq!(tokenized.special, vec![1, 1, 1, 0, 0, 1]);
+        assert_eq!(tokenized.offsets, vec![0, 0, 0, 0, 0, 0, 0, 3, 4, 7, 0, 0]);
+    }
+}
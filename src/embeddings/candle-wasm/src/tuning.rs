@@ -0,0 +1,493 @@
+//! Warmup and batch size tuning for the current device
+//!
+//! The first forward pass pays for growing linear memory and filling caches,
+//! so `warmup()` runs the model once before the first real request. The batch
+//! size with the best throughput also varies several-fold between devices: a
+//! desktop tab keeps improving up to large batches, while a low-end phone
+//! peaks early once activations spill its caches. With `auto_tune`, warmup
+//! times a few batch sizes and keeps the fastest as the micro-batch size.
+//!
+//! `autotune()` also bounds how long one forward pass blocks the thread: it
+//! keeps the fastest micro-batch size whose passes finish within
+//! `target_latency_ms`, then times texts at the model's length limit to cap
+//! the padded tokens per pass so batches of long texts stay within the target
+//! too. Inference is single-threaded in every build, so there is no thread
+//! count to tune.
+
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use tokenizers::Encoding;
+
+use crate::clock;
+use crate::js::parse_options;
+use crate::{EmbeddingEngine, MAX_SEQUENCE_LENGTH};
+
+/// Text embedded by `warmup()`
+const WARMUP_TEXT: &str = "The quick brown fox jumps over the lazy dog";
+
+/// Minimum time spent timing each batch size, so coarse clocks (whole
+/// milliseconds from `Date.now()`) still give a usable rate
+const MIN_SAMPLE_MS: f64 = 20.0;
+
+/// Forward-pass latency `autotune()` aims for unless told otherwise
+const DEFAULT_TARGET_LATENCY_MS: f64 = 100.0;
+
+/// A larger batch size must beat the current choice by this factor; larger
+/// batches cost memory and latency, so a marginal gain isn't worth it
+const MIN_IMPROVEMENT: f64 = 1.05;
+
+/// Options for `warmup`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct WarmupOptions {
+    /// Time the candidate batch sizes and keep the fastest
+    auto_tune: bool,
+    /// Candidate micro-batch sizes
+    batch_sizes: Vec<usize>,
+    /// Minimum passes timed per batch size
+    rounds: usize,
+}
+
+impl Default for WarmupOptions {
+    fn default() -> Self {
+        WarmupOptions {
+            auto_tune: false,
+            batch_sizes: vec![1, 2, 4, 8, 16, 32],
+            rounds: 2,
+        }
+    }
+}
+
+/// Options for `autotune`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct AutotuneOptions {
+    /// Longest a forward pass should block the thread
+    target_latency_ms: f64,
+    /// Candidate micro-batch sizes
+    batch_sizes: Vec<usize>,
+    /// Minimum passes timed per batch size
+    rounds: usize,
+}
+
+impl Default for AutotuneOptions {
+    fn default() -> Self {
+        AutotuneOptions {
+            target_latency_ms: DEFAULT_TARGET_LATENCY_MS,
+            batch_sizes: vec![1, 2, 4, 8, 16, 32, 64],
+            rounds: 2,
+        }
+    }
+}
+
+/// Timing of one batch size
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Measurement {
+    batch_size: usize,
+    texts_per_second: f64,
+    /// Average time of one forward pass
+    latency_ms: f64,
+}
+
+/// Result of `warmup()`
+#[wasm_bindgen]
+pub struct WarmupReport {
+    elapsed_ms: f64,
+    batch_size: usize,
+    measurements: Vec<Measurement>,
+}
+
+#[wasm_bindgen]
+impl WarmupReport {
+    /// Total time spent in `warmup()`
+    #[wasm_bindgen(getter)]
+    pub fn elapsed_ms(&self) -> f64 {
+        self.elapsed_ms
+    }
+
+    /// Micro-batch size chosen by auto-tuning (0 if not tuned)
+    #[wasm_bindgen(getter)]
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Batch sizes that were timed, in increasing order
+    #[wasm_bindgen(getter)]
+    pub fn batch_sizes(&self) -> Vec<u32> {
+        self.measurements
+            .iter()
+            .map(|m| m.batch_size as u32)
+            .collect()
+    }
+
+    /// Measured texts per second for each of `batch_sizes`
+    #[wasm_bindgen(getter)]
+    pub fn texts_per_second(&self) -> Vec<f64> {
+        self.measurements
+            .iter()
+            .map(|m| m.texts_per_second)
+            .collect()
+    }
+}
+
+/// Settings chosen by `autotune()`, already applied to the engine
+#[wasm_bindgen]
+pub struct TuningProfile {
+    micro_batch_size: usize,
+    max_tokens_per_forward: usize,
+    target_latency_ms: f64,
+    elapsed_ms: f64,
+    measurements: Vec<Measurement>,
+}
+
+#[wasm_bindgen]
+impl TuningProfile {
+    /// Chosen micro-batch size
+    #[wasm_bindgen(getter)]
+    pub fn micro_batch_size(&self) -> usize {
+        self.micro_batch_size
+    }
+
+    /// Chosen cap on padded tokens per forward pass (0 when the micro-batch
+    /// size alone keeps passes within the target)
+    #[wasm_bindgen(getter)]
+    pub fn max_tokens_per_forward(&self) -> usize {
+        self.max_tokens_per_forward
+    }
+
+    /// Threads used for inference (always 1: builds are single-threaded)
+    #[wasm_bindgen(getter)]
+    pub fn threads(&self) -> usize {
+        1
+    }
+
+    /// The latency target tuned for
+    #[wasm_bindgen(getter)]
+    pub fn target_latency_ms(&self) -> f64 {
+        self.target_latency_ms
+    }
+
+    /// Measured forward-pass latency at the chosen micro-batch size
+    #[wasm_bindgen(getter)]
+    pub fn latency_ms(&self) -> f64 {
+        self.chosen().map_or(0.0, |m| m.latency_ms)
+    }
+
+    /// Measured throughput of short texts at the chosen micro-batch size
+    #[wasm_bindgen(getter)]
+    pub fn texts_per_second(&self) -> f64 {
+        self.chosen().map_or(0.0, |m| m.texts_per_second)
+    }
+
+    /// Whether passes at the chosen size finished within the target (if not,
+    /// even single texts are slower than the target on this device)
+    #[wasm_bindgen(getter)]
+    pub fn target_met(&self) -> bool {
+        self.latency_ms() <= self.target_latency_ms
+    }
+
+    /// Total time spent tuning
+    #[wasm_bindgen(getter)]
+    pub fn elapsed_ms(&self) -> f64 {
+        self.elapsed_ms
+    }
+
+    /// Batch sizes that were timed, in increasing order
+    #[wasm_bindgen(getter)]
+    pub fn batch_sizes(&self) -> Vec<u32> {
+        self.measurements
+            .iter()
+            .map(|m| m.batch_size as u32)
+            .collect()
+    }
+
+    /// Measured forward-pass latency for each of `batch_sizes`
+    #[wasm_bindgen(getter)]
+    pub fn latencies_ms(&self) -> Vec<f64> {
+        self.measurements.iter().map(|m| m.latency_ms).collect()
+    }
+}
+
+impl TuningProfile {
+    fn chosen(&self) -> Option<&Measurement> {
+        self.measurements
+            .iter()
+            .find(|m| m.batch_size == self.micro_batch_size)
+    }
+}
+
+/// Smallest batch size whose throughput no larger size beats by
+/// `MIN_IMPROVEMENT`; `measurements` are sorted by batch size
+fn pick_batch_size(measurements: &[Measurement]) -> Option<usize> {
+    let mut best = *measurements.first()?;
+    for &m in &measurements[1..] {
+        if m.texts_per_second > best.texts_per_second * MIN_IMPROVEMENT {
+            best = m;
+        }
+    }
+    Some(best.batch_size)
+}
+
+/// `pick_batch_size()` among the sizes within `target_ms`, or the smallest
+/// size when none is
+fn pick_within_latency(measurements: &[Measurement], target_ms: f64) -> Option<usize> {
+    let within: Vec<Measurement> = measurements
+        .iter()
+        .copied()
+        .filter(|m| m.latency_ms <= target_ms)
+        .collect();
+    pick_batch_size(&within).or_else(|| measurements.first().map(|m| m.batch_size))
+}
+
+/// Padded-token cap that keeps passes over `seq_len`-token texts within
+/// `target_ms`, given their timings; `None` when even the largest timed batch
+/// is within it
+fn token_cap(long: &[Measurement], seq_len: usize, target_ms: f64) -> Option<usize> {
+    if long.iter().all(|m| m.latency_ms <= target_ms) {
+        return None;
+    }
+    let fitting = long
+        .iter()
+        .filter(|m| m.latency_ms <= target_ms)
+        .map(|m| m.batch_size)
+        .max()
+        .unwrap_or(1);
+    Some(fitting * seq_len)
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// Run the model once so the first real call is fast, optionally tuning
+    /// the micro-batch size
+    ///
+    /// Options: `{ auto_tune = false, batch_sizes = [1, 2, 4, 8, 16, 32],
+    /// rounds = 2 }`. With `auto_tune`, each size allowed by the memory budget
+    /// and token cap is timed and the best becomes `micro_batch_size()`.
+    /// Warmup passes are not reported to `on_inference`.
+    #[wasm_bindgen]
+    pub fn warmup(&mut self, options: &JsValue) -> Result<WarmupReport, JsValue> {
+        let options: WarmupOptions = parse_options(options)?;
+        self.run_warmup(&options)
+    }
+
+    /// Time micro-batch sizes and token caps on this device and apply the
+    /// fastest settings that keep one forward pass within a latency target
+    ///
+    /// Options: `{ target_latency_ms = 100, batch_sizes = [1, 2, 4, 8, 16,
+    /// 32, 64], rounds = 2 }`. Sets `micro_batch_size()` and
+    /// `max_tokens_per_forward()` (save them with `snapshot()`); sizes over
+    /// the memory budget are skipped. Passes are not reported to
+    /// `on_inference`.
+    #[wasm_bindgen]
+    pub fn autotune(&mut self, options: &JsValue) -> Result<TuningProfile, JsValue> {
+        let options: AutotuneOptions = parse_options(options)?;
+        if options.target_latency_ms.is_nan() || options.target_latency_ms <= 0.0 {
+            return Err(JsValue::from_str("target_latency_ms must be positive"));
+        }
+        self.run_autotune(&options)
+    }
+
+    /// Limit how many texts go through the model in one forward pass (0
+    /// removes the limit)
+    ///
+    /// Set automatically by `warmup({ auto_tune: true })` and `autotune()`.
+    #[wasm_bindgen]
+    pub fn set_micro_batch_size(&mut self, size: usize) {
+        self.micro_batch_size = (size > 0).then_some(size);
+    }
+
+    /// Current micro-batch size (0 when unlimited)
+    #[wasm_bindgen]
+    pub fn micro_batch_size(&self) -> usize {
+        self.micro_batch_size.unwrap_or(0)
+    }
+}
+
+impl EmbeddingEngine {
+    /// Candidate batch sizes, deduplicated and sorted, up to the first that
+    /// doesn't fit the memory budget for `seq_len`-token texts
+    fn candidate_sizes(&self, sizes: &[usize], seq_len: usize) -> Vec<usize> {
+        let cost = self.batch_cost();
+        let mut sizes = sizes.to_vec();
+        sizes.retain(|&size| size > 0);
+        sizes.sort_unstable();
+        sizes.dedup();
+        let fits = sizes
+            .iter()
+            .take_while(|&&size| {
+                self.memory_budget
+                    .zip(cost.as_ref())
+                    .is_none_or(|(budget, cost)| cost(size, seq_len) <= budget)
+            })
+            .count();
+        sizes.truncate(fits);
+        sizes
+    }
+
+    /// Time forward passes over `size` copies of `encoding`
+    fn time_batch(
+        &self,
+        encoding: &Encoding,
+        size: usize,
+        rounds: usize,
+    ) -> Result<Measurement, JsValue> {
+        let batch = vec![encoding.clone(); size];
+        let timer = clock::now_ms();
+        let mut passes = 0;
+        while passes < rounds.max(1) || clock::now_ms() - timer < MIN_SAMPLE_MS {
+            self.embed_encodings(&batch, false)?;
+            passes += 1;
+        }
+        let elapsed = (clock::now_ms() - timer).max(f64::EPSILON);
+        Ok(Measurement {
+            batch_size: size,
+            texts_per_second: (passes * size) as f64 * 1000.0 / elapsed,
+            latency_ms: elapsed / passes as f64,
+        })
+    }
+
+    /// Time `sizes` in order, stopping after the first over `target_ms`
+    fn time_up_to(
+        &self,
+        encoding: &Encoding,
+        sizes: &[usize],
+        rounds: usize,
+        target_ms: f64,
+    ) -> Result<Vec<Measurement>, JsValue> {
+        let mut measurements = Vec::new();
+        for &size in sizes {
+            let measurement = self.time_batch(encoding, size, rounds)?;
+            measurements.push(measurement);
+            if measurement.latency_ms > target_ms {
+                break;
+            }
+        }
+        Ok(measurements)
+    }
+
+    /// `autotune()` with parsed options
+    fn run_autotune(&mut self, options: &AutotuneOptions) -> Result<TuningProfile, JsValue> {
+        let start = clock::now_ms();
+        let target = options.target_latency_ms;
+        let short = self.tokenize(&[WARMUP_TEXT.to_string()])?.remove(0);
+        self.embed_encodings(std::slice::from_ref(&short), false)?;
+
+        let sizes = self.candidate_sizes(&options.batch_sizes, short.len());
+        let measurements = self.time_up_to(&short, &sizes, options.rounds, target)?;
+        let micro_batch = pick_within_latency(&measurements, target).unwrap_or(1);
+
+        // Texts at the length limit, to see how many fit in one pass
+        let long = self
+            .tokenize(&[WARMUP_TEXT.repeat(MAX_SEQUENCE_LENGTH / 4)])?
+            .remove(0);
+        let long_len = long.len().min(MAX_SEQUENCE_LENGTH);
+        let long_sizes: Vec<usize> = self
+            .candidate_sizes(&sizes, long_len)
+            .into_iter()
+            .filter(|&size| size <= micro_batch)
+            .collect();
+        let long_measurements = self.time_up_to(&long, &long_sizes, options.rounds, target)?;
+        let cap = token_cap(&long_measurements, long_len, target);
+        debug_log!(
+            "autotune: {:?}, at {} tokens {:?}",
+            measurements,
+            long_len,
+            long_measurements
+        );
+
+        self.micro_batch_size = Some(micro_batch);
+        self.max_tokens_per_forward = cap;
+        Ok(TuningProfile {
+            micro_batch_size: micro_batch,
+            max_tokens_per_forward: cap.unwrap_or(0),
+            target_latency_ms: target,
+            elapsed_ms: clock::now_ms() - start,
+            measurements,
+        })
+    }
+
+    /// `warmup()` with parsed options
+    fn run_warmup(&mut self, options: &WarmupOptions) -> Result<WarmupReport, JsValue> {
+        let start = clock::now_ms();
+        let sample = self.tokenize(&[WARMUP_TEXT.to_string()])?;
+        self.embed_encodings(&sample, false)?;
+
+        let mut measurements = Vec::new();
+        let mut chosen = None;
+        if options.auto_tune {
+            let seq_len = sample[0].len();
+            for size in self.candidate_sizes(&options.batch_sizes, seq_len) {
+                if self
+                    .max_tokens_per_forward
+                    .is_some_and(|max| size * seq_len > max)
+                {
+                    break;
+                }
+                measurements.push(self.time_batch(&sample[0], size, options.rounds)?);
+            }
+            debug_log!("warmup throughput by batch size: {:?}", measurements);
+            chosen = pick_batch_size(&measurements);
+            if chosen.is_some() {
+                self.micro_batch_size = chosen;
+            }
+        }
+
+        Ok(WarmupReport {
+            elapsed_ms: clock::now_ms() - start,
+            batch_size: chosen.unwrap_or(0),
+            measurements,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Measurements from `(batch size, texts per second)`
+    fn measured(rates: &[(usize, f64)]) -> Vec<Measurement> {
+        rates
+            .iter()
+            .map(|&(batch_size, texts_per_second)| Measurement {
+                batch_size,
+                texts_per_second,
+                latency_ms: batch_size as f64 * 1000.0 / texts_per_second,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pick_batch_size() {
+        // Throughput flattens out after 8
+        let rates = measured(&[(1, 100.0), (2, 180.0), (4, 300.0), (8, 400.0), (16, 410.0)]);
+        assert_eq!(pick_batch_size(&rates), Some(8));
+        // A device that gets slower with larger batches
+        let rates = measured(&[(1, 100.0), (2, 90.0), (4, 60.0)]);
+        assert_eq!(pick_batch_size(&rates), Some(1));
+        assert_eq!(pick_batch_size(&[]), None);
+    }
+
+    #[test]
+    fn test_latency_target_limits_batch_size() {
+        // Latencies 10, 11.1, 13.3, 20 and 39 ms
+        let rates = measured(&[(1, 100.0), (2, 180.0), (4, 300.0), (8, 400.0), (16, 410.0)]);
+        assert_eq!(pick_within_latency(&rates, 15.0), Some(4));
+        assert_eq!(pick_within_latency(&rates, 1000.0), Some(8));
+        assert_eq!(pick_within_latency(&rates, 5.0), Some(1));
+
+        // Long texts: 1 and 2 per pass fit in 15 ms, 4 don't
+        let long = measured(&[(1, 200.0), (2, 150.0), (4, 100.0)]);
+        assert_eq!(token_cap(&long, 128, 15.0), Some(256));
+        assert_eq!(token_cap(&long, 128, 50.0), None);
+        assert_eq!(token_cap(&long[2..], 128, 15.0), Some(128));
+    }
+
+    #[test]
+    fn test_warmup_options() {
+        let options: WarmupOptions =
+            crate::js::options_from_json(r#"{"auto_tune": true}"#).unwrap();
+        assert!(options.auto_tune);
+        assert_eq!(options.batch_sizes, vec![1, 2, 4, 8, 16, 32]);
+    }
+}
@@ -0,0 +1,80 @@
+//! Embedding text given as UTF-8 bytes
+//!
+//! Passing a JS string to WASM means encoding its UTF-16 into a fresh UTF-8
+//! buffer. Pipelines that already hold UTF-8 (file readers, `fetch` bodies,
+//! network streams) can hand over the bytes instead: they are copied into
+//! WASM memory once and only validated, never re-encoded.
+
+use js_sys::{Array, Float32Array, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::{batching, EmbeddingEngine};
+
+/// Take ownership of UTF-8 bytes as a string, without copying
+fn utf8_text(bytes: Vec<u8>, what: &str) -> Result<String, String> {
+    String::from_utf8(bytes).map_err(|e| {
+        format!(
+            "{} is not valid UTF-8 (at byte {})",
+            what,
+            e.utf8_error().valid_up_to()
+        )
+    })
+}
+
+#[wasm_bindgen]
+impl EmbeddingEngine {
+    /// `embed()` for text given as UTF-8 bytes (e.g. a `Uint8Array` from a
+    /// file or response body)
+    #[wasm_bindgen]
+    pub fn embed_utf8(&self, bytes: Vec<u8>) -> Result<Float32Array, JsValue> {
+        let text = utf8_text(bytes, "Input").map_err(|e| JsValue::from_str(&e))?;
+        let embeddings = self.embed_matrix(&[text])?;
+        if embeddings.len() > 0 {
+            Ok(Float32Array::from(embeddings.row(0)))
+        } else {
+            Err(JsValue::from_str("No embedding generated"))
+        }
+    }
+
+    /// `embed_batch()` for an array of `Uint8Array`s holding UTF-8 text
+    #[wasm_bindgen]
+    pub fn embed_batch_utf8(&self, buffers: &Array) -> Result<Array, JsValue> {
+        let mut texts = Vec::with_capacity(buffers.length() as usize);
+        for (i, buffer) in buffers.iter().enumerate() {
+            let bytes = buffer.dyn_into::<Uint8Array>().map_err(|_| {
+                JsValue::from_str(&format!("Item at index {} is not a Uint8Array", i))
+            })?;
+            let text = utf8_text(bytes.to_vec(), &format!("Item at index {}", i))
+                .map_err(|e| JsValue::from_str(&e))?;
+            texts.push(text);
+        }
+        if texts.is_empty() {
+            return Ok(Array::new());
+        }
+
+        let (unique, positions) = batching::dedup_texts(texts);
+        let embeddings = self.embed_matrix(&unique)?;
+        Ok(positions
+            .iter()
+            .map(|&i| JsValue::from(Float32Array::from(embeddings.row(i))))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_validated_in_place() {
+        let bytes = "naïve café".as_bytes().to_vec();
+        let ptr = bytes.as_ptr();
+        let text = utf8_text(bytes, "Input").unwrap();
+        assert_eq!(text, "naïve café");
+        assert_eq!(text.as_ptr(), ptr);
+
+        let err = utf8_text(vec![b'o', b'k', 0xff, b'!'], "Item at index 3").unwrap_err();
+        assert_eq!(err, "Item at index 3 is not valid UTF-8 (at byte 2)");
+    }
+}